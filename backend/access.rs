@@ -0,0 +1,580 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::str::FromStr;
+use tracing::{error, warn};
+use typeshare::typeshare;
+
+use crate::audit_log::record;
+use crate::auth::{ensure_user, user_name, CurrentUser, User};
+use crate::policy::{is_owner_or_admin, Admin, Authorized};
+use crate::schema::{model_grants, models3d};
+use crate::tenants::{ensure_tenant, tenant_name_for_user, TenantScope};
+use crate::types::{File3D, Model3D};
+use crate::{AppState, Config};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    /// only the owner, explicit grants and admins
+    Private,
+    /// every authenticated user
+    Internal,
+    /// everyone, including anonymous requests
+    Public,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Private => "private",
+            Visibility::Internal => "internal",
+            Visibility::Public => "public",
+        }
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "private" => Ok(Visibility::Private),
+            "internal" => Ok(Visibility::Internal),
+            "public" => Ok(Visibility::Public),
+            _ => Err(()),
+        }
+    }
+}
+
+const GRANT_USER: &str = "user";
+const GRANT_GROUP: &str = "group";
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessSettings {
+    pub visibility: String,
+    pub owner: Option<String>,
+    pub users: Vec<String>,
+    pub groups: Vec<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateAccessSettings {
+    pub visibility: String,
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOwnership {
+    /// name of the new owner
+    pub owner: String,
+}
+
+/// Who is asking. Admins see every model of every tenant; as long as no
+/// `admin_users` are configured every request is treated as admin so
+/// installations without authentication keep working unchanged.
+#[derive(Debug, Clone)]
+pub struct Viewer {
+    pub user: Option<User>,
+    pub groups: Vec<String>,
+    pub is_admin: bool,
+    pub scope: TenantScope,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Viewer {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let current_user = CurrentUser::from_request_parts(parts, state).await?;
+        let groups = parts
+            .headers
+            .get(state.config.auth_groups_header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|group| group.trim().to_string())
+                    .filter(|group| !group.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let is_admin = is_admin(&state.config, current_user.0.as_deref());
+
+        let scope = if is_admin {
+            TenantScope::All
+        } else {
+            TenantScope::from_request_parts(parts, state).await?
+        };
+
+        let mut connection = state.connection().await?;
+        let user = CurrentUser::load(&current_user, &mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to load user: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        Ok(Viewer {
+            user,
+            groups,
+            is_admin,
+            scope,
+        })
+    }
+}
+
+pub fn is_admin(config: &Config, name: Option<&str>) -> bool {
+    match name {
+        _ if config.admin_users.is_empty() => true,
+        Some(name) => config.admin_users.iter().any(|admin| admin == name),
+        None => false,
+    }
+}
+
+impl Viewer {
+    /// What `user` may see when acting on their behalf outside of a request.
+    /// Groups only come with request headers, so group grants do not apply.
+    pub async fn for_user<Conn>(
+        config: &Config,
+        connection: &mut Conn,
+        user: User,
+    ) -> QueryResult<Viewer>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let is_admin = is_admin(config, Some(&user.name));
+        let scope = if is_admin || !config.multi_tenant {
+            TenantScope::All
+        } else {
+            let name = tenant_name_for_user(config, &user.name);
+            TenantScope::Tenant(ensure_tenant(connection, &name).await?)
+        };
+        Ok(Viewer {
+            user: Some(user),
+            groups: Vec::new(),
+            is_admin,
+            scope,
+        })
+    }
+
+    fn visible_levels(&self) -> Vec<&'static str> {
+        match self.user {
+            Some(_) => vec![Visibility::Public.as_str(), Visibility::Internal.as_str()],
+            None => vec![Visibility::Public.as_str()],
+        }
+    }
+
+    /// Ids of the models the viewer was granted access to explicitly.
+    fn granted_ids(&self) -> model_grants::BoxedQuery<'static, Sqlite, diesel::sql_types::Integer> {
+        let name = self
+            .user
+            .as_ref()
+            .map(|user| user.name.clone())
+            .unwrap_or_default();
+        model_grants::dsl::model_grants
+            .select(model_grants::dsl::model_id)
+            .filter(
+                model_grants::dsl::principal_kind
+                    .eq(GRANT_USER)
+                    .and(model_grants::dsl::principal.eq(name)),
+            )
+            .or_filter(
+                model_grants::dsl::principal_kind
+                    .eq(GRANT_GROUP)
+                    .and(model_grants::dsl::principal.eq_any(self.groups.clone())),
+            )
+            .into_boxed()
+    }
+
+    /// Ids of all models the viewer may see, `None` when nothing is hidden.
+    /// Tenants are restricted separately by the scope.
+    pub fn visible_ids(
+        &self,
+    ) -> Option<models3d::BoxedQuery<'static, Sqlite, diesel::sql_types::Integer>> {
+        if self.is_admin {
+            return None;
+        }
+
+        let mut ids = models3d::dsl::models3d
+            .select(models3d::dsl::id)
+            .filter(models3d::dsl::visibility.eq_any(self.visible_levels()))
+            .into_boxed();
+        if let Some(user) = &self.user {
+            ids = ids.or_filter(models3d::dsl::owner_id.eq(user.id));
+        }
+        if self.user.is_some() || !self.groups.is_empty() {
+            ids = ids.or_filter(models3d::dsl::id.eq_any(self.granted_ids()));
+        }
+        Some(ids)
+    }
+
+    pub async fn can_view<Conn>(&self, connection: &mut Conn, model: &Model3D) -> QueryResult<bool>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        if !self.scope.allows(model) {
+            return Ok(false);
+        }
+        if self.can_manage(model)
+            || self
+                .visible_levels()
+                .iter()
+                .any(|level| *level == model.visibility)
+        {
+            return Ok(true);
+        }
+
+        let grants = self
+            .granted_ids()
+            .filter(model_grants::dsl::model_id.eq(model.id))
+            .load::<i32>(connection)
+            .await?;
+        Ok(!grants.is_empty())
+    }
+
+    /// Owners and admins may change, move and delete a model.
+    pub fn can_manage(&self, model: &Model3D) -> bool {
+        if self.is_admin {
+            return true;
+        }
+        self.scope.allows(model) && is_owner_or_admin(self, model.owner_id)
+    }
+
+    /// Looks a model up by slug, hiding models the viewer may not see.
+    pub async fn find_model<Conn>(
+        &self,
+        connection: &mut Conn,
+        slug: &str,
+    ) -> Result<Model3D, StatusCode>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let model = self.scope.find_model(connection, slug).await?;
+        self.check_view(connection, model).await
+    }
+
+    /// Like `find_model`, but also requires the viewer to manage the model.
+    pub async fn manage_model<Conn>(
+        &self,
+        connection: &mut Conn,
+        slug: &str,
+    ) -> Result<Model3D, StatusCode>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let model = self.find_model(connection, slug).await?;
+        if self.can_manage(&model) {
+            Ok(model)
+        } else {
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+
+    /// Looks a file up by id together with its model, hiding files of
+    /// models the viewer may not see.
+    pub async fn find_file<Conn>(
+        &self,
+        connection: &mut Conn,
+        id: i32,
+    ) -> Result<(File3D, Model3D), StatusCode>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let (file, model) = self.scope.find_file(connection, id).await?;
+        let model = self.check_view(connection, model).await?;
+        Ok((file, model))
+    }
+
+    async fn check_view<Conn>(
+        &self,
+        connection: &mut Conn,
+        model: Model3D,
+    ) -> Result<Model3D, StatusCode>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        match self.can_view(connection, &model).await {
+            Ok(true) => Ok(model),
+            Ok(false) => Err(StatusCode::NOT_FOUND),
+            Err(e) => {
+                error!("Failed to check access to {}: {}", model.slug, e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// Applies `default_visibility` to a newly discovered model.
+pub async fn apply_default_visibility<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let visibility = Visibility::from_str(&config.default_visibility).unwrap_or_else(|_| {
+        warn!(
+            "Unknown default visibility {:?}, using public",
+            config.default_visibility
+        );
+        Visibility::Public
+    });
+
+    if visibility.as_str() != model.visibility {
+        diesel::update(models3d::dsl::models3d.find(model.id))
+            .set(models3d::dsl::visibility.eq(visibility.as_str()))
+            .execute(connection)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn set_owner<Conn>(connection: &mut Conn, model: &Model3D, user: &User) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::update(models3d::dsl::models3d.find(model.id))
+        .set(models3d::dsl::owner_id.eq(user.id))
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_uploader<Conn>(
+    connection: &mut Conn,
+    model: &Model3D,
+    user: &User,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::update(models3d::dsl::models3d.find(model.id))
+        .set(models3d::dsl::uploader_id.eq(user.id))
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+async fn load_access_settings<Conn>(
+    connection: &mut Conn,
+    model: &Model3D,
+) -> QueryResult<AccessSettings>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let owner = user_name(connection, model.owner_id).await?;
+
+    let grants = model_grants::dsl::model_grants
+        .filter(model_grants::dsl::model_id.eq(model.id))
+        .select((
+            model_grants::dsl::principal_kind,
+            model_grants::dsl::principal,
+        ))
+        .order(model_grants::dsl::principal.asc())
+        .load::<(String, String)>(connection)
+        .await?;
+
+    let principals = |kind: &str| {
+        grants
+            .iter()
+            .filter(|(principal_kind, _)| principal_kind == kind)
+            .map(|(_, principal)| principal.clone())
+            .collect()
+    };
+
+    Ok(AccessSettings {
+        visibility: model.visibility.clone(),
+        owner,
+        users: principals(GRANT_USER),
+        groups: principals(GRANT_GROUP),
+    })
+}
+
+pub async fn handle_get_access(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+) -> Result<Json<AccessSettings>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let model = viewer.manage_model(&mut connection, &slug).await?;
+    let settings = load_access_settings(&mut connection, &model)
+        .await
+        .map_err(|e| {
+            error!("Failed to load access settings of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(settings))
+}
+
+pub async fn handle_update_access(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+    Json(update): Json<UpdateAccessSettings>,
+) -> Result<Json<AccessSettings>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let model = viewer.manage_model(&mut connection, &slug).await?;
+    let visibility =
+        Visibility::from_str(&update.visibility).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+
+    let grants: Vec<_> = update
+        .users
+        .iter()
+        .map(|user| (GRANT_USER, user))
+        .chain(update.groups.iter().map(|group| (GRANT_GROUP, group)))
+        .map(|(kind, principal)| (kind, principal.trim().to_string()))
+        .filter(|(_, principal)| !principal.is_empty())
+        .map(|(kind, principal)| {
+            (
+                model_grants::dsl::model_id.eq(model.id),
+                model_grants::dsl::principal_kind.eq(kind),
+                model_grants::dsl::principal.eq(principal),
+            )
+        })
+        .collect();
+
+    let result: QueryResult<()> = async {
+        diesel::update(models3d::dsl::models3d.find(model.id))
+            .set(models3d::dsl::visibility.eq(visibility.as_str()))
+            .execute(&mut connection)
+            .await?;
+        diesel::delete(
+            model_grants::dsl::model_grants.filter(model_grants::dsl::model_id.eq(model.id)),
+        )
+        .execute(&mut connection)
+        .await?;
+        for grant in &grants {
+            diesel::insert_or_ignore_into(model_grants::table)
+                .values(grant)
+                .execute(&mut connection)
+                .await?;
+        }
+        Ok(())
+    }
+    .await;
+    result.map_err(|e| {
+        error!("Failed to update access settings of {}: {}", model.slug, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if let Err(e) = model.touch(&mut connection).await {
+        error!("Failed to touch {}: {}", model.slug, e);
+    }
+
+    let model = models3d::dsl::models3d
+        .find(model.id)
+        .first::<Model3D>(&mut connection)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let settings = load_access_settings(&mut connection, &model)
+        .await
+        .map_err(|e| {
+            error!("Failed to load access settings of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(settings))
+}
+
+/// Hands a model over to another user, only for admins. Users are created on
+/// first sight, so ownership can be given to someone who has not signed in yet.
+pub async fn handle_transfer_ownership(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Path(slug): Path<String>,
+    Json(transfer): Json<TransferOwnership>,
+) -> Result<Json<AccessSettings>, StatusCode> {
+    let name = transfer.owner.trim();
+    if name.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let mut connection = state.connection().await?;
+
+    let model = viewer.find_model(&mut connection, &slug).await?;
+    let previous_owner = user_name(&mut connection, model.owner_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to load the owner of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let result: QueryResult<()> = async {
+        let owner = ensure_user(&mut connection, name).await?;
+        set_owner(&mut connection, &model, &owner).await?;
+        model.touch(&mut connection).await?;
+        record(
+            &mut connection,
+            &viewer,
+            "model.transfer",
+            &model.slug,
+            json!({ "from": previous_owner, "to": owner.name }),
+        )
+        .await
+    }
+    .await;
+    result.map_err(|e| {
+        error!("Failed to transfer {} to {}: {}", model.slug, name, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let model = models3d::dsl::models3d
+        .find(model.id)
+        .first::<Model3D>(&mut connection)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let settings = load_access_settings(&mut connection, &model)
+        .await
+        .map_err(|e| {
+            error!("Failed to load access settings of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(settings))
+}
+
+/// The model a file below the library belongs to. Every ancestor folder of
+/// the path may be the model folder, the innermost one wins for models
+/// nested in other models.
+pub async fn model_of_path<Conn>(
+    connection: &mut Conn,
+    relative: &std::path::Path,
+) -> QueryResult<Option<Model3D>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let candidates: Vec<String> = relative
+        .ancestors()
+        .skip(1)
+        .map(|ancestor| ancestor.to_string_lossy().into_owned())
+        .filter(|ancestor| !ancestor.is_empty())
+        .collect();
+
+    let models = models3d::dsl::models3d
+        .filter(models3d::dsl::folder_path.eq_any(candidates))
+        .filter(models3d::dsl::deleted_at.is_null())
+        .load::<Model3D>(connection)
+        .await?;
+    Ok(models
+        .into_iter()
+        .max_by_key(|model| model.folder_path.len()))
+}
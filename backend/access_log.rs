@@ -0,0 +1,94 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::json;
+use std::time::Instant;
+use tracing::{info, info_span, Instrument};
+use uuid::Uuid;
+
+use crate::client_ip::ClientIp;
+use crate::AppState;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Emits one line per request to the `access` target in the
+/// `request_log_format`, either `json` or the common log format. Every span
+/// entered while handling the request carries its request id, an incoming
+/// `X-Request-Id` is reused so ids can be correlated with the proxy logs.
+pub async fn log_requests(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        request.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let version = request.version();
+    let user = request
+        .headers()
+        .get(state.config.auth_user_header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let span = info_span!("request", request_id = %request_id);
+    let mut response = next.run(request).instrument(span).await;
+
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let status = response.status().as_u16();
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    if state.config.request_log_format == "json" {
+        let line = json!({
+            "method": method.as_str(),
+            "path": path,
+            "status": status,
+            "latency_ms": latency_ms,
+            "bytes": bytes,
+            "user": user,
+            "ip": ip.to_string(),
+            "request_id": request_id,
+        });
+        info!(target: "access", "{}", line);
+    } else {
+        info!(
+            target: "access",
+            "{} - {} [{}] \"{} {} {:?}\" {} {} {:.1}ms {}",
+            ip,
+            user.as_deref().unwrap_or("-"),
+            chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+            method,
+            path,
+            version,
+            status,
+            bytes.map(|bytes| bytes.to_string()).unwrap_or_else(|| "-".to_string()),
+            latency_ms,
+            request_id
+        );
+    }
+
+    response
+}
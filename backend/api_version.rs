@@ -0,0 +1,134 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{OriginalUri, Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{NaiveDate, NaiveTime};
+use serde_json::{json, Value};
+use tracing::error;
+
+use crate::AppState;
+
+/// Prefix of the current API version.
+pub const CURRENT_PREFIX: &str = "/api/v1";
+/// Prefix of the unversioned aliases of `CURRENT_PREFIX`.
+pub const LEGACY_PREFIX: &str = "/api";
+/// When `/api/v1` was introduced and the unversioned routes deprecated.
+const DEPRECATED_SINCE: (i32, u32, u32) = (2024, 11, 26);
+
+fn is_json(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"))
+}
+
+/// Responses of the versioned API are wrapped, `{"data": ...}` on success and
+/// `{"error": {"status": 404, "message": "Not Found", "details": ...}}`
+/// otherwise. Downloads, images and other bodies which are not JSON are
+/// passed through as they are.
+pub async fn envelope(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let wrap_success = status.is_success() && is_json(&response);
+    let wrap_error = status.is_client_error() || status.is_server_error();
+    if !wrap_success && !wrap_error {
+        return response;
+    }
+
+    let body_is_json = is_json(&response);
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read the response to wrap: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let payload: Option<Value> = if bytes.is_empty() {
+        None
+    } else if body_is_json {
+        serde_json::from_slice(&bytes).ok()
+    } else {
+        Some(Value::String(
+            String::from_utf8_lossy(&bytes).trim().to_string(),
+        ))
+    };
+
+    let wrapped = if wrap_success {
+        json!({ "data": payload })
+    } else {
+        let reason = status.canonical_reason().unwrap_or("Error").to_string();
+        let (message, details) = match payload {
+            Some(Value::String(message)) if !message.is_empty() => (message, Value::Null),
+            Some(details) => (reason, details),
+            None => (reason, Value::Null),
+        };
+        json!({
+            "error": {
+                "status": status.as_u16(),
+                "message": message,
+                "details": details,
+            }
+        })
+    };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Response::from_parts(parts, Body::from(wrapped.to_string()))
+}
+
+/// Marks responses of the unversioned aliases as deprecated, pointing to
+/// their `/api/v1` successor and, once `LEGACY_API_SUNSET` is configured,
+/// announcing when they go away.
+pub async fn deprecated_alias(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.0.clone())
+        .unwrap_or_else(|| request.uri().clone());
+    let successor = path
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or(LEGACY_PREFIX)
+        .strip_prefix(LEGACY_PREFIX)
+        .map(|rest| format!("{}{}", CURRENT_PREFIX, rest));
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    let (year, month, day) = DEPRECATED_SINCE;
+    if let Some(since) = NaiveDate::from_ymd_opt(year, month, day) {
+        // RFC 9745 structured date
+        let timestamp = since.and_time(NaiveTime::MIN).and_utc().timestamp();
+        if let Ok(value) = HeaderValue::from_str(&format!("@{}", timestamp)) {
+            headers.insert("deprecation", value);
+        }
+    }
+    if let Some(sunset) = state.config.legacy_api_sunset {
+        let sunset = sunset
+            .and_time(NaiveTime::MIN)
+            .and_utc()
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        if let Ok(value) = HeaderValue::from_str(&sunset) {
+            headers.insert("sunset", value);
+        }
+    }
+    if let Some(value) = successor.and_then(|successor| {
+        HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor)).ok()
+    }) {
+        headers.append(header::LINK, value);
+    }
+    response
+}
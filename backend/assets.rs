@@ -0,0 +1,185 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+use tower_http::services::ServeDir;
+use tracing::{debug, error};
+
+use crate::access::{model_of_path, Viewer};
+use crate::downloads::DownloadClient;
+use crate::schema::files3d;
+use crate::stats::{record_event_logged, EventKind};
+use crate::AppState;
+
+/// Content types a browser would render or run in the context of the app.
+/// Library files come from anywhere, these are only ever downloaded.
+const ACTIVE_CONTENT_TYPES: &[&str] = &[
+    "text/html",
+    "application/xhtml+xml",
+    "image/svg+xml",
+    "text/xml",
+    "application/xml",
+    "text/javascript",
+    "application/javascript",
+];
+
+/// The requested path relative to the library, `None` unless every
+/// component is a plain name, so `..`, absolute paths and drive prefixes
+/// never reach the file system.
+fn relative_path(uri_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(uri_path).decode_utf8().ok()?;
+    let relative = PathBuf::from(decoded.trim_start_matches('/'));
+    let is_plain = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    (is_plain && relative.components().next().is_some()).then_some(relative)
+}
+
+/// Whether `path` is a file inside the library. Symlinks leading out of the
+/// library are only followed if the scanner follows them too.
+async fn is_library_file(state: &AppState, path: &Path) -> bool {
+    let Ok(target) = tokio::fs::canonicalize(path).await else {
+        return false;
+    };
+    if !state.config.scanner_follow_symlinks {
+        let Ok(library) = tokio::fs::canonicalize(&state.config.libraries_path).await else {
+            return false;
+        };
+        if !target.starts_with(library) {
+            debug!("Refusing {} outside of the library", path.display());
+            return false;
+        }
+    }
+    tokio::fs::metadata(&target)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+}
+
+fn set_asset_headers(response: &mut Response) {
+    let headers = response.headers_mut();
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    let is_active = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| ACTIVE_CONTENT_TYPES.contains(&mime.trim()));
+    if is_active {
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_static("attachment"),
+        );
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("sandbox"),
+        );
+    }
+}
+
+/// Serves the files below `asset_prefix`. A file is only served if it
+/// belongs to a model the viewer may see, files outside of any model folder
+/// are reserved to admins. Fetching an indexed file of a model counts as an
+/// access in the statistics.
+pub async fn handle_asset(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    client: DownloadClient,
+    request: Request,
+) -> Response {
+    let Some(relative) = relative_path(request.uri().path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let mut connection = match state.connection().await {
+        Ok(connection) => connection,
+        Err(status) => return status.into_response(),
+    };
+    let model = match model_of_path(&mut connection, &relative).await {
+        Ok(model) => model,
+        Err(e) => {
+            error!(
+                "Failed to look up the model of {}: {}",
+                relative.display(),
+                e
+            );
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let allowed = match &model {
+        _ if viewer.is_admin => Ok(true),
+        Some(model) => viewer.can_view(&mut connection, model).await,
+        None => Ok(false),
+    };
+    match allowed {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to check asset access: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    // only files that were indexed as part of the model are counted
+    let indexed_file = match &model {
+        Some(model) => {
+            let file_path = relative
+                .strip_prefix(&model.folder_path)
+                .unwrap_or(&relative)
+                .to_string_lossy()
+                .into_owned();
+            files3d::dsl::files3d
+                .filter(files3d::dsl::model_id.eq(model.id))
+                .filter(files3d::dsl::file_path.eq(file_path))
+                .select(files3d::dsl::model_id)
+                .first::<i32>(&mut connection)
+                .await
+                .optional()
+                .unwrap_or_else(|e| {
+                    error!("Failed to look up {}: {}", relative.display(), e);
+                    None
+                })
+        }
+        None => None,
+    };
+    drop(connection);
+
+    let path = state.config.libraries_path.join(&relative);
+    if !is_library_file(&state, &path).await {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let is_get = request.method() == Method::GET;
+    let mut response = match ServeDir::new(&state.config.libraries_path)
+        .append_index_html_on_directories(false)
+        .try_call(request)
+        .await
+    {
+        Ok(response) => response.map(Body::new),
+        Err(e) => {
+            error!("Failed to serve {}: {}", path.display(), e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    set_asset_headers(&mut response);
+
+    let is_served = matches!(
+        response.status(),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT
+    );
+    let counts = is_get && is_served && client.is_counted();
+    if let Some(model_id) = indexed_file.filter(|_| counts) {
+        if let Ok(mut connection) = state.connection().await {
+            record_event_logged(&mut connection, model_id, EventKind::Asset).await;
+        }
+    }
+
+    response
+}
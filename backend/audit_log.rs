@@ -0,0 +1,102 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::policy::{Admin, Authorized};
+use crate::schema::audit_log;
+use crate::AppState;
+
+/// Recorded as actor for requests without an authenticated user, which only
+/// happens without `admin_users`.
+const ANONYMOUS: &str = "anonymous";
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = audit_log)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub actor: String,
+    /// e.g. `tag.rename`
+    pub action: String,
+    /// what the action was applied to, a tag name or model slug
+    pub target: String,
+    /// JSON object with the parameters of the action
+    pub details: String,
+}
+
+#[derive(Deserialize)]
+pub struct AuditLogParams {
+    pub action: Option<String>,
+    /// only entries older than this id, for paging
+    pub before: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+/// Records an administrative change made by `viewer`.
+pub async fn record<Conn>(
+    connection: &mut Conn,
+    viewer: &Viewer,
+    action: &str,
+    target: &str,
+    details: serde_json::Value,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let actor = viewer
+        .user
+        .as_ref()
+        .map(|user| user.name.as_str())
+        .unwrap_or(ANONYMOUS);
+    diesel::insert_into(audit_log::table)
+        .values((
+            audit_log::dsl::created_at.eq(chrono::Utc::now().naive_utc()),
+            audit_log::dsl::actor.eq(actor),
+            audit_log::dsl::action.eq(action),
+            audit_log::dsl::target.eq(target),
+            audit_log::dsl::details.eq(details.to_string()),
+        ))
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// The audit log, latest first, only for admins.
+pub async fn handle_audit_log(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Query(params): Query<AuditLogParams>,
+) -> Result<Json<Vec<AuditEntry>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let mut query = audit_log::dsl::audit_log
+        .order(audit_log::dsl::id.desc())
+        .limit(params.limit.unwrap_or(50).clamp(1, 500))
+        .into_boxed();
+    if let Some(action) = &params.action {
+        query = query.filter(audit_log::dsl::action.eq(action.clone()));
+    }
+    if let Some(before) = params.before {
+        query = query.filter(audit_log::dsl::id.lt(before));
+    }
+
+    let entries = query
+        .select(AuditEntry::as_select())
+        .load::<AuditEntry>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load the audit log: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(entries))
+}
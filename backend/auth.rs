@@ -0,0 +1,99 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use typeshare::typeshare;
+
+use crate::schema::users;
+use crate::AppState;
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = users)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    /// where notifications are sent, unset disables them
+    pub email: Option<String>,
+    /// whether the models the user views are kept in their history
+    pub record_history: bool,
+}
+
+/// The identity forwarded by the authenticating reverse proxy in the
+/// `auth_user_header`. Requests without the header are anonymous.
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub Option<String>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for CurrentUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let name = parts
+            .headers
+            .get(state.config.auth_user_header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        Ok(CurrentUser(name))
+    }
+}
+
+impl CurrentUser {
+    /// Loads the user row, creating it on first access.
+    pub async fn load<Conn>(&self, connection: &mut Conn) -> QueryResult<Option<User>>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        match &self.0 {
+            Some(name) => ensure_user(connection, name).await.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+pub async fn ensure_user<Conn>(connection: &mut Conn, name: &str) -> QueryResult<User>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::insert_or_ignore_into(users::table)
+        .values((
+            users::dsl::name.eq(name),
+            users::dsl::created_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(connection)
+        .await?;
+
+    users::dsl::users
+        .filter(users::dsl::name.eq(name))
+        .first::<User>(connection)
+        .await
+}
+
+/// The name of a user referenced by a model, e.g. its owner.
+pub async fn user_name<Conn>(
+    connection: &mut Conn,
+    user_id: Option<i32>,
+) -> QueryResult<Option<String>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    match user_id {
+        Some(user_id) => users::dsl::users
+            .find(user_id)
+            .select(users::dsl::name)
+            .first::<String>(connection)
+            .await
+            .optional(),
+        None => Ok(None),
+    }
+}
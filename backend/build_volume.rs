@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use typeshare::typeshare;
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BuildVolume {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl BuildVolume {
+    /// Parses `220x220x250`, all dimensions in millimeters.
+    pub fn parse(input: &str) -> Option<Self> {
+        let dimensions: Vec<f64> = input
+            .split(['x', 'X', '*'])
+            .map(|dimension| dimension.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+
+        match dimensions[..] {
+            [x, y, z] if x > 0.0 && y > 0.0 && z > 0.0 => Some(Self { x, y, z }),
+            _ => None,
+        }
+    }
+}
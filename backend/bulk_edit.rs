@@ -0,0 +1,336 @@
+use axum::{extract::State, http::StatusCode, Json};
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::fs;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::{Viewer, Visibility};
+use crate::library_stats::library_of;
+use crate::move_model::{move_model, MoveModelRequest};
+use crate::parse_library::update_modelpack_meta;
+use crate::rollback::{self, Rollback};
+use crate::schema::models3d;
+use crate::tags::{get_model_tags, normalize_tag, normalize_tags, set_model_tags};
+use crate::tenants::tenant_name_for_folder;
+use crate::types::Model3D;
+use crate::{AppState, Config};
+
+/// Changes applied to every model of a bulk edit, unset fields are kept.
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BulkPatch {
+    pub license: Option<String>,
+    #[serde(default)]
+    pub add_tags: Vec<String>,
+    #[serde(default)]
+    pub remove_tags: Vec<String>,
+    /// top level folder below `libraries_path` the models are moved into,
+    /// keeping their path inside the library
+    pub library: Option<String>,
+    pub visibility: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkEditRequest {
+    pub slugs: Vec<String>,
+    pub patch: BulkPatch,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkItemResult {
+    pub slug: String,
+    /// HTTP status of the item, 200 if it was or could have been applied
+    pub status: i32,
+    pub error: Option<String>,
+    pub folder_path: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkEditResponse {
+    /// false if any item failed, in which case none was changed
+    pub applied: bool,
+    pub results: Vec<BulkItemResult>,
+}
+
+/// A model of the bulk edit with everything it will be changed to.
+struct PlannedEdit {
+    model: Model3D,
+    tags: Vec<String>,
+    /// parent directory and folder name after the move
+    destination: Option<PathBuf>,
+    rewrite_meta: bool,
+}
+
+/// Why a bulk edit was not applied, with the index of the item that failed
+/// unless the whole edit did.
+struct Failed(Option<usize>, StatusCode);
+
+impl From<diesel::result::Error> for Failed {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("Failed to apply bulk edit: {}", e);
+        Failed(None, StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+fn item_result(slug: &str, status: StatusCode) -> BulkItemResult {
+    BulkItemResult {
+        slug: slug.to_string(),
+        status: status.as_u16() as i32,
+        error: (!status.is_success())
+            .then(|| status.canonical_reason().unwrap_or("Error").to_string()),
+        folder_path: None,
+        tags: Vec::new(),
+    }
+}
+
+/// The library a model is moved into as single, sanitized path component.
+fn sanitize_library(library: &str) -> Option<String> {
+    let library = sanitize_filename::sanitize(library.trim());
+    (!library.is_empty() && library != "." && library != "..").then_some(library)
+}
+
+async fn plan_edit<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    viewer: &Viewer,
+    slug: &str,
+    patch: &BulkPatch,
+    library: Option<&str>,
+) -> Result<PlannedEdit, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let model = viewer.manage_model(connection, slug).await?;
+
+    let current = get_model_tags(connection, model.id).await.map_err(|e| {
+        error!("Failed to load tags of {}: {}", model.slug, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let removed = normalize_tags(&patch.remove_tags);
+    let tags: Vec<String> = normalize_tags(&current)
+        .into_iter()
+        .chain(normalize_tags(&patch.add_tags))
+        .filter(|tag| !removed.contains(tag))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let destination = match library {
+        Some(library) if library_of(&model.folder_path) != library => {
+            let current = PathBuf::from(&model.folder_path);
+            // models directly below `libraries_path` are not in a library yet
+            let inside: PathBuf = if current.components().count() > 1 {
+                current.components().skip(1).collect()
+            } else {
+                current.clone()
+            };
+            let new_relative = PathBuf::from(library).join(inside);
+            if tenant_name_for_folder(config, &new_relative)
+                != tenant_name_for_folder(config, &current)
+            {
+                return Err(StatusCode::FORBIDDEN);
+            }
+            if fs::try_exists(config.libraries_path.join(&new_relative))
+                .await
+                .unwrap_or(true)
+            {
+                return Err(StatusCode::CONFLICT);
+            }
+            Some(new_relative)
+        }
+        _ => None,
+    };
+
+    Ok(PlannedEdit {
+        rewrite_meta: patch.license.is_some() || tags != current,
+        model,
+        tags,
+        destination,
+    })
+}
+
+/// Writes license and tags to the modelpack.json of a model, so a later
+/// refresh keeps them.
+async fn write_meta(
+    config: &Config,
+    edit: &PlannedEdit,
+    license: Option<&str>,
+) -> anyhow::Result<(PathBuf, Option<String>)> {
+    let dir = edit.model.absolute_path(config);
+    let previous = update_modelpack_meta(config, &dir, |meta| {
+        if let Some(license) = license {
+            meta.license = license.to_string();
+        }
+        meta.tags = Some(edit.tags.clone());
+    })
+    .await?;
+    Ok((dir.join("modelpack.json"), previous))
+}
+
+/// Applies a patch to many models at once. Every model is checked first and
+/// nothing is changed unless all of them can be edited. Every change is
+/// made in a single transaction, moved folders and written modelpack.json
+/// files are reverted along with it if a later step fails.
+pub async fn handle_bulk_edit(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Json(request): Json<BulkEditRequest>,
+) -> Result<Json<BulkEditResponse>, StatusCode> {
+    let config = &state.config;
+    let patch = &request.patch;
+    let visibility = match &patch.visibility {
+        Some(visibility) => {
+            Some(Visibility::from_str(visibility).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?)
+        }
+        None => None,
+    };
+    let library = match &patch.library {
+        Some(library) => Some(sanitize_library(library).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?),
+        None => None,
+    };
+    let license = patch.license.as_deref().map(str::trim);
+    let mut seen = HashSet::new();
+    let slugs: Vec<&String> = request
+        .slugs
+        .iter()
+        .filter(|slug| seen.insert(slug.as_str()))
+        .collect();
+    if slugs.is_empty()
+        || patch
+            .add_tags
+            .iter()
+            .any(|tag| normalize_tag(tag).is_none())
+    {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let mut connection = state.connection().await?;
+
+    let mut edits = Vec::new();
+    let mut results = Vec::new();
+    for (index, slug) in slugs.iter().enumerate() {
+        match plan_edit(
+            config,
+            &mut connection,
+            &viewer,
+            slug,
+            patch,
+            library.as_deref(),
+        )
+        .await
+        {
+            Ok(edit) => {
+                let mut result = item_result(slug, StatusCode::OK);
+                result.folder_path = Some(
+                    edit.destination
+                        .as_ref()
+                        .map(|destination| destination.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| edit.model.folder_path.clone()),
+                );
+                result.tags = edit.tags.clone();
+                results.push(result);
+                edits.push((index, edit));
+            }
+            Err(status) => results.push(item_result(slug, status)),
+        }
+    }
+    // two models moved onto the same folder
+    let destinations: Vec<&PathBuf> = edits
+        .iter()
+        .filter_map(|(_, edit)| edit.destination.as_ref())
+        .collect();
+    if destinations.iter().collect::<BTreeSet<_>>().len() != destinations.len() {
+        for (index, edit) in &edits {
+            if edit.destination.is_some() {
+                results[*index] = item_result(slugs[*index], StatusCode::CONFLICT);
+            }
+        }
+    }
+    if results.iter().any(|result| result.error.is_some()) {
+        return Ok(Json(BulkEditResponse {
+            applied: false,
+            results,
+        }));
+    }
+
+    let rollback = Rollback::default();
+    let (edits_ref, rollback_ref) = (&mut edits, &rollback);
+    let applied = rollback::transaction(&mut *connection, &rollback, |connection| {
+        async move {
+            for (index, edit) in edits_ref.iter_mut() {
+                if let Some(new_relative) = &edit.destination {
+                    let request = MoveModelRequest {
+                        folder_name: None,
+                        destination: new_relative
+                            .parent()
+                            .map(|parent| parent.to_string_lossy().into_owned()),
+                        title: None,
+                    };
+                    let moved = move_model(config, connection, &edit.model, &request)
+                        .await
+                        .map_err(|status| Failed(Some(*index), status))?;
+                    rollback_ref.moved(
+                        edit.model.absolute_path(config),
+                        moved.absolute_path(config),
+                    );
+                    edit.model = moved;
+                }
+                if edit.rewrite_meta {
+                    let (json_pth, previous) =
+                        write_meta(config, edit, license).await.map_err(|e| {
+                            error!("Failed to write metadata of {}: {}", edit.model.slug, e);
+                            Failed(Some(*index), StatusCode::INTERNAL_SERVER_ERROR)
+                        })?;
+                    rollback_ref.written(json_pth, previous);
+                }
+
+                if let Some(license) = license {
+                    diesel::update(models3d::dsl::models3d.find(edit.model.id))
+                        .set(models3d::dsl::license.eq(Some(license)))
+                        .execute(connection)
+                        .await?;
+                }
+                if let Some(visibility) = visibility {
+                    diesel::update(models3d::dsl::models3d.find(edit.model.id))
+                        .set(models3d::dsl::visibility.eq(visibility.as_str()))
+                        .execute(connection)
+                        .await?;
+                }
+                set_model_tags(connection, edit.model.id, &edit.tags).await?;
+                edit.model.touch(connection).await?;
+            }
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await;
+
+    let Err(Failed(failed_index, status)) = applied else {
+        return Ok(Json(BulkEditResponse {
+            applied: true,
+            results,
+        }));
+    };
+    for (index, slug) in slugs.iter().enumerate() {
+        // the failing item keeps its status, the others were rolled back
+        let status = match failed_index {
+            Some(failed_index) if failed_index != index => StatusCode::FAILED_DEPENDENCY,
+            _ => status,
+        };
+        results[index] = item_result(slug, status);
+    }
+    Ok(Json(BulkEditResponse {
+        applied: false,
+        results,
+    }))
+}
@@ -0,0 +1,77 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path};
+use tokio::fs;
+
+use crate::AppState;
+
+/// Preview images are named after the hash of the file they were rendered
+/// from, so a URL never changes its content and may be cached for a year.
+pub async fn immutable(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    if response.status().is_success() {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=31536000, immutable"),
+        );
+    }
+    response
+}
+
+/// Library files can change in place, browsers keep them but revalidate
+/// every use through `Last-Modified`, which usually ends in a 304.
+pub async fn revalidate(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    if response.status().is_success() || response.status().is_redirection() {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    }
+    response
+}
+
+/// `ServeDir` answers `Range` and `HEAD` requests but ignores `If-Range`, so
+/// a resumed download of a file that changed in between would be stitched
+/// together from two versions. Unless `If-Range` names the current
+/// `Last-Modified` date the range is dropped and the whole file is sent.
+/// `ServeDir` sends no ETags, so an entity tag never matches.
+pub async fn if_range(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let if_range = request
+        .headers()
+        .get(header::IF_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    if let (Some(if_range), true) = (if_range, request.headers().contains_key(header::RANGE)) {
+        let relative = percent_decode_str(request.uri().path().trim_start_matches('/'))
+            .decode_utf8_lossy()
+            .into_owned();
+        let modified = if Path::new(&relative)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            fs::metadata(state.config.libraries_path.join(&relative))
+                .await
+                .and_then(|meta| meta.modified())
+                .ok()
+                .map(|modified| DateTime::<Utc>::from(modified).timestamp())
+        } else {
+            None
+        };
+        let validator = DateTime::parse_from_rfc2822(&if_range)
+            .ok()
+            .map(|date| date.timestamp());
+
+        if validator.is_none() || validator != modified {
+            request.headers_mut().remove(header::RANGE);
+        }
+    }
+
+    next.run(request).await
+}
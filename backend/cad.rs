@@ -0,0 +1,225 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::LazyLock;
+use tracing::debug;
+use typeshare::typeshare;
+
+use crate::convert;
+use crate::file_types::file_type_of;
+use crate::Config;
+
+/// File types holding a parametric CAD source rather than a mesh.
+pub const CAD_FILE_TYPES: &[&str] = &["step", "stp", "iges", "igs", "f3d", "f3z", "scad"];
+
+pub fn is_cad_file_type(file_type: &str) -> bool {
+    CAD_FILE_TYPES.contains(&file_type)
+}
+
+/// What little a STEP or IGES header tells without loading the geometry.
+#[typeshare]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CadMetadata {
+    pub units: Option<String>,
+    pub parts: Vec<String>,
+    pub schema: Option<String>,
+    pub originating_system: Option<String>,
+}
+
+static STEP_PRODUCT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\bPRODUCT\s*\(\s*'((?:[^']|'')*)'").unwrap());
+static STEP_SI_UNIT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)LENGTH_UNIT\s*\(\s*\)\s*NAMED_UNIT\s*\(\s*\*\s*\)\s*SI_UNIT\s*\(\s*(\.\w+\.|\$)\s*,\s*\.METRE\.")
+        .unwrap()
+});
+static STEP_CONVERSION_UNIT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)CONVERSION_BASED_UNIT\s*\(\s*'(\w+)'").unwrap());
+static STEP_SCHEMA: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)FILE_SCHEMA\s*\(\s*\(\s*'([^']*)'").unwrap());
+static STEP_FILE_NAME: LazyLock<Regex> = LazyLock::new(|| {
+    // name, time stamp, author, organization, preprocessor version, originating system
+    Regex::new(r"(?is)FILE_NAME\s*\(\s*'(?:[^']|'')*'\s*,\s*'[^']*'\s*,\s*\([^)]*\)\s*,\s*\([^)]*\)\s*,\s*'(?:[^']|'')*'\s*,\s*'((?:[^']|'')*)'")
+        .unwrap()
+});
+
+fn unquote_step(value: &str) -> String {
+    value.replace("''", "'").trim().to_string()
+}
+
+/// Reads units, product names and the schema from a STEP file.
+pub fn parse_step(content: &str) -> CadMetadata {
+    let mut parts: Vec<String> = STEP_PRODUCT
+        .captures_iter(content)
+        .map(|captures| unquote_step(&captures[1]))
+        .filter(|name| !name.is_empty())
+        .collect();
+    parts.dedup();
+
+    let units = match STEP_SI_UNIT.captures(content) {
+        Some(captures) => Some(
+            match captures[1].trim_matches('.').to_uppercase().as_str() {
+                "MILLI" => "mm",
+                "CENTI" => "cm",
+                "$" => "m",
+                "MICRO" => "um",
+                _ => "unknown",
+            }
+            .to_string(),
+        ),
+        None => STEP_CONVERSION_UNIT
+            .captures(content)
+            .map(|captures| captures[1].to_lowercase()),
+    };
+
+    CadMetadata {
+        units,
+        parts,
+        schema: STEP_SCHEMA
+            .captures(content)
+            .map(|captures| captures[1].to_string()),
+        originating_system: STEP_FILE_NAME
+            .captures(content)
+            .map(|captures| unquote_step(&captures[1]))
+            .filter(|system| !system.is_empty()),
+    }
+}
+
+/// Splits the global section of an IGES file into its parameters, strings
+/// are Hollerith encoded like `2HMM`.
+fn iges_global_parameters(content: &str) -> Vec<String> {
+    let global: String = content
+        .lines()
+        .filter(|line| line.len() >= 73 && line.as_bytes()[72] == b'G')
+        .filter_map(|line| line.get(..72))
+        .collect();
+
+    let mut parameters = Vec::new();
+    let mut rest = global.as_str();
+    while !rest.is_empty() {
+        let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if !digits.is_empty() && rest[digits.len()..].starts_with(['H', 'h']) {
+            let start = digits.len() + 1;
+            let end = (start + digits.parse::<usize>().unwrap_or(0)).min(rest.len());
+            parameters.push(rest.get(start..end).unwrap_or_default().to_string());
+            rest = rest.get(end..).unwrap_or_default();
+            rest = rest.trim_start_matches([',', ';']);
+            continue;
+        }
+        let end = rest.find([',', ';']).unwrap_or(rest.len());
+        parameters.push(rest[..end].trim().to_string());
+        rest = rest.get(end + 1..).unwrap_or("");
+    }
+    parameters
+}
+
+/// Reads units and the product id from the global section of an IGES file.
+pub fn parse_iges(content: &str) -> CadMetadata {
+    let parameters = iges_global_parameters(content);
+    let get = |index: usize| {
+        parameters
+            .get(index - 1)
+            .filter(|value| !value.is_empty())
+            .cloned()
+    };
+
+    // 14: unit flag, 15: unit name
+    let units = get(15).map(|name| name.to_lowercase()).or_else(|| {
+        get(14).map(|flag| match flag.as_str() {
+            "1" => "in".to_string(),
+            "2" => "mm".to_string(),
+            "4" => "ft".to_string(),
+            "6" => "m".to_string(),
+            "10" => "cm".to_string(),
+            other => other.to_string(),
+        })
+    });
+
+    CadMetadata {
+        units,
+        parts: get(3).into_iter().collect(),
+        schema: Some("IGES".to_string()),
+        originating_system: get(4),
+    }
+}
+
+/// Extracts the metadata of STEP and IGES files, other files yield `None`.
+pub fn read_metadata(file_pth: &Path, file_type: &str) -> Option<CadMetadata> {
+    let parse = match file_type {
+        "step" | "stp" => parse_step,
+        "iges" | "igs" => parse_iges,
+        _ => return None,
+    };
+    let content = std::fs::read(file_pth).ok()?;
+    Some(parse(&String::from_utf8_lossy(&content)))
+}
+
+/// Turns a CAD file into an STL so previews and sizes can be derived from it.
+pub trait MeshConverter: Send + Sync {
+    fn to_stl(&self, source: &Path, target: &Path) -> anyhow::Result<()>;
+}
+
+/// Tessellates STEP and IGES files with the bundled OpenCascade.
+pub struct OpenCascadeConverter;
+
+impl MeshConverter for OpenCascadeConverter {
+    fn to_stl(&self, source: &Path, target: &Path) -> anyhow::Result<()> {
+        let source = source.to_path_buf();
+        let mesh = match file_type_of(&source).as_str() {
+            "step" | "stp" => convert::load_step(&source)?,
+            "iges" | "igs" => convert::load_iges(&source)?,
+            other => anyhow::bail!("{} files can not be converted", other),
+        };
+        std::fs::write(target, convert::save_as_stl(&mesh)?)?;
+        Ok(())
+    }
+}
+
+/// Runs an external command, `{input}` and `{output}` are replaced with the
+/// source file and the STL to write.
+pub struct CommandConverter {
+    pub command: String,
+}
+
+impl MeshConverter for CommandConverter {
+    fn to_stl(&self, source: &Path, target: &Path) -> anyhow::Result<()> {
+        let mut words = self.command.split_whitespace().map(|word| {
+            word.replace("{input}", &source.to_string_lossy())
+                .replace("{output}", &target.to_string_lossy())
+        });
+        let program = words
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty converter command"))?;
+        let status = Command::new(program).args(words).status()?;
+        if !status.success() {
+            anyhow::bail!("Converter exited with {}", status);
+        }
+        Ok(())
+    }
+}
+
+/// The converter configured in `cad_preview_converter`: unset disables CAD
+/// previews, `builtin` uses OpenCascade and anything else is a command.
+pub fn preview_converter(config: &Config) -> Option<Box<dyn MeshConverter>> {
+    match config.cad_preview_converter.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some("builtin") => Some(Box::new(OpenCascadeConverter)),
+        Some(command) => Some(Box::new(CommandConverter {
+            command: command.to_string(),
+        })),
+    }
+}
+
+/// Converts a CAD file into a temporary STL, the caller removes it.
+pub fn convert_for_preview(config: &Config, source: &Path) -> Option<PathBuf> {
+    let converter = preview_converter(config)?;
+    let target = std::env::temp_dir().join(format!("cad_{}.stl", uuid::Uuid::new_v4()));
+    match converter.to_stl(source, &target) {
+        Ok(()) => Some(target),
+        Err(e) => {
+            debug!("Unable to convert {}: {}", source.display(), e);
+            let _ = std::fs::remove_file(&target);
+            None
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use diesel::prelude::*;
+use diesel_async::AsyncConnection;
+use reqwest::multipart::{Form, Part};
+use serde_json::json;
+use std::io::Cursor;
+use std::path::Path;
+use tracing::debug;
+
+use crate::image_limits;
+use crate::notifications::model_url;
+use crate::types::Model3D;
+use crate::workers;
+use crate::Config;
+
+/// Longest side of the preview image attached to a card.
+const CARD_IMAGE_SIZE: u32 = 800;
+/// Discord allows 4096 characters in an embed, Telegram 1024 in a caption.
+const CARD_DESCRIPTION_CHARS: usize = 300;
+
+/// What a chat gets to see of a newly added model.
+pub struct ModelCard {
+    pub title: String,
+    pub url: String,
+    pub author: Option<String>,
+    pub description: String,
+    /// a JPEG of the first image or the rendered preview
+    pub image: Option<Vec<u8>>,
+}
+
+impl ModelCard {
+    fn caption(&self) -> String {
+        let mut caption = self.title.clone();
+        if let Some(author) = &self.author {
+            caption.push_str(&format!(" by {}", author));
+        }
+        if !self.description.is_empty() {
+            caption.push_str(&format!("\n\n{}", self.description));
+        }
+        caption.push_str(&format!("\n\n{}", self.url));
+        caption
+    }
+}
+
+fn shorten(text: &str) -> String {
+    let text = text.trim();
+    if text.chars().count() <= CARD_DESCRIPTION_CHARS {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(CARD_DESCRIPTION_CHARS).collect();
+    short.push('…');
+    short
+}
+
+/// A JPEG of the image at `path` scaled down to at most `size` pixels,
+/// `None` for images beyond `limits`.
+pub fn thumbnail_jpeg(path: &Path, size: u32, limits: image::Limits) -> Option<Vec<u8>> {
+    let image = image_limits::open(path, limits)
+        .map_err(|e| debug!("No thumbnail of {}: {}", path.display(), e))
+        .ok()?
+        .thumbnail(size, size)
+        .to_rgb8();
+    let mut jpeg = Cursor::new(Vec::new());
+    image.write_to(&mut jpeg, image::ImageFormat::Jpeg).ok()?;
+    Some(jpeg.into_inner())
+}
+
+pub async fn model_card<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> QueryResult<ModelCard>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let limits = image_limits::limits(config);
+    let image = match model.cover_image_path(config, connection).await? {
+        Some(path) => workers::run(move || thumbnail_jpeg(&path, CARD_IMAGE_SIZE, limits))
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+
+    Ok(ModelCard {
+        title: model.title.clone(),
+        url: model_url(config, &model.slug),
+        author: model.author.clone(),
+        description: shorten(&model.description),
+        image,
+    })
+}
+
+/// A chat new models are announced in.
+pub enum ChatNotifier {
+    /// a channel webhook, `https://discord.com/api/webhooks/...`
+    Discord {
+        webhook_url: String,
+    },
+    Telegram {
+        bot_token: String,
+        chat_id: String,
+    },
+}
+
+impl ChatNotifier {
+    pub fn from_config(config: &Config) -> Vec<ChatNotifier> {
+        let mut notifiers = Vec::new();
+        if let Some(webhook_url) = &config.discord_webhook_url {
+            notifiers.push(ChatNotifier::Discord {
+                webhook_url: webhook_url.clone(),
+            });
+        }
+        if let (Some(bot_token), Some(chat_id)) =
+            (&config.telegram_bot_token, &config.telegram_chat_id)
+        {
+            notifiers.push(ChatNotifier::Telegram {
+                bot_token: bot_token.clone(),
+                chat_id: chat_id.clone(),
+            });
+        }
+        notifiers
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatNotifier::Discord { .. } => "Discord",
+            ChatNotifier::Telegram { .. } => "Telegram",
+        }
+    }
+
+    /// Posts the card with its image uploaded along, so the instance does
+    /// not have to be reachable from the chat service.
+    pub async fn post(&self, client: &reqwest::Client, card: &ModelCard) -> anyhow::Result<()> {
+        let request = match self {
+            ChatNotifier::Discord { webhook_url } => {
+                let mut embed = json!({
+                    "title": card.title,
+                    "url": card.url,
+                    "description": card.description,
+                });
+                if let Some(author) = &card.author {
+                    embed["author"] = json!({ "name": author });
+                }
+                match &card.image {
+                    Some(image) => {
+                        embed["image"] = json!({ "url": "attachment://preview.jpg" });
+                        let payload = json!({ "embeds": [embed] });
+                        let form = Form::new().text("payload_json", payload.to_string()).part(
+                            "files[0]",
+                            Part::bytes(image.clone())
+                                .file_name("preview.jpg")
+                                .mime_str("image/jpeg")?,
+                        );
+                        client.post(webhook_url).multipart(form)
+                    }
+                    None => client.post(webhook_url).json(&json!({ "embeds": [embed] })),
+                }
+            }
+            ChatNotifier::Telegram { bot_token, chat_id } => {
+                let api = format!("https://api.telegram.org/bot{}", bot_token);
+                match &card.image {
+                    Some(image) => {
+                        let form = Form::new()
+                            .text("chat_id", chat_id.clone())
+                            .text("caption", card.caption())
+                            .part(
+                                "photo",
+                                Part::bytes(image.clone())
+                                    .file_name("preview.jpg")
+                                    .mime_str("image/jpeg")?,
+                            );
+                        client.post(format!("{}/sendPhoto", api)).multipart(form)
+                    }
+                    None => client
+                        .post(format!("{}/sendMessage", api))
+                        .json(&json!({ "chat_id": chat_id, "text": card.caption() })),
+                }
+            }
+        };
+
+        // the error would contain the url and with it the bot token
+        let response = request.send().await.map_err(|e| e.without_url())?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("{} answered {}: {}", self.name(), status, body);
+        }
+        Ok(())
+    }
+}
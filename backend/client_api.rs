@@ -0,0 +1,650 @@
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequestParts, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tower_http::services::ServeFile;
+use tracing::error;
+use typeshare::typeshare;
+use uuid::Uuid;
+
+use crate::access::Viewer;
+use crate::auth::User;
+use crate::downloads::DownloadClient;
+use crate::model_filter::{filter_models, mentions};
+use crate::policy::{Account, Authorized};
+use crate::schema::{client_recent_files, client_tokens, files3d, users};
+use crate::stats::{record_event_logged, EventKind};
+use crate::types::{comma_separated_to_pathbuf_vec, File3D, ListModelParams, Model3D};
+use crate::{AppState, Config};
+
+/// Prefix of the client API. Its routes and fields are only ever added to,
+/// a breaking change gets a new prefix next to this one.
+pub const PREFIX: &str = "/api/client/v1";
+/// Marks tokens of the client API, so they are recognizable when leaked.
+const TOKEN_PREFIX: &str = "mvc_";
+/// Search and recent files are paged, at most this many per request.
+const MAX_LIMIT: i64 = 100;
+/// Recently used files kept per user, older ones are dropped.
+const MAX_RECENT_FILES: i64 = 100;
+const MAX_TERMS: usize = 8;
+
+const OPENAPI: &str = include_str!("client_api/openapi.json");
+
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = client_tokens)]
+pub struct ClientToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientTokenResponse {
+    pub id: i32,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+impl From<ClientToken> for ClientTokenResponse {
+    fn from(token: ClientToken) -> Self {
+        ClientTokenResponse {
+            id: token.id,
+            name: token.name,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+        }
+    }
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateClientToken {
+    /// what the token is used by, e.g. `PrusaSlicer on the workshop PC`
+    pub name: String,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedClientToken {
+    pub token: ClientTokenResponse,
+    /// sent as `Authorization: Bearer <secret>`, only shown once
+    pub secret: String,
+}
+
+/// The viewer of a client API request. A bearer token acts on behalf of the
+/// user who created it, requests without one are treated like those of the
+/// web app, so the API also works behind an authenticating proxy and for
+/// public models.
+pub struct ClientViewer(pub Viewer);
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+fn hash_token(token: &str) -> String {
+    sha256::digest(token)
+}
+
+/// The user a token was created by, `None` for unknown or revoked tokens.
+async fn user_of_token<Conn>(connection: &mut Conn, token: &str) -> QueryResult<Option<User>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let found = client_tokens::dsl::client_tokens
+        .inner_join(users::table)
+        .filter(client_tokens::dsl::token_hash.eq(hash_token(token)))
+        .select((client_tokens::dsl::id, User::as_select()))
+        .first::<(i32, User)>(connection)
+        .await
+        .optional()?;
+    let Some((token_id, user)) = found else {
+        return Ok(None);
+    };
+    diesel::update(client_tokens::dsl::client_tokens.find(token_id))
+        .set(client_tokens::dsl::last_used_at.eq(Utc::now().naive_utc()))
+        .execute(connection)
+        .await?;
+    Ok(Some(user))
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientViewer {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token) = bearer_token(&parts.headers) else {
+            return Ok(ClientViewer(
+                Viewer::from_request_parts(parts, state).await?,
+            ));
+        };
+        let mut connection = state.connection().await?;
+        let user = user_of_token(&mut connection, token)
+            .await
+            .map_err(|e| {
+                error!("Failed to look up a client token: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        let viewer = Viewer::for_user(&state.config, &mut connection, user)
+            .await
+            .map_err(|e| {
+                error!("Failed to load the viewer of a client token: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        Ok(ClientViewer(viewer))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientModel {
+    pub slug: String,
+    pub name: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    /// path of an image of the model on this server
+    pub thumbnail_url: Option<String>,
+    pub file_count: i32,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientFile {
+    pub id: i32,
+    pub model_slug: String,
+    /// path of the file within the model folder
+    pub name: String,
+    pub file_type: String,
+    pub size_bytes: i64,
+    /// SHA-256 of the contents as hex, missing until the file was hashed
+    pub sha256: Option<String>,
+    /// path on this server to fetch the file from
+    pub download_url: String,
+    pub thumbnail_url: Option<String>,
+}
+
+impl ClientFile {
+    fn new(file: File3D, model: &Model3D, config: &Config) -> Self {
+        ClientFile {
+            download_url: format!("{}/files/{}", PREFIX, file.id),
+            thumbnail_url: file.get_url_preview_path(config),
+            id: file.id,
+            model_slug: model.slug.clone(),
+            name: file.file_path,
+            file_type: file.file_type,
+            size_bytes: file.file_size_bytes.into(),
+            sha256: file.file_hash,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientSearchResponse {
+    pub models: Vec<ClientModel>,
+    /// number of matching models, independent of the page
+    pub total: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientFilesResponse {
+    pub model: ClientModel,
+    pub files: Vec<ClientFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientRecentFile {
+    pub used_at: NaiveDateTime,
+    pub file: ClientFile,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientRecentResponse {
+    /// most recently fetched first
+    pub files: Vec<ClientRecentFile>,
+}
+
+#[derive(Deserialize)]
+pub struct ClientSearchParams {
+    #[serde(default)]
+    pub q: String,
+    /// comma separated file types every returned model must contain, e.g. `3mf,stl`
+    pub has: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct ClientRecentParams {
+    pub limit: Option<i64>,
+}
+
+fn client_model(model: &Model3D, files: &[File3D], config: &Config) -> ClientModel {
+    let image = comma_separated_to_pathbuf_vec(&model.images)
+        .into_iter()
+        .next()
+        .map(|image| {
+            format!(
+                "{}/{}/{}",
+                config.asset_prefix,
+                model.folder_path,
+                image.to_string_lossy()
+            )
+        });
+    ClientModel {
+        slug: model.slug.clone(),
+        name: model.name.clone(),
+        title: model.title.clone(),
+        author: model.author.clone(),
+        license: model.license.clone(),
+        thumbnail_url: image.or_else(|| {
+            files
+                .iter()
+                .find_map(|file| file.get_url_preview_path(config))
+        }),
+        file_count: files.len() as i32,
+        updated_at: model.updated_at,
+    }
+}
+
+async fn files_of<Conn>(
+    connection: &mut Conn,
+    model_ids: Vec<i32>,
+) -> Result<HashMap<i32, Vec<File3D>>, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let files = files3d::dsl::files3d
+        .filter(files3d::dsl::model_id.eq_any(model_ids))
+        .order((files3d::dsl::file_path.asc(), files3d::dsl::id.asc()))
+        .load::<File3D>(connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load files: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let mut files_of: HashMap<i32, Vec<File3D>> = HashMap::new();
+    for file in files {
+        files_of.entry(file.model_id).or_default().push(file);
+    }
+    Ok(files_of)
+}
+
+/// Models whose name, title, tags, author or description contain every
+/// term of `q`, in the order of the library. An empty `q` lists everything.
+pub async fn handle_search(
+    State(state): State<AppState>,
+    ClientViewer(viewer): ClientViewer,
+    Query(params): Query<ClientSearchParams>,
+) -> Result<Json<ClientSearchResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let limit = params.limit.unwrap_or(20).clamp(1, MAX_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let filters = ListModelParams {
+        has: params.has.clone(),
+        ..Default::default()
+    };
+    let terms: Vec<String> = params
+        .q
+        .split_whitespace()
+        .take(MAX_TERMS)
+        .map(str::to_lowercase)
+        .collect();
+    let matching = || -> Result<_, StatusCode> {
+        let mut query = filter_models(&filters, &viewer)?;
+        for term in &terms {
+            query = mentions(query, term);
+        }
+        Ok(query)
+    };
+
+    let total = matching()?
+        .count()
+        .get_result::<i64>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to count client search results: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let models = matching()?
+        .limit(limit)
+        .offset(offset)
+        .load::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to search models for a client: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut files = files_of(
+        &mut connection,
+        models.iter().map(|model| model.id).collect(),
+    )
+    .await?;
+    let models = models
+        .iter()
+        .map(|model| {
+            let files = files.remove(&model.id).unwrap_or_default();
+            client_model(model, &files, &state.config)
+        })
+        .collect();
+
+    Ok(Json(ClientSearchResponse { models, total }))
+}
+
+pub async fn handle_model_files(
+    State(state): State<AppState>,
+    ClientViewer(viewer): ClientViewer,
+    Path(slug): Path<String>,
+) -> Result<Json<ClientFilesResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let model = viewer.find_model(&mut connection, &slug).await?;
+    let files = files_of(&mut connection, vec![model.id])
+        .await?
+        .remove(&model.id)
+        .unwrap_or_default();
+
+    Ok(Json(ClientFilesResponse {
+        model: client_model(&model, &files, &state.config),
+        files: files
+            .into_iter()
+            .map(|file| ClientFile::new(file, &model, &state.config))
+            .collect(),
+    }))
+}
+
+async fn record_recent_file<Conn>(
+    connection: &mut Conn,
+    user: &User,
+    file: &File3D,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let now = Utc::now().naive_utc();
+    diesel::insert_into(client_recent_files::table)
+        .values((
+            client_recent_files::dsl::user_id.eq(user.id),
+            client_recent_files::dsl::file_id.eq(file.id),
+            client_recent_files::dsl::model_id.eq(file.model_id),
+            client_recent_files::dsl::used_at.eq(now),
+        ))
+        .on_conflict((
+            client_recent_files::dsl::user_id,
+            client_recent_files::dsl::file_id,
+        ))
+        .do_update()
+        .set(client_recent_files::dsl::used_at.eq(now))
+        .execute(connection)
+        .await?;
+
+    let oldest_kept = client_recent_files::dsl::client_recent_files
+        .filter(client_recent_files::dsl::user_id.eq(user.id))
+        .order(client_recent_files::dsl::used_at.desc())
+        .select(client_recent_files::dsl::used_at)
+        .offset(MAX_RECENT_FILES - 1)
+        .first::<NaiveDateTime>(connection)
+        .await
+        .optional()?;
+    if let Some(oldest_kept) = oldest_kept {
+        diesel::delete(
+            client_recent_files::dsl::client_recent_files
+                .filter(client_recent_files::dsl::user_id.eq(user.id))
+                .filter(client_recent_files::dsl::used_at.lt(oldest_kept)),
+        )
+        .execute(connection)
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn delete_recent_files<Conn>(connection: &mut Conn, model_id: i32) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::delete(
+        client_recent_files::dsl::client_recent_files
+            .filter(client_recent_files::dsl::model_id.eq(model_id)),
+    )
+    .execute(connection)
+    .await
+}
+
+/// `attachment` with the file name, spelled out for clients which do not
+/// understand the encoded `filename*`.
+fn content_disposition(file: &File3D) -> Option<HeaderValue> {
+    let name = std::path::Path::new(&file.file_path)
+        .file_name()?
+        .to_string_lossy()
+        .into_owned();
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(&name, NON_ALPHANUMERIC)
+    ))
+    .ok()
+}
+
+/// The contents of a file, with ranges for resumed downloads. Fetching a
+/// file adds it to the recently used files of the user and counts as an
+/// access in the statistics.
+pub async fn handle_fetch_file(
+    State(state): State<AppState>,
+    ClientViewer(viewer): ClientViewer,
+    client: DownloadClient,
+    Path(id): Path<i32>,
+    request: Request,
+) -> Response {
+    let mut connection = match state.connection().await {
+        Ok(connection) => connection,
+        Err(status) => return status.into_response(),
+    };
+    let (file, model) = match viewer.find_file(&mut connection, id).await {
+        Ok(found) => found,
+        Err(status) => return status.into_response(),
+    };
+    drop(connection);
+
+    let path = model.absolute_path(&state.config).join(&file.file_path);
+    let mut response = match ServeFile::new(&path).try_call(request).await {
+        Ok(response) => response.map(Body::new),
+        Err(e) => {
+            error!("Failed to serve {}: {}", path.display(), e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if !matches!(
+        response.status(),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT
+    ) {
+        return response;
+    }
+    let headers = response.headers_mut();
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    if let Some(value) = content_disposition(&file) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+
+    if let Ok(mut connection) = state.connection().await {
+        if let Some(user) = &viewer.user {
+            if let Err(e) = record_recent_file(&mut connection, user, &file).await {
+                error!(
+                    "Failed to record the use of file {} by {}: {}",
+                    file.id, user.name, e
+                );
+            }
+        }
+        if client.is_counted() {
+            record_event_logged(&mut connection, model.id, EventKind::Asset).await;
+        }
+    }
+
+    response
+}
+
+/// The files the user fetched through the client API last which they may
+/// still see, for a "recently used" list in the slicer.
+pub async fn handle_recent_files(
+    State(state): State<AppState>,
+    ClientViewer(viewer): ClientViewer,
+    Query(params): Query<ClientRecentParams>,
+) -> Result<Json<ClientRecentResponse>, StatusCode> {
+    let user = viewer.user.clone().ok_or(StatusCode::UNAUTHORIZED)?;
+    let mut connection = state.connection().await?;
+    let limit = params.limit.unwrap_or(20).clamp(1, MAX_LIMIT);
+
+    let recent: Vec<(NaiveDateTime, i32)> = client_recent_files::dsl::client_recent_files
+        .filter(client_recent_files::dsl::user_id.eq(user.id))
+        .order(client_recent_files::dsl::used_at.desc())
+        .select((
+            client_recent_files::dsl::used_at,
+            client_recent_files::dsl::file_id,
+        ))
+        .load(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load the recent files of {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut files = Vec::new();
+    for (used_at, file_id) in recent {
+        if files.len() as i64 >= limit {
+            break;
+        }
+        // files removed since, or of models no longer visible, are skipped
+        let (file, model) = match viewer.find_file(&mut connection, file_id).await {
+            Ok(found) => found,
+            Err(StatusCode::NOT_FOUND) => continue,
+            Err(status) => return Err(status),
+        };
+        files.push(ClientRecentFile {
+            used_at,
+            file: ClientFile::new(file, &model, &state.config),
+        });
+    }
+
+    Ok(Json(ClientRecentResponse { files }))
+}
+
+/// The OpenAPI description of the client API.
+pub async fn handle_openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+pub async fn handle_list_tokens(
+    State(state): State<AppState>,
+    Authorized(_, Account(user)): Authorized<Account>,
+) -> Result<Json<Vec<ClientTokenResponse>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let tokens = client_tokens::dsl::client_tokens
+        .filter(client_tokens::dsl::user_id.eq(user.id))
+        .order(client_tokens::dsl::created_at.desc())
+        .load::<ClientToken>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load the client tokens of {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(tokens.into_iter().map(Into::into).collect()))
+}
+
+/// Creates a token for a slicer plugin or desktop helper. The secret is
+/// returned once, only its hash is stored.
+pub async fn handle_create_token(
+    State(state): State<AppState>,
+    Authorized(_, Account(user)): Authorized<Account>,
+    Json(payload): Json<CreateClientToken>,
+) -> Result<Json<CreatedClientToken>, StatusCode> {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let mut connection = state.connection().await?;
+
+    let secret = format!(
+        "{}{}{}",
+        TOKEN_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    );
+    let token = diesel::insert_into(client_tokens::table)
+        .values((
+            client_tokens::dsl::user_id.eq(user.id),
+            client_tokens::dsl::name.eq(name),
+            client_tokens::dsl::token_hash.eq(hash_token(&secret)),
+            client_tokens::dsl::created_at.eq(Utc::now().naive_utc()),
+        ))
+        .returning(ClientToken::as_returning())
+        .get_result::<ClientToken>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to create a client token for {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(CreatedClientToken {
+        token: token.into(),
+        secret,
+    }))
+}
+
+pub async fn handle_revoke_token(
+    State(state): State<AppState>,
+    Authorized(_, Account(user)): Authorized<Account>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let deleted = diesel::delete(
+        client_tokens::dsl::client_tokens
+            .filter(client_tokens::dsl::id.eq(id))
+            .filter(client_tokens::dsl::user_id.eq(user.id)),
+    )
+    .execute(&mut connection)
+    .await
+    .map_err(|e| {
+        error!("Failed to revoke client token {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if deleted == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
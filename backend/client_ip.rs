@@ -0,0 +1,153 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use tracing::{debug, error};
+
+use crate::AppState;
+
+/// An address range like `10.0.0.0/8` or `fd00::/8`, a plain address
+/// matches only itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|e| format!("{}: {}", s, e))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("{}: invalid prefix length", s))?,
+            None => max_prefix,
+        };
+        Ok(IpNetwork { addr, prefix })
+    }
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual stack listener show up as mapped addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses a list of networks from the config, panicking on invalid entries
+/// like the rest of the config parsing.
+pub fn parse_networks(name: &str, entries: &[String]) -> Vec<IpNetwork> {
+    entries
+        .iter()
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| match IpNetwork::from_str(entry) {
+            Ok(network) => network,
+            Err(e) => panic!("Invalid entry in {}: {}", name, e),
+        })
+        .collect()
+}
+
+fn matches_any(networks: &[IpNetwork], ip: IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(ip))
+}
+
+/// The address of the client. `X-Forwarded-For` is only honoured when the
+/// connection comes from one of the `trusted_proxies`, in which case the
+/// rightmost address not belonging to a trusted proxy is the client.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+pub fn resolve_client_ip(trusted_proxies: &[IpNetwork], peer: IpAddr, parts: &Parts) -> IpAddr {
+    if !matches_any(trusted_proxies, peer) {
+        return peer;
+    }
+
+    let forwarded: Vec<IpAddr> = parts
+        .headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|addr| IpAddr::from_str(addr.trim()).ok())
+        .collect();
+
+    forwarded
+        .iter()
+        .rev()
+        .find(|addr| !matches_any(trusted_proxies, **addr))
+        .or_else(|| forwarded.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(peer) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .copied()
+            .ok_or_else(|| {
+                error!("Missing connection info, is the server started with connect info?");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        Ok(ClientIp(resolve_client_ip(
+            &state.config.trusted_proxy_networks,
+            peer.ip(),
+            parts,
+        )))
+    }
+}
+
+/// Rejects clients matching `ip_deny_list` or, if `ip_allow_list` is set,
+/// clients not matching it.
+pub async fn filter_ips(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let denied = matches_any(&state.config.ip_deny_networks, ip);
+    let allowed = state.config.ip_allow_networks.is_empty()
+        || matches_any(&state.config.ip_allow_networks, ip);
+
+    if denied || !allowed {
+        debug!("Rejected request from {}", ip);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
@@ -243,3 +243,146 @@ pub fn astl_convert_to_bstl(stl_path: &PathBuf) -> anyhow::Result<Vec<u8>> {
     let indexed_mesh = load_stl(stl_path);
     save_as_stl(&indexed_mesh.unwrap())
 }
+
+/// Extent of a mesh along x, y and z in file units (millimeters for
+/// printable files).
+pub fn bounding_box(path: &PathBuf) -> anyhow::Result<[f64; 3]> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let points: Vec<[f64; 3]> = match extension.as_str() {
+        "stl" => load_stl(path)?
+            .vertices
+            .iter()
+            .map(|v| [v[0].into(), v[1].into(), v[2].into()])
+            .collect(),
+        "obj" => {
+            let (models, _) = tobj::load_obj(path, &tobj::LoadOptions::default())?;
+            models
+                .iter()
+                .flat_map(|model| model.mesh.positions.chunks(3))
+                .map(|p| [p[0].into(), p[1].into(), p[2].into()])
+                .collect()
+        }
+        "3mf" => {
+            let file = OpenOptions::new().read(true).open(path)?;
+            threemf::read(file)?
+                .iter()
+                .flat_map(|model| model.resources.object.iter())
+                .filter_map(|object| object.mesh.as_ref())
+                .flat_map(|mesh| mesh.vertices.vertex.iter())
+                .map(|v| [v.x, v.y, v.z])
+                .collect()
+        }
+        _ => anyhow::bail!("No bounding box support for {:?}", path),
+    };
+
+    if points.is_empty() {
+        anyhow::bail!("Mesh {:?} has no vertices", path);
+    }
+
+    let mut min = [f64::MAX; 3];
+    let mut max = [f64::MIN; 3];
+    for point in points {
+        for (axis, value) in point.into_iter().enumerate() {
+            min[axis] = min[axis].min(value);
+            max[axis] = max[axis].max(value);
+        }
+    }
+
+    Ok([max[0] - min[0], max[1] - min[1], max[2] - min[2]])
+}
+
+/// The triangles of an STL, OBJ or 3MF mesh as corner positions.
+pub fn load_triangles(path: &PathBuf) -> anyhow::Result<Vec<[[f64; 3]; 3]>> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let corner = |vertices: &[[f64; 3]], index: usize| -> anyhow::Result<[f64; 3]> {
+        vertices
+            .get(index)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Vertex {} out of range in {:?}", index, path))
+    };
+
+    let mut triangles = Vec::new();
+    match extension.as_str() {
+        "stl" => {
+            let mesh = load_stl(path)?;
+            let vertices: Vec<[f64; 3]> = mesh
+                .vertices
+                .iter()
+                .map(|v| [v[0].into(), v[1].into(), v[2].into()])
+                .collect();
+            for face in &mesh.faces {
+                let [a, b, c] = face.vertices;
+                triangles.push([
+                    corner(&vertices, a)?,
+                    corner(&vertices, b)?,
+                    corner(&vertices, c)?,
+                ]);
+            }
+        }
+        "obj" => {
+            let (models, _) = tobj::load_obj(
+                path,
+                &tobj::LoadOptions {
+                    triangulate: true,
+                    ignore_points: true,
+                    ignore_lines: true,
+                    ..Default::default()
+                },
+            )?;
+            for model in &models {
+                let vertices: Vec<[f64; 3]> = model
+                    .mesh
+                    .positions
+                    .chunks(3)
+                    .map(|p| [p[0].into(), p[1].into(), p[2].into()])
+                    .collect();
+                for face in model.mesh.indices.chunks_exact(3) {
+                    triangles.push([
+                        corner(&vertices, face[0] as usize)?,
+                        corner(&vertices, face[1] as usize)?,
+                        corner(&vertices, face[2] as usize)?,
+                    ]);
+                }
+            }
+        }
+        "3mf" => {
+            let file = OpenOptions::new().read(true).open(path)?;
+            for model in threemf::read(file)? {
+                for mesh in model
+                    .resources
+                    .object
+                    .iter()
+                    .filter_map(|object| object.mesh.as_ref())
+                {
+                    let vertices: Vec<[f64; 3]> = mesh
+                        .vertices
+                        .vertex
+                        .iter()
+                        .map(|v| [v.x, v.y, v.z])
+                        .collect();
+                    for face in &mesh.triangles.triangle {
+                        triangles.push([
+                            corner(&vertices, face.v1)?,
+                            corner(&vertices, face.v2)?,
+                            corner(&vertices, face.v3)?,
+                        ]);
+                    }
+                }
+            }
+        }
+        _ => anyhow::bail!("No triangle support for {:?}", path),
+    }
+
+    if triangles.is_empty() {
+        anyhow::bail!("Mesh {:?} has no triangles", path);
+    }
+    Ok(triangles)
+}
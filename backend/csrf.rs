@@ -0,0 +1,72 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::AppState;
+
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+fn csrf_cookie(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == CSRF_COOKIE)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(method, &Method::GET | &Method::HEAD | &Method::OPTIONS)
+}
+
+/// Double submit protection for state changing requests. Safe requests hand
+/// out a `SameSite=Strict` token cookie, every other request has to echo it
+/// in the `X-CSRF-Token` header, which a foreign page cannot read.
+pub async fn protect(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.config.csrf_protection {
+        return next.run(request).await;
+    }
+
+    let token = csrf_cookie(&request);
+
+    if is_safe(request.method()) {
+        let mut response = next.run(request).await;
+        if token.is_none() {
+            let cookie = format!(
+                "{}={}; Path=/; SameSite=Strict",
+                CSRF_COOKIE,
+                Uuid::new_v4().simple()
+            );
+            if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+                response.headers_mut().append(header::SET_COOKIE, cookie);
+            }
+        }
+        return response;
+    }
+
+    let submitted = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok());
+    match (token.as_deref(), submitted) {
+        (Some(token), Some(submitted)) if token == submitted => next.run(request).await,
+        _ => {
+            debug!(
+                "Rejected {} {} without a valid CSRF token",
+                request.method(),
+                request.uri().path()
+            );
+            (StatusCode::FORBIDDEN, "Missing or invalid CSRF token").into_response()
+        }
+    }
+}
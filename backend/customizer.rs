@@ -0,0 +1,412 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::Response,
+    Json,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::LazyLock;
+use tokio::fs;
+use tracing::{debug, error, info};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::api_version::CURRENT_PREFIX;
+use crate::openscad::{is_openscad_file_type, render_stl};
+use crate::types::File3D;
+use crate::{AppState, Config};
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterKind {
+    Number,
+    Boolean,
+    String,
+    Vector,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: ParameterKind,
+    /// the literal from the source, e.g. `10`, `"text"` or `[1, 2]`
+    pub default: String,
+    pub description: Option<String>,
+    pub group: Option<String>,
+    pub options: Vec<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub step: Option<f64>,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterSet {
+    pub name: String,
+    pub values: HashMap<String, String>,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterSchema {
+    pub parameters: Vec<Parameter>,
+    pub parameter_sets: Vec<ParameterSet>,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomizeRequest {
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomizeResponse {
+    /// the render job, `None` if the result was cached already
+    pub job_id: Option<String>,
+    pub hash: String,
+    pub url: String,
+}
+
+static ASSIGNMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*([A-Za-z_$][A-Za-z0-9_]*)\s*=\s*([^;]+?)\s*;\s*(?://\s*(.*))?$").unwrap()
+});
+static GROUP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*/\*\s*\[([^\]]+)\]\s*\*/").unwrap());
+static NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^-?(\d+\.?\d*|\.\d+)([eE][-+]?\d+)?$").unwrap());
+static VECTOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[\s*-?[\d.eE+-]+(\s*,\s*-?[\d.eE+-]+)*\s*\]$").unwrap());
+
+fn kind_of(literal: &str) -> Option<ParameterKind> {
+    if NUMBER.is_match(literal) {
+        Some(ParameterKind::Number)
+    } else if literal == "true" || literal == "false" {
+        Some(ParameterKind::Boolean)
+    } else if literal.starts_with('"') && literal.ends_with('"') && literal.len() >= 2 {
+        Some(ParameterKind::String)
+    } else if VECTOR.is_match(literal) {
+        Some(ParameterKind::Vector)
+    } else {
+        None
+    }
+}
+
+/// Applies the customizer annotation after an assignment: `[a, b, c]` for
+/// choices, `[max]`, `[min:max]` or `[min:step:max]` for sliders.
+fn apply_annotation(parameter: &mut Parameter, annotation: &str) {
+    let Some(inner) = annotation
+        .trim()
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+    else {
+        return;
+    };
+
+    let bounds: Vec<Option<f64>> = inner.split(':').map(|v| v.trim().parse().ok()).collect();
+    match bounds.as_slice() {
+        [Some(max)] if !inner.contains(',') => parameter.max = Some(*max),
+        [Some(min), Some(max)] => {
+            parameter.min = Some(*min);
+            parameter.max = Some(*max);
+        }
+        [Some(min), Some(step), Some(max)] => {
+            parameter.min = Some(*min);
+            parameter.step = Some(*step);
+            parameter.max = Some(*max);
+        }
+        _ => {
+            // `value:label` pairs keep only the value
+            parameter.options = inner
+                .split(',')
+                .map(|option| {
+                    let value = option.split(':').next().unwrap_or("").trim();
+                    value.trim_matches('"').to_string()
+                })
+                .filter(|option| !option.is_empty())
+                .collect();
+        }
+    }
+}
+
+/// Reads the customizer parameters of an OpenSCAD file: top level
+/// assignments of literals before the first module or function, with the
+/// comment line above as description and `/* [Group] */` headers. The
+/// `Hidden` group is skipped.
+pub fn parse_parameters(source: &str) -> Vec<Parameter> {
+    let mut parameters = Vec::new();
+    let mut group: Option<String> = None;
+    let mut description: Option<String> = None;
+    let mut depth = 0i32;
+
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if depth == 0 && (trimmed.starts_with("module ") || trimmed.starts_with("function ")) {
+            break;
+        }
+
+        if let Some(captures) = GROUP.captures(trimmed) {
+            group = Some(captures[1].trim().to_string());
+            description = None;
+            continue;
+        }
+        if let Some(comment) = trimmed.strip_prefix("//") {
+            description = Some(comment.trim().to_string()).filter(|c| !c.is_empty());
+            continue;
+        }
+
+        if depth == 0 && group.as_deref() != Some("Hidden") {
+            if let Some(captures) = ASSIGNMENT.captures(line) {
+                let literal = captures[2].trim().to_string();
+                if let Some(kind) = kind_of(&literal) {
+                    let mut parameter = Parameter {
+                        name: captures[1].to_string(),
+                        kind,
+                        default: literal,
+                        description: description.take(),
+                        group: group.clone(),
+                        options: Vec::new(),
+                        min: None,
+                        max: None,
+                        step: None,
+                    };
+                    if let Some(annotation) = captures.get(3) {
+                        apply_annotation(&mut parameter, annotation.as_str());
+                    }
+                    parameters.push(parameter);
+                }
+            }
+        }
+
+        depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+        description = None;
+    }
+    parameters
+}
+
+#[derive(Deserialize)]
+struct CustomizerFile {
+    #[serde(rename = "parameterSets", default)]
+    parameter_sets: HashMap<String, HashMap<String, String>>,
+}
+
+/// Parameter sets saved by the OpenSCAD customizer in `<design>.json`.
+async fn read_parameter_sets(source: &FsPath) -> Vec<ParameterSet> {
+    let Ok(content) = fs::read_to_string(source.with_extension("json")).await else {
+        return Vec::new();
+    };
+    match serde_json::from_str::<CustomizerFile>(&content) {
+        Ok(file) => {
+            let mut sets: Vec<ParameterSet> = file
+                .parameter_sets
+                .into_iter()
+                .map(|(name, values)| ParameterSet { name, values })
+                .collect();
+            sets.sort_by(|a, b| a.name.cmp(&b.name));
+            sets
+        }
+        Err(e) => {
+            debug!("Ignoring customizer file of {}: {}", source.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+/// Turns user supplied values into OpenSCAD literals. Only declared
+/// parameters are accepted and every value has to match the kind of its
+/// parameter, strings are quoted, so nothing but literals reach `-D`.
+pub fn validate_values(
+    parameters: &[Parameter],
+    values: &HashMap<String, String>,
+) -> Result<BTreeMap<String, String>, String> {
+    let mut literals = BTreeMap::new();
+    for (name, value) in values {
+        let parameter = parameters
+            .iter()
+            .find(|parameter| &parameter.name == name)
+            .ok_or_else(|| format!("unknown parameter {}", name))?;
+        let value = value.trim();
+
+        let literal = match parameter.kind {
+            ParameterKind::Number if NUMBER.is_match(value) => {
+                let number: f64 = value
+                    .parse()
+                    .map_err(|_| format!("{} is no number", name))?;
+                if parameter.min.is_some_and(|min| number < min)
+                    || parameter.max.is_some_and(|max| number > max)
+                {
+                    return Err(format!("{} is out of range", name));
+                }
+                value.to_string()
+            }
+            ParameterKind::Boolean if value == "true" || value == "false" => value.to_string(),
+            ParameterKind::Vector if VECTOR.is_match(value) => value.to_string(),
+            ParameterKind::String => {
+                let unquoted = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                format!(
+                    "\"{}\"",
+                    unquoted.replace('\\', "\\\\").replace('"', "\\\"")
+                )
+            }
+            _ => return Err(format!("invalid value for {}", name)),
+        };
+
+        if !parameter.options.is_empty()
+            && !parameter
+                .options
+                .iter()
+                .any(|option| option == literal.trim_matches('"'))
+        {
+            return Err(format!("{} is not one of the options", name));
+        }
+        literals.insert(name.clone(), literal);
+    }
+    Ok(literals)
+}
+
+/// Renders are cached by the design's content and the parameters.
+fn render_hash(file: &File3D, literals: &BTreeMap<String, String>) -> String {
+    let key = format!(
+        "{}:{}",
+        file.file_hash
+            .clone()
+            .unwrap_or_else(|| file.id.to_string()),
+        serde_json::to_string(literals).unwrap_or_default()
+    );
+    sha256::digest(key)
+}
+
+fn cached_render(config: &Config, hash: &str) -> PathBuf {
+    config.render_cache_dir.join(format!("{}.stl", hash))
+}
+
+async fn find_design(
+    state: &AppState,
+    viewer: &Viewer,
+    id: i32,
+) -> Result<(File3D, PathBuf, Vec<Parameter>), StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let (file, _) = viewer.find_file(&mut connection, id).await?;
+    if !is_openscad_file_type(&file.file_type) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let source = file.get_file_path(&mut connection, &state.config).await;
+    let content = fs::read_to_string(&source).await.map_err(|e| {
+        error!("Failed to read {}: {}", source.display(), e);
+        StatusCode::NOT_FOUND
+    })?;
+    let parameters = parse_parameters(&content);
+    Ok((file, source, parameters))
+}
+
+pub async fn handle_parameters(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+) -> Result<Json<ParameterSchema>, StatusCode> {
+    let (_, source, parameters) = find_design(&state, &viewer, id).await?;
+
+    Ok(Json(ParameterSchema {
+        parameters,
+        parameter_sets: read_parameter_sets(&source).await,
+    }))
+}
+
+/// Queues a render with the given parameter values. The STL is fetched from
+/// the returned url once the job finished.
+pub async fn handle_customize(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+    Json(request): Json<CustomizeRequest>,
+) -> Result<Json<CustomizeResponse>, (StatusCode, String)> {
+    if state.config.openscad_binary.is_none() {
+        return Err((
+            StatusCode::NOT_IMPLEMENTED,
+            "OpenSCAD is not configured".to_string(),
+        ));
+    }
+    let (file, source, parameters) = find_design(&state, &viewer, id)
+        .await
+        .map_err(|status| (status, String::new()))?;
+    let literals = validate_values(&parameters, &request.values)
+        .map_err(|message| (StatusCode::UNPROCESSABLE_ENTITY, message))?;
+
+    let hash = render_hash(&file, &literals);
+    let url = format!("{}/file/{}/customize/{}", CURRENT_PREFIX, file.id, hash);
+    let target = cached_render(&state.config, &hash);
+    if fs::metadata(&target).await.is_ok() {
+        return Ok(Json(CustomizeResponse {
+            job_id: None,
+            hash,
+            url,
+        }));
+    }
+
+    let (job_id, cancel) = state.jobs.start("customize");
+    let job_hash = hash.clone();
+    tokio::spawn(async move {
+        let parameters: Vec<(String, String)> = literals.into_iter().collect();
+        let partial = target.with_extension("partial.stl");
+        let result = async {
+            fs::create_dir_all(&state.config.render_cache_dir).await?;
+            render_stl(&state.config, &source, &partial, &parameters, &cancel).await?;
+            fs::rename(&partial, &target).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if result.is_err() {
+            let _ = fs::remove_file(&partial).await;
+        } else {
+            info!("Rendered {} with {:?}", source.display(), parameters);
+        }
+        state.jobs.set_result(job_id, &job_hash);
+        state.jobs.complete(job_id, &result);
+    });
+
+    Ok(Json(CustomizeResponse {
+        job_id: Some(job_id.to_string()),
+        hash,
+        url,
+    }))
+}
+
+pub async fn handle_customized_stl(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path((id, hash)): Path<(i32, String)>,
+) -> Result<Response, StatusCode> {
+    if !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (file, _, _) = find_design(&state, &viewer, id).await?;
+
+    let buffer = fs::read(cached_render(&state.config, &hash))
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{}.stl\"",
+                file.get_file_name().await.unwrap()
+            )
+            .as_str(),
+        )
+        .body(Body::from(buffer))
+        .unwrap())
+}
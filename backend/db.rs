@@ -0,0 +1,188 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use diesel::sqlite::SqliteConnection;
+use diesel::{ConnectionError, ConnectionResult};
+use diesel_async::pooled_connection::bb8::{Pool, PooledConnection};
+use diesel_async::pooled_connection::{AsyncDieselConnectionManager, ManagerConfig};
+use diesel_async::sync_connection_wrapper::SyncConnectionWrapper;
+use diesel_async::{AsyncConnection, SimpleAsyncConnection};
+use futures::future::BoxFuture;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::policy::{Admin, Authorized};
+use crate::workers;
+use crate::{AppState, Config};
+
+pub type DbConnection<'a> = PooledConnection<'a, SyncConnectionWrapper<SqliteConnection>>;
+
+/// Checkouts which gave up waiting for a free connection.
+static CHECKOUT_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+const JOURNAL_MODES: &[&str] = &["delete", "truncate", "persist", "memory", "wal", "off"];
+const SYNCHRONOUS_MODES: &[&str] = &["off", "normal", "full", "extra"];
+
+/// Rejects pragma values SQLite does not know, they end up in SQL.
+pub fn check_pragmas(config: &Config) {
+    if !JOURNAL_MODES.contains(&config.sqlite_journal_mode.to_lowercase().as_str()) {
+        panic!(
+            "Invalid SQLITE_JOURNAL_MODE {:?}, expected one of {}",
+            config.sqlite_journal_mode,
+            JOURNAL_MODES.join(", ")
+        );
+    }
+    if !SYNCHRONOUS_MODES.contains(&config.sqlite_synchronous.to_lowercase().as_str()) {
+        panic!(
+            "Invalid SQLITE_SYNCHRONOUS {:?}, expected one of {}",
+            config.sqlite_synchronous,
+            SYNCHRONOUS_MODES.join(", ")
+        );
+    }
+}
+
+/// The pragmas run on every new connection. The busy timeout comes first,
+/// switching the journal mode needs a lock itself.
+pub fn pragmas(config: &Config) -> String {
+    format!(
+        "PRAGMA busy_timeout = {}; PRAGMA journal_mode = {}; PRAGMA synchronous = {}; PRAGMA cache_size = -{};",
+        config.sqlite_busy_timeout_ms,
+        config.sqlite_journal_mode.to_lowercase(),
+        config.sqlite_synchronous.to_lowercase(),
+        config.sqlite_cache_size_kib
+    )
+}
+
+/// Opens a pooled connection and applies `pragmas` to it, giving up after
+/// `timeout`.
+pub fn establish(
+    url: &str,
+    pragmas: String,
+    timeout: Duration,
+) -> BoxFuture<'_, ConnectionResult<SyncConnectionWrapper<SqliteConnection>>> {
+    Box::pin(async move {
+        let connect = async {
+            let mut connection = SyncConnectionWrapper::<SqliteConnection>::establish(url).await?;
+            connection
+                .batch_execute(&pragmas)
+                .await
+                .map_err(ConnectionError::CouldntSetupConfiguration)?;
+            Ok(connection)
+        };
+        match tokio::time::timeout(timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(ConnectionError::BadConnection(format!(
+                "Opening {} timed out",
+                url
+            ))),
+        }
+    })
+}
+
+pub async fn create_connection_pool(
+    config: &Config,
+) -> Pool<SyncConnectionWrapper<SqliteConnection>> {
+    let pragmas = pragmas(config);
+    let connect_timeout = Duration::from_millis(config.db_connect_timeout_ms);
+    let mut db_config = ManagerConfig::default();
+    db_config.custom_setup = Box::new(move |url| establish(url, pragmas.clone(), connect_timeout));
+    let mgr =
+        AsyncDieselConnectionManager::<SyncConnectionWrapper<SqliteConnection>>::new_with_config(
+            config.database_url.to_str().unwrap(),
+            db_config,
+        );
+
+    Pool::builder()
+        .max_size(config.db_pool_max_size)
+        .min_idle(config.db_pool_min_idle)
+        .connection_timeout(Duration::from_millis(config.db_checkout_timeout_ms))
+        .idle_timeout(
+            (config.db_idle_timeout_seconds > 0)
+                .then(|| Duration::from_secs(config.db_idle_timeout_seconds)),
+        )
+        .max_lifetime(
+            (config.db_max_lifetime_seconds > 0)
+                .then(|| Duration::from_secs(config.db_max_lifetime_seconds)),
+        )
+        .build(mgr)
+        .await
+        .unwrap()
+}
+
+impl AppState {
+    /// A connection from the pool. When none frees up within
+    /// `db_checkout_timeout_ms` the request fails with
+    /// `503 Service Unavailable` instead of piling up.
+    pub async fn connection(&self) -> Result<DbConnection<'_>, StatusCode> {
+        self.pool.get().await.map_err(|e| {
+            CHECKOUT_FAILURES.fetch_add(1, Ordering::Relaxed);
+            warn!("No database connection available: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        })
+    }
+}
+
+/// Tells clients of `503 Service Unavailable` responses when to try again.
+pub async fn retry_after(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    if response.status() == StatusCode::SERVICE_UNAVAILABLE
+        && !response.headers().contains_key(header::RETRY_AFTER)
+    {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+    }
+    response
+}
+
+/// Gauges of the connection pool and the CPU worker pool in the Prometheus
+/// text format.
+pub async fn handle_metrics(State(state): State<AppState>, _: Authorized<Admin>) -> Response {
+    let pool_state = state.pool.state();
+    let gauges = [
+        (
+            "modelvault_db_pool_max_size",
+            "gauge",
+            "Most connections the pool opens.",
+            state.config.db_pool_max_size as u64,
+        ),
+        (
+            "modelvault_db_pool_connections",
+            "gauge",
+            "Open connections, idle or in use.",
+            pool_state.connections as u64,
+        ),
+        (
+            "modelvault_db_pool_idle_connections",
+            "gauge",
+            "Open connections waiting for a request.",
+            pool_state.idle_connections as u64,
+        ),
+        (
+            "modelvault_db_pool_in_use_connections",
+            "gauge",
+            "Connections checked out by requests.",
+            pool_state
+                .connections
+                .saturating_sub(pool_state.idle_connections) as u64,
+        ),
+        (
+            "modelvault_db_pool_checkout_failures_total",
+            "counter",
+            "Requests answered with 503 for lack of a connection.",
+            CHECKOUT_FAILURES.load(Ordering::Relaxed),
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, help, value) in gauges.into_iter().chain(workers::gauges()) {
+        body.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+        ));
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
@@ -0,0 +1,386 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use tracing::{error, info};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::audit_log::record;
+use crate::model_filter::filter_models;
+use crate::policy::{Admin, Authorized};
+use crate::schema::{designer_aliases, designers, models3d};
+use crate::slug::slugify;
+use crate::types::ListModelParams;
+use crate::AppState;
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DesignerResponse {
+    pub name: String,
+    pub slug: String,
+    /// author names resolving to this designer
+    pub aliases: Vec<String>,
+    /// models the viewer can see
+    pub model_count: i32,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeDesigner {
+    /// slug of the designer `slug` is merged into
+    pub into: String,
+}
+
+/// Author names are compared lower case with collapsed whitespace, so
+/// `Jane  Doe` and `jane doe` are the same designer.
+pub fn normalize_alias(author: &str) -> Option<String> {
+    let alias = author.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!alias.is_empty()).then(|| alias.to_lowercase())
+}
+
+async fn unique_designer_slug<Conn>(connection: &mut Conn, name: &str) -> QueryResult<String>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let base = slugify(name);
+    let mut slug = base.clone();
+    let mut counter = 2;
+    while designers::dsl::designers
+        .filter(designers::dsl::slug.eq(&slug))
+        .select(designers::dsl::id)
+        .first::<i32>(connection)
+        .await
+        .optional()?
+        .is_some()
+    {
+        slug = format!("{}-{}", base, counter);
+        counter += 1;
+    }
+    Ok(slug)
+}
+
+/// The designer an author name resolves to, created on first sight.
+pub async fn designer_for_author<Conn>(
+    connection: &mut Conn,
+    author: &str,
+) -> QueryResult<Option<i32>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let Some(alias) = normalize_alias(author) else {
+        return Ok(None);
+    };
+    if let Some(designer_id) = designer_aliases::dsl::designer_aliases
+        .find(&alias)
+        .select(designer_aliases::dsl::designer_id)
+        .first::<i32>(connection)
+        .await
+        .optional()?
+    {
+        return Ok(Some(designer_id));
+    }
+
+    let name = author.split_whitespace().collect::<Vec<_>>().join(" ");
+    let slug = unique_designer_slug(connection, &name).await?;
+    let designer_id = diesel::insert_into(designers::table)
+        .values((
+            designers::dsl::name.eq(&name),
+            designers::dsl::slug.eq(&slug),
+        ))
+        .returning(designers::dsl::id)
+        .get_result::<i32>(connection)
+        .await?;
+    diesel::insert_into(designer_aliases::table)
+        .values((
+            designer_aliases::dsl::alias.eq(&alias),
+            designer_aliases::dsl::designer_id.eq(designer_id),
+        ))
+        .execute(connection)
+        .await?;
+    info!("Created designer {}", name);
+    Ok(Some(designer_id))
+}
+
+/// Links a model to the designer of its author, or to none without one.
+pub async fn assign_designer<Conn>(
+    connection: &mut Conn,
+    model_id: i32,
+    author: Option<&str>,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let designer_id = match author {
+        Some(author) => designer_for_author(connection, author).await?,
+        None => None,
+    };
+    diesel::update(models3d::dsl::models3d.find(model_id))
+        .set(models3d::dsl::designer_id.eq(designer_id))
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// Links models indexed before designers existed.
+pub async fn backfill<Conn>(connection: &mut Conn) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let models: Vec<(i32, Option<String>)> = models3d::dsl::models3d
+        .filter(models3d::dsl::designer_id.is_null())
+        .filter(models3d::dsl::author.is_not_null())
+        .filter(models3d::dsl::author.ne(""))
+        .select((models3d::dsl::id, models3d::dsl::author))
+        .load(connection)
+        .await?;
+    for (id, author) in models {
+        assign_designer(connection, id, author.as_deref()).await?;
+    }
+    Ok(())
+}
+
+pub async fn designer_slug<Conn>(
+    connection: &mut Conn,
+    designer_id: Option<i32>,
+) -> QueryResult<Option<String>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    match designer_id {
+        Some(designer_id) => designers::dsl::designers
+            .find(designer_id)
+            .select(designers::dsl::slug)
+            .first::<String>(connection)
+            .await
+            .optional(),
+        None => Ok(None),
+    }
+}
+
+/// Counts of the models each designer has among those the viewer can see.
+async fn visible_counts<Conn>(
+    connection: &mut Conn,
+    viewer: &Viewer,
+) -> Result<HashMap<i32, i32>, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let designer_ids = filter_models(&ListModelParams::default(), viewer)?
+        .filter(models3d::dsl::designer_id.is_not_null())
+        .select(models3d::dsl::designer_id)
+        .load::<Option<i32>>(connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to count models of designers: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let mut counts = HashMap::new();
+    for designer_id in designer_ids.into_iter().flatten() {
+        *counts.entry(designer_id).or_insert(0) += 1;
+    }
+    Ok(counts)
+}
+
+async fn to_responses<Conn>(
+    connection: &mut Conn,
+    designers: Vec<(i32, String, String)>,
+    counts: &HashMap<i32, i32>,
+) -> QueryResult<Vec<DesignerResponse>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let ids: Vec<i32> = designers.iter().map(|(id, _, _)| *id).collect();
+    let mut aliases: HashMap<i32, Vec<String>> = HashMap::new();
+    for (designer_id, alias) in designer_aliases::dsl::designer_aliases
+        .filter(designer_aliases::dsl::designer_id.eq_any(&ids))
+        .select((
+            designer_aliases::dsl::designer_id,
+            designer_aliases::dsl::alias,
+        ))
+        .order(designer_aliases::dsl::alias.asc())
+        .load::<(i32, String)>(connection)
+        .await?
+    {
+        aliases.entry(designer_id).or_default().push(alias);
+    }
+
+    Ok(designers
+        .into_iter()
+        .map(|(id, name, slug)| DesignerResponse {
+            name,
+            slug,
+            aliases: aliases.remove(&id).unwrap_or_default(),
+            model_count: counts.get(&id).copied().unwrap_or(0),
+        })
+        .collect())
+}
+
+/// Designers with at least one model the viewer can see, by name. Their
+/// models are listed with `/models/list?designer=<slug>`.
+pub async fn handle_list_designers(
+    State(state): State<AppState>,
+    viewer: Viewer,
+) -> Result<Json<Vec<DesignerResponse>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let counts = visible_counts(&mut connection, &viewer).await?;
+    let ids: Vec<i32> = counts.keys().copied().collect();
+    let designers = designers::dsl::designers
+        .filter(designers::dsl::id.eq_any(&ids))
+        .select((
+            designers::dsl::id,
+            designers::dsl::name,
+            designers::dsl::slug,
+        ))
+        .order(designers::dsl::name.asc())
+        .load::<(i32, String, String)>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load designers: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let response = to_responses(&mut connection, designers, &counts)
+        .await
+        .map_err(|e| {
+            error!("Failed to load designer aliases: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(response))
+}
+
+/// A designer with at least one model the viewer can see.
+pub async fn handle_get_designer(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+) -> Result<Json<DesignerResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let designer = designers::dsl::designers
+        .filter(designers::dsl::slug.eq(&slug))
+        .select((
+            designers::dsl::id,
+            designers::dsl::name,
+            designers::dsl::slug,
+        ))
+        .first::<(i32, String, String)>(&mut connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load designer {}: {}", slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let counts = visible_counts(&mut connection, &viewer).await?;
+    if !counts.contains_key(&designer.0) && !viewer.is_admin {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let response = to_responses(&mut connection, vec![designer], &counts)
+        .await
+        .map_err(|e| {
+            error!("Failed to load aliases of designer {}: {}", slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(response.into_iter().next().unwrap()))
+}
+
+/// Merges designer `slug` into another one, admins only. The aliases move
+/// along, so later refreshes resolve the old author names to the target.
+pub async fn handle_merge_designer(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Path(slug): Path<String>,
+    Json(payload): Json<MergeDesigner>,
+) -> Result<Json<DesignerResponse>, StatusCode> {
+    if payload.into == slug {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let mut connection = state.connection().await?;
+
+    let find = |slug: String| {
+        designers::dsl::designers
+            .filter(designers::dsl::slug.eq(slug))
+            .select((
+                designers::dsl::id,
+                designers::dsl::name,
+                designers::dsl::slug,
+            ))
+    };
+    let source = find(slug.clone())
+        .first::<(i32, String, String)>(&mut connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load designer {}: {}", slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let target = find(payload.into.clone())
+        .first::<(i32, String, String)>(&mut connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load designer {}: {}", payload.into, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+
+    let (source_ref, target_ref, viewer_ref) = (&source, &target, &viewer);
+    (*connection)
+        .transaction::<_, diesel::result::Error, _>(|connection| {
+            async move {
+                let moved = diesel::update(
+                    models3d::dsl::models3d.filter(models3d::dsl::designer_id.eq(source_ref.0)),
+                )
+                .set(models3d::dsl::designer_id.eq(target_ref.0))
+                .execute(connection)
+                .await?;
+                diesel::update(
+                    designer_aliases::dsl::designer_aliases
+                        .filter(designer_aliases::dsl::designer_id.eq(source_ref.0)),
+                )
+                .set(designer_aliases::dsl::designer_id.eq(target_ref.0))
+                .execute(connection)
+                .await?;
+                diesel::delete(designers::dsl::designers.find(source_ref.0))
+                    .execute(connection)
+                    .await?;
+                record(
+                    connection,
+                    viewer_ref,
+                    "designer.merge",
+                    &source_ref.2,
+                    json!({ "into": target_ref.2, "models": moved }),
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to merge designer {} into {}: {}",
+                slug, payload.into, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let counts = visible_counts(&mut connection, &viewer).await?;
+    let response = to_responses(&mut connection, vec![target], &counts)
+        .await
+        .map_err(|e| {
+            error!("Failed to load aliases of designer {}: {}", payload.into, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(response.into_iter().next().unwrap()))
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::debug;
+use typeshare::typeshare;
+
+use crate::file_types::file_type_of;
+use crate::Config;
+
+/// Files in model folders documenting the model rather than being printed:
+/// PDFs, assembly instructions and bills of materials.
+pub const DOCUMENT_FILE_TYPES: &[&str] = &[
+    "pdf", "doc", "docx", "odt", "rtf", "txt", "md", "xls", "xlsx", "ods", "csv",
+];
+
+const SPREADSHEET_FILE_TYPES: &[&str] = &["xls", "xlsx", "ods", "csv"];
+
+/// Words in file names of assembly instructions, lower case.
+const INSTRUCTION_NAMES: &[&str] = &["instruction", "assembly", "manual", "howto", "anleitung"];
+
+/// Rendering a single page should take seconds even for large manuals.
+const THUMBNAIL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentKind {
+    Instructions,
+    Pdf,
+    /// bills of materials and other tables
+    Spreadsheet,
+    Text,
+}
+
+/// The kind of documentation `pth` is, `None` for any other file.
+pub fn document_kind(pth: &Path) -> Option<DocumentKind> {
+    let file_type = file_type_of(pth);
+    if !DOCUMENT_FILE_TYPES.contains(&file_type.as_str()) {
+        return None;
+    }
+    if SPREADSHEET_FILE_TYPES.contains(&file_type.as_str()) {
+        return Some(DocumentKind::Spreadsheet);
+    }
+    let name = pth
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if INSTRUCTION_NAMES.iter().any(|word| name.contains(word)) {
+        return Some(DocumentKind::Instructions);
+    }
+    match file_type.as_str() {
+        "pdf" => Some(DocumentKind::Pdf),
+        _ => Some(DocumentKind::Text),
+    }
+}
+
+pub fn is_document(pth: &Path) -> bool {
+    document_kind(pth).is_some()
+}
+
+/// Renders the first page of a PDF with the configured `pdftoppm` binary.
+/// Returns the name of the image in the preview cache.
+pub async fn first_page_thumbnail(config: &Config, source: &Path, hash: &str) -> Option<String> {
+    let binary = config.pdftoppm_binary.as_ref()?;
+    if file_type_of(source) != "pdf" {
+        return None;
+    }
+    let file_name = format!("{}.png", hash);
+    // pdftoppm appends the extension to the given prefix
+    let prefix = config.preview_cache_dir.join(hash);
+
+    let child = Command::new(binary)
+        .args([
+            "-png",
+            "-f",
+            "1",
+            "-l",
+            "1",
+            "-singlefile",
+            "-scale-to",
+            "1024",
+        ])
+        .arg(source)
+        .arg(&prefix)
+        .env_clear()
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            debug!("Unable to run {}: {}", binary, e);
+            return None;
+        }
+    };
+    match tokio::time::timeout(THUMBNAIL_TIMEOUT, child.wait_with_output()).await {
+        Ok(Ok(output)) if output.status.success() => config
+            .preview_cache_dir
+            .join(&file_name)
+            .exists()
+            .then_some(file_name),
+        Ok(Ok(output)) => {
+            debug!(
+                "No thumbnail of {}: {}",
+                source.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            None
+        }
+        Ok(Err(e)) => {
+            debug!("pdftoppm failed on {}: {}", source.display(), e);
+            None
+        }
+        Err(_) => {
+            debug!("pdftoppm timed out on {}", source.display());
+            None
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, StatusCode},
+    Json,
+};
+use chrono::{Duration, NaiveDate, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::auth::CurrentUser;
+use crate::client_ip::ClientIp;
+use crate::schema::{download_clients, download_counts};
+use crate::stats::{record_event, EventKind};
+use crate::AppState;
+
+/// User agents of crawlers, link previews and scripted downloads, matched
+/// case insensitively as substrings.
+const BOT_USER_AGENTS: &[&str] = &[
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "curl",
+    "wget",
+    "python",
+    "go-http-client",
+    "java/",
+    "okhttp",
+    "libwww",
+    "httpclient",
+    "axios",
+    "node-fetch",
+    "scrapy",
+    "headless",
+    "rclone",
+];
+
+/// Who is downloading, as far as download statistics are concerned.
+pub struct DownloadClient {
+    /// hash of the user name, or of address and user agent for anonymous
+    /// clients
+    key: String,
+    is_bot: bool,
+    /// a `Range` request continuing an earlier one
+    is_continuation: bool,
+}
+
+impl DownloadClient {
+    /// Whether a request of this client counts towards statistics at all.
+    pub fn is_counted(&self) -> bool {
+        !self.is_bot && !self.is_continuation
+    }
+}
+
+fn is_bot(user_agent: &str, extra_patterns: &[String]) -> bool {
+    let user_agent = user_agent.to_lowercase();
+    user_agent.trim().is_empty()
+        || BOT_USER_AGENTS
+            .iter()
+            .any(|pattern| user_agent.contains(pattern))
+        || extra_patterns
+            .iter()
+            .map(|pattern| pattern.trim().to_lowercase())
+            .any(|pattern| !pattern.is_empty() && user_agent.contains(&pattern))
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for DownloadClient {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let is_continuation = parts
+            .headers
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|range| !range.trim().starts_with("bytes=0-"));
+
+        let key = match user {
+            Some(name) => sha256::digest(format!("user:{}", name)),
+            None => sha256::digest(format!("anonymous:{}:{}", ip, user_agent)),
+        };
+        Ok(DownloadClient {
+            key,
+            is_bot: is_bot(user_agent, &state.config.download_bot_user_agents),
+            is_continuation,
+        })
+    }
+}
+
+/// Counts a finished download of `model_id` once per client and day, bots
+/// and continued ranged downloads are not counted at all.
+pub async fn record_download<Conn>(
+    connection: &mut Conn,
+    model_id: i32,
+    client: &DownloadClient,
+) -> QueryResult<bool>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    if !client.is_counted() {
+        return Ok(false);
+    }
+    let today = Utc::now().date_naive();
+
+    // only today's clients are needed to tell repeated downloads apart
+    diesel::delete(
+        download_clients::dsl::download_clients.filter(download_clients::dsl::day.lt(today)),
+    )
+    .execute(connection)
+    .await?;
+    let inserted = diesel::insert_or_ignore_into(download_clients::table)
+        .values((
+            download_clients::dsl::model_id.eq(model_id),
+            download_clients::dsl::day.eq(today),
+            download_clients::dsl::client.eq(&client.key),
+        ))
+        .execute(connection)
+        .await?;
+    if inserted == 0 {
+        debug!("Download of model {} already counted today", model_id);
+        return Ok(false);
+    }
+
+    diesel::insert_into(download_counts::table)
+        .values((
+            download_counts::dsl::model_id.eq(model_id),
+            download_counts::dsl::day.eq(today),
+            download_counts::dsl::downloads.eq(1),
+        ))
+        .on_conflict((download_counts::dsl::model_id, download_counts::dsl::day))
+        .do_update()
+        .set(download_counts::dsl::downloads.eq(download_counts::dsl::downloads + 1))
+        .execute(connection)
+        .await?;
+    record_event(connection, model_id, EventKind::Download).await?;
+    Ok(true)
+}
+
+/// Statistics must never break the download they are recorded for.
+pub async fn record_download_logged<Conn>(
+    connection: &mut Conn,
+    model_id: i32,
+    client: &DownloadClient,
+) where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    if let Err(e) = record_download(connection, model_id, client).await {
+        error!("Failed to record download of model {}: {}", model_id, e);
+    }
+}
+
+/// Removes the download statistics of a purged model.
+pub async fn delete_downloads<Conn>(connection: &mut Conn, model_id: i32) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::delete(
+        download_clients::dsl::download_clients
+            .filter(download_clients::dsl::model_id.eq(model_id)),
+    )
+    .execute(connection)
+    .await?;
+    diesel::delete(
+        download_counts::dsl::download_counts.filter(download_counts::dsl::model_id.eq(model_id)),
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct DownloadsParams {
+    pub days: Option<i64>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DailyDownloads {
+    pub day: NaiveDate,
+    pub downloads: i32,
+}
+
+/// Downloads of a model per day, oldest first. Days without downloads are
+/// included so the result can be charted as is.
+pub async fn handle_model_downloads(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+    Query(params): Query<DownloadsParams>,
+) -> Result<Json<Vec<DailyDownloads>>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let days = params.days.unwrap_or(30).clamp(1, 366);
+
+    let model = viewer.find_model(&mut connection, &slug).await?;
+    let today = Utc::now().date_naive();
+    let first_day = today - Duration::days(days - 1);
+
+    let counts = download_counts::dsl::download_counts
+        .filter(download_counts::dsl::model_id.eq(model.id))
+        .filter(download_counts::dsl::day.ge(first_day))
+        .select((download_counts::dsl::day, download_counts::dsl::downloads))
+        .load::<(NaiveDate, i32)>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load downloads of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(
+        first_day
+            .iter_days()
+            .take_while(|day| *day <= today)
+            .map(|day| DailyDownloads {
+                day,
+                downloads: counts
+                    .iter()
+                    .find(|(counted, _)| *counted == day)
+                    .map(|(_, downloads)| *downloads)
+                    .unwrap_or_default(),
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,561 @@
+use axum::{
+    extract::{Multipart, Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Component, PathBuf};
+use tokio::fs;
+use tracing::{debug, error, info};
+use typeshare::typeshare;
+use uuid::Uuid;
+
+use crate::access::Viewer;
+use crate::auth::User;
+use crate::file_types::file_type_of;
+use crate::policy::is_owner_or_admin;
+use crate::quota;
+use crate::rollback::Rollback;
+use crate::schema::{upload_drafts, users};
+use crate::types::{ModelPackV0_1, UploadResponse};
+use crate::upload::{
+    categorize_file, check_quota, cleanup_temp_dir, complete_upload_job, content_length,
+    index_uploaded_model, install_new_folder, notify_upload, require_uploader, write_field,
+    QuotaBudget, UploadError,
+};
+use crate::{AppState, Config};
+
+#[derive(Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = upload_drafts)]
+pub struct UploadDraft {
+    pub id: String,
+    pub user_id: Option<i32>,
+    /// folder relative to `libraries_path` the model is published into
+    pub library_root: String,
+    pub title: String,
+    pub author: String,
+    pub origin: String,
+    pub license: String,
+    /// JSON list
+    pub tags: String,
+    pub description: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// What becomes the modelpack.json and README.md of the published model.
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DraftMetadata {
+    pub title: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub origin: String,
+    #[serde(default)]
+    pub license: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DraftFile {
+    /// relative to the model folder, e.g. `files/part.stl`
+    pub path: String,
+    pub file_type: String,
+    pub size_bytes: f64,
+}
+
+/// A draft as it would be published right now.
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DraftPreview {
+    pub id: String,
+    pub metadata: DraftMetadata,
+    /// name of the model folder in the library
+    pub folder_name: String,
+    pub files: Vec<DraftFile>,
+    /// files which were not attached because they are neither meshes, CAD
+    /// files nor images
+    pub ignored: Vec<String>,
+    /// reasons the draft cannot be published yet
+    pub problems: Vec<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+fn draft_dir(config: &Config, id: &str) -> PathBuf {
+    config.drafts_dir.join(id)
+}
+
+fn metadata_of(draft: &UploadDraft) -> DraftMetadata {
+    DraftMetadata {
+        title: draft.title.clone(),
+        author: draft.author.clone(),
+        origin: draft.origin.clone(),
+        license: draft.license.clone(),
+        tags: serde_json::from_str(&draft.tags).unwrap_or_default(),
+        description: draft.description.clone(),
+    }
+}
+
+/// Drafts belong to their creator, admins may see all of them.
+async fn find_draft<Conn>(
+    connection: &mut Conn,
+    viewer: &Viewer,
+    id: &str,
+) -> Result<UploadDraft, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let draft = upload_drafts::dsl::upload_drafts
+        .find(id)
+        .select(UploadDraft::as_select())
+        .first::<UploadDraft>(connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load draft {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !is_owner_or_admin(viewer, draft.user_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(draft)
+}
+
+async fn draft_files(dir: &std::path::Path) -> Vec<DraftFile> {
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut files: Vec<DraftFile> = walkdir::WalkDir::new(&dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| {
+                let relative = entry.path().strip_prefix(&dir).ok()?;
+                Some(DraftFile {
+                    path: relative.to_string_lossy().replace('\\', "/"),
+                    file_type: file_type_of(entry.path()),
+                    size_bytes: entry.metadata().ok()?.len() as f64,
+                })
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files
+    })
+    .await
+    .unwrap_or_default()
+}
+
+async fn preview<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    draft: &UploadDraft,
+    ignored: Vec<String>,
+) -> Result<DraftPreview, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let dir = draft_dir(config, &draft.id);
+    let files = draft_files(&dir).await;
+    let folder_name = sanitize_filename::sanitize(draft.title.trim());
+
+    let mut problems = Vec::new();
+    if folder_name.is_empty() {
+        problems.push("The draft has no title".to_string());
+    } else if config
+        .libraries_path
+        .join(&draft.library_root)
+        .join(&folder_name)
+        .exists()
+    {
+        problems.push(format!("A folder named {} exists already", folder_name));
+    }
+    if files.is_empty() {
+        problems.push("No files are attached".to_string());
+    }
+    let uploader = load_user(connection, draft.user_id).await?;
+    match check_quota(connection, config, uploader.as_ref(), quota::dir_size(&dir)).await {
+        Ok(()) => {}
+        Err(UploadError::Status(status)) => return Err(status),
+        Err(e) => problems.push(e.to_string()),
+    }
+
+    Ok(DraftPreview {
+        id: draft.id.clone(),
+        metadata: metadata_of(draft),
+        folder_name,
+        files,
+        ignored,
+        problems,
+        created_at: draft.created_at,
+        updated_at: draft.updated_at,
+    })
+}
+
+async fn load_user<Conn>(
+    connection: &mut Conn,
+    user_id: Option<i32>,
+) -> Result<Option<User>, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+    users::dsl::users
+        .find(user_id)
+        .select(User::as_select())
+        .first::<User>(connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load user {}: {}", user_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Starts a model with its metadata, files are attached afterwards.
+pub async fn handle_create_draft(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Json(metadata): Json<DraftMetadata>,
+) -> Result<Json<DraftPreview>, StatusCode> {
+    let config = &state.config;
+    require_uploader(config, viewer.user.as_ref())?;
+    let mut connection = state.connection().await?;
+    let library_root =
+        pathdiff::diff_paths(viewer.scope.library_root(config), &config.libraries_path)
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+    let now = Utc::now().naive_utc();
+    let draft = UploadDraft {
+        id: Uuid::new_v4().to_string(),
+        user_id: viewer.user.as_ref().map(|user| user.id),
+        library_root,
+        title: metadata.title.trim().to_string(),
+        author: metadata.author,
+        origin: metadata.origin,
+        license: metadata.license,
+        tags: json!(metadata.tags).to_string(),
+        description: metadata.description,
+        created_at: now,
+        updated_at: now,
+    };
+
+    fs::create_dir_all(draft_dir(config, &draft.id))
+        .await
+        .map_err(|e| {
+            error!("Failed to create the folder of draft {}: {}", draft.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    diesel::insert_into(upload_drafts::table)
+        .values(&draft)
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to create draft: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(
+        preview(config, &mut connection, &draft, Vec::new()).await?,
+    ))
+}
+
+/// The drafts of the current user, newest first.
+pub async fn handle_list_drafts(
+    State(state): State<AppState>,
+    viewer: Viewer,
+) -> Result<Json<Vec<DraftPreview>>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let mut query = upload_drafts::dsl::upload_drafts
+        .order(upload_drafts::dsl::updated_at.desc())
+        .select(UploadDraft::as_select())
+        .into_boxed();
+    query = match &viewer.user {
+        Some(user) => query.filter(upload_drafts::dsl::user_id.eq(user.id)),
+        None => query.filter(upload_drafts::dsl::user_id.is_null()),
+    };
+    let drafts = query
+        .load::<UploadDraft>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load drafts: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut previews = Vec::new();
+    for draft in drafts {
+        previews.push(preview(&state.config, &mut connection, &draft, Vec::new()).await?);
+    }
+    Ok(Json(previews))
+}
+
+pub async fn handle_get_draft(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<String>,
+) -> Result<Json<DraftPreview>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let draft = find_draft(&mut connection, &viewer, &id).await?;
+    Ok(Json(
+        preview(&state.config, &mut connection, &draft, Vec::new()).await?,
+    ))
+}
+
+pub async fn handle_update_draft(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<String>,
+    Json(metadata): Json<DraftMetadata>,
+) -> Result<Json<DraftPreview>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let draft = find_draft(&mut connection, &viewer, &id).await?;
+    diesel::update(upload_drafts::dsl::upload_drafts.find(&draft.id))
+        .set((
+            upload_drafts::dsl::title.eq(metadata.title.trim()),
+            upload_drafts::dsl::author.eq(&metadata.author),
+            upload_drafts::dsl::origin.eq(&metadata.origin),
+            upload_drafts::dsl::license.eq(&metadata.license),
+            upload_drafts::dsl::tags.eq(json!(metadata.tags).to_string()),
+            upload_drafts::dsl::description.eq(&metadata.description),
+            upload_drafts::dsl::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to update draft {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let draft = find_draft(&mut connection, &viewer, &id).await?;
+    Ok(Json(
+        preview(&state.config, &mut connection, &draft, Vec::new()).await?,
+    ))
+}
+
+/// Attaches the files of a multipart upload. Meshes and CAD files go to
+/// `files/`, images to `images/`, a file of the same name is replaced.
+pub async fn handle_attach_files(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Json<DraftPreview>, UploadError> {
+    let mut connection = state.connection().await?;
+    let draft = find_draft(&mut connection, &viewer, &id).await?;
+    let dir = draft_dir(&state.config, &draft.id);
+
+    // files attached before count against the quota as well
+    let uploader = load_user(&mut connection, draft.user_id).await?;
+    require_uploader(&state.config, uploader.as_ref())?;
+    let mut budget = QuotaBudget::load(&mut connection, &state.config, uploader.as_ref()).await?;
+    if let Some(budget) = &mut budget {
+        budget.spend(quota::dir_size(&dir))?;
+        if let Some(length) = content_length(&headers) {
+            budget.check(length)?;
+        }
+    }
+    let (job_id, cancel) = state.jobs.start("upload");
+
+    let result: Result<Json<DraftPreview>, UploadError> = async {
+        let mut ignored = Vec::new();
+        while let Some(mut field) = multipart
+            .next_field()
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?
+        {
+            let file_name = sanitize_filename::sanitize(field.file_name().unwrap_or("unknown"));
+            let folder = match categorize_file(&PathBuf::from(&file_name)) {
+                "mesh" | "cad" => "files",
+                "image" => "images",
+                _ => {
+                    info!("File {} is ignored", file_name);
+                    ignored.push(file_name);
+                    continue;
+                }
+            };
+            let folder = dir.join(folder);
+            fs::create_dir_all(&folder).await.map_err(|e| {
+                error!("Failed to create {}: {}", folder.display(), e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+            write_field(
+                &mut field,
+                &folder.join(&file_name),
+                &cancel,
+                budget.as_mut(),
+            )
+            .await?;
+            debug!("Attached {} to draft {}", file_name, draft.id);
+        }
+
+        diesel::update(upload_drafts::dsl::upload_drafts.find(&draft.id))
+            .set(upload_drafts::dsl::updated_at.eq(Utc::now().naive_utc()))
+            .execute(&mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to update draft {}: {}", draft.id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        let draft = find_draft(&mut connection, &viewer, &id).await?;
+        Ok(Json(
+            preview(&state.config, &mut connection, &draft, ignored).await?,
+        ))
+    }
+    .await;
+    complete_upload_job(&state.jobs, job_id, &cancel, &result);
+    result
+}
+
+/// Removes an attached file, `path` as listed in the preview.
+pub async fn handle_remove_file(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path((id, path)): Path<(String, String)>,
+) -> Result<Json<DraftPreview>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let draft = find_draft(&mut connection, &viewer, &id).await?;
+    let relative = std::path::Path::new(&path);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let file = draft_dir(&state.config, &draft.id).join(relative);
+    if !file.is_file() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    fs::remove_file(&file).await.map_err(|e| {
+        error!("Failed to remove {}: {}", file.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(
+        preview(&state.config, &mut connection, &draft, Vec::new()).await?,
+    ))
+}
+
+async fn delete_draft<Conn>(config: &Config, connection: &mut Conn, id: &str) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    cleanup_temp_dir(&draft_dir(config, id));
+    diesel::delete(upload_drafts::dsl::upload_drafts.find(id))
+        .execute(connection)
+        .await
+}
+
+/// Discards a draft and its files.
+pub async fn handle_delete_draft(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<String>,
+) -> StatusCode {
+    let mut connection = match state.connection().await {
+        Ok(connection) => connection,
+        Err(status) => return status,
+    };
+    let draft = match find_draft(&mut connection, &viewer, &id).await {
+        Ok(draft) => draft,
+        Err(status) => return status,
+    };
+    match delete_draft(&state.config, &mut connection, &draft.id).await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Failed to delete draft {}: {}", draft.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Moves the draft into the library as a new model, once the preview has
+/// no problems left. The draft is gone afterwards.
+pub async fn handle_publish_draft(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, UploadError> {
+    let config = &state.config;
+    let mut connection = state.connection().await?;
+    let draft = find_draft(&mut connection, &viewer, &id).await?;
+    let uploader = load_user(&mut connection, draft.user_id).await?;
+
+    let result: Result<Json<Value>, UploadError> = async {
+        let preview = preview(config, &mut connection, &draft, Vec::new()).await?;
+        if !preview.problems.is_empty() {
+            info!("Draft {} is not ready: {:?}", draft.id, preview.problems);
+            return Err(StatusCode::CONFLICT.into());
+        }
+
+        let dir = draft_dir(config, &draft.id);
+        let metadata = preview.metadata;
+        let modelpack = ModelPackV0_1 {
+            version: "0.1".to_string(),
+            title: metadata.title,
+            author: metadata.author,
+            origin: metadata.origin,
+            license: metadata.license,
+            tags: (!metadata.tags.is_empty()).then_some(metadata.tags),
+        };
+        let rollback = Rollback::default();
+        let written = async {
+            fs::write(
+                dir.join("modelpack.json"),
+                serde_json::to_string_pretty(&modelpack)?,
+            )
+            .await?;
+            if !metadata.description.trim().is_empty() {
+                fs::write(dir.join("README.md"), &metadata.description).await?;
+            }
+            let library_root = config.libraries_path.join(&draft.library_root);
+            fs::create_dir_all(&library_root).await?;
+            let final_path = library_root.join(&preview.folder_name);
+            install_new_folder(&dir, &final_path).await?;
+            // the files go back into the draft if indexing fails
+            rollback.moved(&dir, &final_path);
+            anyhow::Ok(final_path)
+        }
+        .await;
+        let final_path = written.map_err(|e| {
+            error!("Failed to publish draft {}: {:#}", draft.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        let size = quota::dir_size(&final_path);
+        let model = index_uploaded_model(
+            &mut connection,
+            config,
+            &final_path,
+            uploader.as_ref(),
+            size,
+            None,
+            &rollback,
+        )
+        .await?;
+        if let Err(e) = delete_draft(config, &mut connection, &draft.id).await {
+            error!("Failed to delete published draft {}: {}", draft.id, e);
+        }
+        info!("Published draft {} as {}", draft.id, model.slug);
+
+        let response = UploadResponse {
+            success: true,
+            slug: model.slug,
+            message: format!("Successfully published {} files", preview.files.len()),
+        };
+        Ok(Json(json!(response)))
+    }
+    .await;
+    notify_upload(&state.notifier, uploader.as_ref(), &result);
+    result
+}
@@ -0,0 +1,262 @@
+use axum::{extract::State, http::StatusCode, Json};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::convert;
+use crate::model_filter::filter_models;
+use crate::schema::files3d;
+use crate::types::{File3D, ListModelParams, Model3D};
+use crate::AppState;
+
+/// Histogram buckets of the distance distribution.
+const BINS: usize = 32;
+/// Distances are measured relative to their mean, longer ones share the last bucket.
+const MAX_RELATIVE_DISTANCE: f64 = 3.0;
+const SAMPLES: usize = 4096;
+/// Summed absolute difference of two histograms below which meshes are
+/// reported as likely duplicates, identical meshes score close to 0 and
+/// unrelated ones 0.3 and up.
+const SIMILAR_DISTANCE: f64 = 0.08;
+
+/// Deterministic xorshift, so a mesh always gets the same fingerprint.
+struct Sampler(u64);
+
+impl Sampler {
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn area(triangle: &[[f64; 3]; 3]) -> f64 {
+    let [a, b, c] = triangle;
+    let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    let cross = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    (cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2]).sqrt() / 2.0
+}
+
+/// A random point on the surface, triangles are picked by their area so a
+/// finer triangulation of the same shape samples the same way.
+fn surface_point(
+    triangles: &[[[f64; 3]; 3]],
+    cumulative: &[f64],
+    sampler: &mut Sampler,
+) -> [f64; 3] {
+    let total = cumulative.last().copied().unwrap_or_default();
+    let target = sampler.next() * total;
+    let index = cumulative
+        .partition_point(|area| *area < target)
+        .min(triangles.len() - 1);
+    let [a, b, c] = triangles[index];
+
+    let (mut r1, mut r2) = (sampler.next(), sampler.next());
+    if r1 + r2 > 1.0 {
+        (r1, r2) = (1.0 - r1, 1.0 - r2);
+    }
+    [0, 1, 2].map(|axis| a[axis] + r1 * (b[axis] - a[axis]) + r2 * (c[axis] - a[axis]))
+}
+
+/// The D2 shape distribution of a mesh: a histogram of distances between
+/// random surface points, normalized by their mean. It ignores position,
+/// scale, rotation and triangulation, so rescaled or re-exported copies of a
+/// model end up with nearly the same fingerprint.
+pub fn geometry_fingerprint(mesh_pth: &Path) -> anyhow::Result<Vec<f32>> {
+    let triangles = convert::load_triangles(&mesh_pth.to_path_buf())?;
+    let cumulative: Vec<f64> = triangles
+        .iter()
+        .scan(0.0, |sum, triangle| {
+            *sum += area(triangle);
+            Some(*sum)
+        })
+        .collect();
+    if cumulative.last().copied().unwrap_or_default() <= 0.0 {
+        anyhow::bail!("Mesh {} has no surface", mesh_pth.display());
+    }
+
+    let mut sampler = Sampler(0x9e3779b97f4a7c15);
+    let distances: Vec<f64> = (0..SAMPLES)
+        .map(|_| {
+            let a = surface_point(&triangles, &cumulative, &mut sampler);
+            let b = surface_point(&triangles, &cumulative, &mut sampler);
+            ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+        })
+        .collect();
+    let mean = distances.iter().sum::<f64>() / SAMPLES as f64;
+    if mean <= 0.0 {
+        anyhow::bail!("Mesh {} has no extent", mesh_pth.display());
+    }
+
+    let mut histogram = vec![0.0; BINS];
+    for distance in distances {
+        let bin = (distance / mean / MAX_RELATIVE_DISTANCE * BINS as f64) as usize;
+        histogram[bin.min(BINS - 1)] += 1.0 / SAMPLES as f32;
+    }
+    Ok(histogram)
+}
+
+fn fingerprint_distance(a: &[f32], b: &[f32]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b).abs() as f64).sum()
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateKind {
+    /// byte for byte the same file
+    Exact,
+    /// same shape, maybe rescaled or exported differently
+    Similar,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicateFile {
+    pub id: i32,
+    pub file_path: String,
+    pub model_slug: String,
+    pub model_name: String,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    pub files: Vec<DuplicateFile>,
+}
+
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+}
+
+fn find_root(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[index] = root;
+    root
+}
+
+/// Groups files with the same hash, then files whose geometry fingerprints
+/// are close. Exact copies count once when looking for similar shapes.
+fn group_duplicates(files: &[File3D]) -> Vec<(DuplicateKind, Vec<usize>)> {
+    let mut groups = Vec::new();
+
+    let mut by_hash: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        if let Some(hash) = &file.file_hash {
+            by_hash.entry(hash).or_default().push(index);
+        }
+    }
+    let mut representatives = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        match file.file_hash.as_deref().and_then(|hash| by_hash.get(hash)) {
+            Some(copies) if copies[0] != index => continue,
+            Some(copies) if copies.len() > 1 => {
+                groups.push((DuplicateKind::Exact, copies.clone()));
+                representatives.push(index);
+            }
+            _ => representatives.push(index),
+        }
+    }
+
+    let fingerprints: Vec<(usize, Vec<f32>)> = representatives
+        .into_iter()
+        .filter_map(|index| {
+            let fingerprint = files[index].geometry_fingerprint.as_deref()?;
+            Some((index, serde_json::from_str(fingerprint).ok()?))
+        })
+        .collect();
+    let mut parents: Vec<usize> = (0..fingerprints.len()).collect();
+    for (a, (_, first)) in fingerprints.iter().enumerate() {
+        for (b, (_, second)) in fingerprints.iter().enumerate().skip(a + 1) {
+            if fingerprint_distance(first, second) < SIMILAR_DISTANCE {
+                let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+                parents[root_b] = root_a;
+            }
+        }
+    }
+    let mut similar: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (position, (index, _)) in fingerprints.iter().enumerate() {
+        let root = find_root(&mut parents, position);
+        similar.entry(root).or_default().push(*index);
+    }
+    let mut similar: Vec<Vec<usize>> = similar
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    similar.sort();
+    groups.extend(
+        similar
+            .into_iter()
+            .map(|group| (DuplicateKind::Similar, group)),
+    );
+
+    groups
+}
+
+/// Files that exist more than once among the models the viewer may see.
+pub async fn handle_list_duplicates(
+    State(state): State<AppState>,
+    viewer: Viewer,
+) -> Result<Json<DuplicateReport>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let models: HashMap<i32, Model3D> = filter_models(&ListModelParams::default(), &viewer)?
+        .load::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load models: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .map(|model| (model.id, model))
+        .collect();
+
+    let files = files3d::dsl::files3d
+        .filter(files3d::dsl::model_id.eq_any(models.keys().copied().collect::<Vec<_>>()))
+        .order(files3d::dsl::id)
+        .load::<File3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load files: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let groups = group_duplicates(&files)
+        .into_iter()
+        .map(|(kind, indices)| DuplicateGroup {
+            kind,
+            files: indices
+                .into_iter()
+                .map(|index| {
+                    let file = &files[index];
+                    let model = &models[&file.model_id];
+                    DuplicateFile {
+                        id: file.id,
+                        file_path: file.file_path.clone(),
+                        model_slug: model.slug.clone(),
+                        model_name: model.name.clone(),
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(Json(DuplicateReport { groups }))
+}
@@ -0,0 +1,133 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDateTime;
+use diesel::dsl::{count_star, max};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::access::Viewer;
+use crate::schema::{files3d, models3d};
+use crate::types::Model3D;
+
+/// A cheap token changing whenever a model is added, changed or removed,
+/// or a file or preview shows up.
+#[derive(Debug, Hash)]
+pub struct LibraryVersion {
+    models: i64,
+    last_update: Option<NaiveDateTime>,
+    files: i64,
+    last_file: Option<i32>,
+    previews: i64,
+}
+
+pub async fn library_version<Conn>(connection: &mut Conn) -> QueryResult<LibraryVersion>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let (models, last_update) = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
+        .select((count_star(), max(models3d::dsl::updated_at)))
+        .first::<(i64, Option<NaiveDateTime>)>(connection)
+        .await?;
+    let (files, last_file) = files3d::dsl::files3d
+        .select((count_star(), max(files3d::dsl::id)))
+        .first::<(i64, Option<i32>)>(connection)
+        .await?;
+    let previews = files3d::dsl::files3d
+        .filter(files3d::dsl::preview_image.is_not_null())
+        .count()
+        .get_result::<i64>(connection)
+        .await?;
+
+    Ok(LibraryVersion {
+        models,
+        last_update,
+        files,
+        last_file,
+        previews,
+    })
+}
+
+/// The token of a single model, its files are covered by their count and
+/// previews.
+pub async fn model_version<Conn>(connection: &mut Conn, model: &Model3D) -> QueryResult<impl Hash>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let (files, last_file) = files3d::dsl::files3d
+        .filter(files3d::dsl::model_id.eq(model.id))
+        .select((count_star(), max(files3d::dsl::id)))
+        .first::<(i64, Option<i32>)>(connection)
+        .await?;
+    let previews = files3d::dsl::files3d
+        .filter(files3d::dsl::model_id.eq(model.id))
+        .filter(files3d::dsl::preview_image.is_not_null())
+        .count()
+        .get_result::<i64>(connection)
+        .await?;
+
+    Ok((
+        model.id,
+        model.updated_at,
+        model.visibility.clone(),
+        files,
+        last_file,
+        previews,
+    ))
+}
+
+/// Builds a weak ETag from a version token. Responses filtered by the viewer
+/// include who is asking, since two users may see different models.
+pub fn make_etag(version: impl Hash, viewer: Option<&Viewer>, query: Option<&str>) -> String {
+    let mut hasher = DefaultHasher::new();
+    version.hash(&mut hasher);
+    if let Some(viewer) = viewer {
+        viewer.user.as_ref().map(|user| user.id).hash(&mut hasher);
+        viewer.groups.hash(&mut hasher);
+        viewer.is_admin.hash(&mut hasher);
+        viewer.scope.tenant_id().hash(&mut hasher);
+    }
+    query.hash(&mut hasher);
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// Whether `If-None-Match` already names the current ETag, compared weakly.
+pub fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+    let strip = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || strip(tag) == strip(etag))
+}
+
+/// Answers with 304 if the client already has `etag`, otherwise builds the
+/// response and tags it. `no-cache` makes browsers revalidate every time.
+pub async fn respond_with_etag<F, R>(
+    headers: &HeaderMap,
+    etag: String,
+    build: F,
+) -> Result<Response, StatusCode>
+where
+    F: std::future::Future<Output = Result<R, StatusCode>>,
+    R: IntoResponse,
+{
+    let mut response = if is_fresh(headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        build.await?.into_response()
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    Ok(response)
+}
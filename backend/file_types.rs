@@ -0,0 +1,77 @@
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::cad;
+use crate::documents::DOCUMENT_FILE_TYPES;
+use crate::upload::IMAGE_FILE_FORMATS;
+
+/// Extensions which are treated as the same kind of file when filtering.
+const ALIASES: &[(&str, &[&str])] = &[
+    ("step", &["step", "stp"]),
+    ("iges", &["iges", "igs"]),
+    ("gcode", &["gcode", "bgcode", "gco"]),
+    ("f3d", &["f3d", "f3z"]),
+];
+
+/// The lower case extension stored in `files3d.file_type`.
+pub fn file_type_of(pth: &Path) -> String {
+    pth.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Parses a `has` query like `3mf,step` into one list of accepted
+/// extensions per requested file type.
+pub fn parse_has(has: &str) -> Vec<Vec<String>> {
+    has.split(',')
+        .map(|file_type| file_type.trim().trim_start_matches('.').to_lowercase())
+        .filter(|file_type| !file_type.is_empty())
+        .map(|file_type| {
+            ALIASES
+                .iter()
+                .find(|(_, extensions)| extensions.contains(&file_type.as_str()))
+                .map(|(_, extensions)| extensions.iter().map(|ext| ext.to_string()).collect())
+                .unwrap_or_else(|| vec![file_type])
+        })
+        .collect()
+}
+
+/// Files which go straight to a slicer or a printer.
+const PRINTABLE_FILE_TYPES: &[&str] = &["stl", "3mf", "obj", "gcode", "bgcode", "gco"];
+
+/// The part of a model a zip download is limited to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadKind {
+    /// meshes and sliced files
+    Printable,
+    Images,
+    /// CAD and OpenSCAD sources
+    Source,
+    /// PDFs, instructions and spreadsheets
+    Documentation,
+    #[default]
+    All,
+}
+
+impl DownloadKind {
+    pub fn includes(self, file_type: &str) -> bool {
+        match self {
+            DownloadKind::Printable => PRINTABLE_FILE_TYPES.contains(&file_type),
+            DownloadKind::Images => IMAGE_FILE_FORMATS.contains(&file_type),
+            DownloadKind::Source => cad::is_cad_file_type(file_type),
+            DownloadKind::Documentation => DOCUMENT_FILE_TYPES.contains(&file_type),
+            DownloadKind::All => true,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DownloadKind::Printable => "printable",
+            DownloadKind::Images => "images",
+            DownloadKind::Source => "source",
+            DownloadKind::Documentation => "documentation",
+            DownloadKind::All => "all",
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use axum::{
+    extract::Request,
+    handler::HandlerWithoutStateExt,
+    http::{header, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use std::path::{Path, PathBuf};
+use tower_http::services::ServeDir;
+use tracing::error;
+
+use crate::api_version::{CURRENT_PREFIX, LEGACY_PREFIX};
+use crate::client_api;
+use crate::Config;
+
+const DEFAULT_FRONTEND_DIR: &str = "dist";
+
+/// Prefixes of the paths answered by the backend itself.
+fn backend_prefixes(config: &Config) -> Vec<String> {
+    vec![
+        CURRENT_PREFIX.to_string(),
+        LEGACY_PREFIX.to_string(),
+        client_api::PREFIX.to_string(),
+        config.asset_prefix.clone(),
+        config.cache_prefix.clone(),
+    ]
+}
+
+/// Client side routes like `/model/foo` have no file of their own, they get
+/// `index.html` and the router of the frontend takes over. Missing files
+/// with an extension and unknown paths below `backend_prefixes` stay a 404,
+/// so a stale bundle or an API client fails loudly instead of being handed
+/// HTML.
+fn is_client_route(backend_prefixes: &[String], path: &str) -> bool {
+    let path = format!("/{}", path.trim_start_matches('/'));
+    let is_backend = backend_prefixes.iter().any(|prefix| {
+        path.strip_prefix(prefix.trim_end_matches('/'))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+    !is_backend && Path::new(&path).extension().is_none()
+}
+
+/// Vite names the files below `assets/` after their content.
+fn cache_control(path: &str) -> &'static str {
+    if path.starts_with("assets/") {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    }
+}
+
+async fn index_fallback(uri: Uri, index: PathBuf, backend_prefixes: Vec<String>) -> Response {
+    if !is_client_route(&backend_prefixes, uri.path()) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match tokio::fs::read(&index).await {
+        Ok(content) => (
+            [
+                (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            content,
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to read {}: {}", index.display(), e);
+            StatusCode::NOT_FOUND.into_response()
+        }
+    }
+}
+
+async fn cache_headers(request: Request, next: Next) -> Response {
+    let path = request.uri().path().trim_start_matches('/').to_string();
+    let mut response = next.run(request).await;
+    if response.status().is_success() && !response.headers().contains_key(header::CACHE_CONTROL) {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control(&path)),
+        );
+    }
+    response
+}
+
+/// Serves the built frontend from `frontend_dir`, `dist` by default. Builds
+/// with the `embed-frontend` feature serve the bundle compiled into the
+/// binary unless `frontend_dir` is set.
+pub fn router(config: &Config) -> Router {
+    let prefixes = backend_prefixes(config);
+    #[cfg(feature = "embed-frontend")]
+    if config.frontend_dir.is_none() {
+        return Router::new().fallback(move |uri: Uri| embedded::serve(uri, prefixes.clone()));
+    }
+
+    let frontend_dir = config
+        .frontend_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_FRONTEND_DIR));
+    let index = frontend_dir.join("index.html");
+    let fallback = move |uri: Uri| index_fallback(uri, index.clone(), prefixes.clone());
+    Router::new()
+        .fallback_service(ServeDir::new(&frontend_dir).fallback(fallback.into_service()))
+        .layer(middleware::from_fn(cache_headers))
+}
+
+/// The frontend compiled into the binary with the `embed-frontend`
+/// feature, for deployments of a single file.
+#[cfg(feature = "embed-frontend")]
+mod embedded {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/frontend_assets.rs"));
+
+    fn content_type(path: &str) -> &'static str {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("html") => "text/html; charset=utf-8",
+            Some("js") | Some("mjs") => "text/javascript",
+            Some("css") => "text/css",
+            Some("json") | Some("map") => "application/json",
+            Some("svg") => "image/svg+xml",
+            Some("png") => "image/png",
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("webp") => "image/webp",
+            Some("ico") => "image/x-icon",
+            Some("woff") => "font/woff",
+            Some("woff2") => "font/woff2",
+            Some("wasm") => "application/wasm",
+            Some("txt") => "text/plain; charset=utf-8",
+            _ => "application/octet-stream",
+        }
+    }
+
+    fn find(path: &str) -> Option<&'static [u8]> {
+        ASSETS
+            .iter()
+            .find(|(name, _)| *name == path)
+            .map(|(_, content)| *content)
+    }
+
+    fn respond(path: &str, content: &'static [u8]) -> Response {
+        (
+            [
+                (header::CONTENT_TYPE, content_type(path)),
+                (header::CACHE_CONTROL, cache_control(path)),
+            ],
+            content,
+        )
+            .into_response()
+    }
+
+    pub async fn serve(uri: Uri, backend_prefixes: Vec<String>) -> Response {
+        let path = uri.path().trim_start_matches('/');
+        let path = if path.is_empty() { "index.html" } else { path };
+        if let Some(content) = find(path) {
+            return respond(path, content);
+        }
+        match find("index.html") {
+            Some(content) if is_client_route(&backend_prefixes, path) => {
+                respond("index.html", content)
+            }
+            _ => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,85 @@
+//! Unknown paths of the frontend get `index.html`, unknown paths of the
+//! API stay a 404 for API clients.
+
+use reqwest::StatusCode;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use super::router;
+use crate::Config;
+
+/// A built frontend in a fresh temporary folder served on a free port,
+/// removed again when dropped.
+struct Frontend {
+    root: PathBuf,
+    url: String,
+}
+
+impl Frontend {
+    async fn serve() -> Frontend {
+        let root = std::env::temp_dir().join(format!("meshvault-frontend-{}", Uuid::new_v4()));
+        let dist = root.join("dist");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("index.html"), "<html></html>").unwrap();
+        let mut config: Config = envy::from_iter([
+            (
+                "LIBRARIES_PATH".to_string(),
+                root.join("libraries").display().to_string(),
+            ),
+            (
+                "DATA_DIR".to_string(),
+                root.join("data").display().to_string(),
+            ),
+            ("FRONTEND_DIR".to_string(), dist.display().to_string()),
+        ])
+        .unwrap();
+        config.initialize();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = router(&config);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Frontend { root, url }
+    }
+
+    async fn status(&self, path: &str) -> StatusCode {
+        reqwest::get(format!("{}{}", self.url, path))
+            .await
+            .unwrap()
+            .status()
+    }
+}
+
+impl Drop for Frontend {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[tokio::test]
+async fn client_routes_get_the_index() {
+    let frontend = Frontend::serve().await;
+    assert_eq!(frontend.status("/model/benchy").await, StatusCode::OK);
+    assert_eq!(frontend.status("/apiary").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn unknown_api_paths_are_not_found() {
+    let frontend = Frontend::serve().await;
+    for path in [
+        "/api",
+        "/api/v1/foo",
+        "/api/client/v1/foo",
+        "/3d/foo",
+        "/cache/foo",
+        "/assets/missing.js",
+    ] {
+        assert_eq!(
+            frontend.status(path).await,
+            StatusCode::NOT_FOUND,
+            "{}",
+            path
+        );
+    }
+}
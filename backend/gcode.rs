@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use typeshare::typeshare;
+
+/// Cura writes its summary before the first move, Bambu Studio a header
+/// block at the start.
+const HEAD_BYTES: u64 = 64 * 1024;
+/// PrusaSlicer and OrcaSlicer append their summary and config at the end.
+const TAIL_BYTES: u64 = 256 * 1024;
+
+/// Filament usage and print time the slicer noted in G-code comments.
+#[typeshare]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GcodeMetadata {
+    pub slicer: Option<String>,
+    pub print_time_seconds: Option<i32>,
+    pub filament_length_mm: Option<f64>,
+    pub filament_volume_cm3: Option<f64>,
+    pub filament_weight_g: Option<f64>,
+    pub filament_type: Option<String>,
+    pub filament_density: Option<f64>,
+    pub filament_diameter: Option<f64>,
+}
+
+impl GcodeMetadata {
+    /// The filament weight, calculated from the used volume or length if
+    /// the slicer did not note it. `density` in g/cm³ and `diameter` in mm
+    /// are used where the G-code does not tell.
+    pub fn filament_grams(&self, density: f64, diameter: f64) -> Option<f64> {
+        if let Some(weight) = self.filament_weight_g.filter(|weight| *weight > 0.0) {
+            return Some(weight);
+        }
+        let density = self
+            .filament_density
+            .filter(|d| *d > 0.0)
+            .unwrap_or(density);
+        if let Some(volume) = self.filament_volume_cm3.filter(|volume| *volume > 0.0) {
+            return Some(volume * density);
+        }
+        let diameter = self
+            .filament_diameter
+            .filter(|d| *d > 0.0)
+            .unwrap_or(diameter);
+        let length = self.filament_length_mm.filter(|length| *length > 0.0)?;
+        let area = std::f64::consts::PI * (diameter / 2.0).powi(2);
+        Some(area * length / 1000.0 * density)
+    }
+}
+
+/// Parses durations like `1d 2h 3m 4s`, `2h 05m` or a plain number of seconds.
+fn parse_duration(value: &str) -> Option<i32> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Some(seconds as i32);
+    }
+    let mut total = 0;
+    let mut found = false;
+    for part in value.split_whitespace() {
+        let unit_start = part.find(|c: char| !c.is_ascii_digit())?;
+        let (amount, unit) = part.split_at(unit_start);
+        let amount: i32 = amount.parse().ok()?;
+        total += amount
+            * match unit {
+                "d" => 86400,
+                "h" => 3600,
+                "m" => 60,
+                "s" => 1,
+                _ => return None,
+            };
+        found = true;
+    }
+    found.then_some(total)
+}
+
+/// Sums the per extruder values of multi material prints, `1.5, 0.3`.
+fn parse_sum(value: &str) -> Option<f64> {
+    value
+        .split(',')
+        .map(|part| part.trim().parse::<f64>().ok())
+        .sum::<Option<f64>>()
+}
+
+fn parse_first(value: &str) -> Option<f64> {
+    value.split(',').next()?.trim().parse().ok()
+}
+
+fn read_head_and_tail(file_pth: &Path) -> std::io::Result<String> {
+    let mut file = File::open(file_pth)?;
+    let length = file.metadata()?.len();
+
+    let mut head = Vec::new();
+    (&mut file).take(HEAD_BYTES).read_to_end(&mut head)?;
+    let mut text = String::from_utf8_lossy(&head).into_owned();
+    if length > HEAD_BYTES {
+        file.seek(SeekFrom::Start(
+            length.saturating_sub(TAIL_BYTES).max(HEAD_BYTES),
+        ))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        text.push('\n');
+        text.push_str(&String::from_utf8_lossy(&tail));
+    }
+    Ok(text)
+}
+
+fn slicer_of(text: &str) -> Option<String> {
+    text.lines().take(20).find_map(|line| {
+        let line = line.trim_start_matches(';').trim();
+        let lower = line.to_lowercase();
+        // `generated by PrusaSlicer 2.8.1 on 2024-11-02 at 10:12:55 UTC`
+        for prefix in ["generated by ", "generated with "] {
+            if lower.starts_with(prefix) {
+                let name = &line[prefix.len()..];
+                let name_end = name.find(" on ").unwrap_or(name.len());
+                return Some(name[..name_end].trim().to_string());
+            }
+        }
+        lower.starts_with("bambustudio").then(|| line.to_string())
+    })
+}
+
+/// Reads the slicer summary of a text G-code file. Binary G-code and files
+/// without any summary give `None`.
+pub fn read_metadata(file_pth: &Path) -> Option<GcodeMetadata> {
+    let text = read_head_and_tail(file_pth).ok()?;
+
+    // `; key = value` (PrusaSlicer), `; key: value` (Bambu) and `;KEY:value` (Cura)
+    let mut values: HashMap<String, String> = HashMap::new();
+    for line in text.lines() {
+        let Some(comment) = line.trim().strip_prefix(';') else {
+            continue;
+        };
+        let separator = match (comment.find(" = "), comment.find(':')) {
+            (Some(equals), _) => (equals, 3),
+            (None, Some(colon)) => (colon, 1),
+            (None, None) => continue,
+        };
+        let (key, value) = comment.split_at(separator.0);
+        let key = key.trim().to_lowercase();
+        if !key.is_empty() {
+            values
+                .entry(key)
+                .or_insert_with(|| value[separator.1..].trim().to_string());
+        }
+    }
+    let value = |keys: &[&str]| keys.iter().find_map(|key| values.get(*key));
+
+    let metadata = GcodeMetadata {
+        slicer: slicer_of(&text),
+        print_time_seconds: value(&[
+            "estimated printing time (normal mode)",
+            "total estimated time",
+            "model printing time",
+            "time",
+        ])
+        .and_then(|time| parse_duration(time.split(';').next().unwrap_or(time))),
+        filament_length_mm: value(&["filament used [mm]", "total filament length [mm]"])
+            .and_then(|length| parse_sum(length))
+            .or_else(|| {
+                // Cura notes metres, `1.2345m`
+                value(&["filament used"])
+                    .and_then(|length| parse_sum(&length.replace('m', "")))
+                    .map(|metres| metres * 1000.0)
+            }),
+        filament_volume_cm3: value(&["filament used [cm3]", "total filament volume [cm3]"])
+            .and_then(|volume| parse_sum(volume)),
+        filament_weight_g: value(&[
+            "total filament used [g]",
+            "filament used [g]",
+            "total filament weight [g]",
+        ])
+        .and_then(|weight| parse_sum(weight)),
+        filament_type: value(&["filament_type", "filament type"])
+            .and_then(|types| types.split([';', ',']).next())
+            .map(|material| material.trim().to_string())
+            .filter(|material| !material.is_empty()),
+        filament_density: value(&["filament_density", "filament density"])
+            .and_then(|density| parse_first(density)),
+        filament_diameter: value(&["filament_diameter", "filament diameter"])
+            .and_then(|diameter| parse_first(diameter)),
+    };
+
+    let has_usage = metadata.print_time_seconds.is_some()
+        || metadata.filament_length_mm.is_some()
+        || metadata.filament_volume_cm3.is_some()
+        || metadata.filament_weight_g.is_some();
+    has_usage.then_some(metadata)
+}
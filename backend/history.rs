@@ -0,0 +1,220 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::auth::User;
+use crate::model_filter::filter_models;
+use crate::policy::{Account, Authorized};
+use crate::schema::{models3d, users, view_history};
+use crate::types::{ListModelParams, Model3D, ModelResponse};
+use crate::AppState;
+
+/// Views kept per user, older ones are dropped.
+const MAX_ENTRIES: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    pub limit: Option<i64>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub viewed_at: NaiveDateTime,
+    pub model: ModelResponse,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryResponse {
+    /// whether views are recorded, the history stays empty otherwise
+    pub enabled: bool,
+    /// most recently viewed first
+    pub entries: Vec<HistoryEntry>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistorySettings {
+    pub enabled: bool,
+}
+
+async fn record_view<Conn>(connection: &mut Conn, user: &User, model_id: i32) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let now = Utc::now().naive_utc();
+    diesel::insert_into(view_history::table)
+        .values((
+            view_history::dsl::user_id.eq(user.id),
+            view_history::dsl::model_id.eq(model_id),
+            view_history::dsl::viewed_at.eq(now),
+        ))
+        .on_conflict((view_history::dsl::user_id, view_history::dsl::model_id))
+        .do_update()
+        .set(view_history::dsl::viewed_at.eq(now))
+        .execute(connection)
+        .await?;
+
+    let oldest_kept = view_history::dsl::view_history
+        .filter(view_history::dsl::user_id.eq(user.id))
+        .order(view_history::dsl::viewed_at.desc())
+        .select(view_history::dsl::viewed_at)
+        .offset(MAX_ENTRIES - 1)
+        .first::<NaiveDateTime>(connection)
+        .await
+        .optional()?;
+    if let Some(oldest_kept) = oldest_kept {
+        diesel::delete(
+            view_history::dsl::view_history
+                .filter(view_history::dsl::user_id.eq(user.id))
+                .filter(view_history::dsl::viewed_at.lt(oldest_kept)),
+        )
+        .execute(connection)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Adds a model to the history of the viewer, unless they are anonymous or
+/// opted out. The history must never break the request it is recorded for.
+pub async fn record_view_logged<Conn>(connection: &mut Conn, viewer: &Viewer, model_id: i32)
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let Some(user) = viewer.user.as_ref().filter(|user| user.record_history) else {
+        return;
+    };
+    if let Err(e) = record_view(connection, user, model_id).await {
+        error!(
+            "Failed to record the view of {} by {}: {}",
+            model_id, user.name, e
+        );
+    }
+}
+
+pub async fn delete_history<Conn>(connection: &mut Conn, model_id: i32) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::delete(view_history::dsl::view_history.filter(view_history::dsl::model_id.eq(model_id)))
+        .execute(connection)
+        .await
+}
+
+async fn clear_history<Conn>(connection: &mut Conn, user: &User) -> Result<(), StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::delete(view_history::dsl::view_history.filter(view_history::dsl::user_id.eq(user.id)))
+        .execute(connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to clear the history of {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(())
+}
+
+/// The models the viewer looked at last which they may still see, for a
+/// "continue where you left off" row.
+pub async fn handle_history(
+    State(state): State<AppState>,
+    Authorized(viewer, Account(user)): Authorized<Account>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<HistoryResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let limit = params.limit.unwrap_or(20).clamp(1, MAX_ENTRIES);
+
+    let views: Vec<(i32, NaiveDateTime)> = view_history::dsl::view_history
+        .filter(view_history::dsl::user_id.eq(user.id))
+        .order(view_history::dsl::viewed_at.desc())
+        .select((view_history::dsl::model_id, view_history::dsl::viewed_at))
+        .load(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load the history of {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let model_ids: Vec<i32> = views.iter().map(|(model_id, _)| *model_id).collect();
+    let mut models: HashMap<i32, Model3D> = filter_models(&ListModelParams::default(), &viewer)?
+        .filter(models3d::dsl::id.eq_any(model_ids))
+        .load::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load the history of {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .map(|model| (model.id, model))
+        .collect();
+
+    let mut entries = Vec::new();
+    for (model_id, viewed_at) in views {
+        if entries.len() as i64 >= limit {
+            break;
+        }
+        let Some(model) = models.remove(&model_id) else {
+            continue;
+        };
+        let model = ModelResponse::from_model_3d(&model, &state.config, &mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to build model response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        entries.push(HistoryEntry { viewed_at, model });
+    }
+
+    Ok(Json(HistoryResponse {
+        enabled: user.record_history,
+        entries,
+    }))
+}
+
+pub async fn handle_clear_history(
+    State(state): State<AppState>,
+    Authorized(_, Account(user)): Authorized<Account>,
+) -> Result<StatusCode, StatusCode> {
+    let mut connection = state.connection().await?;
+    clear_history(&mut connection, &user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Turns recording of views on or off. Turning it off also forgets the
+/// views recorded so far.
+pub async fn handle_update_history_settings(
+    State(state): State<AppState>,
+    Authorized(_, Account(user)): Authorized<Account>,
+    Json(payload): Json<HistorySettings>,
+) -> Result<Json<HistorySettings>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    diesel::update(users::dsl::users.find(user.id))
+        .set(users::dsl::record_history.eq(payload.enabled))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to store the history setting of {}: {}",
+                user.name, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !payload.enabled {
+        clear_history(&mut connection, &user).await?;
+    }
+
+    Ok(Json(payload))
+}
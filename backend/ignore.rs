@@ -0,0 +1,221 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::Config;
+
+/// Per directory exclusion patterns, applying to the directory and below.
+pub const IGNORE_FILE: &str = ".modelvaultignore";
+
+/// Files of unfinished downloads. Folders holding one are skipped as a whole
+/// until the download completes, so half a model is never indexed.
+const PARTIAL_DOWNLOAD_SUFFIXES: &[&str] = &[".part", ".crdownload", ".!qb", ".!ut", ".aria2"];
+
+/// A gitignore style pattern.
+#[derive(Debug, Clone)]
+struct Pattern {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+    /// patterns with a slash match the path below their base directory,
+    /// others the name of a file or folder at any depth
+    anchored: bool,
+}
+
+/// Translates glob syntax into a regex matching a whole path.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    regex.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|c| *c == ']') {
+                Some(length) => {
+                    let class: String = chars[i + 1..i + 1 + length].iter().collect();
+                    let class = match class.strip_prefix('!') {
+                        Some(class) => format!("^{}", class),
+                        None => class,
+                    };
+                    regex.push('[');
+                    regex.push_str(&class.replace('\\', "\\\\"));
+                    regex.push(']');
+                    i += length + 2;
+                    continue;
+                }
+                None => regex.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+                continue;
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex.push('$');
+    regex
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Pattern> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+
+        match Regex::new(&glob_to_regex(line)) {
+            Ok(regex) => Some(Pattern {
+                regex,
+                negated,
+                dir_only,
+                anchored,
+            }),
+            Err(e) => {
+                warn!("Ignoring invalid ignore pattern {:?}: {}", line, e);
+                None
+            }
+        }
+    }
+
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.regex.is_match(relative)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            self.regex.is_match(name)
+        }
+    }
+}
+
+fn parse_patterns<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<Pattern> {
+    lines.into_iter().filter_map(Pattern::parse).collect()
+}
+
+/// Patterns of `scanner_ignore` plus those of every `.modelvaultignore`
+/// between the library root and a path. Like gitignore the last matching
+/// pattern decides, so `!pattern` re-includes what an earlier one excluded.
+pub struct IgnoreRules {
+    root: PathBuf,
+    global: Vec<Pattern>,
+    /// patterns of the ignore file of each directory seen so far
+    directories: HashMap<PathBuf, Vec<Pattern>>,
+}
+
+impl IgnoreRules {
+    pub fn new(config: &Config) -> IgnoreRules {
+        IgnoreRules {
+            root: config.libraries_path.clone(),
+            global: parse_patterns(config.scanner_ignore.iter().map(String::as_str)),
+            directories: HashMap::new(),
+        }
+    }
+
+    fn directory_patterns(&mut self, dir: &Path) -> &[Pattern] {
+        self.directories
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| match std::fs::read_to_string(dir.join(IGNORE_FILE)) {
+                Ok(content) => parse_patterns(content.lines()),
+                Err(_) => Vec::new(),
+            })
+    }
+
+    /// Whether `path` is excluded. Excluded directories hide everything below
+    /// them, which callers get by not descending into them.
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if relative.as_os_str().is_empty() {
+            return false;
+        }
+        let relative_str = relative.to_string_lossy().replace('\\', "/");
+        let mut ignored = self
+            .global
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(&relative_str, is_dir))
+            .map(|pattern| !pattern.negated);
+
+        // deeper ignore files take precedence over shallower ones
+        let mut dir = self.root.clone();
+        let mut components: Vec<_> = relative.components().collect();
+        components.pop();
+        let mut bases = vec![dir.clone()];
+        for component in components {
+            dir.push(component);
+            bases.push(dir.clone());
+        }
+        for base in bases {
+            let below = path
+                .strip_prefix(&base)
+                .map(|below| below.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_default();
+            if let Some(pattern) = self
+                .directory_patterns(&base)
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(&below, is_dir))
+            {
+                ignored = Some(!pattern.negated);
+            }
+        }
+
+        let ignored = ignored.unwrap_or(false);
+        if ignored {
+            debug!("Ignoring {}", path.display());
+        }
+        ignored
+    }
+}
+
+/// Whether a folder is or holds files of an unfinished download.
+pub fn has_partial_download(dir: &Path) -> bool {
+    let is_partial = |name: &str| {
+        PARTIAL_DOWNLOAD_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+    };
+    if dir
+        .file_name()
+        .is_some_and(|name| is_partial(&name.to_string_lossy().to_lowercase()))
+    {
+        return true;
+    }
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .any(|entry| is_partial(&entry.file_name().to_string_lossy().to_lowercase()))
+        })
+        .unwrap_or(false)
+}
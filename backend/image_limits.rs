@@ -0,0 +1,123 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader, ImageResult, Limits};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::workers;
+use crate::Config;
+
+/// Limits for decoding images of the library, so a single huge photo cannot
+/// exhaust the memory while thumbnails are made.
+pub fn limits(config: &Config) -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.image_max_dimension);
+    limits.max_image_height = Some(config.image_max_dimension);
+    limits.max_alloc = Some(config.image_max_memory_mb * 1024 * 1024);
+    limits
+}
+
+/// Decodes the image at `path` within `limits`.
+pub fn open(path: &Path, limits: Limits) -> ImageResult<DynamicImage> {
+    let mut reader = ImageReader::open(path)?.with_guessed_format()?;
+    reader.limits(limits);
+    reader.decode()
+}
+
+/// Width, height and decoded size of an image, read from its header only.
+fn measure(path: &Path) -> ImageResult<(u32, u32, u64)> {
+    let mut reader = ImageReader::open(path)?.with_guessed_format()?;
+    reader.no_limits();
+    let decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    Ok((width, height, decoder.total_bytes()))
+}
+
+/// Why the image at `path` would not be decoded, `None` if it fits.
+pub fn exceeded_limit(config: &Config, path: &Path) -> Option<String> {
+    let (width, height, bytes) = match measure(path) {
+        Ok(measured) => measured,
+        Err(e) => return Some(format!("unreadable: {}", e)),
+    };
+    if width.max(height) > config.image_max_dimension {
+        return Some(format!(
+            "{}x{} exceeds {} pixels",
+            width, height, config.image_max_dimension
+        ));
+    }
+    if bytes > config.image_max_memory_mb * 1024 * 1024 {
+        return Some(format!(
+            "needs {} MB, more than {} MB",
+            bytes / 1024 / 1024,
+            config.image_max_memory_mb
+        ));
+    }
+    None
+}
+
+/// Scales an original down to `image_ingest_max_size` in place, keeping
+/// its format. Returns whether the image was changed.
+fn downscale(config: &Config, path: &Path) -> anyhow::Result<bool> {
+    let Some(max_size) = config.image_ingest_max_size else {
+        return Ok(false);
+    };
+    let (width, height, _) = measure(path)?;
+    if width.max(height) <= max_size {
+        return Ok(false);
+    }
+
+    let image = open(path, limits(config))?.resize(max_size, max_size, FilterType::Lanczos3);
+    let format = image::ImageFormat::from_path(path)?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // the scanner skips `.part` files until the rename
+    let partial = path.with_file_name(format!(".{}.{}.part", name, Uuid::new_v4()));
+    let written = image
+        .save_with_format(&partial, format)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| std::fs::rename(&partial, path).map_err(Into::into));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    written?;
+    info!(
+        "Scaled {} down from {}x{} to {}x{}",
+        path.display(),
+        width,
+        height,
+        image.width(),
+        image.height()
+    );
+    Ok(true)
+}
+
+/// The images of a model folder that may be shown and thumbnailed. Larger
+/// originals are scaled down first if configured, images beyond the limits
+/// are left out with a warning instead of failing the model.
+pub async fn admit_images(config: &Config, base_dir: &Path, images: Vec<PathBuf>) -> Vec<PathBuf> {
+    let config = config.clone();
+    let base_dir = base_dir.to_path_buf();
+    let checked = images.clone();
+    workers::run(move || {
+        checked
+            .into_iter()
+            .filter(|relative| {
+                let path = base_dir.join(relative);
+                if let Err(e) = downscale(&config, &path) {
+                    warn!("Unable to scale down {}: {}", path.display(), e);
+                }
+                match exceeded_limit(&config, &path) {
+                    Some(reason) => {
+                        warn!("Skipping image {}: {}", path.display(), reason);
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or(images)
+}
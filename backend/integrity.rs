@@ -0,0 +1,208 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::jobs::check_cancelled;
+use crate::policy::{Admin, Authorized};
+use crate::schema::{files3d, models3d};
+use crate::types::Model3D;
+use crate::workers;
+use crate::{AppState, Config};
+
+/// the file is gone from the model folder
+const FILE_MISSING: &str = "missing";
+/// the content changed since it was indexed
+const FILE_MISMATCH: &str = "mismatch";
+const FILE_UNREADABLE: &str = "unreadable";
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileProblem {
+    pub file_id: i32,
+    pub path: String,
+    /// `missing`, `mismatch` or `unreadable`
+    pub problem: String,
+    pub expected_sha256: Option<String>,
+    pub actual_sha256: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelVerification {
+    pub slug: String,
+    pub title: String,
+    pub checked_files: i32,
+    /// files indexed without a hash, which got one now
+    pub hashed_files: i32,
+    pub problems: Vec<FileProblem>,
+    pub verified_at: NaiveDateTime,
+}
+
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LibraryVerification {
+    pub checked_models: i32,
+    pub checked_files: i32,
+    /// only the models with at least one problem
+    pub models: Vec<ModelVerification>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerificationStarted {
+    pub job_id: String,
+}
+
+/// Hashes every file of a model again and compares it with the hash taken
+/// when it was indexed. Nothing is changed on disk or in the index, so a
+/// damaged file can still be restored from a backup.
+pub async fn verify_model<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> anyhow::Result<ModelVerification>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let base_path = model.absolute_path(config);
+    let mut verification = ModelVerification {
+        slug: model.slug.clone(),
+        title: model.title.clone(),
+        checked_files: 0,
+        hashed_files: 0,
+        problems: Vec::new(),
+        verified_at: Utc::now().naive_utc(),
+    };
+
+    for file in model.get_files3d(connection).await? {
+        verification.checked_files += 1;
+        let path = base_path.join(&file.file_path);
+        let problem = |problem: &str, actual_sha256: Option<String>| FileProblem {
+            file_id: file.id,
+            path: file.file_path.clone(),
+            problem: problem.to_string(),
+            expected_sha256: file.file_hash.clone(),
+            actual_sha256,
+        };
+
+        if !path.is_file() {
+            verification.problems.push(problem(FILE_MISSING, None));
+            continue;
+        }
+        let actual = match workers::digest(&path).await {
+            Ok(actual) => actual,
+            Err(e) => {
+                warn!("Unable to hash {}: {}", path.display(), e);
+                verification.problems.push(problem(FILE_UNREADABLE, None));
+                continue;
+            }
+        };
+        match &file.file_hash {
+            Some(expected) if *expected == actual => {}
+            Some(_) => verification
+                .problems
+                .push(problem(FILE_MISMATCH, Some(actual))),
+            None => {
+                diesel::update(files3d::dsl::files3d.find(file.id))
+                    .set(files3d::dsl::file_hash.eq(actual))
+                    .execute(connection)
+                    .await?;
+                verification.hashed_files += 1;
+            }
+        }
+    }
+
+    Ok(verification)
+}
+
+/// Verifies every model in the library, one model at a time so the
+/// connection is not held while large files are read.
+pub async fn verify_library(
+    state: &AppState,
+    cancel: &CancellationToken,
+) -> anyhow::Result<LibraryVerification> {
+    let models = {
+        let mut connection = state.pool.get().await?;
+        models3d::dsl::models3d
+            .filter(models3d::dsl::deleted_at.is_null())
+            .order(models3d::dsl::sort_key.asc())
+            .select(Model3D::as_select())
+            .load::<Model3D>(&mut connection)
+            .await?
+    };
+
+    let mut report = LibraryVerification::default();
+    for model in models {
+        check_cancelled(cancel)?;
+        let mut connection = state.pool.get().await?;
+        let verification = verify_model(&state.config, &mut connection, &model).await?;
+        drop(connection);
+
+        report.checked_models += 1;
+        report.checked_files += verification.checked_files;
+        if !verification.problems.is_empty() {
+            warn!(
+                "{} has {} damaged or missing files",
+                model.folder_path,
+                verification.problems.len()
+            );
+            report.models.push(verification);
+        }
+    }
+
+    info!(
+        "Verified {} files of {} models, {} models with problems",
+        report.checked_files,
+        report.checked_models,
+        report.models.len()
+    );
+    Ok(report)
+}
+
+/// Re-hashes the files of one model and reports what no longer matches.
+pub async fn handle_verify_model(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+) -> Result<Json<ModelVerification>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let model = viewer.find_model(&mut connection, &slug).await?;
+    let verification = verify_model(&state.config, &mut connection, &model)
+        .await
+        .map_err(|e| {
+            error!("Failed to verify {}: {}", slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(verification))
+}
+
+/// Starts verifying the whole library, admins only. The report becomes the
+/// result of the returned job.
+pub async fn handle_verify_library(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Json<VerificationStarted>, StatusCode> {
+    let (job_id, cancel) = state.jobs.start("verify");
+    tokio::spawn(async move {
+        let result = verify_library(&state, &cancel).await;
+        match &result {
+            Ok(report) => state.jobs.set_result(job_id, report),
+            Err(e) => error!("Verifying the library failed: {}", e),
+        }
+        state.jobs.complete(job_id, &result);
+    });
+
+    Ok(Json(VerificationStarted {
+        job_id: job_id.to_string(),
+    }))
+}
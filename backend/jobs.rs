@@ -0,0 +1,194 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+use typeshare::typeshare;
+use uuid::Uuid;
+
+use crate::policy::{Admin, Authorized};
+use crate::AppState;
+
+/// Number of finished jobs kept around for inspection.
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// Returned by long running operations when their job got cancelled.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "job was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+pub fn check_cancelled(token: &CancellationToken) -> anyhow::Result<()> {
+    if token.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    Ok(())
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: String,
+    pub status: JobStatus,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub message: Option<String>,
+    pub result: Option<Value>,
+}
+
+struct JobEntry {
+    info: JobInfo,
+    token: CancellationToken,
+}
+
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    jobs: Arc<Mutex<HashMap<Uuid, JobEntry>>>,
+}
+
+impl JobRegistry {
+    pub fn start(&self, kind: &str) -> (Uuid, CancellationToken) {
+        let id = Uuid::new_v4();
+        let token = CancellationToken::new();
+        let mut jobs = self.jobs.lock().unwrap();
+
+        let mut finished: Vec<(Uuid, NaiveDateTime)> = jobs
+            .iter()
+            .filter(|(_, job)| job.info.status != JobStatus::Running)
+            .map(|(id, job)| (*id, job.info.started_at))
+            .collect();
+        if finished.len() >= MAX_FINISHED_JOBS {
+            finished.sort_by_key(|(_, started_at)| *started_at);
+            for (old_id, _) in &finished[..=finished.len() - MAX_FINISHED_JOBS] {
+                jobs.remove(old_id);
+            }
+        }
+
+        jobs.insert(
+            id,
+            JobEntry {
+                info: JobInfo {
+                    id: id.to_string(),
+                    kind: kind.to_string(),
+                    status: JobStatus::Running,
+                    started_at: chrono::Utc::now().naive_utc(),
+                    finished_at: None,
+                    message: None,
+                    result: None,
+                },
+                token: token.clone(),
+            },
+        );
+        debug!("Started {} job {}", kind, id);
+
+        (id, token)
+    }
+
+    pub fn complete<T>(&self, id: Uuid, result: &anyhow::Result<T>) {
+        let (status, message) = match result {
+            Ok(_) => (JobStatus::Finished, None),
+            Err(e) if e.is::<Cancelled>() => (JobStatus::Cancelled, None),
+            Err(e) => (JobStatus::Failed, Some(e.to_string())),
+        };
+        self.finish(id, status, message);
+    }
+
+    /// Stores the outcome of a job, e.g. a refresh report, for later retrieval.
+    pub fn set_result<T: Serialize>(&self, id: Uuid, result: &T) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.info.result = serde_json::to_value(result).ok();
+        }
+    }
+
+    pub fn finish(&self, id: Uuid, status: JobStatus, message: Option<String>) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.info.status = status;
+            job.info.finished_at = Some(chrono::Utc::now().naive_utc());
+            job.info.message = message;
+        }
+    }
+
+    /// Requests cancellation, returns `None` for unknown jobs and `Some(false)`
+    /// if the job is not running anymore.
+    pub fn cancel(&self, id: Uuid) -> Option<bool> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(&id)?;
+        if job.info.status != JobStatus::Running {
+            return Some(false);
+        }
+        job.token.cancel();
+        info!("Cancellation of {} job {} requested", job.info.kind, id);
+        Some(true)
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|job| job.info.clone())
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.info.clone())
+            .collect();
+        jobs.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        jobs
+    }
+}
+
+pub async fn handle_list_jobs(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Json<Vec<JobInfo>> {
+    Json(state.jobs.list())
+}
+
+pub async fn handle_get_job(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<JobInfo>, StatusCode> {
+    state.jobs.get(id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn handle_cancel_job(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Path(id): Path<Uuid>,
+) -> StatusCode {
+    match state.jobs.cancel(id) {
+        Some(true) => StatusCode::ACCEPTED,
+        Some(false) => StatusCode::CONFLICT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
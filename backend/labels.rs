@@ -0,0 +1,325 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+use std::io::{Cursor, Write};
+use tracing::error;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::access::Viewer;
+use crate::chat_notifiers::thumbnail_jpeg;
+use crate::image_limits;
+use crate::model_filter::filter_models;
+use crate::notifications::model_url;
+use crate::types::{ListModelParams, Model3D};
+use crate::workers;
+use crate::{AppState, Config};
+
+/// Resolution of PNG labels, thermal label printers print at 300 dpi.
+const PNG_DPI: f32 = 300.0;
+/// resvg measures millimetres at 96 dpi.
+const SVG_DPI: f32 = 96.0;
+/// Margin around the label and between its parts, in mm.
+const MARGIN: f32 = 2.0;
+const THUMBNAIL_PIXELS: u32 = 400;
+/// Labels generated by one batch request.
+const MAX_BATCH: i64 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LabelFormat {
+    Png,
+    Svg,
+}
+
+/// Label size in mm, named sizes match common label printer rolls.
+fn parse_size(size: Option<&str>) -> Option<(f32, f32)> {
+    match size.unwrap_or("medium").trim() {
+        "small" => Some((38.0, 19.0)),
+        "medium" => Some((62.0, 29.0)),
+        "large" => Some((102.0, 51.0)),
+        custom => {
+            let (width, height) = custom.split_once('x')?;
+            let size: (f32, f32) = (width.trim().parse().ok()?, height.trim().parse().ok()?);
+            let valid = |mm: f32| (10.0..=300.0).contains(&mm);
+            (valid(size.0) && valid(size.1) && size.0 >= size.1).then_some(size)
+        }
+    }
+}
+
+fn parse_format(format: Option<&str>) -> Option<LabelFormat> {
+    match format.unwrap_or("png") {
+        "png" => Some(LabelFormat::Png),
+        "svg" => Some(LabelFormat::Svg),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LabelParams {
+    /// `small`, `medium`, `large` or `<width>x<height>` in mm
+    pub size: Option<String>,
+    /// `png` or `svg`
+    pub format: Option<String>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Splits `text` into at most `max_lines` lines of `line_chars`, the last
+/// one ends with an ellipsis if the text does not fit.
+fn wrap(text: &str, line_chars: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= line_chars => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.chars().take(line_chars).collect()),
+        }
+    }
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            let mut shortened: String = last.chars().take(line_chars.saturating_sub(1)).collect();
+            shortened.push('…');
+            *last = shortened;
+        }
+    }
+    lines
+}
+
+/// The dark modules of a QR code as one SVG path, a module is one unit.
+fn qr_path(code: &QrCode) -> String {
+    let width = code.width();
+    let mut path = String::new();
+    for (index, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            path.push_str(&format!("M{},{}h1v1h-1z", index % width, index / width));
+        }
+    }
+    path
+}
+
+/// A label with the QR code linking to the model on the left, its name in
+/// the middle and the cover image on the right, if there is room for it.
+fn label_svg(
+    title: &str,
+    slug: &str,
+    url: &str,
+    thumbnail: Option<&[u8]>,
+    (width, height): (f32, f32),
+) -> anyhow::Result<String> {
+    let code = QrCode::with_error_correction_level(url, EcLevel::M)?;
+    let qr_size = height - 2.0 * MARGIN;
+    let qr_scale = qr_size / code.width() as f32;
+
+    let square = height - 2.0 * MARGIN;
+    let thumbnail = thumbnail.filter(|_| width >= 2.0 * square + 3.0 * MARGIN + 15.0);
+    let text_x = MARGIN + qr_size + MARGIN;
+    let text_end = match thumbnail {
+        Some(_) => width - MARGIN - square - MARGIN,
+        None => width - MARGIN,
+    };
+
+    let title_size = height / 6.5;
+    // average glyph widths are a little over half the font size
+    let line_chars = (((text_end - text_x) / (title_size * 0.55)) as usize).max(4);
+    let lines = wrap(title, line_chars, 3);
+    let slug_size = title_size * 0.6;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}mm\" height=\"{height}mm\" \
+         viewBox=\"0 0 {width} {height}\">\
+         <rect width=\"{width}\" height=\"{height}\" fill=\"#fff\"/>\
+         <path transform=\"translate({MARGIN} {MARGIN}) scale({qr_scale})\" d=\"{qr}\" fill=\"#000\"/>",
+        qr = qr_path(&code),
+    );
+    let block_height = lines.len() as f32 * title_size * 1.2 + slug_size * 1.4;
+    let mut y = (height - block_height) / 2.0 + title_size;
+    for line in &lines {
+        svg.push_str(&format!(
+            "<text x=\"{text_x}\" y=\"{y}\" font-family=\"sans-serif\" font-weight=\"bold\" \
+             font-size=\"{title_size}\">{}</text>",
+            escape(line)
+        ));
+        y += title_size * 1.2;
+    }
+    svg.push_str(&format!(
+        "<text x=\"{text_x}\" y=\"{}\" font-family=\"monospace\" font-size=\"{slug_size}\" \
+         fill=\"#444\">{}</text>",
+        y + slug_size * 0.4,
+        escape(slug)
+    ));
+    if let Some(thumbnail) = thumbnail {
+        svg.push_str(&format!(
+            "<image x=\"{}\" y=\"{MARGIN}\" width=\"{square}\" height=\"{square}\" \
+             preserveAspectRatio=\"xMidYMid meet\" href=\"data:image/jpeg;base64,{}\"/>",
+            width - MARGIN - square,
+            STANDARD.encode(thumbnail)
+        ));
+    }
+    svg.push_str("</svg>");
+    Ok(svg)
+}
+
+fn svg_to_png(svg: &str) -> anyhow::Result<Vec<u8>> {
+    let mut options = resvg::usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    let tree = resvg::usvg::Tree::from_str(svg, &options)?;
+
+    let scale = PNG_DPI / SVG_DPI;
+    let width = (tree.size().width() * scale).ceil() as u32;
+    let height = (tree.size().height() * scale).ceil() as u32;
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| anyhow::anyhow!("Cannot render a label of {}x{} px", width, height))?;
+    resvg::render(
+        &tree,
+        resvg::tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    Ok(pixmap.encode_png()?)
+}
+
+async fn render_label<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+    size: (f32, f32),
+    format: LabelFormat,
+) -> anyhow::Result<Vec<u8>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let cover = model.cover_image_path(config, connection).await?;
+    let url = model_url(config, &model.slug);
+    let (title, slug) = (model.title.clone(), model.slug.clone());
+    let limits = image_limits::limits(config);
+    workers::run(move || {
+        let thumbnail = cover.and_then(|path| thumbnail_jpeg(&path, THUMBNAIL_PIXELS, limits));
+        let svg = label_svg(&title, &slug, &url, thumbnail.as_deref(), size)?;
+        match format {
+            LabelFormat::Svg => Ok(svg.into_bytes()),
+            LabelFormat::Png => svg_to_png(&svg),
+        }
+    })
+    .await?
+}
+
+fn content_type(format: LabelFormat) -> &'static str {
+    match format {
+        LabelFormat::Png => "image/png",
+        LabelFormat::Svg => "image/svg+xml",
+    }
+}
+
+fn extension(format: LabelFormat) -> &'static str {
+    match format {
+        LabelFormat::Png => "png",
+        LabelFormat::Svg => "svg",
+    }
+}
+
+/// A printable label for the storage bin of a model, its QR code links to
+/// the model page.
+pub async fn handle_label(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+    Query(params): Query<LabelParams>,
+) -> Result<Response, StatusCode> {
+    let size = parse_size(params.size.as_deref()).ok_or(StatusCode::BAD_REQUEST)?;
+    let format = parse_format(params.format.as_deref()).ok_or(StatusCode::BAD_REQUEST)?;
+    let mut connection = state.connection().await?;
+
+    let model = viewer.find_model(&mut connection, &slug).await?;
+    let label = render_label(&state.config, &mut connection, &model, size, format)
+        .await
+        .map_err(|e| {
+            error!("Failed to render the label of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, content_type(format))
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("inline; filename=\"{}.{}\"", model.slug, extension(format)),
+        )
+        .body(Body::from(label))
+        .unwrap())
+}
+
+#[derive(Deserialize)]
+pub struct BatchLabelParams {
+    pub size: Option<String>,
+    pub format: Option<String>,
+    #[serde(flatten)]
+    pub filters: ListModelParams,
+}
+
+/// Labels of every model matching the list filters as a zip archive, for
+/// labelling a whole shelf at once.
+pub async fn handle_batch_labels(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(params): Query<BatchLabelParams>,
+) -> Result<Response, StatusCode> {
+    let size = parse_size(params.size.as_deref()).ok_or(StatusCode::BAD_REQUEST)?;
+    let format = parse_format(params.format.as_deref()).ok_or(StatusCode::BAD_REQUEST)?;
+    let mut connection = state.connection().await?;
+
+    let models = filter_models(&params.filters, &viewer)?
+        .limit(MAX_BATCH)
+        .load::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load models for labels: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    for model in &models {
+        let label = render_label(&state.config, &mut connection, model, size, format)
+            .await
+            .map_err(|e| {
+                error!("Failed to render the label of {}: {}", model.slug, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        archive
+            .start_file(
+                format!("{}.{}", model.slug, extension(format)),
+                SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored),
+            )
+            .and_then(|_| archive.write_all(&label).map_err(Into::into))
+            .map_err(|e| {
+                error!("Failed to add the label of {}: {}", model.slug, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    }
+    let archive = archive.finish().map_err(|e| {
+        error!("Failed to finish the label archive: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"labels.zip\"",
+        )
+        .body(Body::from(archive.into_inner()))
+        .unwrap())
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::{debug, error};
+
+use crate::documents;
+use crate::ignore::IGNORE_FILE;
+use crate::manifest::MANIFEST_FILE;
+use crate::parse_library::walk_dir;
+use crate::types::ModelPackV0_1;
+use crate::upload::IMAGE_FILE_FORMATS;
+use crate::Config;
+
+/// Files in a model folder which describe the model rather than being part of it.
+const METADATA_FILES: &[&str] = &["modelpack.json", "readme.md", MANIFEST_FILE];
+
+/// Whether a file at the top of a model folder describes the model.
+pub fn is_metadata_file(pth: &Path) -> bool {
+    let file_name = pth
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    METADATA_FILES.contains(&file_name.as_str())
+}
+
+/// Resolves a configured layout directory, `.` stands for the model folder itself.
+fn resolve(model_base_path: &Path, dir: &str) -> PathBuf {
+    if dir == "." || dir.is_empty() {
+        model_base_path.to_path_buf()
+    } else {
+        model_base_path.join(dir)
+    }
+}
+
+pub fn file_roots(config: &Config, model_base_path: &Path) -> Vec<PathBuf> {
+    config
+        .layout_files_dirs
+        .iter()
+        .map(|dir| resolve(model_base_path, dir))
+        .collect()
+}
+
+pub fn image_roots(config: &Config, model_base_path: &Path) -> Vec<PathBuf> {
+    config
+        .layout_images_dirs
+        .iter()
+        .map(|dir| resolve(model_base_path, dir))
+        .collect()
+}
+
+pub fn doc_roots(config: &Config, model_base_path: &Path) -> Vec<PathBuf> {
+    config
+        .layout_docs_dirs
+        .iter()
+        .map(|dir| resolve(model_base_path, dir))
+        .collect()
+}
+
+fn has_extension(pth: &Path, extensions: &[&str]) -> bool {
+    pth.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| extensions.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+pub fn is_image(pth: &Path) -> bool {
+    has_extension(pth, IMAGE_FILE_FORMATS)
+}
+
+/// In marker based layouts a folder is a model if it holds a modelpack.json
+/// next to at least one of the configured file directories.
+pub fn is_model_dir(config: &Config, dir: &Path) -> bool {
+    dir.join("modelpack.json").is_file() && file_roots(config, dir).iter().any(|root| root.is_dir())
+}
+
+/// Whether a file found below one of the file roots is a printable part of
+/// the model. Files living in image or doc directories are excluded, as are
+/// metadata and images when the file root is the model folder itself.
+fn is_model_file(config: &Config, model_base_path: &Path, pth: &Path) -> bool {
+    if pth.file_name().is_some_and(|name| name == IGNORE_FILE) {
+        return false;
+    }
+    let in_other_root = image_roots(config, model_base_path)
+        .iter()
+        .chain(doc_roots(config, model_base_path).iter())
+        .any(|root| root != model_base_path && pth.starts_with(root));
+    if in_other_root {
+        return false;
+    }
+
+    if pth.parent() == Some(model_base_path) && (is_metadata_file(pth) || is_image(pth)) {
+        return false;
+    }
+
+    true
+}
+
+/// All printable files of a model according to the configured layout.
+pub fn model_files(config: &Config, model_base_path: &Path) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+
+    for root in file_roots(config, model_base_path) {
+        for entry in walk_dir(config, &root) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    error!("Skipping entry in {}: {}", root.display(), e);
+                    continue;
+                }
+            };
+            if entry.file_type().is_file() && is_model_file(config, model_base_path, entry.path()) {
+                files.insert(entry.path().to_path_buf());
+            }
+        }
+    }
+
+    files.into_iter().collect()
+}
+
+/// All files of the configured doc directories of a model.
+pub fn doc_files(config: &Config, model_base_path: &Path) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+
+    for root in doc_roots(config, model_base_path) {
+        if !root.is_dir() || root == model_base_path {
+            continue;
+        }
+        for entry in walk_dir(config, &root).filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                files.insert(entry.path().to_path_buf());
+            }
+        }
+    }
+
+    files.into_iter().collect()
+}
+
+/// Every file indexed for a model: its printable files and the documents
+/// of its doc directories.
+pub fn indexed_files(config: &Config, model_base_path: &Path) -> Vec<PathBuf> {
+    let mut files: BTreeSet<PathBuf> = model_files(config, model_base_path).into_iter().collect();
+    files.extend(
+        doc_files(config, model_base_path)
+            .into_iter()
+            .filter(|pth| documents::is_document(pth)),
+    );
+    files.into_iter().collect()
+}
+
+/// Metadata for model folders found by depth which come without a modelpack.json.
+pub fn fallback_meta(dir: &Path) -> ModelPackV0_1 {
+    let title = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    debug!("No modelpack.json in {}, using folder name", dir.display());
+
+    ModelPackV0_1 {
+        version: "0.1".to_string(),
+        title,
+        author: String::new(),
+        origin: String::new(),
+        license: String::new(),
+        tags: None,
+    }
+}
@@ -0,0 +1,306 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use tracing::{debug, error};
+use typeshare::typeshare;
+
+use crate::schema::{files3d, library_stats, model_tags, models3d, tenants};
+use crate::tenants::TenantScope;
+use crate::AppState;
+
+/// Number of snapshots returned as growth history.
+const GROWTH_POINTS: i64 = 90;
+
+#[typeshare]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StorageStats {
+    pub name: String,
+    pub models: i32,
+    pub files: i32,
+    pub bytes: f64,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrowthPoint {
+    pub date: NaiveDateTime,
+    pub models: i32,
+    pub files: i32,
+    pub bytes: f64,
+}
+
+#[typeshare]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LibraryStats {
+    pub computed_at: Option<NaiveDateTime>,
+    pub total_models: i32,
+    pub total_files: i32,
+    pub total_bytes: f64,
+    pub libraries: Vec<StorageStats>,
+    pub file_types: Vec<StorageStats>,
+    pub models_without_previews: i32,
+    pub models_without_tags: i32,
+    /// one point per refresh, oldest first
+    #[serde(default)]
+    pub growth: Vec<GrowthPoint>,
+}
+
+#[derive(Queryable)]
+struct LibraryStatsRow {
+    created_at: NaiveDateTime,
+    total_models: i32,
+    total_files: i32,
+    total_bytes: i64,
+    details: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = library_stats)]
+struct NewLibraryStatsRow {
+    created_at: NaiveDateTime,
+    total_models: i32,
+    total_files: i32,
+    total_bytes: i64,
+    details: String,
+    tenant_id: Option<i32>,
+}
+
+/// The library of a model is the first component of its folder path.
+pub fn library_of(folder_path: &str) -> String {
+    folder_path
+        .split(['/', '\\'])
+        .find(|component| !component.is_empty())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn add_file(stats: &mut StorageStats, bytes: i32) {
+    stats.files += 1;
+    stats.bytes += bytes as f64;
+}
+
+/// Statistics of the whole library or, given a tenant, of its models only.
+pub async fn compute_stats<Conn>(
+    connection: &mut Conn,
+    tenant_id: Option<i32>,
+) -> QueryResult<LibraryStats>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let mut models = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
+        .into_boxed();
+    let mut files = files3d::dsl::files3d
+        .inner_join(models3d::table)
+        .filter(models3d::dsl::deleted_at.is_null())
+        .into_boxed();
+    if let Some(tenant_id) = tenant_id {
+        models = models.filter(models3d::dsl::tenant_id.eq(tenant_id));
+        files = files.filter(models3d::dsl::tenant_id.eq(tenant_id));
+    }
+
+    let models = models
+        .select((
+            models3d::dsl::id,
+            models3d::dsl::folder_path,
+            models3d::dsl::images,
+        ))
+        .load::<(i32, String, String)>(connection)
+        .await?;
+
+    let files = files
+        .select((
+            files3d::dsl::model_id,
+            files3d::dsl::file_type,
+            files3d::dsl::file_size_bytes,
+            files3d::dsl::preview_image,
+        ))
+        .load::<(i32, String, i32, Option<String>)>(connection)
+        .await?;
+
+    let tagged: HashSet<i32> = model_tags::dsl::model_tags
+        .select(model_tags::dsl::model_id)
+        .distinct()
+        .load::<i32>(connection)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut stats = LibraryStats::default();
+    let mut libraries: BTreeMap<String, StorageStats> = BTreeMap::new();
+    let mut file_types: BTreeMap<String, StorageStats> = BTreeMap::new();
+    let mut library_by_model = BTreeMap::new();
+    let mut with_preview: HashSet<i32> = HashSet::new();
+
+    for (id, folder_path, images) in &models {
+        let library = library_of(folder_path);
+        libraries
+            .entry(library.clone())
+            .or_insert_with(|| StorageStats {
+                name: library.clone(),
+                ..Default::default()
+            })
+            .models += 1;
+        library_by_model.insert(*id, library);
+        if !images.trim().is_empty() {
+            with_preview.insert(*id);
+        }
+        if !tagged.contains(id) {
+            stats.models_without_tags += 1;
+        }
+    }
+
+    for (model_id, file_type, file_size_bytes, preview_image) in &files {
+        stats.total_files += 1;
+        stats.total_bytes += *file_size_bytes as f64;
+        if preview_image.is_some() {
+            with_preview.insert(*model_id);
+        }
+
+        if let Some(library) = library_by_model
+            .get(model_id)
+            .and_then(|library| libraries.get_mut(library))
+        {
+            add_file(library, *file_size_bytes);
+        }
+
+        let by_type = file_types
+            .entry(file_type.clone())
+            .or_insert_with(|| StorageStats {
+                name: file_type.clone(),
+                ..Default::default()
+            });
+        add_file(by_type, *file_size_bytes);
+    }
+
+    // count each model once per file type it contains
+    let model_types: HashSet<(i32, &String)> = files
+        .iter()
+        .map(|(model_id, file_type, _, _)| (*model_id, file_type))
+        .collect();
+    for (_, file_type) in model_types {
+        if let Some(by_type) = file_types.get_mut(file_type) {
+            by_type.models += 1;
+        }
+    }
+
+    stats.total_models = models.len() as i32;
+    stats.models_without_previews = models
+        .iter()
+        .filter(|(id, _, _)| !with_preview.contains(id))
+        .count() as i32;
+    stats.libraries = libraries.into_values().collect();
+    stats.file_types = file_types.into_values().collect();
+    stats.file_types.sort_by(|a, b| b.bytes.total_cmp(&a.bytes));
+    Ok(stats)
+}
+
+/// Computes the statistics and stores them as the newest snapshot. Called at
+/// the end of every library refresh so requests only read snapshots.
+pub async fn record_snapshot<Conn>(
+    connection: &mut Conn,
+    tenant_id: Option<i32>,
+) -> anyhow::Result<LibraryStats>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let mut stats = compute_stats(connection, tenant_id).await?;
+    let computed_at = Utc::now().naive_utc();
+    stats.computed_at = Some(computed_at);
+
+    diesel::insert_into(library_stats::table)
+        .values(&NewLibraryStatsRow {
+            created_at: computed_at,
+            total_models: stats.total_models,
+            total_files: stats.total_files,
+            total_bytes: stats.total_bytes as i64,
+            details: serde_json::to_string(&stats)?,
+            tenant_id,
+        })
+        .execute(connection)
+        .await?;
+    debug!("Recorded library statistics");
+
+    Ok(stats)
+}
+
+/// Snapshots of the whole library and of every tenant.
+pub async fn record_snapshots<Conn>(connection: &mut Conn) -> anyhow::Result<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    record_snapshot(connection, None).await?;
+    let tenant_ids = tenants::dsl::tenants
+        .select(tenants::dsl::id)
+        .load::<i32>(connection)
+        .await?;
+    for tenant_id in tenant_ids {
+        record_snapshot(connection, Some(tenant_id)).await?;
+    }
+    Ok(())
+}
+
+async fn latest_stats<Conn>(
+    connection: &mut Conn,
+    tenant_id: Option<i32>,
+) -> anyhow::Result<LibraryStats>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let mut rows = library_stats::dsl::library_stats.into_boxed();
+    rows = match tenant_id {
+        Some(tenant_id) => rows.filter(library_stats::dsl::tenant_id.eq(tenant_id)),
+        None => rows.filter(library_stats::dsl::tenant_id.is_null()),
+    };
+
+    let rows = rows
+        .order(library_stats::dsl::id.desc())
+        .limit(GROWTH_POINTS)
+        .select((
+            library_stats::dsl::created_at,
+            library_stats::dsl::total_models,
+            library_stats::dsl::total_files,
+            library_stats::dsl::total_bytes,
+            library_stats::dsl::details,
+        ))
+        .load::<LibraryStatsRow>(connection)
+        .await?;
+
+    let mut stats = match rows.as_slice() {
+        [latest, ..] => serde_json::from_str(&latest.details)?,
+        // no refresh finished yet
+        [] => record_snapshot(connection, tenant_id).await?,
+    };
+
+    stats.growth = rows
+        .iter()
+        .rev()
+        .map(|row| GrowthPoint {
+            date: row.created_at,
+            models: row.total_models,
+            files: row.total_files,
+            bytes: row.total_bytes as f64,
+        })
+        .collect();
+
+    Ok(stats)
+}
+
+pub async fn handle_library_stats(
+    State(state): State<AppState>,
+    scope: TenantScope,
+) -> Result<Json<LibraryStats>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let stats = latest_stats(&mut connection, scope.tenant_id())
+        .await
+        .map_err(|e| {
+            error!("Failed to load library statistics: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(stats))
+}
@@ -0,0 +1,267 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::jobs::check_cancelled;
+use crate::model_filter::filter_models;
+use crate::policy::{Admin, Authorized};
+use crate::schema::{link_checks, models3d};
+use crate::types::ListModelParams;
+use crate::AppState;
+
+const LINK_OK: &str = "ok";
+/// the site answered that the page is gone
+const LINK_DEAD: &str = "dead";
+/// no usable answer, which may well be temporary
+const LINK_UNREACHABLE: &str = "unreachable";
+
+/// Pause between two requests, so checking a large library does not look
+/// like an attack to the sites hosting the sources.
+const REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = link_checks, primary_key(model_id))]
+pub struct LinkCheck {
+    pub model_id: i32,
+    pub url: String,
+    pub status: String,
+    pub http_status: Option<i32>,
+    pub checked_at: NaiveDateTime,
+    /// first check of the current run of failures
+    pub failing_since: Option<NaiveDateTime>,
+}
+
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LinkCheckSummary {
+    pub checked: i32,
+    pub ok: i32,
+    pub dead: i32,
+    pub unreachable: i32,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkCheckStarted {
+    pub job_id: String,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BrokenLink {
+    pub slug: String,
+    pub title: String,
+    pub url: String,
+    /// `dead` or `unreachable`
+    pub status: String,
+    pub http_status: Option<i32>,
+    pub checked_at: NaiveDateTime,
+    pub failing_since: Option<NaiveDateTime>,
+}
+
+/// Only web pages are checked, other origins like local paths are kept as
+/// they are.
+fn is_web_link(origin: &str) -> bool {
+    url::Url::parse(origin.trim())
+        .map(|url| matches!(url.scheme(), "http" | "https"))
+        .unwrap_or(false)
+}
+
+async fn request(client: &Client, method: Method, url: &str) -> Option<u16> {
+    match client.request(method, url).send().await {
+        Ok(response) => Some(response.status().as_u16()),
+        Err(e) => {
+            debug!("Unable to reach {}: {}", url, e);
+            None
+        }
+    }
+}
+
+/// Asks for the headers first and falls back to a full request for sites
+/// which refuse `HEAD`.
+async fn check_link(client: &Client, url: &str) -> (&'static str, Option<i32>) {
+    let mut status = request(client, Method::HEAD, url).await;
+    if matches!(status, Some(403 | 405 | 501)) {
+        status = request(client, Method::GET, url).await;
+    }
+    let verdict = match status {
+        Some(200..=399) => LINK_OK,
+        Some(404 | 410) => LINK_DEAD,
+        _ => LINK_UNREACHABLE,
+    };
+    (verdict, status.map(i32::from))
+}
+
+/// Checks the source link of every model in the library and stores the
+/// outcome for the report.
+pub async fn check_links(
+    state: &AppState,
+    cancel: &CancellationToken,
+) -> anyhow::Result<LinkCheckSummary> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(state.config.link_check_timeout_seconds))
+        .user_agent(concat!("ModelVault/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+
+    let models = {
+        let mut connection = state.pool.get().await?;
+        models3d::dsl::models3d
+            .filter(models3d::dsl::deleted_at.is_null())
+            .filter(models3d::dsl::origin.is_not_null())
+            .select((models3d::dsl::id, models3d::dsl::origin))
+            .load::<(i32, Option<String>)>(&mut connection)
+            .await?
+    };
+
+    let mut summary = LinkCheckSummary::default();
+    for (model_id, origin) in models {
+        let Some(url) = origin.filter(|origin| is_web_link(origin)) else {
+            continue;
+        };
+        check_cancelled(cancel)?;
+
+        let (status, http_status) = check_link(&client, url.trim()).await;
+        summary.checked += 1;
+        match status {
+            LINK_OK => summary.ok += 1,
+            LINK_DEAD => summary.dead += 1,
+            _ => summary.unreachable += 1,
+        }
+
+        let mut connection = state.pool.get().await?;
+        let now = Utc::now().naive_utc();
+        let previous = link_checks::dsl::link_checks
+            .find(model_id)
+            .select(LinkCheck::as_select())
+            .first::<LinkCheck>(&mut connection)
+            .await
+            .optional()?;
+        let failing_since = match previous {
+            _ if status == LINK_OK => None,
+            Some(previous) if previous.url == url && previous.status != LINK_OK => {
+                previous.failing_since.or(Some(now))
+            }
+            _ => Some(now),
+        };
+        let check = LinkCheck {
+            model_id,
+            url,
+            status: status.to_string(),
+            http_status,
+            checked_at: now,
+            failing_since,
+        };
+        diesel::insert_into(link_checks::table)
+            .values(&check)
+            .on_conflict(link_checks::dsl::model_id)
+            .do_update()
+            .set(&check)
+            .execute(&mut connection)
+            .await?;
+        drop(connection);
+
+        tokio::time::sleep(REQUEST_INTERVAL).await;
+    }
+
+    info!(
+        "Checked {} source links, {} dead, {} unreachable",
+        summary.checked, summary.dead, summary.unreachable
+    );
+    Ok(summary)
+}
+
+/// Starts checking all source links, admins only. The summary becomes the
+/// result of the returned job.
+pub async fn handle_check_links(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Json<LinkCheckStarted>, StatusCode> {
+    let (job_id, cancel) = state.jobs.start("link_check");
+    tokio::spawn(async move {
+        let result = check_links(&state, &cancel).await;
+        match &result {
+            Ok(summary) => state.jobs.set_result(job_id, summary),
+            Err(e) => error!("Checking source links failed: {}", e),
+        }
+        state.jobs.complete(job_id, &result);
+    });
+
+    Ok(Json(LinkCheckStarted {
+        job_id: job_id.to_string(),
+    }))
+}
+
+/// Models whose source link failed the last check, longest failing first.
+/// Checks of links the model no longer points to are left out.
+pub async fn handle_link_report(
+    State(state): State<AppState>,
+    viewer: Viewer,
+) -> Result<Json<Vec<BrokenLink>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let visible: HashSet<i32> = filter_models(&ListModelParams::default(), &viewer)?
+        .select(models3d::dsl::id)
+        .load::<i32>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load visible models: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .collect();
+
+    let broken = link_checks::dsl::link_checks
+        .inner_join(models3d::table)
+        .filter(link_checks::dsl::status.ne(LINK_OK))
+        .filter(models3d::dsl::origin.eq(link_checks::dsl::url.nullable()))
+        .order((
+            link_checks::dsl::failing_since.asc(),
+            models3d::dsl::sort_key.asc(),
+        ))
+        .select((
+            models3d::dsl::id,
+            models3d::dsl::slug,
+            models3d::dsl::title,
+            LinkCheck::as_select(),
+        ))
+        .load::<(i32, String, String, LinkCheck)>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load link checks: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(
+        broken
+            .into_iter()
+            .filter(|(id, _, _, _)| visible.contains(id))
+            .map(|(_, slug, title, check)| BrokenLink {
+                slug,
+                title,
+                url: check.url,
+                status: check.status,
+                http_status: check.http_status,
+                checked_at: check.checked_at,
+                failing_since: check.failing_since,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn delete_link_check<Conn>(connection: &mut Conn, model_id: i32) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::delete(link_checks::dsl::link_checks.find(model_id))
+        .execute(connection)
+        .await
+}
@@ -0,0 +1,148 @@
+use anyhow::Context;
+use axum::{extract::ConnectInfo, Extension, Router};
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpListener, UnixListener};
+use tracing::{debug, info, warn};
+
+use crate::Config;
+
+/// The first socket systemd passes on socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Where the server accepts connections.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(address) => write!(f, "{}", address),
+                Err(_) => write!(f, "a TCP socket"),
+            },
+            Listener::Unix(_, Some(path)) => write!(f, "{}", path.display()),
+            Listener::Unix(_, None) => write!(f, "a Unix socket"),
+        }
+    }
+}
+
+/// The socket passed by systemd if the service was socket activated. Only
+/// the first one is used.
+fn activated_socket() -> anyhow::Result<Option<Listener>> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !for_us || count < 1 {
+        return Ok(None);
+    }
+    if count > 1 {
+        warn!("systemd passed {} sockets, only the first is used", count);
+    }
+
+    // systemd leaves the socket inheritable, the clones are closed on exec
+    // so converters started later do not keep it open
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    if tcp.local_addr().is_ok() {
+        let listener = tcp.try_clone()?;
+        drop(tcp);
+        listener.set_nonblocking(true)?;
+        return Ok(Some(Listener::Tcp(TcpListener::from_std(listener)?)));
+    }
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    let path = unix
+        .local_addr()
+        .context("The socket passed by systemd is neither TCP nor a Unix socket")?
+        .as_pathname()
+        .map(Path::to_path_buf);
+    let listener = unix.try_clone()?;
+    drop(unix);
+    listener.set_nonblocking(true)?;
+    Ok(Some(Listener::Unix(
+        UnixListener::from_std(listener)?,
+        path,
+    )))
+}
+
+fn bind_unix(path: &Path, mode: &str) -> anyhow::Result<Listener> {
+    let mode = u32::from_str_radix(mode.trim(), 8)
+        .with_context(|| format!("Invalid UNIX_SOCKET_MODE {:?}, expected octal", mode))?;
+    // a socket left behind by an earlier run, other files are kept
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Unable to listen on {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(Listener::Unix(listener, Some(path.to_path_buf())))
+}
+
+/// A socket handed over by systemd, else `listen_unix_socket`, else the
+/// TCP `host` and `port`.
+pub async fn bind(config: &Config) -> anyhow::Result<Listener> {
+    if let Some(listener) = activated_socket()? {
+        info!("Using the socket passed by systemd");
+        return Ok(listener);
+    }
+    if let Some(path) = &config.listen_unix_socket {
+        return bind_unix(path, &config.unix_socket_mode);
+    }
+    let listener = TcpListener::bind(&config.address)
+        .await
+        .with_context(|| format!("Unable to listen on {}", config.address))?;
+    Ok(Listener::Tcp(listener))
+}
+
+/// Serves `app` until the listener fails. Clients on a Unix socket count
+/// as connecting from localhost, add it to `trusted_proxies` to take the
+/// address forwarded by the proxy in front.
+pub async fn serve(listener: Listener, app: Router) -> std::io::Result<()> {
+    let listener = match listener {
+        Listener::Tcp(listener) => {
+            return axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await;
+        }
+        Listener::Unix(listener, _) => listener,
+    };
+
+    let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let app = app.layer(Extension(ConnectInfo(peer)));
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                // e.g. out of file descriptors, which passes again
+                warn!("Failed to accept a connection: {}", e);
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .with_upgrades()
+                .await
+            {
+                debug!("Connection closed with an error: {}", e);
+            }
+        });
+    }
+}
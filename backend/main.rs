@@ -1,19 +1,19 @@
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::Response;
 use axum::{
     body::Body,
     extract::Path,
-    extract::{DefaultBodyLimit, Query, State},
+    extract::{DefaultBodyLimit, Query, RawQuery, State},
+    middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 use axum::{http, Json};
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use diesel_async::pooled_connection::bb8::Pool;
-use diesel_async::pooled_connection::AsyncDieselConnectionManager;
-use diesel_async::pooled_connection::ManagerConfig;
 use diesel_async::sync_connection_wrapper::SyncConnectionWrapper;
 use diesel_async::{AsyncConnection, RunQueryDsl};
 use diesel_migrations::MigrationHarness;
@@ -23,22 +23,110 @@ use schema::files3d::{self};
 use serde_derive::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
-use tower_http::services::{ServeDir, ServeFile};
+use tower_http::services::ServeDir;
+use tower_http::timeout::TimeoutLayer;
 use tracing::{debug, error, info};
 use tracing_subscriber::EnvFilter;
-use types::{DetailedModelResponse, FileType, ModelResponseList};
+use types::{DetailedModelResponse, FileType, ModelResponse, ModelResponseList};
 
+pub mod access;
+pub mod access_log;
+pub mod api_version;
+pub mod assets;
+pub mod audit_log;
+pub mod auth;
+pub mod build_volume;
+pub mod bulk_edit;
+pub mod cache_control;
+pub mod cad;
+pub mod chat_notifiers;
+pub mod client_api;
+pub mod client_ip;
 pub mod convert;
+pub mod csrf;
+pub mod customizer;
+pub mod db;
+pub mod designers;
+pub mod documents;
+pub mod downloads;
+pub mod drafts;
+pub mod duplicates;
+pub mod etag;
+pub mod file_types;
+pub mod frontend;
+pub mod gcode;
+pub mod history;
+pub mod ignore;
+pub mod image_limits;
+pub mod integrity;
+pub mod jobs;
+pub mod labels;
+pub mod layout;
+pub mod library_stats;
+pub mod link_checks;
+pub mod listener;
+pub mod maintenance;
+pub mod manifest;
+pub mod materials;
+pub mod measurements;
+pub mod media;
+pub mod merge_models;
+pub mod mesh_repair;
+pub mod mesh_units;
+pub mod model_filter;
+pub mod move_model;
+pub mod names;
+pub mod notifications;
+pub mod openscad;
+pub mod parse_errors;
 pub mod parse_library;
+pub mod parts;
+pub mod policy;
+pub mod print_cost;
+pub mod print_logs;
+pub mod publish;
+pub mod quota;
+pub mod recent;
+pub mod refresh_report;
+pub mod response_cache;
+pub mod rollback;
+pub mod saved_searches;
+pub mod scheduler;
 pub mod schema;
+pub mod search;
+pub mod seo;
+pub mod similar;
+pub mod slicer_settings;
+pub mod slug;
+pub mod sort_key;
+pub mod stats;
 pub mod stream_dl;
+pub mod subscriptions;
+pub mod suggest;
+pub mod tags;
+pub mod tenants;
+pub mod thing_import;
+pub mod timeline;
+pub mod trash;
 pub mod types;
 pub mod upload;
+pub mod webcams;
+pub mod workers;
+pub mod zip_cache;
+use crate::access::Viewer;
+use crate::policy::{Admin, Authorized};
 use crate::schema::models3d;
-use crate::types::File3D;
+use crate::stats::EventKind;
+use crate::types::DeleteModelParams;
 use crate::types::ListModelParams;
 use crate::types::Model3D;
+use crate::types::RandomModelParams;
+use crate::types::RefreshParams;
+use crate::types::ZipDownloadParams;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Config {
@@ -50,10 +138,25 @@ pub struct Config {
     host: String,
     #[serde(default = "default_port")]
     port: String,
+    /// path of a Unix domain socket to listen on instead of `host` and
+    /// `port`, a socket passed by systemd takes precedence over both
+    #[serde(default)]
+    listen_unix_socket: Option<PathBuf>,
+    /// octal permissions of `listen_unix_socket`
+    #[serde(default = "default_unix_socket_mode")]
+    unix_socket_mode: String,
     #[serde(default = "default_asset_prefix")]
     asset_prefix: String,
+    /// the built frontend, `dist` unless it is embedded into the binary
+    #[serde(default)]
+    frontend_dir: Option<PathBuf>,
     #[serde(default = "default_cache_prefix")]
     cache_prefix: String,
+    #[serde(default = "default_upload_cache_max_age_hours")]
+    upload_cache_max_age_hours: u64,
+    /// Thingiverse and Printables exports to import, `DATA_DIR/import` if unset
+    #[serde(default)]
+    import_dir: Option<PathBuf>,
     #[serde(skip_deserializing)]
     database_url: PathBuf,
     #[serde(skip_deserializing)]
@@ -62,12 +165,254 @@ pub struct Config {
     preview_cache_dir: PathBuf,
     #[serde(skip_deserializing)]
     address: String,
+    #[serde(skip_deserializing)]
+    trash_dir: PathBuf,
+    #[serde(skip_deserializing)]
+    render_cache_dir: PathBuf,
+    /// largest total size of cached zip downloads, `0` disables the cache
+    #[serde(default)]
+    zip_cache_max_mb: u64,
+    #[serde(skip_deserializing)]
+    zip_cache_dir: PathBuf,
+    /// seconds list and detail responses are answered from memory, `0`
+    /// disables the cache
+    #[serde(default = "default_response_cache_ttl_seconds")]
+    response_cache_ttl_seconds: u64,
+    /// longest side in pixels of an image that is decoded at all
+    #[serde(default = "default_image_max_dimension")]
+    image_max_dimension: u32,
+    /// memory a single image may take while decoding
+    #[serde(default = "default_image_max_memory_mb")]
+    image_max_memory_mb: u64,
+    /// originals with a longer side are scaled down in place when scanned,
+    /// unset keeps them as they are
+    #[serde(default)]
+    image_ingest_max_size: Option<u32>,
+    /// previews, hashes and mesh analysis running at once, all but one
+    /// core by default
+    #[serde(default = "default_cpu_workers")]
+    cpu_workers: usize,
+    #[serde(skip_deserializing)]
+    drafts_dir: PathBuf,
+    #[serde(skip_deserializing)]
+    print_snapshot_dir: PathBuf,
+    /// days a deleted model stays in the trash, negative values keep it forever
+    #[serde(default = "default_trash_retention_days")]
+    trash_retention_days: i64,
+    #[serde(default = "default_trash_purge_interval_minutes")]
+    trash_purge_interval_minutes: u64,
+    /// cron expression triggering a library refresh, e.g. `0 3 * * *`
+    #[serde(default)]
+    refresh_schedule: Option<String>,
+    /// cron expression triggering a check of the source links of all models
+    #[serde(default)]
+    link_check_schedule: Option<String>,
+    #[serde(default = "default_link_check_timeout_seconds")]
+    link_check_timeout_seconds: u64,
+    /// cron expression triggering a checksum verification of all files
+    #[serde(default)]
+    verify_schedule: Option<String>,
+    /// cron expression triggering a sync of all subscriptions to other
+    /// instances
+    #[serde(default)]
+    sync_schedule: Option<String>,
+    #[serde(default = "default_scanner_follow_symlinks")]
+    scanner_follow_symlinks: bool,
+    /// keep indexed models whose folder became unreadable as they were
+    /// until it is fixed, instead of removing them
+    #[serde(default = "default_quarantine_broken_models")]
+    quarantine_broken_models: bool,
+    /// do not descend into other mounts below `libraries_path`
+    #[serde(default)]
+    scanner_same_file_system: bool,
+    /// gitignore style patterns the scanner skips, extended per directory by
+    /// `.modelvaultignore` files
+    #[serde(default = "default_scanner_ignore")]
+    scanner_ignore: Vec<String>,
+    /// treat every folder at this depth below `libraries_path` as a model,
+    /// unset means folders are recognized by their modelpack.json
+    #[serde(default)]
+    layout_model_depth: Option<usize>,
+    /// subfolders of a model holding printable files, `.` is the model folder
+    #[serde(default = "default_layout_files_dirs")]
+    layout_files_dirs: Vec<String>,
+    #[serde(default = "default_layout_images_dirs")]
+    layout_images_dirs: Vec<String>,
+    #[serde(default = "default_layout_docs_dirs")]
+    layout_docs_dirs: Vec<String>,
+    /// header carrying the user name set by the authenticating reverse proxy
+    #[serde(default = "default_auth_user_header")]
+    auth_user_header: String,
+    /// per user limit on uploaded data, unset means unlimited
+    #[serde(default)]
+    upload_quota_mb: Option<u64>,
+    /// largest single upload or model mirrored from another instance,
+    /// unset means unlimited
+    #[serde(default)]
+    max_upload_size_mb: Option<u64>,
+    /// every top level folder below `libraries_path` belongs to its own tenant
+    #[serde(default)]
+    multi_tenant: bool,
+    /// tenant memberships like `club:alice|bob`, other users get a personal tenant
+    #[serde(default)]
+    tenant_members: Vec<String>,
+    /// header carrying the comma separated groups of the user
+    #[serde(default = "default_auth_groups_header")]
+    auth_groups_header: String,
+    /// users allowed to see and manage every model, empty treats every
+    /// request as admin as long as no authentication is set up
+    #[serde(default)]
+    admin_users: Vec<String>,
+    /// visibility of newly discovered models: public, internal or private
+    #[serde(default = "default_visibility")]
+    default_visibility: String,
+    /// addresses or networks allowed to connect, empty allows everyone
+    #[serde(default)]
+    ip_allow_list: Vec<String>,
+    /// addresses or networks never allowed to connect
+    #[serde(default)]
+    ip_deny_list: Vec<String>,
+    /// reverse proxies whose `X-Forwarded-For` header is trusted
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
+    /// renders previews of STEP/IGES files: `builtin` or a command like
+    /// `cadconvert {input} {output}` writing an STL, unset disables them
+    #[serde(default)]
+    cad_preview_converter: Option<String>,
+    /// `openscad` executable used to render `.scad` files, unset disables it
+    #[serde(default)]
+    openscad_binary: Option<String>,
+    #[serde(default = "default_openscad_timeout_seconds")]
+    openscad_timeout_seconds: u64,
+    /// `ffmpeg` executable extracting poster frames of videos, unset leaves
+    /// them without a preview
+    #[serde(default)]
+    ffmpeg_binary: Option<String>,
+    /// snapshot URLs of printer webcams like
+    /// `voron=http://voron.local/webcam/?action=snapshot`, taken when a
+    /// print of that printer is logged
+    #[serde(default)]
+    printer_webcams: Vec<String>,
+    #[serde(default = "default_webcam_timeout_seconds")]
+    webcam_timeout_seconds: u64,
+    /// `pdftoppm` executable rendering the first page of PDFs, unset leaves
+    /// them without a thumbnail
+    #[serde(default)]
+    pdftoppm_binary: Option<String>,
+    /// `json` or `common`, the format of the per request log lines
+    #[serde(default = "default_request_log_format")]
+    request_log_format: String,
+    /// require the `X-CSRF-Token` header on state changing API requests
+    #[serde(default)]
+    csrf_protection: bool,
+    /// user agent fragments not counted as downloads, on top of the built in
+    /// crawler and script patterns
+    #[serde(default)]
+    download_bot_user_agents: Vec<String>,
+    /// address the instance is reached at, used for links in notifications
+    /// and the sitemap, which is only served when it is set
+    #[serde(default)]
+    public_url: Option<String>,
+    /// SMTP server sending notification emails, unset disables them. Secrets
+    /// like the password are left out when the config is logged
+    #[serde(default)]
+    smtp_host: Option<String>,
+    #[serde(default = "default_smtp_port")]
+    smtp_port: u16,
+    #[serde(default)]
+    smtp_username: Option<String>,
+    #[serde(default, skip_serializing)]
+    smtp_password: Option<String>,
+    /// `starttls`, `tls` or `none`
+    #[serde(default = "default_smtp_security")]
+    smtp_security: String,
+    #[serde(default = "default_smtp_from")]
+    smtp_from: String,
+    /// events users may subscribe to by email
+    #[serde(default = "default_email_events")]
+    email_events: Vec<String>,
+    /// folder of `<event>.txt` files replacing the built in messages, the
+    /// first line is the subject
+    #[serde(default)]
+    email_templates_dir: Option<PathBuf>,
+    /// webhook of a Discord channel new models are announced in
+    #[serde(default, skip_serializing)]
+    discord_webhook_url: Option<String>,
+    /// bot posting new models to `telegram_chat_id`
+    #[serde(default, skip_serializing)]
+    telegram_bot_token: Option<String>,
+    #[serde(default)]
+    telegram_chat_id: Option<String>,
+    /// app token models are pushed to Thingiverse with
+    #[serde(default, skip_serializing)]
+    thingiverse_token: Option<String>,
+    /// price of filament not priced by a configured spool
+    #[serde(default)]
+    filament_price_per_kg: Option<f64>,
+    /// unset leaves electricity out of print costs
+    #[serde(default)]
+    energy_price_per_kwh: Option<f64>,
+    /// average power draw of the printer while printing
+    #[serde(default = "default_printer_watts")]
+    printer_watts: f64,
+    #[serde(default = "default_currency")]
+    currency: String,
+    /// day the unversioned `/api` aliases of `/api/v1` are announced to go
+    /// away, unset leaves the `Sunset` header out
+    #[serde(default)]
+    legacy_api_sunset: Option<chrono::NaiveDate>,
+    /// `wal` lets the scanner write while others read
+    #[serde(default = "default_sqlite_journal_mode")]
+    sqlite_journal_mode: String,
+    #[serde(default = "default_sqlite_synchronous")]
+    sqlite_synchronous: String,
+    /// how long a connection waits for a lock before `database is locked`
+    #[serde(default = "default_sqlite_busy_timeout_ms")]
+    sqlite_busy_timeout_ms: u64,
+    /// page cache of each connection
+    #[serde(default = "default_sqlite_cache_size_kib")]
+    sqlite_cache_size_kib: u64,
+    /// time for a request to be answered, uploads are not limited
+    #[serde(default = "default_request_timeout_seconds")]
+    request_timeout_seconds: u64,
+    /// the same for refreshes, conversions, merges and archive downloads
+    #[serde(default = "default_slow_request_timeout_seconds")]
+    slow_request_timeout_seconds: u64,
+    /// largest request body accepted outside of uploads
+    #[serde(default = "default_request_body_limit_kb")]
+    request_body_limit_kb: usize,
+    #[serde(default = "default_db_pool_max_size")]
+    db_pool_max_size: u32,
+    /// connections kept open while idle, unset opens them on demand
+    #[serde(default)]
+    db_pool_min_idle: Option<u32>,
+    /// how long a request waits for a free connection before a 503
+    #[serde(default = "default_db_checkout_timeout_ms")]
+    db_checkout_timeout_ms: u64,
+    #[serde(default = "default_db_connect_timeout_ms")]
+    db_connect_timeout_ms: u64,
+    /// idle connections are closed after this long, 0 keeps them
+    #[serde(default = "default_db_idle_timeout_seconds")]
+    db_idle_timeout_seconds: u64,
+    /// connections are replaced after this long, 0 keeps them
+    #[serde(default = "default_db_max_lifetime_seconds")]
+    db_max_lifetime_seconds: u64,
+    #[serde(skip)]
+    ip_allow_networks: Vec<client_ip::IpNetwork>,
+    #[serde(skip)]
+    ip_deny_networks: Vec<client_ip::IpNetwork>,
+    #[serde(skip)]
+    trusted_proxy_networks: Vec<client_ip::IpNetwork>,
 }
 
 fn default_host() -> String {
     "localhost".to_string()
 }
 
+fn default_unix_socket_mode() -> String {
+    "660".to_string()
+}
+
 fn default_port() -> String {
     "51100".to_string()
 }
@@ -84,12 +429,189 @@ fn default_cache_prefix() -> String {
     "/cache".to_string()
 }
 
+fn default_upload_cache_max_age_hours() -> u64 {
+    24
+}
+
+fn default_scanner_follow_symlinks() -> bool {
+    true
+}
+
+fn default_quarantine_broken_models() -> bool {
+    true
+}
+
+fn default_scanner_ignore() -> Vec<String> {
+    [
+        "@eaDir/",
+        ".stversions/",
+        "#recycle/",
+        "#snapshot/",
+        ".Trash-*/",
+        ".DS_Store",
+        "Thumbs.db",
+        "*.bak",
+        "*.tmp",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_layout_files_dirs() -> Vec<String> {
+    vec!["files".to_string()]
+}
+
+fn default_layout_images_dirs() -> Vec<String> {
+    vec!["images".to_string()]
+}
+
+fn default_layout_docs_dirs() -> Vec<String> {
+    vec!["docs".to_string()]
+}
+
+fn default_auth_user_header() -> String {
+    "Remote-User".to_string()
+}
+
+fn default_auth_groups_header() -> String {
+    "Remote-Groups".to_string()
+}
+
+fn default_visibility() -> String {
+    "public".to_string()
+}
+
+fn default_request_log_format() -> String {
+    "common".to_string()
+}
+
+fn default_openscad_timeout_seconds() -> u64 {
+    120
+}
+
+fn default_link_check_timeout_seconds() -> u64 {
+    15
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_smtp_security() -> String {
+    "starttls".to_string()
+}
+
+fn default_smtp_from() -> String {
+    "ModelVault <modelvault@localhost>".to_string()
+}
+
+fn default_email_events() -> Vec<String> {
+    notifications::EmailEvent::ALL
+        .iter()
+        .map(|event| event.as_str().to_string())
+        .collect()
+}
+
+fn default_printer_watts() -> f64 {
+    120.0
+}
+
+fn default_currency() -> String {
+    "EUR".to_string()
+}
+
+fn default_trash_retention_days() -> i64 {
+    30
+}
+
+fn default_trash_purge_interval_minutes() -> u64 {
+    60
+}
+
+fn default_sqlite_journal_mode() -> String {
+    "wal".to_string()
+}
+
+fn default_sqlite_synchronous() -> String {
+    "normal".to_string()
+}
+
+fn default_sqlite_busy_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_sqlite_cache_size_kib() -> u64 {
+    16384
+}
+
+fn default_request_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_slow_request_timeout_seconds() -> u64 {
+    900
+}
+
+fn default_request_body_limit_kb() -> usize {
+    1024
+}
+
+fn default_db_pool_max_size() -> u32 {
+    10
+}
+
+fn default_response_cache_ttl_seconds() -> u64 {
+    30
+}
+
+fn default_webcam_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_image_max_dimension() -> u32 {
+    16384
+}
+
+fn default_image_max_memory_mb() -> u64 {
+    512
+}
+
+fn default_cpu_workers() -> usize {
+    workers::default_size()
+}
+
+fn default_db_checkout_timeout_ms() -> u64 {
+    10000
+}
+
+fn default_db_connect_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_db_idle_timeout_seconds() -> u64 {
+    600
+}
+
+fn default_db_max_lifetime_seconds() -> u64 {
+    1800
+}
+
 impl Config {
     fn initialize(&mut self) {
         self.database_url = self.data_dir.join("db.sqlite3");
         self.preview_cache_dir = self.data_dir.join("preview_cache");
         self.address = format!("{}:{}", self.host, self.port);
         self.upload_cache = self.data_dir.join("upload_cache");
+        self.trash_dir = self.data_dir.join("trash");
+        self.render_cache_dir = self.data_dir.join("render_cache");
+        self.zip_cache_dir = self.data_dir.join("zip_cache");
+        self.drafts_dir = self.data_dir.join("drafts");
+        self.print_snapshot_dir = self.data_dir.join("print_snapshots");
+        self.ip_allow_networks = client_ip::parse_networks("IP_ALLOW_LIST", &self.ip_allow_list);
+        self.ip_deny_networks = client_ip::parse_networks("IP_DENY_LIST", &self.ip_deny_list);
+        self.trusted_proxy_networks =
+            client_ip::parse_networks("TRUSTED_PROXIES", &self.trusted_proxies);
+        db::check_pragmas(self);
     }
 }
 
@@ -106,6 +628,10 @@ fn parse_config() -> Config {
 pub struct AppState {
     config: Config,
     pool: Pool<SyncConnectionWrapper<SqliteConnection>>,
+    jobs: jobs::JobRegistry,
+    suggest: suggest::SuggestIndex,
+    notifier: notifications::Notifier,
+    cache: response_cache::ResponseCache,
 }
 
 async fn healthz() -> impl IntoResponse {
@@ -114,71 +640,121 @@ async fn healthz() -> impl IntoResponse {
 
 async fn get_model_by_slug(
     State(state): State<AppState>,
+    viewer: Viewer,
+    headers: HeaderMap,
     Path(slug): Path<String>,
-) -> impl IntoResponse {
-    let mut connection = state.pool.get().await.unwrap();
+) -> Result<Response, StatusCode> {
+    let mut connection = state.connection().await?;
 
-    let result = models3d::dsl::models3d
-        .filter(models3d::dsl::name.eq(slug))
-        .first::<Model3D>(&mut connection)
-        .await
-        .unwrap();
-    let response = DetailedModelResponse::from_model_3d(&result, &state.config, &mut connection)
+    let result = viewer.find_model(&mut connection, &slug).await?;
+    stats::record_event_logged(&mut connection, result.id, EventKind::View).await;
+    history::record_view_logged(&mut connection, &viewer, result.id).await;
+
+    let version = etag::model_version(&mut connection, &result)
         .await
-        .unwrap();
-    (StatusCode::OK, Json(response))
+        .map_err(|e| {
+            error!("Failed to compute the version of {}: {}", result.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let etag = etag::make_etag(version, None, None);
+    let key = format!("model:{}", etag);
+    etag::respond_with_etag(&headers, etag, async {
+        if let Some(cached) = state.cache.get(&key) {
+            return Ok(cached);
+        }
+        let generation = state.cache.generation();
+        let response =
+            DetailedModelResponse::from_model_3d(&result, &state.config, &mut connection)
+                .await
+                .map_err(|e| {
+                    error!("Failed to build the response of {}: {}", result.slug, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+        state.cache.store(key, generation, &response)
+    })
+    .await
 }
 
 async fn refresh_model(
     State(state): State<AppState>,
+    viewer: Viewer,
     Path(slug): Path<String>,
-) -> impl IntoResponse {
-    let mut connection = state.pool.get().await.unwrap();
+) -> Result<Json<DetailedModelResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
 
-    let result = models3d::dsl::models3d
-        .filter(models3d::dsl::name.eq(slug.clone()))
-        .first::<Model3D>(&mut connection)
+    let result = viewer.manage_model(&mut connection, &slug).await?;
+    result
+        .scan(&state.config, &mut connection)
         .await
-        .unwrap();
-    result.scan(&state.config, &mut connection).await;
+        .map_err(|e| {
+            error!("Failed to scan {}: {:#}", result.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
     let reloaded_result = models3d::dsl::models3d
-        .filter(models3d::dsl::name.eq(slug))
+        .find(result.id)
         .first::<Model3D>(&mut connection)
         .await
-        .unwrap();
+        .map_err(|e| {
+            error!("Failed to reload {}: {}", result.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
     let response =
         DetailedModelResponse::from_model_3d(&reloaded_result, &state.config, &mut connection)
             .await
-            .unwrap();
-    (StatusCode::OK, Json(response))
+            .map_err(|e| {
+                error!("Failed to build the response of {}: {}", result.slug, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    Ok(Json(response))
 }
 
 async fn delete_model(
     State(state): State<AppState>,
+    viewer: Viewer,
     Path(slug): Path<String>,
+    Query(params): Query<DeleteModelParams>,
 ) -> impl IntoResponse {
-    let mut connection = state.pool.get().await.unwrap();
+    let mut connection = match state.connection().await {
+        Ok(connection) => connection,
+        Err(status) => return status,
+    };
 
-    let result = models3d::dsl::models3d
-        .filter(models3d::dsl::name.eq(slug))
-        .first::<Model3D>(&mut connection)
+    let result = match viewer.manage_model(&mut connection, &slug).await {
+        Ok(result) => result,
+        Err(status) => return status,
+    };
+
+    let retention_days = params
+        .retention_days
+        .unwrap_or(state.config.trash_retention_days);
+    let purge_after = (!params.keep && retention_days >= 0)
+        .then(|| chrono::Utc::now().naive_utc() + chrono::Duration::days(retention_days));
+
+    match result
+        .trash(&state.config, &mut connection, purge_after)
         .await
-        .unwrap();
-    match result.delete(&state.config, &mut connection).await {
+    {
         Ok(_) => StatusCode::OK,
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
-async fn delete_file(State(state): State<AppState>, Path(pk): Path<i32>) -> impl IntoResponse {
-    let mut connection = state.pool.get().await.unwrap();
+async fn delete_file(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(pk): Path<i32>,
+) -> impl IntoResponse {
+    let mut connection = match state.connection().await {
+        Ok(connection) => connection,
+        Err(status) => return status,
+    };
 
-    let result = files3d::dsl::files3d
-        .filter(files3d::dsl::id.eq(pk))
-        .first::<File3D>(&mut connection)
-        .await
-        .unwrap();
+    let result = match viewer.find_file(&mut connection, pk).await {
+        Ok((result, model)) if viewer.can_manage(&model) => result,
+        Ok(_) => return StatusCode::FORBIDDEN,
+        Err(status) => return status,
+    };
     match result.delete(&state.config, &mut connection).await {
         Ok(_) => StatusCode::OK,
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -187,15 +763,13 @@ async fn delete_file(State(state): State<AppState>, Path(pk): Path<i32>) -> impl
 
 async fn convert_file(
     State(state): State<AppState>,
+    viewer: Viewer,
+    client: downloads::DownloadClient,
     Path((pk, target_type)): Path<(i32, String)>,
 ) -> impl IntoResponse {
-    let mut connection = state.pool.get().await.unwrap();
+    let mut connection = state.connection().await?;
 
-    let result = files3d::dsl::files3d
-        .filter(files3d::dsl::id.eq(pk))
-        .first::<File3D>(&mut connection)
-        .await
-        .unwrap();
+    let (result, _) = viewer.find_file(&mut connection, pk).await?;
 
     let mut file_ending = "";
     let buffer = match FileType::from_str(&target_type) {
@@ -241,120 +815,299 @@ async fn convert_file(
         },
         _ => return Err(StatusCode::UNPROCESSABLE_ENTITY),
     };
+    downloads::record_download_logged(&mut connection, result.model_id, &client).await;
     let body = Body::from(buffer);
+    let file_name = result.get_file_name().await.unwrap_or_default();
 
-    let response = Response::builder()
+    Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/octet-stream")
         .header(
             header::CONTENT_DISPOSITION,
-            format!(
-                "attachment; filename=\"{}.{}\"",
-                result.get_file_name().await.unwrap(),
-                file_ending
-            )
-            .as_str(),
+            format!("attachment; filename=\"{}.{}\"", file_name, file_ending).as_str(),
         )
         .body(body)
-        .unwrap();
+        .map_err(|e| {
+            error!("Failed to build the response of file {}: {}", pk, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
 
-    Ok(response)
+async fn handle_refresh(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Query(params): Query<RefreshParams>,
+) -> Response {
+    if params.dry_run {
+        let mut connection = match state.connection().await {
+            Ok(connection) => connection,
+            Err(status) => return status.into_response(),
+        };
+        return match parse_library::plan_refresh(&state.config, &mut connection).await {
+            Ok(plan) => (StatusCode::OK, Json(plan)).into_response(),
+            Err(e) => {
+                error!("Dry-run refresh failed: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+        };
+    }
+
+    run_refresh(state, std::path::Path::new("")).await
 }
 
-async fn handle_refresh(State(state): State<AppState>) -> impl IntoResponse {
-    parse_library::refresh_library(state.pool, state.config.clone())
-        .await
-        .unwrap();
+/// Re-parses the folder of one model, e.g. after fixing its modelpack.json.
+async fn handle_refresh_model(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+) -> Response {
+    let folder_path = {
+        let mut connection = match state.connection().await {
+            Ok(connection) => connection,
+            Err(status) => return status.into_response(),
+        };
+        match viewer.manage_model(&mut connection, &slug).await {
+            Ok(model) => model.folder_path,
+            Err(status) => return status.into_response(),
+        }
+    };
+    run_refresh(state, std::path::Path::new(&folder_path)).await
+}
 
-    (StatusCode::OK, "Done".to_string())
+/// Rescans a single library, a top level folder below `libraries_path`.
+async fn handle_refresh_library(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Path(library): Path<String>,
+) -> Response {
+    let scope = std::path::Path::new(&library);
+    let is_library = matches!(
+        scope.components().collect::<Vec<_>>().as_slice(),
+        [std::path::Component::Normal(_)]
+    );
+    if !is_library || !state.config.libraries_path.join(scope).is_dir() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    run_refresh(state, scope).await
+}
+
+async fn run_refresh(state: AppState, scope: &std::path::Path) -> Response {
+    let (job_id, cancel) = state.jobs.start("refresh");
+    let result =
+        parse_library::refresh_below(state.pool, state.config.clone(), scope, cancel).await;
+    state.cache.invalidate();
+    if let Ok(report) = &result {
+        state.jobs.set_result(job_id, report);
+    }
+    state.jobs.complete(job_id, &result);
+
+    match result {
+        Ok(report) => {
+            state
+                .notifier
+                .notify(notifications::Notification::LibraryChanged);
+            (StatusCode::OK, Json(report)).into_response()
+        }
+        Err(e) if e.is::<jobs::Cancelled>() => {
+            (StatusCode::CONFLICT, "Cancelled".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("Refresh failed: {}", e);
+            state
+                .notifier
+                .notify(notifications::Notification::RefreshFailed {
+                    trigger: "manual",
+                    error: e.to_string(),
+                });
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
 }
 
 async fn list_models(
     State(state): State<AppState>,
+    viewer: Viewer,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
     Query(params): Query<ListModelParams>,
-) -> impl IntoResponse {
-    let mut connection = state.pool.get().await.unwrap();
-
-    let mut models = models3d::dsl::models3d.into_boxed();
-
-    if let Some(ref q) = params.q {
-        let pattern = format!("%{}%", q);
-        models = models.filter(
-            models3d::dsl::name
-                .like(pattern.clone())
-                .or(models3d::dsl::title.like(pattern.clone()))
-                .or(models3d::dsl::description.like(pattern.clone()))
-                .or(models3d::dsl::author.like(pattern.clone())),
-        );
-    }
+) -> Result<Response, StatusCode> {
+    let mut connection = state.connection().await?;
 
-    if let Some(licenses) = params.licenses {
-        let split_licenses: Vec<Option<String>> = licenses
-            .clone()
-            .split(',')
-            .map(|s| Some(s.to_string()))
-            .collect();
-        for (i, license) in split_licenses.iter().enumerate() {
-            debug!("Filtering models with license: {:?}", license.clone());
-            if i == 0 {
-                models = models.filter(models3d::dsl::license.eq(license.clone()));
-            } else {
-                models = models.or_filter(models3d::dsl::license.eq(license.clone()));
-            }
+    let models = model_filter::filter_models(&params, &viewer)?;
+
+    let version = etag::library_version(&mut connection).await.map_err(|e| {
+        error!("Failed to compute the library version: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let etag = etag::make_etag(version, Some(&viewer), query.as_deref());
+    let key = format!("models:{}", etag);
+    etag::respond_with_etag(&headers, etag, async {
+        if let Some(cached) = state.cache.get(&key) {
+            return Ok(cached);
         }
-    }
+        let generation = state.cache.generation();
+        let licenses_to_select: Vec<String> = models3d::dsl::models3d
+            .select(models3d::dsl::license)
+            .distinct()
+            .filter(models3d::dsl::deleted_at.is_null())
+            .filter(models3d::dsl::license.ne(""))
+            .load::<Option<String>>(&mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to load licenses: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .into_iter()
+            .filter_map(|license| license)
+            .collect();
 
-    let licenses_to_select: Vec<String> = models3d::dsl::models3d
-        .select(models3d::dsl::license)
-        .distinct()
-        .filter(models3d::dsl::license.ne(""))
-        .load::<Option<String>>(&mut connection)
+        let models = models.load::<Model3D>(&mut connection).await.map_err(|e| {
+            error!("Failed to load models: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let response = ModelResponseList::from_model_3d(
+            models,
+            licenses_to_select,
+            &state.config,
+            &mut connection,
+        )
         .await
-        .unwrap()
-        .into_iter()
-        .filter_map(|license| license)
-        .collect();
-
-    let response = ModelResponseList::from_model_3d(
-        models.load::<Model3D>(&mut connection).await.unwrap(),
-        licenses_to_select,
-        &state.config,
-        &mut connection,
-    )
+        .map_err(|e| {
+            error!("Failed to build model responses: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        state.cache.store(key, generation, &response)
+    })
     .await
-    .unwrap();
+}
 
-    (StatusCode::OK, Json(response))
+async fn random_models(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(params): Query<RandomModelParams>,
+) -> Result<Json<Vec<ModelResponse>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let count = params.count.unwrap_or(12).clamp(1, 100);
+    let models = model_filter::filter_models(&params.filters, &viewer)?
+        .order(model_filter::random())
+        .limit(count)
+        .load::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load random models: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut response = Vec::new();
+    for model in &models {
+        let model_response = ModelResponse::from_model_3d(model, &state.config, &mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to build model response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        response.push(model_response);
+    }
+
+    Ok(Json(response))
 }
 
 async fn handle_zip_download(
     State(state): State<AppState>,
+    viewer: Viewer,
+    client: downloads::DownloadClient,
     Path(folder_path): Path<String>,
-) -> impl IntoResponse {
+    Query(params): Query<ZipDownloadParams>,
+) -> Response {
+    let mut connection = match state.connection().await {
+        Ok(connection) => connection,
+        Err(status) => return status.into_response(),
+    };
+    let model = models3d::dsl::models3d
+        .filter(models3d::dsl::folder_path.eq(&folder_path))
+        .filter(models3d::dsl::deleted_at.is_null())
+        .first::<Model3D>(&mut connection)
+        .await;
+    let allowed = match &model {
+        Ok(model) => viewer
+            .can_view(&mut connection, model)
+            .await
+            .unwrap_or(false),
+        // folders outside of any model are reserved to admins
+        Err(_) => viewer.is_admin,
+    };
+    if !allowed {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let mut generated = Vec::new();
+    if let (true, Ok(model)) = (params.manifest, &model) {
+        let mut manifest =
+            match manifest::model_manifest(&state.config, &mut connection, model).await {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    error!("Failed to build the manifest of {}: {}", model.slug, e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+        // list only the files in the archive, an import verifies all of them
+        manifest
+            .files
+            .retain(|file| params.kind.includes(&file.file_type));
+        let path = std::path::Path::new(&model.folder_path).join(manifest::MANIFEST_FILE);
+        match serde_json::to_vec_pretty(&manifest) {
+            Ok(content) => generated.push((path.to_string_lossy().into_owned(), content)),
+            Err(e) => error!("Failed to serialize the manifest of {}: {}", model.slug, e),
+        }
+    }
+    drop(connection);
+
+    // counted once the whole archive was handed to the client, aborted
+    // downloads do not count
+    let pool = state.pool.clone();
+    let model_id = model.ok().map(|model| model.id);
+    let on_complete = async move {
+        let Some(model_id) = model_id else {
+            return;
+        };
+        match pool.get().await {
+            Ok(mut connection) => {
+                downloads::record_download_logged(&mut connection, model_id, &client).await
+            }
+            Err(e) => error!("Failed to get a connection: {}", e),
+        }
+    };
+
     let mut path = state.config.libraries_path.clone();
     path.push(folder_path);
-    stream_dl::zip_folder_stream(path, &state.config).await
+    stream_dl::zip_folder_stream(path, &state.config, params.kind, generated, on_complete)
+        .await
+        .into_response()
 }
 
-pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
-
-async fn create_connection_pool(config: &Config) -> Pool<SyncConnectionWrapper<SqliteConnection>> {
-    let mut db_config = ManagerConfig::default();
-    db_config.custom_setup =
-        Box::new(|url| SyncConnectionWrapper::<SqliteConnection>::establish(url));
-    let mgr =
-        AsyncDieselConnectionManager::<SyncConnectionWrapper<SqliteConnection>>::new_with_config(
-            config.database_url.to_str().unwrap(),
-            db_config,
-        );
-
-    Pool::builder().max_size(10).build(mgr).await.unwrap()
+/// Compresses API responses and the frontend bundle. Zip downloads and
+/// converted files are binary already, images are skipped by the default
+/// predicate and library assets are never compressed to keep ranged
+/// requests intact.
+fn compression() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(
+        DefaultPredicate::new()
+            .and(NotForContentType::new("application/zip"))
+            .and(NotForContentType::new("application/octet-stream")),
+    )
 }
 
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
+
 fn migrate(config: &Config) {
     let mut connection =
         SqliteConnection::establish(config.database_url.to_str().expect("Invalid Path"))
             .unwrap_or_else(|_| panic!("Error connecting to {}", config.database_url.display()));
+    connection
+        .batch_execute(&db::pragmas(config))
+        .unwrap_or_else(|e| panic!("Error configuring the database: {}", e));
 
     info!("DB connection established successfully");
     info!("Please wait while DB is migrating");
@@ -378,6 +1131,7 @@ async fn main() {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::new(config.log_level.clone()))
         .init();
+    workers::init(&config);
 
     match serde_json::to_string(&config) {
         Ok(json) => debug!("Config: {}", json),
@@ -386,55 +1140,334 @@ async fn main() {
 
     migrate(&config);
 
-    let pool = create_connection_pool(&config).await;
+    let pool = db::create_connection_pool(&config).await;
+    match pool.get().await {
+        Ok(mut connection) => {
+            if let Err(e) = sort_key::backfill(&mut connection).await {
+                error!("Failed to compute sort keys: {}", e);
+            }
+            if let Err(e) = designers::backfill(&mut connection).await {
+                error!("Failed to link models to designers: {}", e);
+            }
+            if let Err(e) = names::backfill(&mut connection).await {
+                error!("Failed to normalize model names: {}", e);
+            }
+        }
+        Err(e) => error!("Failed to get a connection: {}", e),
+    }
 
     let app_state = AppState {
         config: config.clone(),
+        notifier: notifications::Notifier::spawn(&config, pool.clone()),
         pool,
+        jobs: jobs::JobRegistry::default(),
+        suggest: suggest::SuggestIndex::default(),
+        cache: response_cache::ResponseCache::new(config.response_cache_ttl_seconds),
     };
 
+    scheduler::spawn_scheduler(app_state.clone());
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let api = Router::new()
-        .route("/refresh", post(handle_refresh))
+    let api: Router<AppState> = Router::new()
         .route("/models/list", get(list_models))
+        .route("/models/random", get(random_models))
+        .route("/models/recent", get(recent::handle_recent_models))
+        .route("/models/trending", get(stats::handle_trending))
+        .route("/search", get(search::handle_search))
+        .route("/search/suggest", get(suggest::handle_suggest))
+        .route("/stats/library", get(library_stats::handle_library_stats))
+        .route("/designers", get(designers::handle_list_designers))
+        .route("/designer/:slug", get(designers::handle_get_designer))
+        .route(
+            "/designers/:slug/merge",
+            post(designers::handle_merge_designer),
+        )
+        .route("/tags", get(tags::handle_tag_stats))
+        .route("/tags/suggest", get(tags::handle_suggest_tags))
+        .route("/tags/:name/rename", post(tags::handle_rename_tag))
+        .route("/tags/:name/merge", post(tags::handle_merge_tag))
+        .route("/tags/:name/delete", post(tags::handle_delete_tag))
+        .route("/users/me/quota", get(quota::handle_my_quota))
+        .route(
+            "/users/me/notifications",
+            get(notifications::handle_get_email_settings)
+                .post(notifications::handle_update_email_settings),
+        )
+        .route("/users/me/history", get(history::handle_history))
+        .route(
+            "/users/me/history/clear",
+            post(history::handle_clear_history),
+        )
+        .route(
+            "/users/me/history/settings",
+            post(history::handle_update_history_settings),
+        )
+        .route(
+            "/users/me/client-tokens",
+            get(client_api::handle_list_tokens).post(client_api::handle_create_token),
+        )
+        .route(
+            "/users/me/client-tokens/:id/delete",
+            post(client_api::handle_revoke_token),
+        )
+        .route(
+            "/users/me/searches",
+            get(saved_searches::handle_list_searches).post(saved_searches::handle_save_search),
+        )
+        .route(
+            "/users/me/searches/:id/delete",
+            post(saved_searches::handle_delete_search),
+        )
+        .route(
+            "/users/me/searches/:id/new",
+            post(saved_searches::handle_new_matches),
+        )
         .route("/model/:slug", get(get_model_by_slug))
-        .route("/model/:slug/refresh", get(refresh_model))
-        .route("/model/:slug/update", post(upload::handle_upload_update))
+        .route("/model/:slug/similar", get(similar::handle_similar))
+        .route("/model/:slug/timeline", get(timeline::handle_timeline))
+        .route("/model/:slug/metadata", get(seo::handle_model_metadata))
+        .route("/model/:slug/label", get(labels::handle_label))
+        .route(
+            "/model/:slug/downloads",
+            get(downloads::handle_model_downloads),
+        )
+        .route(
+            "/model/:slug/files/:file/repair",
+            post(mesh_repair::handle_repair),
+        )
         .route("/model/:slug/delete", post(delete_model))
+        .route(
+            "/model/:slug/access",
+            get(access::handle_get_access).post(access::handle_update_access),
+        )
+        .route(
+            "/model/:slug/owner",
+            post(access::handle_transfer_ownership),
+        )
+        .route(
+            "/model/:slug/prints",
+            get(print_logs::handle_list_prints).post(print_logs::handle_log_print),
+        )
+        .route("/prints/:id/delete", post(print_logs::handle_delete_print))
+        .route(
+            "/prints/:id/snapshot",
+            get(print_logs::handle_print_snapshot),
+        )
+        .route(
+            "/prints/:id/timelapse",
+            post(print_logs::handle_attach_timelapse),
+        )
+        .route(
+            "/filaments",
+            get(print_cost::handle_list_filaments).post(print_cost::handle_save_filament),
+        )
+        .route(
+            "/filaments/:id/delete",
+            post(print_cost::handle_delete_filament),
+        )
         .route("/file/:id/delete", post(delete_file))
+        .route("/file/:id/cost", get(print_cost::handle_file_cost))
+        .route(
+            "/file/:id/measurements",
+            get(measurements::handle_file_measurements),
+        )
+        .route("/file/:id/filament", post(print_cost::handle_link_filament))
+        .route("/file/:id/parameters", get(customizer::handle_parameters))
+        .route("/file/:id/customize", post(customizer::handle_customize))
+        .route(
+            "/file/:id/customize/:hash",
+            get(customizer::handle_customized_stl),
+        )
+        .route(
+            "/maintenance/duplicates",
+            get(duplicates::handle_list_duplicates),
+        )
+        .route(
+            "/maintenance/orphans",
+            get(maintenance::handle_list_orphans),
+        )
+        .route("/audit-log", get(audit_log::handle_audit_log))
+        .route("/jobs", get(jobs::handle_list_jobs))
+        .route(
+            "/jobs/:id",
+            get(jobs::handle_get_job).delete(jobs::handle_cancel_job),
+        )
+        .route(
+            "/subscriptions",
+            get(subscriptions::handle_list_subscriptions).post(subscriptions::handle_subscribe),
+        )
+        .route(
+            "/subscriptions/opml",
+            get(subscriptions::handle_export_opml).post(subscriptions::handle_import_opml),
+        )
+        .route(
+            "/subscriptions/:id/delete",
+            post(subscriptions::handle_unsubscribe),
+        )
+        .route("/subscriptions/:id/sync", post(subscriptions::handle_sync))
+        .route(
+            "/import/things",
+            get(thing_import::handle_list_imports).post(thing_import::handle_import_things),
+        )
+        .route("/refresh/errors", get(parse_errors::handle_parse_errors))
+        .route(
+            "/drafts",
+            get(drafts::handle_list_drafts).post(drafts::handle_create_draft),
+        )
+        .route(
+            "/drafts/:id",
+            get(drafts::handle_get_draft)
+                .post(drafts::handle_update_draft)
+                .delete(drafts::handle_delete_draft),
+        )
+        .route(
+            "/drafts/:id/files/*path",
+            delete(drafts::handle_remove_file),
+        )
+        .route("/links/report", get(link_checks::handle_link_report))
+        .route("/links/check", post(link_checks::handle_check_links))
+        .route("/trash", get(trash::handle_list_trash))
+        .route("/trash/:slug", delete(trash::handle_purge_model))
+        .route("/trash/:slug/restore", post(trash::handle_restore_model))
+        .route(
+            "/trash/retention",
+            get(trash::handle_retention_report).post(trash::handle_run_retention),
+        )
+        .layer(TimeoutLayer::new(Duration::from_secs(
+            config.request_timeout_seconds,
+        )))
+        .layer(DefaultBodyLimit::max(config.request_body_limit_kb * 1024));
+
+    // rescans, conversions and archives which may take a while
+    let slow: Router<AppState> = Router::new()
+        .route("/refresh", post(handle_refresh))
+        .route("/refresh/:slug", post(handle_refresh_model))
+        .route("/refresh/library/:library", post(handle_refresh_library))
+        .route("/models/bulk", post(bulk_edit::handle_bulk_edit))
+        .route("/models/labels", get(labels::handle_batch_labels))
+        .route("/model/:slug/refresh", post(refresh_model))
+        .route("/model/:slug/move", post(move_model::handle_move_model))
+        .route(
+            "/model/:slug/merge",
+            post(merge_models::handle_merge_models),
+        )
         .route("/file/:id/convert/:target_type", get(convert_file))
+        .route("/file/:id/render", post(openscad::handle_render))
         .route("/download/:folder", get(handle_zip_download))
+        .route("/drafts/:id/publish", post(drafts::handle_publish_draft))
+        .route(
+            "/model/:slug/publish/:site",
+            get(publish::handle_publish_bundle).post(publish::handle_publish),
+        )
+        .route("/model/:slug/verify", get(integrity::handle_verify_model))
+        .route("/verify", post(integrity::handle_verify_library))
+        .route(
+            "/maintenance/orphans/purge",
+            post(maintenance::handle_purge_orphans),
+        )
+        .layer(TimeoutLayer::new(Duration::from_secs(
+            config.slow_request_timeout_seconds,
+        )))
+        .layer(DefaultBodyLimit::max(config.request_body_limit_kb * 1024));
+
+    // uploads are streamed to disk and may take as long as the client needs
+    let uploads: Router<AppState> = Router::new()
+        .route("/model/:slug/update", post(upload::handle_upload_update))
         .route("/upload", post(upload::handle_upload))
-        .layer(DefaultBodyLimit::disable())
-        .with_state(app_state);
+        .route("/drafts/:id/files", post(drafts::handle_attach_files))
+        .layer(DefaultBodyLimit::disable());
+
+    let api = api
+        .merge(slow)
+        .merge(uploads)
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            response_cache::invalidate_on_change,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            csrf::protect,
+        ));
+
+    // the envelope reads bodies before they are compressed
+    let api_v1 = api
+        .clone()
+        .layer(middleware::from_fn(api_version::envelope))
+        .layer(compression())
+        .with_state(app_state.clone());
+    let api_legacy = api
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            api_version::deprecated_alias,
+        ))
+        .layer(compression())
+        .with_state(app_state.clone());
+
+    // plain JSON for slicer plugins, outside of the web app's versioning and
+    // CSRF protection as it is read-only and authenticated by bearer tokens
+    let client = Router::new()
+        .route("/search", get(client_api::handle_search))
+        .route("/models/:slug/files", get(client_api::handle_model_files))
+        .route("/recent", get(client_api::handle_recent_files))
+        .route("/openapi.json", get(client_api::handle_openapi))
+        .layer(TimeoutLayer::new(Duration::from_secs(
+            config.request_timeout_seconds,
+        )))
+        .layer(compression())
+        // served like assets, ranges of compressed bodies would not resume
+        .route("/files/:id", get(client_api::handle_fetch_file))
+        .with_state(app_state.clone());
+
+    let assets = Router::new()
+        .fallback(assets::handle_asset)
+        .layer(middleware::from_fn(cache_control::revalidate))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            cache_control::if_range,
+        ))
+        .with_state(app_state.clone());
+
+    let previews = Router::new()
+        .fallback_service(ServeDir::new(&config.preview_cache_dir))
+        .layer(middleware::from_fn(cache_control::immutable));
+
+    let frontend = frontend::router(&config).layer(compression());
 
     let app = Router::new()
         .route("/healthz", get(healthz))
-        .nest("/api/", api)
-        .nest_service(
-            &config.asset_prefix.to_string(),
-            ServeDir::new(config.libraries_path),
-        )
-        .nest_service(
-            &config.cache_prefix.to_string(),
-            ServeDir::new(config.preview_cache_dir),
+        .route(
+            "/metrics",
+            get(db::handle_metrics).with_state(app_state.clone()),
         )
-        .nest_service(
-            "/",
-            ServeDir::new("dist").fallback(ServeFile::new("dist/index.html")),
+        .route(
+            "/sitemap.xml",
+            get(seo::handle_sitemap).with_state(app_state.clone()),
         )
+        .nest(client_api::PREFIX, client)
+        .nest(api_version::CURRENT_PREFIX, api_v1)
+        .nest(api_version::LEGACY_PREFIX, api_legacy)
+        .nest(&config.asset_prefix.to_string(), assets)
+        .nest(&config.cache_prefix.to_string(), previews)
+        .nest_service("/", frontend)
         .fallback(fallback_404)
+        .layer(middleware::from_fn(db::retry_after))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            client_ip::filter_ips,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state,
+            access_log::log_requests,
+        ))
         .layer(cors);
 
-    let listener = tokio::net::TcpListener::bind(&config.address.to_string())
-        .await
-        .unwrap();
+    let listener = listener::bind(&config).await.unwrap();
 
-    info!("Server running on {}", config.address);
+    info!("Server running on {}", listener);
 
-    axum::serve(listener, app).await.unwrap();
+    listener::serve(listener, app).await.unwrap();
 }
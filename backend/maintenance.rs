@@ -0,0 +1,185 @@
+use axum::{extract::State, http::StatusCode, Json};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+use tracing::{debug, error, info};
+use typeshare::typeshare;
+
+use crate::policy::{Admin, Authorized};
+use crate::schema::{files3d, models3d};
+use crate::types::Model3D;
+use crate::{AppState, Config};
+
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OrphanedModel {
+    pub id: i32,
+    pub name: String,
+    pub folder_path: String,
+}
+
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OrphanReport {
+    pub models: Vec<OrphanedModel>,
+    pub preview_images: Vec<String>,
+    pub upload_cache_entries: Vec<String>,
+    pub purged: bool,
+}
+
+/// Collects everything that is no longer backed by the library: model rows
+/// whose folder vanished, preview images no file references and upload
+/// cache entries older than `upload_cache_max_age_hours`.
+pub async fn find_orphans<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+) -> anyhow::Result<OrphanReport>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let mut report = OrphanReport::default();
+
+    let models = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
+        .load::<Model3D>(connection)
+        .await?;
+
+    for model in models {
+        if fs::metadata(model.absolute_path(config)).await.is_err() {
+            report.models.push(OrphanedModel {
+                id: model.id,
+                name: model.name.clone(),
+                folder_path: model.folder_path.clone(),
+            });
+        }
+    }
+
+    let referenced_previews: HashSet<String> = files3d::dsl::files3d
+        .select(files3d::dsl::preview_image)
+        .filter(files3d::dsl::preview_image.is_not_null())
+        .load::<Option<String>>(connection)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    if let Ok(mut entries) = fs::read_dir(&config.preview_cache_dir).await {
+        while let Some(entry) = entries.next_entry().await? {
+            let pth = entry.path();
+            if !pth.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if !referenced_previews.contains(&file_name) {
+                report.preview_images.push(file_name);
+            }
+        }
+    }
+
+    let max_age = Duration::from_secs(config.upload_cache_max_age_hours * 60 * 60);
+    if let Ok(mut entries) = fs::read_dir(&config.upload_cache).await {
+        while let Some(entry) = entries.next_entry().await? {
+            let modified = entry
+                .metadata()
+                .await
+                .and_then(|meta| meta.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if age > max_age {
+                report
+                    .upload_cache_entries
+                    .push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+pub async fn purge_orphans<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    report: &mut OrphanReport,
+) -> anyhow::Result<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    for model in &report.models {
+        diesel::delete(models3d::dsl::models3d.filter(models3d::dsl::id.eq(model.id)))
+            .execute(connection)
+            .await?;
+        diesel::delete(files3d::dsl::files3d.filter(files3d::dsl::model_id.eq(model.id)))
+            .execute(connection)
+            .await?;
+        debug!("Purged orphaned model {:?}", model.folder_path);
+    }
+
+    for preview_image in &report.preview_images {
+        let pth = config.preview_cache_dir.join(preview_image);
+        match fs::remove_file(&pth).await {
+            Ok(()) => debug!("Purged orphaned preview {}", pth.display()),
+            Err(e) => error!("Failed to purge preview {}: {}", pth.display(), e),
+        }
+    }
+
+    for entry in &report.upload_cache_entries {
+        let pth = config.upload_cache.join(entry);
+        if let Err(e) = fs_extra::remove_items(&[&pth]) {
+            error!("Failed to purge upload cache {}: {}", pth.display(), e);
+        }
+    }
+
+    report.purged = true;
+    info!(
+        "Purged {} models, {} previews and {} upload cache entries",
+        report.models.len(),
+        report.preview_images.len(),
+        report.upload_cache_entries.len()
+    );
+
+    Ok(())
+}
+
+pub async fn handle_list_orphans(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Json<OrphanReport>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let report = find_orphans(&state.config, &mut connection)
+        .await
+        .map_err(|e| {
+            error!("Orphan detection failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(report))
+}
+
+pub async fn handle_purge_orphans(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Json<OrphanReport>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let mut report = find_orphans(&state.config, &mut connection)
+        .await
+        .map_err(|e| {
+            error!("Orphan detection failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    purge_orphans(&state.config, &mut connection, &mut report)
+        .await
+        .map_err(|e| {
+            error!("Orphan purge failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(report))
+}
@@ -0,0 +1,121 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel_async::AsyncConnection;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use typeshare::typeshare;
+
+use crate::designers::designer_slug;
+use crate::notifications::base_url;
+use crate::tags::get_model_tags;
+use crate::types::Model3D;
+use crate::Config;
+
+/// Name of the manifest inside exported model folders.
+pub const MANIFEST_FILE: &str = "modelvault.json";
+
+/// Bumped when fields change meaning, importers refuse newer versions.
+pub const MANIFEST_VERSION: i32 = 1;
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// relative to the model folder
+    pub path: String,
+    pub sha256: Option<String>,
+    pub size_bytes: i32,
+    pub file_type: String,
+}
+
+/// Everything about a model which is not already in its folder, so an
+/// archive can be imported into another instance without losing anything.
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelManifest {
+    pub version: i32,
+    pub title: String,
+    pub name: String,
+    pub slug: String,
+    pub author: Option<String>,
+    pub designer: Option<String>,
+    pub origin: Option<String>,
+    pub license: Option<String>,
+    pub description: String,
+    pub notes: String,
+    pub tags: Vec<String>,
+    pub images: Vec<String>,
+    pub files: Vec<ManifestFile>,
+    /// human readable credit line for the license
+    pub attribution: String,
+    pub date_added: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub exported_at: NaiveDateTime,
+    pub exported_from: String,
+}
+
+/// Title, author, source and license of a model as a single credit line.
+pub fn attribution(model: &Model3D) -> String {
+    let mut attribution = model.title.clone();
+    if let Some(author) = model.author.as_ref().filter(|author| !author.is_empty()) {
+        attribution.push_str(&format!(" by {}", author));
+    }
+    if let Some(origin) = model.origin.as_ref().filter(|origin| !origin.is_empty()) {
+        attribution.push_str(&format!(" ({})", origin));
+    }
+    if let Some(license) = model.license.as_ref().filter(|license| !license.is_empty()) {
+        attribution.push_str(&format!(", licensed under {}", license));
+    }
+    attribution
+}
+
+pub async fn model_manifest<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> anyhow::Result<ModelManifest>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let files = model
+        .get_files3d(connection)
+        .await?
+        .into_iter()
+        .map(|file| ManifestFile {
+            path: file.file_path,
+            sha256: file.file_hash,
+            size_bytes: file.file_size_bytes,
+            file_type: file.file_type,
+        })
+        .collect();
+
+    Ok(ModelManifest {
+        version: MANIFEST_VERSION,
+        title: model.title.clone(),
+        name: model.name.clone(),
+        slug: model.slug.clone(),
+        author: model.author.clone(),
+        designer: designer_slug(connection, model.designer_id).await?,
+        origin: model.origin.clone(),
+        license: model.license.clone(),
+        description: model.description.clone(),
+        notes: model.notes.clone(),
+        tags: get_model_tags(connection, model.id).await?,
+        images: model
+            .relative_image_paths()
+            .iter()
+            .map(|image| image.to_string_lossy().into_owned())
+            .collect(),
+        files,
+        attribution: attribution(model),
+        date_added: model.date_added,
+        updated_at: model.updated_at,
+        exported_at: Utc::now().naive_utc(),
+        exported_from: base_url(config),
+    })
+}
+
+pub async fn read_manifest(dir: &Path) -> Option<ModelManifest> {
+    let content = tokio::fs::read_to_string(dir.join(MANIFEST_FILE))
+        .await
+        .ok()?;
+    serde_json::from_str(&content).ok()
+}
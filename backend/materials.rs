@@ -0,0 +1,218 @@
+use image::Limits;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tracing::debug;
+
+use crate::file_types::file_type_of;
+use crate::image_limits;
+use crate::slicer_settings;
+
+/// RGB in `0.0..=1.0`.
+pub type Colour = [f32; 3];
+
+/// MTL statements naming a texture, the file name is their last argument.
+const MTL_TEXTURE_STATEMENTS: &[&str] = &[
+    "map_Ka", "map_Kd", "map_Ks", "map_Ns", "map_d", "map_bump", "map_Bump", "bump", "disp",
+    "decal", "norm", "map_Pr", "map_Pm", "map_Ke",
+];
+
+static THREEMF_MATERIAL_GROUP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)<(?:\w+:)?(basematerials|colorgroup)\b[^>]*\bid="(\d+)"[^>]*>(.*?)</(?:\w+:)?(?:basematerials|colorgroup)>"#)
+        .unwrap()
+});
+static THREEMF_COLOUR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r##"<(?:\w+:)?(?:base|color)\b[^>]*\b(?:displaycolor|color)="#([0-9A-Fa-f]{6})"##)
+        .unwrap()
+});
+static THREEMF_OBJECT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?s)<object\b([^>]*)>(.*?)</object>"#).unwrap());
+static THREEMF_TRIANGLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<triangle\b([^>]*)/>"#).unwrap());
+static XML_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\b(pid|pindex|p1)="(\d+)""#).unwrap());
+
+/// Parses `#RRGGBB`, an alpha channel is ignored.
+pub fn parse_hex_colour(value: &str) -> Option<Colour> {
+    let hex = value.trim().trim_start_matches('#');
+    let channel = |index: usize| {
+        hex.get(index..index + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+            .map(|channel| channel as f32 / 255.0)
+    };
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// The statement arguments of an MTL or OBJ line, `None` for other lines.
+fn statement<'a>(line: &'a str, names: &[&str]) -> Option<&'a str> {
+    let (name, arguments) = line.trim().split_once(char::is_whitespace)?;
+    names.contains(&name).then(|| arguments.trim())
+}
+
+/// The material libraries an OBJ file pulls in.
+fn obj_material_libraries(obj_pth: &Path) -> Vec<PathBuf> {
+    let Ok(content) = std::fs::read_to_string(obj_pth) else {
+        return Vec::new();
+    };
+    let folder = obj_pth.parent().unwrap_or(Path::new("."));
+    content
+        .lines()
+        .filter_map(|line| statement(line, &["mtllib"]))
+        .map(|library| folder.join(library.replace('\\', "/")))
+        .collect()
+}
+
+/// The textures an MTL file references, resolved next to it.
+fn mtl_textures(mtl_pth: &Path) -> Vec<PathBuf> {
+    let Ok(content) = std::fs::read_to_string(mtl_pth) else {
+        return Vec::new();
+    };
+    let folder = mtl_pth.parent().unwrap_or(Path::new("."));
+    content
+        .lines()
+        .filter_map(|line| statement(line, MTL_TEXTURE_STATEMENTS))
+        .filter_map(|arguments| arguments.split_whitespace().last())
+        .map(|texture| folder.join(texture.replace('\\', "/")))
+        .collect()
+}
+
+/// The MTL files and textures an OBJ file needs, wherever they live. Missing
+/// files are left out.
+pub fn obj_dependencies(obj_pth: &Path) -> Vec<PathBuf> {
+    let mut dependencies = Vec::new();
+    for library in obj_material_libraries(obj_pth) {
+        if !library.is_file() {
+            debug!(
+                "{} references missing {}",
+                obj_pth.display(),
+                library.display()
+            );
+            continue;
+        }
+        dependencies.extend(mtl_textures(&library).into_iter().filter(|t| t.is_file()));
+        dependencies.push(library);
+    }
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+/// The average colour of a texture.
+fn texture_colour(texture_pth: &Path, limits: Limits) -> Option<Colour> {
+    let pixel = image_limits::open(texture_pth, limits)
+        .map_err(|e| debug!("No colour of texture {}: {}", texture_pth.display(), e))
+        .ok()?
+        .thumbnail_exact(1, 1)
+        .to_rgb8();
+    let [r, g, b] = pixel.get_pixel(0, 0).0;
+    Some([r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0])
+}
+
+/// The colour covering most faces of an OBJ file, from the diffuse colour or
+/// texture of its materials.
+fn obj_colour(obj_pth: &Path, limits: Limits) -> Option<Colour> {
+    let (models, materials) = tobj::load_obj(
+        obj_pth,
+        &tobj::LoadOptions {
+            triangulate: true,
+            ignore_points: true,
+            ignore_lines: true,
+            ..Default::default()
+        },
+    )
+    .ok()?;
+    let materials = materials.ok()?;
+    let folder = obj_pth.parent().unwrap_or(Path::new("."));
+
+    let mut faces: HashMap<usize, usize> = HashMap::new();
+    for model in &models {
+        if let Some(material_id) = model.mesh.material_id {
+            *faces.entry(material_id).or_default() += model.mesh.indices.len() / 3;
+        }
+    }
+    let (material_id, _) = faces.into_iter().max_by_key(|(_, faces)| *faces)?;
+    let material = materials.get(material_id)?;
+
+    material
+        .diffuse_texture
+        .as_ref()
+        .and_then(|texture| texture_colour(&folder.join(texture.replace('\\', "/")), limits))
+        .or(material.diffuse)
+}
+
+/// The colour most triangles of a 3MF model reference through base
+/// materials or colour groups, falling back to the first filament colour a
+/// slicer saved.
+fn threemf_colour(threemf_pth: &Path) -> Option<Colour> {
+    let file = File::open(threemf_pth).ok()?;
+    let mut archive = zip::ZipArchive::new(file).ok()?;
+    let mut model = String::new();
+    archive
+        .by_name("3D/3dmodel.model")
+        .ok()?
+        .read_to_string(&mut model)
+        .ok()?;
+
+    let groups: HashMap<&str, Vec<Colour>> = THREEMF_MATERIAL_GROUP
+        .captures_iter(&model)
+        .map(|captures| {
+            let colours = THREEMF_COLOUR
+                .captures_iter(captures.get(3).unwrap().as_str())
+                .filter_map(|colour| parse_hex_colour(&colour[1]))
+                .collect();
+            (captures.get(2).unwrap().as_str(), colours)
+        })
+        .collect();
+
+    let attributes = |tag: &str| -> HashMap<String, String> {
+        XML_ATTRIBUTE
+            .captures_iter(tag)
+            .map(|captures| (captures[1].to_string(), captures[2].to_string()))
+            .collect()
+    };
+
+    let mut usage: HashMap<(String, String), usize> = HashMap::new();
+    if !groups.is_empty() {
+        for object in THREEMF_OBJECT.captures_iter(&model) {
+            let defaults = attributes(&object[1]);
+            for triangle in THREEMF_TRIANGLE.captures_iter(&object[2]) {
+                let triangle = attributes(&triangle[1]);
+                let pid = triangle.get("pid").or(defaults.get("pid"));
+                let index = triangle.get("p1").or(defaults.get("pindex"));
+                if let (Some(pid), Some(index)) = (pid, index) {
+                    *usage.entry((pid.clone(), index.clone())).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    let mut usage: Vec<_> = usage.into_iter().collect();
+    usage.sort_by(|a, b| b.1.cmp(&a.1));
+    usage
+        .into_iter()
+        .find_map(|((pid, index), _)| {
+            groups
+                .get(pid.as_str())?
+                .get(index.parse::<usize>().ok()?)
+                .copied()
+        })
+        .or_else(|| {
+            slicer_settings::read_print_settings(threemf_pth)?
+                .colour
+                .as_deref()
+                .and_then(parse_hex_colour)
+        })
+}
+
+/// The colour a preview of `file_pth` is rendered in, `None` keeps the
+/// default material. Textures are decoded within `limits`.
+pub fn preview_colour(file_pth: &Path, limits: Limits) -> Option<Colour> {
+    match file_type_of(file_pth).as_str() {
+        "obj" => obj_colour(file_pth, limits),
+        "3mf" => threemf_colour(file_pth),
+        _ => None,
+    }
+}
@@ -0,0 +1,255 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::convert;
+use crate::schema::files3d;
+use crate::workers;
+use crate::AppState;
+
+/// Height above the lowest point the bed contact is measured at, about a
+/// first layer.
+const CONTACT_HEIGHT: f64 = 0.1;
+/// Resolution face normals are grouped by when looking for flat sides.
+const NORMAL_STEP: f64 = 0.02;
+const MESH_FILE_TYPES: &[&str] = &["stl", "3mf", "obj"];
+/// Downwards, where the side resting on the bed should face.
+const DOWN: [f64; 3] = [0.0, 0.0, -1.0];
+
+/// Geometry the viewer shows without loading the mesh, all lengths in mm.
+#[typeshare]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileMeasurements {
+    pub bounding_box_min: [f64; 3],
+    pub bounding_box_max: [f64; 3],
+    /// assuming uniform density, the surface centroid for open meshes
+    pub center_of_mass: [f64; 3],
+    pub volume_mm3: f64,
+    pub surface_area_mm2: f64,
+    /// area of the cross section just above the lowest point, what touches
+    /// the bed when printed as is
+    pub bed_contact_area_mm2: f64,
+    pub orientation: SuggestedOrientation,
+}
+
+/// The rotation putting the largest flat side of the mesh onto the bed.
+#[typeshare]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuggestedOrientation {
+    /// outward normal of the side that should face down
+    pub down: [f64; 3],
+    pub flat_area_mm2: f64,
+    pub rotation_axis: [f64; 3],
+    pub rotation_degrees: f64,
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(a: [f64; 3]) -> Option<[f64; 3]> {
+    let length = dot(a, a).sqrt();
+    (length > f64::EPSILON).then(|| a.map(|value| value / length))
+}
+
+fn round(value: f64, digits: i32) -> f64 {
+    let factor = 10f64.powi(digits);
+    (value * factor).round() / factor
+}
+
+fn round_point(point: [f64; 3]) -> [f64; 3] {
+    point.map(|value| round(value, 3))
+}
+
+/// The area of the horizontal cross section at `height`. By Green's theorem
+/// the segments where triangles cross the plane add up to the enclosed
+/// area without being chained into outlines, as long as each runs with
+/// the outside on its right.
+fn cross_section_area(triangles: &[[[f64; 3]; 3]], height: f64) -> f64 {
+    let mut area = 0.0;
+    for triangle in triangles {
+        let normal = cross(sub(triangle[1], triangle[0]), sub(triangle[2], triangle[0]));
+        let mut points = Vec::with_capacity(2);
+        for corner in 0..3 {
+            let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+            if (a[2] - height) * (b[2] - height) < 0.0 {
+                let t = (height - a[2]) / (b[2] - a[2]);
+                points.push([a[0] + t * (b[0] - a[0]), a[1] + t * (b[1] - a[1])]);
+            }
+        }
+        let [p, q] = points[..] else {
+            continue;
+        };
+        let direction = [q[0] - p[0], q[1] - p[1]];
+        // z of direction × normal, negative when the outside is on the right
+        let (p, q) = if direction[0] * normal[1] - direction[1] * normal[0] > 0.0 {
+            (q, p)
+        } else {
+            (p, q)
+        };
+        area += (p[0] * q[1] - q[0] * p[1]) / 2.0;
+    }
+    area.abs()
+}
+
+/// The side with the largest area of about equally oriented faces.
+fn suggest_orientation(triangles: &[[[f64; 3]; 3]]) -> SuggestedOrientation {
+    let mut sides: HashMap<[i64; 3], (f64, [f64; 3])> = HashMap::new();
+    for triangle in triangles {
+        let normal = cross(sub(triangle[1], triangle[0]), sub(triangle[2], triangle[0]));
+        let area = dot(normal, normal).sqrt() / 2.0;
+        let Some(unit) = normalize(normal) else {
+            continue;
+        };
+        let key = unit.map(|value| (value / NORMAL_STEP).round() as i64);
+        let side = sides.entry(key).or_insert((0.0, [0.0; 3]));
+        side.0 += area;
+        for (sum, value) in side.1.iter_mut().zip(unit) {
+            *sum += value * area;
+        }
+    }
+
+    let (flat_area, down) = sides
+        .into_values()
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(area, sum)| (area, normalize(sum).unwrap_or(DOWN)))
+        .unwrap_or((0.0, DOWN));
+
+    let angle = dot(down, DOWN).clamp(-1.0, 1.0).acos();
+    // facing straight up any horizontal axis turns it over
+    let rotation_axis = normalize(cross(down, DOWN)).unwrap_or(if angle > 1.0 {
+        [1.0, 0.0, 0.0]
+    } else {
+        [0.0, 0.0, 1.0]
+    });
+    SuggestedOrientation {
+        down: down.map(|value| round(value, 4)),
+        flat_area_mm2: round(flat_area, 2),
+        rotation_axis: rotation_axis.map(|value| round(value, 4)),
+        rotation_degrees: round(angle.to_degrees(), 2),
+    }
+}
+
+pub fn measure(triangles: &[[[f64; 3]; 3]]) -> anyhow::Result<FileMeasurements> {
+    if triangles.is_empty() {
+        anyhow::bail!("Mesh has no triangles");
+    }
+    let mut min = [f64::MAX; 3];
+    let mut max = [f64::MIN; 3];
+    let mut volume = 0.0;
+    let mut volume_moment = [0.0; 3];
+    let mut surface_area = 0.0;
+    let mut surface_moment = [0.0; 3];
+
+    for triangle in triangles {
+        for corner in triangle {
+            for (axis, value) in corner.iter().enumerate() {
+                min[axis] = min[axis].min(*value);
+                max[axis] = max[axis].max(*value);
+            }
+        }
+        let [a, b, c] = *triangle;
+        // signed volume of the tetrahedron spanned with the origin
+        let tetrahedron = dot(a, cross(b, c)) / 6.0;
+        let normal = cross(sub(b, a), sub(c, a));
+        let area = dot(normal, normal).sqrt() / 2.0;
+        volume += tetrahedron;
+        surface_area += area;
+        let corner_sum = [0, 1, 2].map(|axis| a[axis] + b[axis] + c[axis]);
+        for (axis, sum) in corner_sum.into_iter().enumerate() {
+            volume_moment[axis] += tetrahedron * sum / 4.0;
+            surface_moment[axis] += area * sum / 3.0;
+        }
+    }
+
+    // open or inconsistently wound meshes enclose no sensible volume
+    let center_of_mass = if volume.abs() > f64::EPSILON {
+        volume_moment.map(|moment| moment / volume)
+    } else if surface_area > 0.0 {
+        surface_moment.map(|moment| moment / surface_area)
+    } else {
+        [0, 1, 2].map(|axis| (min[axis] + max[axis]) / 2.0)
+    };
+
+    Ok(FileMeasurements {
+        bounding_box_min: round_point(min),
+        bounding_box_max: round_point(max),
+        center_of_mass: round_point(center_of_mass),
+        volume_mm3: round(volume.abs(), 2),
+        surface_area_mm2: round(surface_area, 2),
+        bed_contact_area_mm2: round(cross_section_area(triangles, min[2] + CONTACT_HEIGHT), 2),
+        orientation: suggest_orientation(triangles),
+    })
+}
+
+/// The serialized measurements of a mesh, stored with its file.
+pub fn measure_file(mesh_pth: &std::path::Path) -> Option<String> {
+    match convert::load_triangles(&mesh_pth.to_path_buf()).and_then(|triangles| measure(&triangles))
+    {
+        Ok(measurements) => serde_json::to_string(&measurements).ok(),
+        Err(e) => {
+            error!("Unable to measure {}: {}", mesh_pth.display(), e);
+            None
+        }
+    }
+}
+
+pub async fn handle_file_measurements(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+) -> Result<Json<FileMeasurements>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let (file, _) = viewer.find_file(&mut connection, id).await?;
+
+    let measurements = match file.measurements {
+        Some(measurements) => measurements,
+        None if !MESH_FILE_TYPES.contains(&file.file_type.as_str()) => {
+            return Err(StatusCode::NOT_FOUND)
+        }
+        // files scanned before measurements were introduced
+        None => {
+            let mesh_pth = file.get_file_path(&mut connection, &state.config).await;
+            let measurements = workers::run(move || measure_file(&mesh_pth))
+                .await
+                .ok()
+                .flatten()
+                .ok_or(StatusCode::NOT_FOUND)?;
+            diesel::update(files3d::dsl::files3d.find(file.id))
+                .set(files3d::dsl::measurements.eq(&measurements))
+                .execute(&mut connection)
+                .await
+                .map_err(|e| {
+                    error!("Failed to store measurements of file {}: {}", file.id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            measurements
+        }
+    };
+
+    serde_json::from_str(&measurements).map(Json).map_err(|e| {
+        error!("Invalid measurements of file {}: {}", file.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::Config;
+
+/// Videos in model folders, usually timelapses of prints. They are served
+/// from the library like any other file, which answers ranged requests so
+/// players can seek.
+pub const VIDEO_FILE_TYPES: &[&str] = &["mp4", "webm"];
+
+/// Extracting a single frame should take seconds even for long videos.
+const POSTER_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub fn is_video_file_type(file_type: &str) -> bool {
+    VIDEO_FILE_TYPES.contains(&file_type)
+}
+
+/// Extracts a frame one second into a video as its preview, with the
+/// configured `ffmpeg` binary. Videos shorter than that get their first
+/// frame. Returns the name of the image in the preview cache.
+pub async fn poster_frame(config: &Config, source: &Path, hash: &str) -> Option<String> {
+    let binary = config.ffmpeg_binary.as_ref()?;
+    let file_name = format!("{}.png", hash);
+    let target = config.preview_cache_dir.join(&file_name);
+
+    for seek in ["1", "0"] {
+        let child = Command::new(binary)
+            .args(["-nostdin", "-y", "-loglevel", "error", "-ss", seek, "-i"])
+            .arg(source)
+            .args(["-frames:v", "1", "-vf", "scale='min(1024,iw)':-2"])
+            .arg(&target)
+            .env_clear()
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                debug!("Unable to run {}: {}", binary, e);
+                return None;
+            }
+        };
+        match tokio::time::timeout(POSTER_TIMEOUT, child.wait_with_output()).await {
+            Ok(Ok(output)) if output.status.success() && target.exists() => {
+                return Some(file_name);
+            }
+            Ok(Ok(output)) => debug!(
+                "No poster frame at {}s of {}: {}",
+                seek,
+                source.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Ok(Err(e)) => debug!("ffmpeg failed on {}: {}", source.display(), e),
+            Err(_) => {
+                debug!("ffmpeg timed out on {}", source.display());
+                return None;
+            }
+        }
+    }
+    None
+}
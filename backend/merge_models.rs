@@ -0,0 +1,411 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::fs;
+use tracing::{debug, error};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::audit_log::record;
+use crate::parse_library::update_modelpack_meta;
+use crate::rollback::{self, Rollback};
+use crate::schema::{
+    download_clients, download_counts, files3d, model_events, print_logs, slug_redirects,
+};
+use crate::tags::{get_model_tags, normalize_tags, set_model_tags};
+use crate::trash::purge_model;
+use crate::types::{File3D, Model3D};
+use crate::workers;
+use crate::{AppState, Config};
+
+/// What happens to a file of the merged model if the target already has a
+/// different file at the same path. Identical files are never copied.
+#[typeshare]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    KeepTarget,
+    KeepSource,
+    /// the file of the merged model is copied under a new name
+    #[default]
+    KeepBoth,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeModelsRequest {
+    /// slug of the duplicate merged into the model of the path
+    pub source: String,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+    /// remove the folder of the duplicate instead of moving it to the trash
+    #[serde(default)]
+    pub delete_source: bool,
+}
+
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MergeReport {
+    pub slug: String,
+    /// paths inside the model folder, as copied
+    pub copied: Vec<String>,
+    pub replaced: Vec<String>,
+    /// identical files and conflicts resolved for the target
+    pub skipped: Vec<String>,
+    pub tags: Vec<String>,
+    pub print_logs: i32,
+    pub source_deleted: bool,
+}
+
+/// A free path for a conflicting file, `part_<slug>.stl` and numbered after
+/// that.
+async fn free_path(dir: &std::path::Path, relative: &std::path::Path, slug: &str) -> PathBuf {
+    let stem = relative
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = relative
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut suffix = 1;
+    loop {
+        let name = match suffix {
+            1 => format!("{}_{}{}", stem, slug, extension),
+            _ => format!("{}_{}_{}{}", stem, slug, suffix, extension),
+        };
+        let candidate = relative.with_file_name(name);
+        if !fs::try_exists(dir.join(&candidate)).await.unwrap_or(true) {
+            return candidate;
+        }
+        suffix += 1;
+    }
+}
+
+async fn same_content(a: &std::path::Path, b: &std::path::Path) -> bool {
+    match (workers::digest(a).await, workers::digest(b).await) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Copies the files and images of `source` into the folder of `target`.
+/// Returns where each copied file ended up inside the target folder.
+/// Replaced and new files are recorded in `rollback`.
+async fn copy_files(
+    config: &Config,
+    target: &Model3D,
+    source: &Model3D,
+    files: &[File3D],
+    policy: ConflictPolicy,
+    report: &mut MergeReport,
+    rollback: &Rollback,
+) -> anyhow::Result<HashMap<String, String>> {
+    let source_dir = source.absolute_path(config);
+    let target_dir = target.absolute_path(config);
+    let relative_paths = files
+        .iter()
+        .map(|file| PathBuf::from(&file.file_path))
+        .chain(source.relative_image_paths());
+
+    let mut locations = HashMap::new();
+    for relative in relative_paths {
+        let from = source_dir.join(&relative);
+        if !fs::symlink_metadata(&from).await?.is_file() {
+            anyhow::bail!("{} is not a regular file", from.display());
+        }
+        let mut destination = relative.clone();
+        let to = target_dir.join(&relative);
+        let exists = fs::try_exists(&to).await?;
+        if exists {
+            if same_content(&from, &to).await || policy == ConflictPolicy::KeepTarget {
+                let relative = relative.to_string_lossy().into_owned();
+                report.skipped.push(relative.clone());
+                locations.insert(relative.clone(), relative);
+                continue;
+            }
+            if policy == ConflictPolicy::KeepBoth {
+                destination = free_path(&target_dir, &relative, &source.slug).await;
+            }
+        }
+
+        let to = target_dir.join(&destination);
+        if let Some(parent) = to.parent() {
+            rollback.create_dir_all(parent).await?;
+        }
+        let replaced = exists && destination == relative;
+        if replaced {
+            rollback.replace(&to).await?;
+        } else {
+            rollback.created(&to);
+        }
+        fs::copy(&from, &to).await?;
+        debug!("Copied {} to {}", from.display(), to.display());
+        let destination = destination.to_string_lossy().into_owned();
+        if replaced {
+            report.replaced.push(destination.clone());
+        } else {
+            report.copied.push(destination.clone());
+        }
+        locations.insert(relative.to_string_lossy().into_owned(), destination);
+    }
+    Ok(locations)
+}
+
+/// Moves statistics, print logs and slugs of `source` over to `target`.
+async fn move_records<Conn>(
+    connection: &mut Conn,
+    target: &Model3D,
+    source: &Model3D,
+    file_ids: &HashMap<i32, i32>,
+) -> QueryResult<i32>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    for (source_file, target_file) in file_ids {
+        diesel::update(
+            print_logs::dsl::print_logs.filter(print_logs::dsl::file_id.eq(*source_file)),
+        )
+        .set(print_logs::dsl::file_id.eq(Some(*target_file)))
+        .execute(connection)
+        .await?;
+        diesel::update(
+            print_logs::dsl::print_logs.filter(print_logs::dsl::timelapse_id.eq(*source_file)),
+        )
+        .set(print_logs::dsl::timelapse_id.eq(Some(*target_file)))
+        .execute(connection)
+        .await?;
+    }
+    let source_files: Vec<i32> = files3d::dsl::files3d
+        .filter(files3d::dsl::model_id.eq(source.id))
+        .select(files3d::dsl::id)
+        .load::<i32>(connection)
+        .await?;
+    // prints of files which were not copied keep their figures
+    diesel::update(
+        print_logs::dsl::print_logs
+            .filter(print_logs::dsl::model_id.eq(source.id))
+            .filter(print_logs::dsl::file_id.eq_any(&source_files)),
+    )
+    .set(print_logs::dsl::file_id.eq(None::<i32>))
+    .execute(connection)
+    .await?;
+    diesel::update(
+        print_logs::dsl::print_logs
+            .filter(print_logs::dsl::model_id.eq(source.id))
+            .filter(print_logs::dsl::timelapse_id.eq_any(&source_files)),
+    )
+    .set(print_logs::dsl::timelapse_id.eq(None::<i32>))
+    .execute(connection)
+    .await?;
+    let moved_logs =
+        diesel::update(print_logs::dsl::print_logs.filter(print_logs::dsl::model_id.eq(source.id)))
+            .set(print_logs::dsl::model_id.eq(target.id))
+            .execute(connection)
+            .await?;
+
+    diesel::update(
+        model_events::dsl::model_events.filter(model_events::dsl::model_id.eq(source.id)),
+    )
+    .set(model_events::dsl::model_id.eq(target.id))
+    .execute(connection)
+    .await?;
+
+    let downloads = download_counts::dsl::download_counts
+        .filter(download_counts::dsl::model_id.eq(source.id))
+        .select((download_counts::dsl::day, download_counts::dsl::downloads))
+        .load::<(chrono::NaiveDate, i32)>(connection)
+        .await?;
+    for (day, count) in downloads {
+        diesel::insert_into(download_counts::table)
+            .values((
+                download_counts::dsl::model_id.eq(target.id),
+                download_counts::dsl::day.eq(day),
+                download_counts::dsl::downloads.eq(count),
+            ))
+            .on_conflict((download_counts::dsl::model_id, download_counts::dsl::day))
+            .do_update()
+            .set(download_counts::dsl::downloads.eq(download_counts::dsl::downloads + count))
+            .execute(connection)
+            .await?;
+    }
+    diesel::delete(
+        download_counts::dsl::download_counts.filter(download_counts::dsl::model_id.eq(source.id)),
+    )
+    .execute(connection)
+    .await?;
+    diesel::delete(
+        download_clients::dsl::download_clients
+            .filter(download_clients::dsl::model_id.eq(source.id)),
+    )
+    .execute(connection)
+    .await?;
+
+    // old links to the duplicate lead to the merged model
+    diesel::update(
+        slug_redirects::dsl::slug_redirects.filter(slug_redirects::dsl::model_id.eq(source.id)),
+    )
+    .set(slug_redirects::dsl::model_id.eq(target.id))
+    .execute(connection)
+    .await?;
+    diesel::insert_into(slug_redirects::table)
+        .values((
+            slug_redirects::dsl::slug.eq(&source.slug),
+            slug_redirects::dsl::model_id.eq(target.id),
+        ))
+        .on_conflict(slug_redirects::dsl::slug)
+        .do_update()
+        .set(slug_redirects::dsl::model_id.eq(target.id))
+        .execute(connection)
+        .await?;
+
+    Ok(moved_logs as i32)
+}
+
+/// Merges a duplicate into the model of the path: files and images are
+/// copied over, tags combined, statistics and print logs moved and the slug
+/// of the duplicate redirects to the merged model. The duplicate is moved to
+/// the trash afterwards, or removed with `delete_source`. Until then a
+/// failure removes the copied files again and restores replaced ones.
+pub async fn handle_merge_models(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+    Json(request): Json<MergeModelsRequest>,
+) -> Result<Json<MergeReport>, StatusCode> {
+    let config = &state.config;
+    let mut connection = state.connection().await?;
+
+    let target = viewer.manage_model(&mut connection, &slug).await?;
+    let source = viewer
+        .manage_model(&mut connection, &request.source)
+        .await?;
+    if target.id == source.id {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let mut report = MergeReport {
+        slug: target.slug.clone(),
+        ..Default::default()
+    };
+    let source_files = source.get_files3d(&mut connection).await.map_err(|e| {
+        error!("Failed to load files of {}: {}", source.slug, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let tags = [
+        get_model_tags(&mut connection, target.id).await,
+        get_model_tags(&mut connection, source.id).await,
+    ]
+    .into_iter()
+    .collect::<QueryResult<Vec<_>>>()
+    .map_err(|e| {
+        error!("Failed to load tags: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .concat();
+    report.tags = normalize_tags(&tags).into_iter().collect();
+
+    let rollback = Rollback::default();
+    let copied = copy_files(
+        config,
+        &target,
+        &source,
+        &source_files,
+        request.on_conflict,
+        &mut report,
+        &rollback,
+    )
+    .await;
+    let locations = match copied {
+        Ok(locations) => locations,
+        Err(e) => {
+            error!("Failed to copy {} into {}: {}", source.slug, target.slug, e);
+            rollback.undo().await;
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let dir = target.absolute_path(config);
+    match update_modelpack_meta(config, &dir, |meta| meta.tags = Some(report.tags.clone())).await {
+        Ok(previous) => rollback.written(dir.join("modelpack.json"), previous),
+        Err(e) => error!("Failed to write tags of {}: {}", target.slug, e),
+    }
+
+    let (viewer_ref, target_ref, source_ref, source_files_ref, locations_ref, tags_ref) = (
+        &viewer,
+        &target,
+        &source,
+        &source_files,
+        &locations,
+        &report.tags,
+    );
+    let on_conflict = request.on_conflict;
+    let moved_logs = rollback::transaction(&mut *connection, &rollback, |connection| {
+        async move {
+            // indexes the copied files
+            target_ref.scan(config, connection).await?;
+            let target_files = target_ref.get_files3d(connection).await?;
+            let file_ids: HashMap<i32, i32> = source_files_ref
+                .iter()
+                .filter_map(|file| {
+                    let location = locations_ref.get(&file.file_path)?;
+                    let copy = target_files
+                        .iter()
+                        .find(|copy| &copy.file_path == location)?;
+                    Some((file.id, copy.id))
+                })
+                .collect();
+
+            set_model_tags(connection, target_ref.id, tags_ref).await?;
+            let moved_logs = move_records(connection, target_ref, source_ref, &file_ids).await?;
+            record(
+                connection,
+                viewer_ref,
+                "model.merge",
+                &target_ref.slug,
+                json!({
+                    "source": source_ref.slug,
+                    "on_conflict": on_conflict,
+                    "files": file_ids.len(),
+                }),
+            )
+            .await?;
+            anyhow::Ok(moved_logs)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(|e| {
+        error!(
+            "Failed to merge {} into {}: {:#}",
+            source.slug, target.slug, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    report.print_logs = moved_logs;
+
+    let disposed = if request.delete_source {
+        purge_model(config, &mut connection, &source).await
+    } else {
+        source.trash(config, &mut connection, None).await
+    };
+    if let Err(e) = disposed {
+        error!("Failed to remove merged model {}: {}", source.slug, e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    report.source_deleted = request.delete_source;
+    if let Err(e) = target.touch(&mut connection).await {
+        error!("Failed to touch {}: {}", target.slug, e);
+    }
+
+    Ok(Json(report))
+}
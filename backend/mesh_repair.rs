@@ -0,0 +1,423 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Cursor;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::convert::load_triangles;
+use crate::jobs::check_cancelled;
+use crate::schema::files3d;
+use crate::types::{File3D, Model3D};
+use crate::workers;
+use crate::AppState;
+
+/// Corners closer than this in mm are merged into one vertex.
+const WELD_TOLERANCE: f64 = 1e-4;
+/// Triangles with less area in mm² are dropped.
+const MIN_AREA: f64 = 1e-10;
+/// Holes bordered by more edges are left open, filling them with a fan
+/// would change the shape.
+const MAX_HOLE_EDGES: usize = 32;
+
+const REPAIRABLE_FILE_TYPES: &[&str] = &["stl", "obj", "3mf"];
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepairStarted {
+    pub job_id: String,
+}
+
+/// What a repair pass changed, stored as the result of its job.
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RepairSummary {
+    /// the repaired variant, once the model was rescanned
+    pub file_id: Option<i32>,
+    pub file_path: String,
+    pub triangles_before: i32,
+    pub triangles_after: i32,
+    pub removed_degenerate: i32,
+    pub removed_duplicates: i32,
+    pub flipped_triangles: i32,
+    pub closed_holes: i32,
+    pub added_triangles: i32,
+    /// edges of holes too large to close
+    pub open_edges_left: i32,
+}
+
+struct Mesh {
+    vertices: Vec<[f64; 3]>,
+    faces: Vec<[usize; 3]>,
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+impl Mesh {
+    /// Merges coincident corners, so triangles share their vertices.
+    fn weld(triangles: &[[[f64; 3]; 3]]) -> Mesh {
+        let mut indices: HashMap<[i64; 3], usize> = HashMap::new();
+        let mut vertices = Vec::new();
+        let mut faces = Vec::with_capacity(triangles.len());
+        for triangle in triangles {
+            let face = triangle.map(|corner| {
+                let key = corner.map(|value| (value / WELD_TOLERANCE).round() as i64);
+                *indices.entry(key).or_insert_with(|| {
+                    vertices.push(corner);
+                    vertices.len() - 1
+                })
+            });
+            faces.push(face);
+        }
+        Mesh { vertices, faces }
+    }
+
+    fn normal(&self, face: [usize; 3]) -> [f64; 3] {
+        let [a, b, c] = face.map(|index| self.vertices[index]);
+        cross(sub(b, a), sub(c, a))
+    }
+
+    /// Drops triangles without area and repeated triangles, returns how
+    /// many of each were removed.
+    fn remove_degenerate(&mut self) -> (i32, i32) {
+        let mut degenerate = 0;
+        let mut duplicates = 0;
+        let mut seen = HashSet::new();
+        let faces = std::mem::take(&mut self.faces);
+        for face in faces {
+            let [a, b, c] = face;
+            if a == b || b == c || a == c || length(self.normal(face)) / 2.0 < MIN_AREA {
+                degenerate += 1;
+                continue;
+            }
+            let mut key = face;
+            key.sort_unstable();
+            if !seen.insert(key) {
+                duplicates += 1;
+                continue;
+            }
+            self.faces.push(face);
+        }
+        (degenerate, duplicates)
+    }
+
+    /// The faces using each undirected edge.
+    fn edge_faces(&self) -> HashMap<(usize, usize), Vec<usize>> {
+        let mut edges: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (index, face) in self.faces.iter().enumerate() {
+            for corner in 0..3 {
+                let (a, b) = (face[corner], face[(corner + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_default().push(index);
+            }
+        }
+        edges
+    }
+
+    fn has_directed_edge(face: [usize; 3], a: usize, b: usize) -> bool {
+        (0..3).any(|corner| face[corner] == a && face[(corner + 1) % 3] == b)
+    }
+
+    fn flip(&mut self, index: usize) {
+        self.faces[index].swap(1, 2);
+    }
+
+    /// Groups faces connected by manifold edges, flipping neighbours whose
+    /// winding disagrees on the way. Returns the groups and the flip count.
+    fn orient_consistently(&mut self) -> (Vec<Vec<usize>>, i32) {
+        let edges = self.edge_faces();
+        let mut visited = vec![false; self.faces.len()];
+        let mut components = Vec::new();
+        let mut flipped = 0;
+
+        for start in 0..self.faces.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut component = vec![start];
+            let mut queue = VecDeque::from([start]);
+            while let Some(current) = queue.pop_front() {
+                let face = self.faces[current];
+                for corner in 0..3 {
+                    let (a, b) = (face[corner], face[(corner + 1) % 3]);
+                    let neighbours = &edges[&(a.min(b), a.max(b))];
+                    if neighbours.len() != 2 {
+                        continue;
+                    }
+                    for &neighbour in neighbours {
+                        if visited[neighbour] {
+                            continue;
+                        }
+                        visited[neighbour] = true;
+                        // a consistent neighbour runs along the edge the other way
+                        if Self::has_directed_edge(self.faces[neighbour], a, b) {
+                            self.flip(neighbour);
+                            flipped += 1;
+                        }
+                        component.push(neighbour);
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+            components.push(component);
+        }
+        (components, flipped)
+    }
+
+    /// Edges used by a single face, in the direction of that face.
+    fn boundary_edges(&self) -> Vec<(usize, usize)> {
+        let edges = self.edge_faces();
+        let mut boundary = Vec::new();
+        for face in &self.faces {
+            for corner in 0..3 {
+                let (a, b) = (face[corner], face[(corner + 1) % 3]);
+                if edges[&(a.min(b), a.max(b))].len() == 1 {
+                    boundary.push((a, b));
+                }
+            }
+        }
+        boundary
+    }
+
+    /// Closes small holes with a fan around their centre. Returns the
+    /// closed holes and the triangles added.
+    fn fill_holes(&mut self) -> (i32, i32) {
+        let mut next: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (a, b) in self.boundary_edges() {
+            next.entry(a).or_default().push(b);
+        }
+        let mut used: HashSet<usize> = HashSet::new();
+        let mut holes = 0;
+        let mut added = 0;
+
+        let starts: Vec<usize> = next.keys().copied().collect();
+        for start in starts {
+            if used.contains(&start) {
+                continue;
+            }
+            // walk the border, giving up where it touches itself
+            let mut hole = vec![start];
+            let mut current = start;
+            let closed = loop {
+                match next.get(&current).map(Vec::as_slice) {
+                    Some([following]) if *following == start => break true,
+                    Some([following]) if !hole.contains(following) => {
+                        current = *following;
+                        hole.push(current);
+                        if hole.len() > MAX_HOLE_EDGES {
+                            break false;
+                        }
+                    }
+                    _ => break false,
+                }
+            };
+            used.extend(&hole);
+            if !closed {
+                continue;
+            }
+
+            // the border runs along its faces, the patch the other way round
+            if hole.len() == 3 {
+                self.faces.push([hole[0], hole[2], hole[1]]);
+                added += 1;
+            } else {
+                let mut centre = [0.0; 3];
+                for &index in &hole {
+                    for (axis, value) in self.vertices[index].into_iter().enumerate() {
+                        centre[axis] += value / hole.len() as f64;
+                    }
+                }
+                self.vertices.push(centre);
+                let centre = self.vertices.len() - 1;
+                for (position, &a) in hole.iter().enumerate() {
+                    let b = hole[(position + 1) % hole.len()];
+                    self.faces.push([b, a, centre]);
+                    added += 1;
+                }
+            }
+            holes += 1;
+        }
+        (holes, added)
+    }
+
+    /// Turns every group of faces inside out whose enclosed volume is
+    /// negative, so normals point outwards.
+    fn orient_outwards(&mut self, components: &[Vec<usize>]) -> i32 {
+        let mut flipped = 0;
+        for component in components {
+            let volume: f64 = component
+                .iter()
+                .map(|&index| {
+                    let [a, b, c] = self.faces[index].map(|vertex| self.vertices[vertex]);
+                    dot(a, cross(b, c))
+                })
+                .sum();
+            if volume < 0.0 {
+                for &index in component {
+                    self.flip(index);
+                }
+                flipped += component.len() as i32;
+            }
+        }
+        flipped
+    }
+
+    fn to_stl(&self) -> anyhow::Result<Vec<u8>> {
+        let to_f32 = |point: [f64; 3]| point.map(|value| value as f32);
+        let triangles: Vec<stl_io::Triangle> = self
+            .faces
+            .iter()
+            .map(|&face| {
+                let normal = self.normal(face);
+                let norm = length(normal).max(f64::MIN_POSITIVE);
+                stl_io::Triangle {
+                    normal: stl_io::Normal::new(to_f32(normal.map(|value| value / norm))),
+                    vertices: face.map(|index| stl_io::Vertex::new(to_f32(self.vertices[index]))),
+                }
+            })
+            .collect();
+        let mut buffer = Cursor::new(Vec::new());
+        stl_io::write_stl(&mut buffer, triangles.iter())?;
+        Ok(buffer.into_inner())
+    }
+}
+
+/// Runs the repair pass on the triangles of a mesh, returning the STL of
+/// the repaired mesh.
+fn repair(triangles: &[[[f64; 3]; 3]]) -> anyhow::Result<(Vec<u8>, RepairSummary)> {
+    let mut mesh = Mesh::weld(triangles);
+    let (removed_degenerate, removed_duplicates) = mesh.remove_degenerate();
+    let (_, flipped_inconsistent) = mesh.orient_consistently();
+    let (closed_holes, added_triangles) = mesh.fill_holes();
+    // closing holes joins faces into the groups they are oriented by
+    let (components, _) = mesh.orient_consistently();
+    let flipped_outwards = mesh.orient_outwards(&components);
+    if mesh.faces.is_empty() {
+        anyhow::bail!("No triangles left after removing degenerate ones");
+    }
+
+    let summary = RepairSummary {
+        triangles_before: triangles.len() as i32,
+        triangles_after: mesh.faces.len() as i32,
+        removed_degenerate,
+        removed_duplicates,
+        flipped_triangles: flipped_inconsistent + flipped_outwards,
+        closed_holes,
+        added_triangles,
+        open_edges_left: mesh.boundary_edges().len() as i32,
+        ..Default::default()
+    };
+    Ok((mesh.to_stl()?, summary))
+}
+
+/// `part.3mf` is repaired into `part_repaired.stl` next to it, a repeated
+/// repair replaces the earlier variant.
+fn variant_path(source: &std::path::Path) -> PathBuf {
+    let stem = source
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    source.with_file_name(format!("{}_repaired.stl", stem))
+}
+
+async fn run_repair(
+    state: &AppState,
+    model: &Model3D,
+    file: &File3D,
+    cancel: &CancellationToken,
+) -> anyhow::Result<RepairSummary> {
+    let mut connection = state.pool.get().await?;
+    let source = file.get_file_path(&mut connection, &state.config).await;
+    let target = variant_path(&source);
+
+    let triangles_source = source.clone();
+    let (stl, mut summary) = workers::run(move || {
+        let triangles = load_triangles(&triangles_source)?;
+        repair(&triangles)
+    })
+    .await??;
+    check_cancelled(cancel)?;
+
+    fs::write(&target, stl).await?;
+    model.scan(&state.config, &mut connection).await?;
+
+    let relative_path = pathdiff::diff_paths(&target, model.absolute_path(&state.config))
+        .unwrap_or_else(|| target.clone());
+    summary.file_path = relative_path.to_string_lossy().into_owned();
+    summary.file_id = files3d::dsl::files3d
+        .filter(files3d::dsl::model_id.eq(model.id))
+        .filter(files3d::dsl::file_path.eq(&summary.file_path))
+        .select(files3d::dsl::id)
+        .first::<i32>(&mut connection)
+        .await
+        .optional()?;
+    info!(
+        "Repaired {} into {}: {:?}",
+        source.display(),
+        target.display(),
+        summary
+    );
+    Ok(summary)
+}
+
+/// Queues a repair of a mesh file. The repaired variant is written next to
+/// the original, the summary becomes the result of the returned job.
+pub async fn handle_repair(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path((slug, file_id)): Path<(String, i32)>,
+) -> Result<Json<RepairStarted>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let model = viewer.manage_model(&mut connection, &slug).await?;
+    let (file, _) = viewer.find_file(&mut connection, file_id).await?;
+    if file.model_id != model.id {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if !REPAIRABLE_FILE_TYPES.contains(&file.file_type.as_str()) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    drop(connection);
+
+    let (job_id, cancel) = state.jobs.start("repair");
+    tokio::spawn(async move {
+        let result = run_repair(&state, &model, &file, &cancel).await;
+        match &result {
+            Ok(summary) => state.jobs.set_result(job_id, summary),
+            Err(e) => error!("Repairing file {} failed: {}", file.id, e),
+        }
+        state.jobs.complete(job_id, &result);
+    });
+
+    Ok(Json(RepairStarted {
+        job_id: job_id.to_string(),
+    }))
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use typeshare::typeshare;
+
+/// Meshes whose largest side is outside this range in mm were probably
+/// exported in other units than millimetres.
+const PLAUSIBLE_MM: std::ops::RangeInclusive<f64> = 3.0..=2000.0;
+/// Sizes a printable part usually has, a scale is only suggested if it
+/// brings the mesh into this range.
+const TYPICAL_MM: std::ops::RangeInclusive<f64> = 10.0..=500.0;
+
+/// Units tried for meshes which are too small, with the factor to mm.
+const SMALL_UNITS: &[(f64, &str)] = &[(25.4, "inches"), (10.0, "centimetres"), (1000.0, "metres")];
+/// Units tried for meshes which are too large.
+const LARGE_UNITS: &[(f64, &str)] = &[(0.001, "micrometres"), (0.1, "tenths of a millimetre")];
+
+/// A mesh which is suspiciously small or large to be printed as is.
+#[typeshare]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScaleWarning {
+    pub largest_side_mm: f64,
+    /// what the coordinates of the mesh are probably meant in
+    pub suspected_unit: String,
+    /// factor scaling the mesh to millimetres
+    pub suggested_scale: f64,
+}
+
+/// Checks the bounding box of a mesh, `None` if its size is plausible or no
+/// other unit makes it so.
+pub fn check_scale(size: [Option<f64>; 3]) -> Option<ScaleWarning> {
+    let largest = size
+        .into_iter()
+        .collect::<Option<Vec<f64>>>()?
+        .into_iter()
+        .fold(0.0, f64::max);
+    if largest <= 0.0 || PLAUSIBLE_MM.contains(&largest) {
+        return None;
+    }
+    let candidates = if largest < *PLAUSIBLE_MM.start() {
+        SMALL_UNITS
+    } else {
+        LARGE_UNITS
+    };
+    candidates
+        .iter()
+        .find(|(scale, _)| TYPICAL_MM.contains(&(largest * scale)))
+        .map(|(scale, unit)| ScaleWarning {
+            largest_side_mm: largest,
+            suspected_unit: unit.to_string(),
+            suggested_scale: *scale,
+        })
+}
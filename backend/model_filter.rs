@@ -0,0 +1,165 @@
+use axum::http::StatusCode;
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use tracing::debug;
+
+use crate::access::Viewer;
+use crate::build_volume::BuildVolume;
+use crate::file_types;
+use crate::names::fold_name;
+use crate::schema::{designers, files3d, model_tags, models3d, tags, users};
+use crate::tags::normalize_tag;
+use crate::types::ListModelParams;
+
+define_sql_function! {
+    /// SQLite `RANDOM()`, used to shuffle models
+    fn random() -> BigInt;
+}
+
+/// Narrows `models` to those mentioning `term` in their texts, file names
+/// or tags.
+pub fn mentions<'a>(
+    models: models3d::BoxedQuery<'a, Sqlite>,
+    term: &str,
+) -> models3d::BoxedQuery<'a, Sqlite> {
+    let pattern = format!("%{}%", term);
+    models.filter(
+        models3d::dsl::name
+            .like(pattern.clone())
+            .or(models3d::dsl::name_key.like(format!("%{}%", fold_name(term))))
+            .or(models3d::dsl::title.like(pattern.clone()))
+            .or(models3d::dsl::description.like(pattern.clone()))
+            .or(models3d::dsl::notes.like(pattern.clone()))
+            .or(models3d::dsl::id.eq_any(
+                files3d::dsl::files3d
+                    .filter(files3d::dsl::file_path.like(pattern.clone()))
+                    .select(files3d::dsl::model_id),
+            ))
+            .or(models3d::dsl::id.eq_any(
+                model_tags::dsl::model_tags
+                    .inner_join(tags::table)
+                    .filter(tags::dsl::name.like(pattern.clone()))
+                    .select(model_tags::dsl::model_id),
+            ))
+            .or(models3d::dsl::author.like(pattern)),
+    )
+}
+
+/// Builds the query for all models in the library matching the list filters,
+/// in natural order of their titles. Trashed models, models of other tenants
+/// and models hidden from the viewer are never included.
+pub fn filter_models(
+    params: &ListModelParams,
+    viewer: &Viewer,
+) -> Result<models3d::BoxedQuery<'static, Sqlite>, StatusCode> {
+    let mut models = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
+        .order((models3d::dsl::sort_key.asc(), models3d::dsl::id.asc()))
+        .into_boxed();
+
+    if let Some(tenant_id) = viewer.scope.tenant_id() {
+        models = models.filter(models3d::dsl::tenant_id.eq(tenant_id));
+    }
+
+    if let Some(visible_ids) = viewer.visible_ids() {
+        models = models.filter(models3d::dsl::id.eq_any(visible_ids));
+    }
+
+    if let Some(ref q) = params.q {
+        models = mentions(models, q);
+    }
+
+    if let Some(licenses) = &params.licenses {
+        let split_licenses: Vec<String> = licenses.split(',').map(|s| s.to_string()).collect();
+        debug!("Filtering models with licenses: {:?}", split_licenses);
+        models = models.filter(models3d::dsl::license.eq_any(split_licenses));
+    }
+
+    if let Some(ref has) = params.has {
+        for extensions in file_types::parse_has(has) {
+            debug!("Filtering models containing: {:?}", extensions);
+            models = models.filter(
+                models3d::dsl::id.eq_any(
+                    files3d::dsl::files3d
+                        .filter(files3d::dsl::file_type.eq_any(extensions))
+                        .select(files3d::dsl::model_id),
+                ),
+            );
+        }
+    }
+
+    if let Some(ref tag_list) = params.tags {
+        for tag in tag_list.split(',').filter_map(normalize_tag) {
+            debug!("Filtering models tagged: {:?}", tag);
+            models = models.filter(
+                models3d::dsl::id.eq_any(
+                    model_tags::dsl::model_tags
+                        .inner_join(tags::table)
+                        .filter(tags::dsl::name.eq(tag))
+                        .select(model_tags::dsl::model_id),
+                ),
+            );
+        }
+    }
+
+    if let Some(ref uploader) = params.uploader {
+        debug!("Filtering models uploaded by {:?}", uploader);
+        models = match (uploader.trim(), &viewer.user) {
+            ("me", Some(user)) => models.filter(models3d::dsl::uploader_id.eq(user.id)),
+            ("me", None) => return Err(StatusCode::UNAUTHORIZED),
+            (name, _) => models.filter(
+                models3d::dsl::uploader_id.eq_any(
+                    users::dsl::users
+                        .filter(users::dsl::name.eq(name.to_string()))
+                        .select(users::dsl::id.nullable()),
+                ),
+            ),
+        };
+    }
+
+    if let Some(ref designer) = params.designer {
+        debug!("Filtering models of designer {:?}", designer);
+        models = models.filter(
+            models3d::dsl::designer_id.eq_any(
+                designers::dsl::designers
+                    .filter(designers::dsl::slug.eq(designer.trim().to_string()))
+                    .select(designers::dsl::id.nullable()),
+            ),
+        );
+    }
+
+    if let Some(ref fits) = params.fits {
+        let Some(volume) = BuildVolume::parse(fits) else {
+            return Err(StatusCode::BAD_REQUEST);
+        };
+        debug!("Filtering models fitting into {:?}", volume);
+
+        // parts may be rotated around z on the bed
+        let too_large = files3d::dsl::size_z.gt(volume.z).or(files3d::dsl::size_x
+            .gt(volume.x)
+            .or(files3d::dsl::size_y.gt(volume.y))
+            .and(
+                files3d::dsl::size_x
+                    .gt(volume.y)
+                    .or(files3d::dsl::size_y.gt(volume.x)),
+            ));
+
+        models = models
+            .filter(
+                models3d::dsl::id.eq_any(
+                    files3d::dsl::files3d
+                        .filter(files3d::dsl::size_x.is_not_null())
+                        .select(files3d::dsl::model_id),
+                ),
+            )
+            .filter(
+                models3d::dsl::id.ne_all(
+                    files3d::dsl::files3d
+                        .filter(too_large)
+                        .select(files3d::dsl::model_id),
+                ),
+            );
+    }
+
+    Ok(models)
+}
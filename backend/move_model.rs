@@ -0,0 +1,192 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, PathBuf};
+use tokio::fs;
+use tracing::{debug, error};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::parse_library::{add_or_update_model, get_modelpack_meta};
+use crate::schema::models3d;
+use crate::tenants::tenant_name_for_folder;
+use crate::types::{DetailedModelResponse, Model3D};
+use crate::{AppState, Config};
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MoveModelRequest {
+    /// new name of the model folder, defaults to the current one
+    pub folder_name: Option<String>,
+    /// directory relative to the libraries path the folder is moved into,
+    /// defaults to the current parent directory
+    pub destination: Option<String>,
+    /// new title written to modelpack.json, which also renames the slug
+    pub title: Option<String>,
+}
+
+/// Turns user input into a relative path that stays inside the library.
+fn sanitize_relative_path(input: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in std::path::Path::new(input).components() {
+        match component {
+            Component::Normal(part) => {
+                let part = sanitize_filename::sanitize(part.to_string_lossy());
+                if !part.is_empty() {
+                    path.push(part);
+                }
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+pub async fn move_folder(from: &PathBuf, to: &PathBuf) -> anyhow::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    if fs::rename(from, to).await.is_err() {
+        // destination is on another file system
+        fs_extra::dir::move_dir(
+            from,
+            to,
+            &fs_extra::dir::CopyOptions {
+                copy_inside: true,
+                ..fs_extra::dir::CopyOptions::new()
+            },
+        )?;
+    }
+    Ok(())
+}
+
+async fn write_title(dir: &PathBuf, title: &str) -> anyhow::Result<()> {
+    let mut meta = get_modelpack_meta(dir).await?;
+    meta.title = title.to_string();
+    fs::write(
+        dir.join("modelpack.json"),
+        serde_json::to_string_pretty(&meta)?,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Moves the model folder on disk and updates its row. Preview images are
+/// keyed by file hash and need no update. If the database update fails the
+/// folder is moved back.
+pub async fn move_model<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+    request: &MoveModelRequest,
+) -> Result<Model3D, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let current = PathBuf::from(&model.folder_path);
+
+    let folder_name = match &request.folder_name {
+        Some(folder_name) => sanitize_filename::sanitize(folder_name),
+        None => current
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    let destination = match &request.destination {
+        Some(destination) => sanitize_relative_path(destination).ok_or(StatusCode::BAD_REQUEST)?,
+        None => current.parent().map(PathBuf::from).unwrap_or_default(),
+    };
+    if folder_name.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let new_relative = destination.join(folder_name);
+    // a model may not leave the library root of its tenant
+    if tenant_name_for_folder(config, &new_relative) != tenant_name_for_folder(config, &current) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let old_absolute = model.absolute_path(config);
+    let new_absolute = config.libraries_path.join(&new_relative);
+
+    if new_relative != current {
+        if fs::try_exists(&new_absolute).await.unwrap_or(true) {
+            return Err(StatusCode::CONFLICT);
+        }
+        // moving a folder into itself is impossible
+        if new_relative.starts_with(&current) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        move_folder(&old_absolute, &new_absolute)
+            .await
+            .map_err(|e| {
+                error!("Failed to move {}: {}", old_absolute.display(), e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        debug!(
+            "Moved {} to {}",
+            old_absolute.display(),
+            new_absolute.display()
+        );
+
+        let updated = diesel::update(models3d::dsl::models3d.find(model.id))
+            .set((
+                models3d::dsl::folder_path.eq(new_relative.to_string_lossy().into_owned()),
+                models3d::dsl::updated_at.eq(Some(chrono::Utc::now().naive_utc())),
+            ))
+            .execute(connection)
+            .await;
+        if let Err(e) = updated {
+            error!("Failed to update folder of {}: {}", model.slug, e);
+            if let Err(e) = move_folder(&new_absolute, &old_absolute).await {
+                error!("Failed to move {} back: {}", new_absolute.display(), e);
+            }
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    if let Some(title) = &request.title {
+        write_title(&new_absolute, title).await.map_err(|e| {
+            error!("Failed to write title of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+
+    // picks up the new title and slug
+    add_or_update_model(config, connection, &new_absolute)
+        .await
+        .map_err(|e| {
+            error!("Failed to rescan {}: {}", new_absolute.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    models3d::dsl::models3d
+        .find(model.id)
+        .first::<Model3D>(connection)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub async fn handle_move_model(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+    Json(request): Json<MoveModelRequest>,
+) -> Result<Json<DetailedModelResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let model = viewer.manage_model(&mut connection, &slug).await?;
+
+    let moved = move_model(&state.config, &mut connection, &model, &request).await?;
+
+    let response = DetailedModelResponse::from_model_3d(&moved, &state.config, &mut connection)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(response))
+}
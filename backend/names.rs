@@ -0,0 +1,107 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use std::collections::HashMap;
+use tracing::info;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::schema::{models3d, slug_redirects};
+use crate::slug::{change_slug, slug_is_taken, slugify, unique_slug};
+use crate::sort_key::sort_key;
+use crate::types::Model3D;
+
+/// A model name as it is stored, trimmed and NFC normalized. Folders named
+/// on macOS come decomposed, so the same title would otherwise be stored
+/// differently depending on where the folder was created.
+pub fn normalize_name(name: &str) -> String {
+    name.trim().nfc().collect()
+}
+
+/// The key two names are the same under, normalized and case folded. Unlike
+/// `LIKE` in SQLite this also ignores the case of letters beyond ASCII.
+pub fn fold_name(name: &str) -> String {
+    normalize_name(name).to_lowercase().nfc().collect()
+}
+
+/// Normalizes the names of models indexed before the rules existed and
+/// resolves slugs which are only the same under them. The model already
+/// holding the folded slug keeps it, otherwise the oldest model does, the
+/// others get a numeric suffix and keep their previous slug as a redirect.
+pub async fn backfill<Conn>(connection: &mut Conn) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let models = models3d::dsl::models3d
+        .filter(models3d::dsl::name_key.eq(""))
+        .order(models3d::dsl::id.asc())
+        .load::<Model3D>(connection)
+        .await?;
+    if models.is_empty() {
+        return Ok(());
+    }
+    info!("Normalizing the names of {} models", models.len());
+
+    let slugs: Vec<(i32, String)> = models3d::dsl::models3d
+        .order(models3d::dsl::id.asc())
+        .select((models3d::dsl::id, models3d::dsl::slug))
+        .load(connection)
+        .await?;
+    let mut keepers: HashMap<String, i32> = HashMap::new();
+    for (id, slug) in &slugs {
+        let key = fold_name(slug);
+        if *slug == key {
+            keepers.insert(key, *id);
+        } else {
+            keepers.entry(key).or_insert(*id);
+        }
+    }
+
+    // keepers first, so the others find their slug taken
+    let (first, rest): (Vec<Model3D>, Vec<Model3D>) = models
+        .into_iter()
+        .partition(|model| keepers.get(&fold_name(&model.slug)) == Some(&model.id));
+    for model in first.into_iter().chain(rest) {
+        let key = fold_name(&model.slug);
+        if key != model.slug || keepers.get(&key) != Some(&model.id) {
+            let slug = unique_slug(connection, &key, Some(model.id)).await?;
+            if slug != model.slug {
+                info!("Renaming slug {} to {}", model.slug, slug);
+                change_slug(connection, &model, &slug).await?;
+            }
+        }
+
+        let title = normalize_name(&model.title);
+        diesel::update(models3d::dsl::models3d.find(model.id))
+            .set((
+                models3d::dsl::name.eq(slugify(&title)),
+                models3d::dsl::sort_key.eq(sort_key(&title)),
+                models3d::dsl::name_key.eq(fold_name(&title)),
+                models3d::dsl::title.eq(title),
+            ))
+            .execute(connection)
+            .await?;
+    }
+
+    // redirects are looked up folded as well, those taken by now are dropped
+    let redirects: Vec<String> = slug_redirects::dsl::slug_redirects
+        .order(slug_redirects::dsl::slug.asc())
+        .select(slug_redirects::dsl::slug)
+        .load(connection)
+        .await?;
+    for slug in redirects {
+        let key = fold_name(&slug);
+        if key == slug {
+            continue;
+        }
+        if slug_is_taken(connection, &key, None).await? {
+            diesel::delete(slug_redirects::dsl::slug_redirects.find(&slug))
+                .execute(connection)
+                .await?;
+        } else {
+            diesel::update(slug_redirects::dsl::slug_redirects.find(&slug))
+                .set(slug_redirects::dsl::slug.eq(&key))
+                .execute(connection)
+                .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,621 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+use diesel_async::pooled_connection::bb8::Pool;
+use diesel_async::sync_connection_wrapper::SyncConnectionWrapper;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info};
+use typeshare::typeshare;
+
+use crate::access::{is_admin, Viewer, Visibility};
+use crate::auth::User;
+use crate::chat_notifiers::{model_card, ChatNotifier};
+use crate::policy::{Account, Authorized};
+use crate::saved_searches::{matches_since, SavedSearch};
+use crate::schema::{email_subscriptions, models3d, saved_searches, users};
+use crate::types::Model3D;
+use crate::{AppState, Config};
+
+/// Models listed in one saved search email.
+const MAX_LISTED_MODELS: usize = 20;
+/// Cards posted at once, a first refresh of a big library should not flood
+/// the chats.
+const MAX_ANNOUNCED_MODELS: i64 = 10;
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailEvent {
+    /// an upload of the user was indexed
+    UploadFinished,
+    /// a library refresh failed, only sent to admins
+    RefreshFailed,
+    /// saved searches with `notify` set have new matches
+    SavedSearchMatches,
+}
+
+impl EmailEvent {
+    pub const ALL: [EmailEvent; 3] = [
+        EmailEvent::UploadFinished,
+        EmailEvent::RefreshFailed,
+        EmailEvent::SavedSearchMatches,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailEvent::UploadFinished => "upload_finished",
+            EmailEvent::RefreshFailed => "refresh_failed",
+            EmailEvent::SavedSearchMatches => "saved_search_matches",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<EmailEvent> {
+        EmailEvent::ALL
+            .into_iter()
+            .find(|event| event.as_str() == name)
+    }
+
+    /// The subject line followed by the body, `{name}` is replaced by the
+    /// value of `name`.
+    fn default_template(&self) -> &'static str {
+        match self {
+            EmailEvent::UploadFinished => {
+                "Upload of {title} finished\n\
+                 \n\
+                 Hi {user},\n\
+                 \n\
+                 your upload of {title} is now in the library:\n\
+                 {url}\n"
+            }
+            EmailEvent::RefreshFailed => {
+                "Library refresh failed\n\
+                 \n\
+                 Hi {user},\n\
+                 \n\
+                 the {trigger} library refresh failed:\n\
+                 \n\
+                 {error}\n"
+            }
+            EmailEvent::SavedSearchMatches => {
+                "{count} new models for {search}\n\
+                 \n\
+                 Hi {user},\n\
+                 \n\
+                 these models were added since the last email and match your\n\
+                 saved search {search}:\n\
+                 \n\
+                 {models}\n"
+            }
+        }
+    }
+}
+
+fn is_enabled(config: &Config, event: EmailEvent) -> bool {
+    config
+        .email_events
+        .iter()
+        .any(|name| name.trim() == event.as_str())
+}
+
+/// Something that happened which may be worth an email or a chat post.
+#[derive(Debug, Clone)]
+pub enum Notification {
+    UploadFinished {
+        user_id: i32,
+        slug: String,
+    },
+    /// `trigger` tells a manual refresh from a scheduled one
+    RefreshFailed {
+        trigger: &'static str,
+        error: String,
+    },
+    /// the library changed, there may be new models to announce and saved
+    /// searches with new matches
+    LibraryChanged,
+}
+
+/// Hands notifications to a background task sending the emails and chat
+/// posts, so requests never wait for the mail or chat servers.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    sender: Option<mpsc::UnboundedSender<Notification>>,
+    sends_emails: bool,
+}
+
+impl Notifier {
+    /// Starts the sending task. Without `smtp_host` and chats notifications
+    /// are dropped.
+    pub fn spawn(config: &Config, pool: Pool<SyncConnectionWrapper<SqliteConnection>>) -> Notifier {
+        let mailer = config
+            .smtp_host
+            .as_ref()
+            .and_then(|host| match Mailer::new(config, host) {
+                Ok(mailer) => {
+                    info!("Sending email notifications through {}", host);
+                    Some(mailer)
+                }
+                Err(e) => {
+                    error!("Email notifications are disabled: {}", e);
+                    None
+                }
+            });
+        let chats = ChatNotifier::from_config(config);
+        for chat in &chats {
+            info!("Announcing new models on {}", chat.name());
+        }
+        if mailer.is_none() && chats.is_empty() {
+            return Notifier::default();
+        }
+
+        let sends_emails = mailer.is_some();
+        let dispatcher = Dispatcher {
+            config: config.clone(),
+            pool,
+            mailer,
+            chats,
+            client: reqwest::Client::new(),
+            announced_until: Utc::now().naive_utc(),
+        };
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(dispatcher.run(receiver));
+        Notifier {
+            sender: Some(sender),
+            sends_emails,
+        }
+    }
+
+    pub fn sends_emails(&self) -> bool {
+        self.sends_emails
+    }
+
+    pub fn notify(&self, notification: Notification) {
+        if let Some(sender) = &self.sender {
+            if sender.send(notification).is_err() {
+                error!("Notifications stopped, dropping notification");
+            }
+        }
+    }
+}
+
+struct Dispatcher {
+    config: Config,
+    pool: Pool<SyncConnectionWrapper<SqliteConnection>>,
+    mailer: Option<Mailer>,
+    chats: Vec<ChatNotifier>,
+    client: reqwest::Client,
+    /// models added up to here were announced in the chats
+    announced_until: NaiveDateTime,
+}
+
+impl Dispatcher {
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<Notification>) {
+        while let Some(notification) = receiver.recv().await {
+            if let Err(e) = self.handle(notification).await {
+                error!("Failed to send notification: {}", e);
+            }
+        }
+    }
+
+    async fn handle(&mut self, notification: Notification) -> anyhow::Result<()> {
+        let pool = self.pool.clone();
+        let mut connection = pool.get().await?;
+        if let Some(mailer) = &self.mailer {
+            let sent = match &notification {
+                Notification::UploadFinished { user_id, slug } => {
+                    mailer
+                        .upload_finished(&mut connection, *user_id, slug)
+                        .await
+                }
+                Notification::RefreshFailed { trigger, error } => {
+                    mailer.refresh_failed(&mut connection, trigger, error).await
+                }
+                Notification::LibraryChanged => Ok(()),
+            };
+            if let Err(e) = sent {
+                error!("Failed to send email notification: {}", e);
+            }
+            if !matches!(notification, Notification::RefreshFailed { .. }) {
+                if let Err(e) = mailer.saved_search_matches(&mut connection).await {
+                    error!("Failed to email saved search matches: {}", e);
+                }
+            }
+        }
+
+        if !self.chats.is_empty() && !matches!(notification, Notification::RefreshFailed { .. }) {
+            self.announce_new_models(&mut connection).await?;
+        }
+        Ok(())
+    }
+
+    /// Posts a card for every model added since the last announcement.
+    /// Private models are never announced.
+    async fn announce_new_models<Conn>(&mut self, connection: &mut Conn) -> anyhow::Result<()>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let now = Utc::now().naive_utc();
+        let models = models3d::dsl::models3d
+            .filter(models3d::dsl::deleted_at.is_null())
+            .filter(models3d::dsl::date_added.gt(self.announced_until))
+            .filter(models3d::dsl::visibility.ne(Visibility::Private.as_str()))
+            .order(models3d::dsl::date_added.asc())
+            .limit(MAX_ANNOUNCED_MODELS)
+            .load::<Model3D>(connection)
+            .await?;
+        self.announced_until = now;
+
+        for model in models {
+            let card = model_card(&self.config, connection, &model).await?;
+            for chat in &self.chats {
+                match chat.post(&self.client, &card).await {
+                    Ok(()) => debug!("Announced {} on {}", model.slug, chat.name()),
+                    Err(e) => error!(
+                        "Failed to announce {} on {}: {}",
+                        model.slug,
+                        chat.name(),
+                        e
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `public_url` without a trailing slash, or the listen address.
+pub fn base_url(config: &Config) -> String {
+    match &config.public_url {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => format!("http://{}", config.address),
+    }
+}
+
+/// Link to the page of a model, relative to `public_url` if it is set.
+pub fn model_url(config: &Config, slug: &str) -> String {
+    format!("{}/model/{}", base_url(config), slug)
+}
+
+fn render(template: &str, values: &[(&str, String)]) -> (String, String) {
+    let mut text = template.to_string();
+    for (name, value) in values {
+        text = text.replace(&format!("{{{}}}", name), value);
+    }
+    match text.split_once('\n') {
+        Some((subject, body)) => (subject.trim().to_string(), body.trim_start().to_string()),
+        None => (text.trim().to_string(), String::new()),
+    }
+}
+
+fn load_template(config: &Config, event: EmailEvent) -> String {
+    if let Some(dir) = &config.email_templates_dir {
+        let path = dir.join(format!("{}.txt", event.as_str()));
+        if path.exists() {
+            match std::fs::read_to_string(&path) {
+                Ok(template) => return template,
+                Err(e) => error!("Failed to read {}: {}", path.display(), e),
+            }
+        }
+    }
+    event.default_template().to_string()
+}
+
+/// Users with an address who opted in to `event`.
+async fn subscribers<Conn>(connection: &mut Conn, event: EmailEvent) -> QueryResult<Vec<User>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    users::dsl::users
+        .inner_join(email_subscriptions::table)
+        .filter(email_subscriptions::dsl::event.eq(event.as_str()))
+        .filter(users::dsl::email.is_not_null())
+        .select(User::as_select())
+        .load::<User>(connection)
+        .await
+}
+
+struct Mailer {
+    config: Config,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    fn new(config: &Config, host: &str) -> anyhow::Result<Mailer> {
+        let builder = match config.smtp_security.as_str() {
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            other => anyhow::bail!("Unknown SMTP_SECURITY '{}'", other),
+        };
+        let mut builder = builder.port(config.smtp_port);
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Mailer {
+            config: config.clone(),
+            transport: builder.build(),
+            from: config.smtp_from.parse()?,
+        })
+    }
+
+    async fn send(
+        &self,
+        user: &User,
+        event: EmailEvent,
+        values: &[(&str, String)],
+    ) -> anyhow::Result<()> {
+        let Some(email) = &user.email else {
+            return Ok(());
+        };
+        let mut values = values.to_vec();
+        values.push(("user", user.name.clone()));
+        let (subject, body) = render(&load_template(&self.config, event), &values);
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(Mailbox::new(Some(user.name.clone()), email.parse()?))
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?;
+        self.transport.send(message).await?;
+        debug!("Emailed {} to {}", event.as_str(), user.name);
+        Ok(())
+    }
+
+    async fn upload_finished<Conn>(
+        &self,
+        connection: &mut Conn,
+        user_id: i32,
+        slug: &str,
+    ) -> anyhow::Result<()>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let event = EmailEvent::UploadFinished;
+        if !is_enabled(&self.config, event) {
+            return Ok(());
+        }
+        let Some(user) = subscribers(connection, event)
+            .await?
+            .into_iter()
+            .find(|user| user.id == user_id)
+        else {
+            return Ok(());
+        };
+        let model = models3d::dsl::models3d
+            .filter(models3d::dsl::slug.eq(slug))
+            .first::<Model3D>(connection)
+            .await?;
+
+        self.send(
+            &user,
+            event,
+            &[
+                ("title", model.title.clone()),
+                ("url", model_url(&self.config, &model.slug)),
+            ],
+        )
+        .await
+    }
+
+    async fn refresh_failed<Conn>(
+        &self,
+        connection: &mut Conn,
+        trigger: &str,
+        error: &str,
+    ) -> anyhow::Result<()>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let event = EmailEvent::RefreshFailed;
+        if !is_enabled(&self.config, event) {
+            return Ok(());
+        }
+        for user in subscribers(connection, event).await? {
+            if !is_admin(&self.config, Some(&user.name)) {
+                continue;
+            }
+            let values = [
+                ("trigger", trigger.to_string()),
+                ("error", error.to_string()),
+            ];
+            if let Err(e) = self.send(&user, event, &values).await {
+                error!("Failed to email {}: {}", user.name, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Emails the models added since the last email for every saved search
+    /// with `notify` set, as far as the owner may see them.
+    async fn saved_search_matches<Conn>(&self, connection: &mut Conn) -> anyhow::Result<()>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let event = EmailEvent::SavedSearchMatches;
+        if !is_enabled(&self.config, event) {
+            return Ok(());
+        }
+        for user in subscribers(connection, event).await? {
+            let searches = saved_searches::dsl::saved_searches
+                .filter(saved_searches::dsl::user_id.eq(user.id))
+                .filter(saved_searches::dsl::notify.eq(true))
+                .load::<SavedSearch>(connection)
+                .await?;
+            if searches.is_empty() {
+                continue;
+            }
+            let viewer = Viewer::for_user(&self.config, connection, user.clone()).await?;
+
+            for search in searches {
+                let now = Utc::now().naive_utc();
+                let since = search.last_notified_at.unwrap_or(search.created_at);
+                let models = matches_since(connection, &search, &viewer, since)
+                    .await
+                    .map_err(|status| anyhow::anyhow!("Saved search {}: {}", search.id, status))?;
+                if models.is_empty() {
+                    continue;
+                }
+
+                let mut listed: Vec<String> = models
+                    .iter()
+                    .take(MAX_LISTED_MODELS)
+                    .map(|model| {
+                        format!("- {} {}", model.title, model_url(&self.config, &model.slug))
+                    })
+                    .collect();
+                if models.len() > MAX_LISTED_MODELS {
+                    listed.push(format!("and {} more", models.len() - MAX_LISTED_MODELS));
+                }
+                let values = [
+                    ("search", search.name.clone()),
+                    ("count", models.len().to_string()),
+                    ("models", listed.join("\n")),
+                ];
+                if let Err(e) = self.send(&user, event, &values).await {
+                    error!("Failed to email {}: {}", user.name, e);
+                    continue;
+                }
+
+                diesel::update(saved_searches::dsl::saved_searches.find(search.id))
+                    .set(saved_searches::dsl::last_notified_at.eq(now))
+                    .execute(connection)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailSettings {
+    pub email: Option<String>,
+    pub events: Vec<EmailEvent>,
+    /// events the server sends emails for, none without SMTP configured
+    pub available_events: Vec<EmailEvent>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateEmailSettings {
+    pub email: Option<String>,
+    pub events: Vec<EmailEvent>,
+}
+
+fn available_events(state: &AppState) -> Vec<EmailEvent> {
+    if !state.notifier.sends_emails() {
+        return Vec::new();
+    }
+    EmailEvent::ALL
+        .into_iter()
+        .filter(|event| is_enabled(&state.config, *event))
+        .collect()
+}
+
+async fn load_settings<Conn>(
+    state: &AppState,
+    connection: &mut Conn,
+    user: &User,
+) -> Result<EmailSettings, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let events = email_subscriptions::dsl::email_subscriptions
+        .filter(email_subscriptions::dsl::user_id.eq(user.id))
+        .select(email_subscriptions::dsl::event)
+        .load::<String>(connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load email subscriptions of {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(EmailSettings {
+        email: user.email.clone(),
+        events: events
+            .iter()
+            .filter_map(|event| EmailEvent::from_name(event))
+            .collect(),
+        available_events: available_events(state),
+    })
+}
+
+pub async fn handle_get_email_settings(
+    State(state): State<AppState>,
+    Authorized(_, Account(user)): Authorized<Account>,
+) -> Result<Json<EmailSettings>, StatusCode> {
+    let mut connection = state.connection().await?;
+    Ok(Json(load_settings(&state, &mut connection, &user).await?))
+}
+
+/// Stores the address and replaces the subscribed events. Refresh failures
+/// are accepted from everyone but only emailed to admins.
+pub async fn handle_update_email_settings(
+    State(state): State<AppState>,
+    Authorized(_, Account(mut user)): Authorized<Account>,
+    Json(payload): Json<UpdateEmailSettings>,
+) -> Result<Json<EmailSettings>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let email = payload
+        .email
+        .map(|email| email.trim().to_string())
+        .filter(|email| !email.is_empty());
+    if let Some(email) = &email {
+        email
+            .parse::<Address>()
+            .map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+    }
+
+    diesel::update(users::dsl::users.find(user.id))
+        .set(users::dsl::email.eq(&email))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to store the email of {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    diesel::delete(
+        email_subscriptions::dsl::email_subscriptions
+            .filter(email_subscriptions::dsl::user_id.eq(user.id)),
+    )
+    .execute(&mut connection)
+    .await
+    .map_err(|e| {
+        error!(
+            "Failed to clear email subscriptions of {}: {}",
+            user.name, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    for event in payload.events {
+        diesel::insert_or_ignore_into(email_subscriptions::table)
+            .values((
+                email_subscriptions::dsl::user_id.eq(user.id),
+                email_subscriptions::dsl::event.eq(event.as_str()),
+            ))
+            .execute(&mut connection)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to subscribe {} to {}: {}",
+                    user.name,
+                    event.as_str(),
+                    e
+                );
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    }
+
+    user.email = email;
+    Ok(Json(load_settings(&state, &mut connection, &user).await?))
+}
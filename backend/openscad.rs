@@ -0,0 +1,162 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::Response,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use std::path::{Path as FsPath, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use crate::access::Viewer;
+use crate::jobs::Cancelled;
+use crate::parse_library::render_preview;
+use crate::schema::files3d;
+use crate::{AppState, Config};
+
+pub fn is_openscad_file_type(file_type: &str) -> bool {
+    file_type == "scad"
+}
+
+/// Renders a `.scad` file into `target` with the configured `openscad`
+/// binary. The process runs in the folder of the design with an empty
+/// environment, no stdin and a timeout, and is killed when the job gets
+/// cancelled. `parameters` are passed as `-D name=value`.
+pub async fn render_stl(
+    config: &Config,
+    source: &FsPath,
+    target: &FsPath,
+    parameters: &[(String, String)],
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let binary = config
+        .openscad_binary
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("OPENSCAD_BINARY is not configured"))?;
+
+    let mut command = Command::new(binary);
+    command
+        .arg("-o")
+        .arg(target)
+        .arg("--export-format")
+        .arg("binstl");
+    for (name, value) in parameters {
+        command.arg("-D").arg(format!("{}={}", name, value));
+    }
+    command
+        .arg(source)
+        .current_dir(source.parent().unwrap_or(FsPath::new(".")))
+        .env_clear()
+        .env("HOME", std::env::temp_dir())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let child = command.spawn()?;
+    let timeout = Duration::from_secs(config.openscad_timeout_seconds);
+    let output = tokio::select! {
+        output = tokio::time::timeout(timeout, child.wait_with_output()) => match output {
+            Ok(output) => output?,
+            Err(_) => anyhow::bail!("openscad did not finish within {:?}", timeout),
+        },
+        _ = cancel.cancelled() => return Err(Cancelled.into()),
+    };
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "openscad exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Renders a `.scad` file into a temporary STL for its preview, the caller
+/// removes it.
+pub async fn render_for_preview(config: &Config, source: &FsPath) -> Option<PathBuf> {
+    config.openscad_binary.as_ref()?;
+    let target = std::env::temp_dir().join(format!("scad_{}.stl", uuid::Uuid::new_v4()));
+    match render_stl(config, source, &target, &[], &CancellationToken::new()).await {
+        Ok(()) => Some(target),
+        Err(e) => {
+            debug!("Unable to render {}: {}", source.display(), e);
+            let _ = tokio::fs::remove_file(&target).await;
+            None
+        }
+    }
+}
+
+/// Renders an OpenSCAD file to STL. Files without a preview get one from
+/// the rendered mesh.
+pub async fn handle_render(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+) -> Result<Response, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let (file, _) = viewer.find_file(&mut connection, id).await?;
+    if !is_openscad_file_type(&file.file_type) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    if state.config.openscad_binary.is_none() {
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+
+    let source = file.get_file_path(&mut connection, &state.config).await;
+    let target = std::env::temp_dir().join(format!("scad_{}.stl", uuid::Uuid::new_v4()));
+
+    let (job_id, cancel) = state.jobs.start("render");
+    let result = render_stl(&state.config, &source, &target, &[], &cancel).await;
+    state.jobs.complete(job_id, &result);
+    let buffer = match result {
+        Ok(()) => tokio::fs::read(&target).await.map_err(|e| {
+            error!("Failed to read rendered {}: {}", target.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }),
+        Err(e) if e.is::<Cancelled>() => Err(StatusCode::CONFLICT),
+        Err(e) => {
+            error!("Rendering {} failed: {}", source.display(), e);
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        }
+    };
+
+    if buffer.is_ok() && file.preview_image.is_none() {
+        let hash = file
+            .file_hash
+            .clone()
+            .unwrap_or_else(|| file.id.to_string());
+        if let Some(preview_image) = render_preview(&state.config, &target, &source, &hash).await {
+            diesel::update(files3d::dsl::files3d.find(file.id))
+                .set(files3d::dsl::preview_image.eq(preview_image))
+                .execute(&mut connection)
+                .await
+                .map_err(|e| {
+                    error!("Failed to store preview of {}: {}", source.display(), e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+        }
+    }
+    let _ = tokio::fs::remove_file(&target).await;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{}.stl\"",
+                file.get_file_name().await.unwrap()
+            )
+            .as_str(),
+        )
+        .body(Body::from(buffer?))
+        .unwrap())
+}
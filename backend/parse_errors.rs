@@ -0,0 +1,147 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::policy::{Admin, Authorized};
+use crate::refresh_report::RefreshReport;
+use crate::schema::{models3d, parse_errors};
+use crate::AppState;
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParseError {
+    pub folder_path: String,
+    pub message: String,
+    pub first_seen: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    /// refreshes in a row which failed on the folder
+    pub occurrences: i32,
+    /// the model kept from before the folder broke, if any
+    pub slug: Option<String>,
+}
+
+/// Replaces the stored errors below `scope` with those of a finished
+/// refresh of it. Folders failing again keep the time they first failed.
+pub async fn store<Conn>(
+    connection: &mut Conn,
+    report: &RefreshReport,
+    scope: &Path,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let mut messages: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for error in &report.errors {
+        messages
+            .entry(error.folder_path.as_str())
+            .or_default()
+            .push(error.message.as_str());
+    }
+    let now = Utc::now().naive_utc();
+
+    connection
+        .transaction::<_, diesel::result::Error, _>(|connection| {
+            async move {
+                let resolved: Vec<String> = parse_errors::dsl::parse_errors
+                    .select(parse_errors::dsl::folder_path)
+                    .load::<String>(connection)
+                    .await?
+                    .into_iter()
+                    .filter(|folder_path| {
+                        Path::new(folder_path).starts_with(scope)
+                            && !messages.contains_key(folder_path.as_str())
+                    })
+                    .collect();
+                diesel::delete(
+                    parse_errors::dsl::parse_errors
+                        .filter(parse_errors::dsl::folder_path.eq_any(resolved)),
+                )
+                .execute(connection)
+                .await?;
+                for (folder_path, messages) in &messages {
+                    diesel::insert_into(parse_errors::table)
+                        .values((
+                            parse_errors::dsl::folder_path.eq(*folder_path),
+                            parse_errors::dsl::message.eq(messages.join("\n")),
+                            parse_errors::dsl::first_seen.eq(now),
+                            parse_errors::dsl::last_seen.eq(now),
+                            parse_errors::dsl::occurrences.eq(1),
+                        ))
+                        .on_conflict(parse_errors::dsl::folder_path)
+                        .do_update()
+                        .set((
+                            parse_errors::dsl::message.eq(excluded(parse_errors::dsl::message)),
+                            parse_errors::dsl::last_seen.eq(now),
+                            parse_errors::dsl::occurrences.eq(parse_errors::dsl::occurrences + 1),
+                        ))
+                        .execute(connection)
+                        .await?;
+                }
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// Folders the last refresh skipped because they could not be read, admins
+/// only as the paths may belong to hidden models.
+pub async fn handle_parse_errors(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Json<Vec<ParseError>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let errors = parse_errors::dsl::parse_errors
+        .left_join(
+            models3d::table.on(models3d::dsl::folder_path
+                .eq(parse_errors::dsl::folder_path)
+                .and(models3d::dsl::deleted_at.is_null())),
+        )
+        .select((
+            parse_errors::dsl::folder_path,
+            parse_errors::dsl::message,
+            parse_errors::dsl::first_seen,
+            parse_errors::dsl::last_seen,
+            parse_errors::dsl::occurrences,
+            models3d::dsl::slug.nullable(),
+        ))
+        .order(parse_errors::dsl::folder_path.asc())
+        .load::<(
+            String,
+            String,
+            NaiveDateTime,
+            NaiveDateTime,
+            i32,
+            Option<String>,
+        )>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load parse errors: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(
+        errors
+            .into_iter()
+            .map(
+                |(folder_path, message, first_seen, last_seen, occurrences, slug)| ParseError {
+                    folder_path,
+                    message,
+                    first_seen,
+                    last_seen,
+                    occurrences,
+                    slug,
+                },
+            )
+            .collect(),
+    ))
+}
@@ -1,7 +1,34 @@
+use crate::access::apply_default_visibility;
+use crate::cad;
+use crate::convert;
+use crate::designers::assign_designer;
+use crate::documents;
+use crate::duplicates;
+use crate::file_types::file_type_of;
+use crate::gcode;
+use crate::ignore::{self, IgnoreRules};
+use crate::image_limits;
+use crate::jobs::check_cancelled;
+use crate::layout;
+use crate::library_stats;
+use crate::materials;
+use crate::measurements;
+use crate::media;
+use crate::openscad;
+use crate::parse_errors;
+use crate::print_logs;
+use crate::refresh_report::{timed, Phase, PhaseClock, RefreshReport};
 use crate::schema::{files3d, models3d};
+use crate::similar;
+use crate::slicer_settings;
+use crate::slug::{change_slug, unique_slug};
+use crate::tags::{get_model_tags, normalize_tags, set_model_tags};
+use crate::tenants::assign_tenant;
 use crate::types::ModelPackV0_1;
 use crate::types::{File3D, Model3D, NewFile3D, NewModel3D};
+use crate::workers;
 use crate::Config;
+use anyhow::Context;
 use chrono::Local;
 use diesel::prelude::*;
 use diesel::SqliteConnection;
@@ -9,43 +36,125 @@ use diesel_async::pooled_connection::bb8::Pool;
 use diesel_async::sync_connection_wrapper::SyncConnectionWrapper;
 use diesel_async::AsyncConnection;
 use diesel_async::RunQueryDsl;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::panic;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use tokio::fs;
-use tracing::{debug, error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, Instrument};
 
-pub async fn find_modelpack_directories(start_path: PathBuf) -> anyhow::Result<Vec<PathBuf>> {
+/// Device id of the file system the file lives on.
+fn device_id(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.dev()
+}
+
+/// Whether the scanner should descend into `pth`, honouring the symlink and
+/// file system boundaries configured for the library.
+pub async fn should_descend(config: &Config, pth: &Path, root_device: Option<u64>) -> bool {
+    let link_metadata = match fs::symlink_metadata(pth).await {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    if link_metadata.file_type().is_symlink() && !config.scanner_follow_symlinks {
+        debug!("Skipping symlink {}", pth.display());
+        return false;
+    }
+
+    match fs::metadata(pth).await {
+        Ok(metadata) if metadata.is_dir() => match root_device {
+            Some(device) if config.scanner_same_file_system && device_id(&metadata) != device => {
+                debug!("Not crossing into other file system at {}", pth.display());
+                false
+            }
+            _ => true,
+        },
+        _ => false,
+    }
+}
+
+/// Walks `dir` without descending into what the ignore patterns exclude.
+pub fn walk_dir(
+    config: &Config,
+    dir: &Path,
+) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> {
+    let mut rules = IgnoreRules::new(config);
+    walkdir::WalkDir::new(dir)
+        .follow_links(config.scanner_follow_symlinks)
+        .same_file_system(config.scanner_same_file_system)
+        .into_iter()
+        .filter_entry(move |entry| {
+            entry.depth() == 0 || !rules.is_ignored(entry.path(), entry.file_type().is_dir())
+        })
+}
+
+/// Finds all model folders, either by their modelpack.json or, if
+/// `layout_model_depth` is set, by their depth below the library root.
+pub async fn find_modelpack_directories(config: &Config) -> anyhow::Result<Vec<PathBuf>> {
+    find_modelpack_directories_below(config, Path::new("")).await
+}
+
+/// Like `find_modelpack_directories`, limited to the folder `scope`
+/// relative to the library root.
+pub async fn find_modelpack_directories_below(
+    config: &Config,
+    scope: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let root_device = fs::metadata(&config.libraries_path)
+        .await
+        .ok()
+        .map(|metadata| device_id(&metadata));
+
+    let mut rules = IgnoreRules::new(config);
     let mut modelpack_dirs = Vec::new();
-    let mut dirs_to_check = vec![start_path];
+    let mut visited = HashSet::new();
+    let start_path = config.libraries_path.join(scope);
+    if rules.is_ignored(&start_path, true) {
+        return Ok(modelpack_dirs);
+    }
+    let mut dirs_to_check = vec![(start_path, scope.components().count())];
+
+    while let Some((current_dir, depth)) = dirs_to_check.pop() {
+        // symlinks may point back up the tree
+        match fs::canonicalize(&current_dir).await {
+            Ok(canonical) => {
+                if !visited.insert(canonical) {
+                    debug!("Skipping already visited {}", current_dir.display());
+                    continue;
+                }
+            }
+            Err(_) => continue,
+        }
+
+        let is_model = match config.layout_model_depth {
+            Some(model_depth) => depth == model_depth,
+            None => layout::is_model_dir(config, &current_dir),
+        };
+        if is_model && ignore::has_partial_download(&current_dir) {
+            info!(
+                "Skipping {}, a download is in progress",
+                current_dir.display()
+            );
+            continue;
+        }
+        if is_model {
+            debug!("Found ModelPack at: {:?}", current_dir);
+            modelpack_dirs.push(current_dir);
+            continue;
+        }
 
-    while let Some(current_dir) = dirs_to_check.pop() {
         let mut dir_entries = match fs::read_dir(&current_dir).await {
             Ok(entries) => entries,
             Err(_) => continue,
         };
 
-        let mut has_modelpack = false;
-        let mut subdirs = Vec::new();
-
         while let Ok(Some(entry)) = dir_entries.next_entry().await {
             let pth = entry.path();
-            if pth.file_name() == Some("modelpack.json".as_ref()) && {
-                let parent_dir = pth.parent().unwrap_or_else(|| Path::new(""));
-                parent_dir.join("files").is_dir()
-            } {
-                has_modelpack = true;
-            } else if pth.is_dir() {
-                subdirs.push(pth);
+            if should_descend(config, &pth, root_device).await && !rules.is_ignored(&pth, true) {
+                dirs_to_check.push((pth, depth + 1));
             }
         }
-
-        if has_modelpack {
-            debug!("Found ModelPack at: {:?}", current_dir);
-            modelpack_dirs.push(current_dir);
-        } else {
-            dirs_to_check.extend(subdirs);
-        }
     }
 
     Ok(modelpack_dirs)
@@ -65,51 +174,106 @@ pub async fn get_modelpack_meta(pth: &PathBuf) -> anyhow::Result<ModelPackV0_1,
     Ok(model_pack)
 }
 
-async fn get_all_image_files(
-    image_dir: &PathBuf,
-    base_dir: &PathBuf,
-) -> anyhow::Result<Vec<PathBuf>> {
-    let supported_extensions = ["jpg", "jpeg", "png", "gif", "bmp", "tiff", "webp"];
+/// Changes the modelpack.json of a model folder, creating it for folders
+/// found by depth. Returns the previous content, `None` if there was none.
+pub async fn update_modelpack_meta(
+    config: &Config,
+    dir: &PathBuf,
+    update: impl FnOnce(&mut ModelPackV0_1),
+) -> anyhow::Result<Option<String>> {
+    let json_pth = dir.join("modelpack.json");
+    let previous = fs::read_to_string(&json_pth).await.ok();
+
+    let mut meta = match get_modelpack_meta(dir).await {
+        Ok(meta) => meta,
+        Err(_) if config.layout_model_depth.is_some() => layout::fallback_meta(dir),
+        Err(e) => return Err(e),
+    };
+    update(&mut meta);
+    fs::write(&json_pth, serde_json::to_string_pretty(&meta)?).await?;
+    Ok(previous)
+}
+
+async fn get_all_image_files(config: &Config, base_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
     let mut image_files = Vec::new();
 
-    let mut dir_entries = fs::read_dir(&image_dir).await.unwrap();
+    for image_dir in layout::image_roots(config, base_dir) {
+        let mut dir_entries = match fs::read_dir(&image_dir).await {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
 
-    while let Some(entry) = dir_entries.next_entry().await? {
-        let pth = entry.path();
+        while let Some(entry) = dir_entries.next_entry().await? {
+            let pth = entry.path();
 
-        if pth.is_file() {
-            if let Some(extension) = pth.extension() {
-                if supported_extensions.contains(
-                    &extension
-                        .to_str()
-                        .unwrap_or("")
-                        .to_ascii_lowercase()
-                        .as_str(),
-                ) {
-                    if let Some(relative_path) = pathdiff::diff_paths(pth, base_dir) {
-                        image_files.push(relative_path);
-                    }
+            if pth.is_file() && layout::is_image(&pth) {
+                if let Some(relative_path) = pathdiff::diff_paths(pth, base_dir) {
+                    image_files.push(relative_path);
                 }
             }
         }
     }
 
-    Ok(image_files)
+    image_files.sort();
+    Ok(image_limits::admit_images(config, base_dir, image_files).await)
 }
 
+/// Rescans the whole library. Cancellation is honoured between models, so a
+/// cancelled refresh never leaves a partially indexed model behind. Errors of
+/// single folders are collected in the report instead of aborting the scan.
 pub async fn refresh_library(
     pool: Pool<SyncConnectionWrapper<SqliteConnection>>,
     config: Config,
-) -> anyhow::Result<()> {
+    cancel: CancellationToken,
+) -> anyhow::Result<RefreshReport> {
+    refresh_below(pool, config, Path::new(""), cancel).await
+}
+
+/// Rescans the models below `scope`, a folder relative to the library root,
+/// and leaves all other models untouched. The report tells how long each
+/// phase of the refresh took.
+pub async fn refresh_below(
+    pool: Pool<SyncConnectionWrapper<SqliteConnection>>,
+    config: Config,
+    scope: &Path,
+    cancel: CancellationToken,
+) -> anyhow::Result<RefreshReport> {
+    let clock = PhaseClock::default();
+    let span = info_span!("refresh", scope = %scope.display());
+    let mut report = clock
+        .measure(scan_below(pool, config, scope, cancel))
+        .instrument(span)
+        .await?;
+    report.timings = clock.timings();
+    info!(
+        "Refresh of {:?} took {}ms: {}",
+        scope, report.duration_ms, report.timings
+    );
+    Ok(report)
+}
+
+async fn scan_below(
+    pool: Pool<SyncConnectionWrapper<SqliteConnection>>,
+    config: Config,
+    scope: &Path,
+    cancel: CancellationToken,
+) -> anyhow::Result<RefreshReport> {
+    let started = Instant::now();
+    let mut report = RefreshReport::new(false);
     info!(
-        "Started lib scan at {}",
+        "Started lib scan of {:?} at {}",
+        scope,
         Local::now().format("%Y-%m-%d %H:%M:%S")
     );
+    let in_scope = |folder_path: &str| Path::new(folder_path).starts_with(scope);
 
-    let data_dirs = find_modelpack_directories(config.libraries_path.clone())
-        .await
-        .unwrap();
-    let mut connection = pool.get().await.unwrap();
+    let data_dirs = timed(
+        Phase::Walk,
+        find_modelpack_directories_below(&config, scope),
+    )
+    .await?;
+    let mut connection = pool.get().await?;
+    check_cancelled(&cancel)?;
 
     // delete models from db which do not exist anymore in the fs
     let dirs_set: HashSet<PathBuf> = data_dirs
@@ -118,90 +282,355 @@ pub async fn refresh_library(
         .collect();
 
     let possibly_old_models = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
         .load::<Model3D>(&mut connection)
-        .await
-        .unwrap();
+        .await?;
 
-    for model in possibly_old_models {
+    let mut removed_hashes = HashMap::new();
+    let mut known_folders = HashSet::new();
+
+    for model in possibly_old_models
+        .into_iter()
+        .filter(|model| in_scope(&model.folder_path))
+    {
         if !dirs_set.contains(&PathBuf::from(&model.folder_path)) {
+            let files = model.get_files3d(&mut connection).await?;
+            removed_hashes.insert(model.folder_path.clone(), file_hashes(&files));
+
+            diesel::delete(files3d::dsl::files3d.filter(files3d::dsl::model_id.eq(model.id)))
+                .execute(&mut connection)
+                .await?;
             diesel::delete(models3d::dsl::models3d.filter(models3d::dsl::id.eq(model.id)))
                 .execute(&mut connection)
-                .await
-                .unwrap();
+                .await?;
 
             debug!(
                 "Deleted model from database: {:?} (id: {})",
                 model.folder_path, model.id
             );
+            report.removed.push(model.folder_path);
+        } else {
+            known_folders.insert(model.folder_path.clone());
         }
     }
 
     // add new or update models
 
     for dir in data_dirs {
-        add_or_update_model(&config, &mut connection, &dir).await?;
+        check_cancelled(&cancel)?;
+        let relative_dir = pathdiff::diff_paths(&dir, &config.libraries_path)
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let before = models3d::dsl::models3d
+            .filter(models3d::dsl::folder_path.eq(&relative_dir))
+            .filter(models3d::dsl::deleted_at.is_null())
+            .first::<Model3D>(&mut connection)
+            .await
+            .ok();
+
+        match timed(
+            Phase::Database,
+            add_or_update_model(&config, &mut connection, &dir),
+        )
+        .await
+        {
+            Ok(model) => match before {
+                None => report.added.push(relative_dir),
+                Some(before) => {
+                    let after = models3d::dsl::models3d
+                        .find(model.id)
+                        .first::<Model3D>(&mut connection)
+                        .await?;
+                    if model_metadata_changed(&before, &NewModel3D::from(&after)) {
+                        report.mark_updated(&relative_dir);
+                    }
+                }
+            },
+            Err(e) => {
+                error!("Failed to parse {}: {:#}", dir.display(), e);
+                if known_folders.contains(&relative_dir) && !config.quarantine_broken_models {
+                    report.removed.push(relative_dir.clone());
+                }
+                report.error(relative_dir, &e);
+            }
+        }
     }
 
     // generate file3d entrys
     fs::create_dir_all(config.preview_cache_dir.clone()).await?;
 
-    let files = files3d::dsl::files3d
-        .load::<File3D>(&mut connection)
-        .await
-        .unwrap();
+    let files: Vec<File3D> = files3d::dsl::files3d
+        .inner_join(models3d::table)
+        .filter(models3d::dsl::deleted_at.is_null())
+        .select((File3D::as_select(), models3d::dsl::folder_path))
+        .load::<(File3D, String)>(&mut connection)
+        .await?
+        .into_iter()
+        .filter(|(_, folder_path)| in_scope(folder_path))
+        .map(|(file, _)| file)
+        .collect();
 
-    clean_file_system(&config, &mut connection, files).await?;
+    let mut removed_files: HashMap<i32, Vec<String>> = HashMap::new();
+    for file in timed(
+        Phase::Database,
+        clean_file_system(&config, &mut connection, files),
+    )
+    .await?
+    {
+        removed_files
+            .entry(file.model_id)
+            .or_default()
+            .push(file.file_path);
+    }
 
     let models = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
         .load::<Model3D>(&mut connection)
-        .await
-        .unwrap();
+        .await?;
+
+    let mut added_hashes = HashMap::new();
 
     // add refresh files in model folders
-    for model in models {
-        load_files_and_preview(&config, &mut connection, &model).await?;
+    for model in models
+        .into_iter()
+        .filter(|model| in_scope(&model.folder_path))
+    {
+        check_cancelled(&cancel)?;
+        let added_files = match timed(
+            Phase::Database,
+            load_files_and_preview(&config, &mut connection, &model),
+        )
+        .await
+        {
+            Ok(added_files) => added_files,
+            Err(e) => {
+                error!("Failed to load files of {}: {}", model.folder_path, e);
+                report.error(model.folder_path.clone(), &e);
+                Vec::new()
+            }
+        };
+
+        if report.added.contains(&model.folder_path) {
+            let files = model.get_files3d(&mut connection).await?;
+            added_hashes.insert(model.folder_path.clone(), file_hashes(&files));
+        } else {
+            report.record_file_changes(
+                &model.folder_path,
+                removed_files.remove(&model.id).unwrap_or_default(),
+                added_files,
+            );
+        }
+    }
+
+    // file changes count as an update of the model as well
+    diesel::update(
+        models3d::dsl::models3d.filter(models3d::dsl::folder_path.eq_any(report.updated.clone())),
+    )
+    .set(models3d::dsl::updated_at.eq(Some(chrono::Utc::now().naive_utc())))
+    .execute(&mut connection)
+    .await?;
+
+    report.detect_renames(&removed_hashes, &added_hashes);
+
+    if let Err(e) = parse_errors::store(&mut connection, &report, scope).await {
+        error!("Failed to store parse errors: {}", e);
     }
 
     // delete old cache images
     clean_cache(config, &mut connection).await?;
 
+    if let Err(e) = library_stats::record_snapshots(&mut connection).await {
+        error!("Failed to record library statistics: {}", e);
+    }
+
+    report.finish(started);
     info!(
-        "Finished lib scan at {}",
-        Local::now().format("%Y-%m-%d %H:%M:%S")
+        "Finished lib scan at {} ({} added, {} updated, {} removed, {} errors)",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        report.added.len(),
+        report.updated.len(),
+        report.removed.len(),
+        report.errors.len()
     );
 
-    Ok(())
+    Ok(report)
+}
+
+fn file_hashes(files: &[File3D]) -> BTreeSet<String> {
+    files
+        .iter()
+        .filter_map(|file| file.file_hash.clone())
+        .collect()
+}
+
+fn model_metadata_changed(model: &Model3D, new_model: &NewModel3D) -> bool {
+    model.title != new_model.title
+        || model.name != new_model.name
+        || model.license != new_model.license
+        || model.author != new_model.author
+        || model.origin != new_model.origin
+        || model.images != new_model.images
+        || model.description != new_model.description
+        || model.notes != new_model.notes
+}
+
+fn files_on_disk(config: &Config, model_base_path: &Path) -> HashSet<String> {
+    layout::indexed_files(config, model_base_path)
+        .into_iter()
+        .filter_map(|pth| pathdiff::diff_paths(pth, model_base_path))
+        .map(|pth| pth.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Compares the indexed files of a model with the files on disk and returns
+/// the relative paths which a refresh would remove and add.
+async fn diff_model_files<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> anyhow::Result<(Vec<String>, Vec<String>)>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let model_base_path = model.absolute_path(config);
+    let mut on_disk = files_on_disk(config, &model_base_path);
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+
+    for file in model.get_files3d(connection).await? {
+        if !on_disk.remove(&file.file_path) {
+            removed.push(file.file_path);
+            continue;
+        }
+        let current_sha = workers::digest(model_base_path.join(&file.file_path)).await?;
+        if Some(current_sha) != file.file_hash {
+            removed.push(file.file_path.clone());
+            added.push(file.file_path);
+        }
+    }
+    added.extend(on_disk);
+
+    Ok((removed, added))
+}
+
+/// Scans the library like `refresh_library` does and reports what would
+/// change, without writing to the database.
+pub async fn plan_refresh<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+) -> anyhow::Result<RefreshReport>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let started = Instant::now();
+    let mut report = RefreshReport::new(true);
+    let data_dirs = find_modelpack_directories(config).await?;
+
+    let mut models: HashMap<String, Model3D> = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
+        .load::<Model3D>(connection)
+        .await?
+        .into_iter()
+        .map(|model| (model.folder_path.clone(), model))
+        .collect();
+
+    let mut added_dirs = Vec::new();
+
+    for dir in data_dirs {
+        let relative_dir = pathdiff::diff_paths(&dir, &config.libraries_path)
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let new_model = match read_model_dir(config, &dir).await {
+            Ok((new_model, _)) => new_model,
+            Err(e) => {
+                report.error(relative_dir, &e);
+                continue;
+            }
+        };
+
+        match models.remove(&new_model.folder_path) {
+            None => {
+                report.added.push(new_model.folder_path);
+                added_dirs.push(dir);
+            }
+            Some(model) => {
+                if model_metadata_changed(&model, &new_model) {
+                    report.mark_updated(&model.folder_path);
+                }
+                let (removed, added) = diff_model_files(config, connection, &model).await?;
+                report.record_file_changes(&model.folder_path, removed, added);
+            }
+        }
+    }
+
+    // whatever was not found on disk anymore gets removed
+    let mut removed_hashes = HashMap::new();
+    for (folder_path, model) in models {
+        let files = model.get_files3d(connection).await?;
+        removed_hashes.insert(folder_path.clone(), file_hashes(&files));
+        report.removed.push(folder_path);
+    }
+
+    let mut added_hashes = HashMap::new();
+    if !removed_hashes.is_empty() {
+        for dir in added_dirs {
+            let mut hashes = BTreeSet::new();
+            for file_path in files_on_disk(config, &dir) {
+                hashes.insert(workers::digest(dir.join(file_path)).await?);
+            }
+            let relative_dir = pathdiff::diff_paths(&dir, &config.libraries_path)
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            added_hashes.insert(relative_dir, hashes);
+        }
+    }
+    report.detect_renames(&removed_hashes, &added_hashes);
+
+    report.finish(started);
+    Ok(report)
 }
 
 pub async fn clean_file_system<Conn>(
     config: &Config,
     connection: &mut Conn,
     files: Vec<File3D>,
-) -> anyhow::Result<()>
+) -> anyhow::Result<Vec<File3D>>
 where
     Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
 {
+    let mut removed = Vec::new();
+
     // delete file references deleted in fs
     for file in files {
         let file_pth = file.get_file_path(connection, config).await.clone();
 
         if fs::metadata(&file_pth).await.is_ok() {
-            let current_sha = sha256::try_async_digest(file_pth.clone())
-                .await
-                .unwrap()
-                .to_string();
-            let saved_sha = file.file_hash.unwrap_or("".to_string());
+            let current_sha = match timed(Phase::Hash, workers::digest(&file_pth)).await {
+                Ok(sha) => sha,
+                // an unreadable file keeps its entry until it can be read again
+                Err(e) => {
+                    error!("Unable to hash {}: {}", file_pth.display(), e);
+                    continue;
+                }
+            };
+            let saved_sha = file.file_hash.clone().unwrap_or("".to_string());
             if current_sha == saved_sha {
                 continue;
             }
         }
         diesel::delete(files3d::dsl::files3d.filter(files3d::dsl::id.eq(file.id)))
             .execute(connection)
-            .await
-            .unwrap();
-        debug!("Deleted File Reference from DB {:?}", file.file_path)
+            .await?;
+        print_logs::detach_file(connection, file.id).await?;
+        debug!("Deleted File Reference from DB {:?}", file.file_path);
+        removed.push(file);
     }
-    anyhow::Ok(())
+    anyhow::Ok(removed)
 }
 
 async fn read_readme(dir: &std::path::Path) -> anyhow::Result<String> {
@@ -222,6 +651,50 @@ async fn read_readme(dir: &std::path::Path) -> anyhow::Result<String> {
     )))
 }
 
+/// Notes the maker kept next to the README, empty if there are none.
+async fn read_notes(dir: &std::path::Path) -> String {
+    for name in ["notes.md", "NOTES.md", "notes.txt", "NOTES.txt"] {
+        if let Ok(content) = fs::read_to_string(dir.join(name)).await {
+            return content;
+        }
+    }
+    String::new()
+}
+
+/// Builds the model row as it would be stored for `dir` without touching the
+/// database.
+/// Reads the metadata of a model folder, returning the model row and its tags.
+pub async fn read_model_dir(
+    config: &Config,
+    dir: &PathBuf,
+) -> anyhow::Result<(NewModel3D, Vec<String>)> {
+    let relative_dir = pathdiff::diff_paths(dir, &config.libraries_path)
+        .with_context(|| format!("{} is outside of the library", dir.display()))?;
+
+    let model_pack_meta = match get_modelpack_meta(dir).await {
+        Ok(meta) => meta,
+        Err(_) if config.layout_model_depth.is_some() => layout::fallback_meta(dir),
+        Err(e) => return Err(e),
+    };
+
+    let readme = match read_readme(dir).await {
+        Ok(content) => content,
+        Err(_) => String::new(),
+    };
+
+    let new_model = NewModel3D::from_model_pack_v0_1(
+        &model_pack_meta,
+        &relative_dir,
+        timed(Phase::Images, get_all_image_files(config, dir)).await?,
+        readme,
+    )?;
+    let new_model = NewModel3D {
+        notes: read_notes(dir).await,
+        ..new_model
+    };
+    Ok((new_model, model_pack_meta.tags.unwrap_or_default()))
+}
+
 pub async fn add_or_update_model<Conn>(
     config: &Config,
     connection: &mut Conn,
@@ -230,41 +703,50 @@ pub async fn add_or_update_model<Conn>(
 where
     Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
 {
-    let relative_dir = pathdiff::diff_paths(dir, &config.libraries_path).unwrap();
-
-    let result: Option<Model3D> = models3d::dsl::models3d
-        .filter(models3d::dsl::folder_path.eq(relative_dir.to_str().unwrap()))
+    let relative_dir = pathdiff::diff_paths(dir, &config.libraries_path)
+        .with_context(|| format!("{} is outside of the library", dir.display()))?;
+    let relative_folder = relative_dir
+        .to_str()
+        .with_context(|| format!("{} is not valid UTF-8", relative_dir.display()))?;
+
+    let mut result: Option<Model3D> = models3d::dsl::models3d
+        .filter(models3d::dsl::folder_path.eq(relative_folder))
         .first::<Model3D>(connection)
         .await
         .ok();
 
-    let mut image_dir = dir.clone();
-    image_dir.push("images");
+    // a new folder took the place of a trashed model
+    if let Some(trashed_model) = result.as_ref().filter(|m| m.is_trashed()) {
+        trashed_model.delete(config, connection).await?;
+        result = None;
+    }
 
-    let model_pack_meta = match get_modelpack_meta(dir).await {
-        Ok(meta) => meta,
-        Err(_) => {
-            if let Some(existing_model) = result {
+    let (new_object, tags) = match read_model_dir(config, dir).await {
+        Ok(new_object) => new_object,
+        Err(e) => {
+            // quarantined models stay as they were last read until fixed
+            if let Some(existing_model) = result.filter(|_| !config.quarantine_broken_models) {
                 existing_model.delete(config, connection).await?;
             }
-            return Err(anyhow::Error::msg("Failed to get model pack meta"));
+            return Err(e.context("Failed to get model pack meta"));
         }
     };
-
-    let readme = match read_readme(&dir).await {
-        Ok(content) => content,
-        Err(_) => String::new(),
-    };
-
-    let new_object: NewModel3D = NewModel3D::from_model_pack_v0_1(
-        &model_pack_meta,
-        &relative_dir,
-        get_all_image_files(&image_dir, dir).await.unwrap(),
-        readme.clone(),
-    )
-    .unwrap();
+    let readme = new_object.description.clone();
+    let embedding = similar::embed(&new_object.name, &new_object.title, &tags, &readme);
 
     if let Some(existing_model) = result {
+        let tags_changed = get_model_tags(connection, existing_model.id).await?
+            != normalize_tags(&tags).into_iter().collect::<Vec<_>>();
+        if model_metadata_changed(&existing_model, &new_object) || tags_changed {
+            existing_model.touch(connection).await?;
+        }
+        set_model_tags(connection, existing_model.id, &tags).await?;
+        assign_tenant(config, connection, &existing_model).await?;
+        if existing_model.name != new_object.name {
+            let slug = unique_slug(connection, &new_object.name, Some(existing_model.id)).await?;
+            change_slug(connection, &existing_model, &slug).await?;
+        }
+
         diesel::update(models3d::dsl::models3d.find(existing_model.id))
             .set((
                 models3d::dsl::title.eq(&new_object.title),
@@ -274,135 +756,370 @@ where
                 models3d::dsl::origin.eq(&new_object.origin),
                 models3d::dsl::images.eq(new_object.images),
                 models3d::dsl::description.eq(readme.clone()),
+                models3d::dsl::notes.eq(&new_object.notes),
+                models3d::dsl::sort_key.eq(&new_object.sort_key),
+                models3d::dsl::name_key.eq(&new_object.name_key),
             ))
             .execute(connection)
-            .await
-            .unwrap();
+            .await?;
+        assign_designer(connection, existing_model.id, new_object.author.as_deref()).await?;
+        similar::store_embedding(connection, existing_model.id, &embedding).await?;
         debug!("Scanning {:?}", new_object.folder_path);
         anyhow::Ok(existing_model)
     } else {
+        let new_object = NewModel3D {
+            slug: unique_slug(connection, &new_object.name, None).await?,
+            ..new_object
+        };
         diesel::insert_into(models3d::table)
             .values(&new_object)
             .execute(connection)
-            .await
-            .ok();
+            .await?;
         debug!("Created Model {:?}", new_object.folder_path);
 
         let result = models3d::dsl::models3d
-            .filter(models3d::dsl::folder_path.eq(relative_dir.to_str().unwrap()))
+            .filter(models3d::dsl::folder_path.eq(relative_folder))
             .first::<Model3D>(connection)
-            .await
-            .unwrap();
+            .await?;
+        result.touch(connection).await?;
+        set_model_tags(connection, result.id, &tags).await?;
+        assign_tenant(config, connection, &result).await?;
+        apply_default_visibility(config, connection, &result).await?;
+        assign_designer(connection, result.id, new_object.author.as_deref()).await?;
+        similar::store_embedding(connection, result.id, &embedding).await?;
         anyhow::Ok(result)
     }
 }
 
+fn mesh_size(file_pth: &Path) -> [Option<f64>; 3] {
+    match convert::bounding_box(&file_pth.to_path_buf()) {
+        Ok(size) => size.map(Some),
+        Err(e) => {
+            error!("Unable to measure {}: {}", file_pth.display(), e);
+            [None; 3]
+        }
+    }
+}
+
+/// The serialized geometry fingerprint of a mesh, used to find duplicates.
+fn fingerprint(mesh_pth: &Path) -> Option<String> {
+    match duplicates::geometry_fingerprint(mesh_pth) {
+        Ok(fingerprint) => serde_json::to_string(&fingerprint).ok(),
+        Err(e) => {
+            error!("Unable to fingerprint {}: {}", mesh_pth.display(), e);
+            None
+        }
+    }
+}
+
+/// Runs the synchronous analysis of a file on the CPU worker pool.
+async fn analyse<T, F>(pth: &Path, analysis: F) -> T
+where
+    F: FnOnce(&Path) -> T + Send + 'static,
+    T: Default + Send + 'static,
+{
+    let pth = pth.to_path_buf();
+    let shown = pth.display().to_string();
+    workers::run(move || analysis(&pth))
+        .await
+        .unwrap_or_else(|e| {
+            error!("Unable to analyse {}: {}", shown, e);
+            T::default()
+        })
+}
+
+/// Renders the preview of a mesh into the cache, named after the hash of
+/// the library file it shows.
+pub async fn render_preview(
+    config: &Config,
+    mesh_pth: &Path,
+    file_pth: &Path,
+    hash: &str,
+) -> Option<String> {
+    let config = config.clone();
+    let mesh_pth = mesh_pth.to_path_buf();
+    let file_pth = file_pth.to_path_buf();
+    let hash = hash.to_string();
+    let rendered =
+        workers::run(move || render_preview_blocking(&config, &mesh_pth, &file_pth, &hash));
+    timed(Phase::Previews, rendered).await.unwrap_or_else(|e| {
+        error!("Unable to render preview: {}", e);
+        None
+    })
+}
+
+fn render_preview_blocking(
+    config: &Config,
+    mesh_pth: &Path,
+    file_pth: &Path,
+    hash: &str,
+) -> Option<String> {
+    let file_name = format!("{}.png", hash);
+    let img_path = config.preview_cache_dir.join(&file_name);
+
+    let mut render_config = stl_thumb::config::Config::default();
+    render_config.model_filename = mesh_pth.to_str().unwrap().to_string();
+    render_config.img_filename = img_path.to_str().unwrap().to_string();
+    if let Some(colour) = materials::preview_colour(file_pth, image_limits::limits(config)) {
+        render_config.material.diffuse = colour;
+        render_config.material.ambient = colour.map(|channel| channel * 0.3);
+    }
+
+    if let Err(err) = panic::catch_unwind(|| {
+        stl_thumb::render_to_file(&render_config).unwrap();
+    }) {
+        error!(
+            "Unable to render preview: {:?} Error: {:?}",
+            file_pth.to_str(),
+            err
+        );
+        None
+    } else {
+        Some(file_name)
+    }
+}
+
 pub async fn load_files_and_preview<Conn>(
     config: &Config,
     connection: &mut Conn,
     model: &Model3D,
-) -> anyhow::Result<()>
+) -> anyhow::Result<Vec<String>>
 where
     Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
 {
+    let mut added = Vec::new();
     let mut model_base_path = config.libraries_path.clone();
     model_base_path.push(model.folder_path.clone());
 
-    let mut search_dir = model_base_path.clone();
-    search_dir.push("files");
-
     debug!("starting search for {:?}", model_base_path);
-    for entry in walkdir::WalkDir::new(&search_dir) {
-        let entry = match entry {
-            Ok(path) => path,
-            Err(e) => return Err(e.into()),
-        };
-
-        debug!("scanning {}", entry.path().display());
+    for file_pth in layout::indexed_files(config, &model_base_path) {
+        debug!("scanning {}", file_pth.display());
 
         let mesh_files = ["stl", "3mf", "obj"];
-        let file_pth = entry.path();
+        let file_pth = file_pth.as_path();
 
-        if file_pth.is_dir() {
+        let Some(relative_path) = pathdiff::diff_paths(file_pth, &model_base_path)
+            .and_then(|pth| pth.to_str().map(str::to_string))
+        else {
+            error!("Skipping {}, not valid UTF-8", file_pth.display());
             continue;
-        }
-
-        let relative_path = pathdiff::diff_paths(file_pth, &model_base_path).unwrap();
+        };
 
         let result: Option<File3D> = files3d::dsl::files3d
-            .filter(files3d::dsl::file_path.eq(relative_path.to_str().unwrap()))
+            .filter(files3d::dsl::file_path.eq(&relative_path))
             .filter(files3d::dsl::model_id.eq(model.id))
             .first::<File3D>(connection)
             .await
             .ok();
 
         // entry exist and everything is fine
-        if result.is_some() {
-            debug!("skipping {}", relative_path.display());
+        if let Some(existing) = result {
+            if existing.cad_metadata.is_none() {
+                if let Some(metadata) = cad::read_metadata(file_pth, &existing.file_type) {
+                    diesel::update(files3d::dsl::files3d.find(existing.id))
+                        .set(files3d::dsl::cad_metadata.eq(serde_json::to_string(&metadata)?))
+                        .execute(connection)
+                        .await?;
+                }
+            }
+            if existing.print_settings.is_none() && existing.file_type == "3mf" {
+                if let Some(settings) = slicer_settings::read_print_settings(file_pth) {
+                    diesel::update(files3d::dsl::files3d.find(existing.id))
+                        .set(files3d::dsl::print_settings.eq(serde_json::to_string(&settings)?))
+                        .execute(connection)
+                        .await?;
+                }
+            }
+            if existing.gcode_metadata.is_none() && existing.file_type == "gcode" {
+                if let Some(metadata) = gcode::read_metadata(file_pth) {
+                    diesel::update(files3d::dsl::files3d.find(existing.id))
+                        .set(files3d::dsl::gcode_metadata.eq(serde_json::to_string(&metadata)?))
+                        .execute(connection)
+                        .await?;
+                }
+            }
+            if existing.geometry_fingerprint.is_none()
+                && mesh_files.contains(&existing.file_type.as_str())
+            {
+                if let Some(fingerprint) = analyse(file_pth, fingerprint).await {
+                    diesel::update(files3d::dsl::files3d.find(existing.id))
+                        .set(files3d::dsl::geometry_fingerprint.eq(fingerprint))
+                        .execute(connection)
+                        .await?;
+                }
+            }
+            if existing.measurements.is_none() && mesh_files.contains(&existing.file_type.as_str())
+            {
+                if let Some(measurements) = analyse(file_pth, measurements::measure_file).await {
+                    diesel::update(files3d::dsl::files3d.find(existing.id))
+                        .set(files3d::dsl::measurements.eq(measurements))
+                        .execute(connection)
+                        .await?;
+                }
+            }
+            if existing.preview_image.is_none() && media::is_video_file_type(&existing.file_type) {
+                let hash = existing
+                    .file_hash
+                    .clone()
+                    .unwrap_or_else(|| existing.id.to_string());
+                if let Some(preview_image) = timed(
+                    Phase::Previews,
+                    media::poster_frame(config, file_pth, &hash),
+                )
+                .await
+                {
+                    diesel::update(files3d::dsl::files3d.find(existing.id))
+                        .set(files3d::dsl::preview_image.eq(preview_image))
+                        .execute(connection)
+                        .await?;
+                }
+            }
+            if existing.preview_image.is_none() && existing.file_type == "pdf" {
+                let hash = existing
+                    .file_hash
+                    .clone()
+                    .unwrap_or_else(|| existing.id.to_string());
+                if let Some(preview_image) = timed(
+                    Phase::Previews,
+                    documents::first_page_thumbnail(config, file_pth, &hash),
+                )
+                .await
+                {
+                    diesel::update(files3d::dsl::files3d.find(existing.id))
+                        .set(files3d::dsl::preview_image.eq(preview_image))
+                        .execute(connection)
+                        .await?;
+                }
+            }
+            if existing.size_x.is_none() && mesh_files.contains(&existing.file_type.as_str()) {
+                let [size_x, size_y, size_z] = analyse(file_pth, mesh_size).await;
+                diesel::update(files3d::dsl::files3d.find(existing.id))
+                    .set((
+                        files3d::dsl::size_x.eq(size_x),
+                        files3d::dsl::size_y.eq(size_y),
+                        files3d::dsl::size_z.eq(size_z),
+                    ))
+                    .execute(connection)
+                    .await?;
+            }
+            debug!("skipping {}", relative_path);
             continue;
         }
 
-        let hash = sha256::try_async_digest(file_pth)
+        let hash = timed(Phase::Hash, workers::digest(file_pth))
             .await
-            .unwrap()
-            .to_string();
-
-        let preview_image: Option<String>;
-        if file_pth
-            .extension()
-            .filter(|ext| mesh_files.contains(&ext.to_str().unwrap().to_lowercase().as_str()))
-            .is_some()
-        {
-            let mut img_path = config.preview_cache_dir.clone();
-            let file_name = format!("{}.png", hash);
-            img_path.push(file_name.clone());
-
-            let mut render_config = stl_thumb::config::Config::default();
-            render_config.model_filename = file_pth.to_str().unwrap().to_string();
-            render_config.img_filename = img_path.to_str().unwrap().to_string();
-
-            preview_image = if let Err(err) = panic::catch_unwind(|| {
-                stl_thumb::render_to_file(&render_config).unwrap();
-            }) {
-                error!(
-                    "Unable to render preview: {:?} Error: {:?}",
-                    file_pth.to_str(),
-                    err
-                );
+            .with_context(|| format!("Unable to read {}", file_pth.display()))?;
+
+        let file_type = file_type_of(file_pth);
+        let is_mesh = mesh_files.contains(&file_type.as_str());
+        let cad_metadata = cad::read_metadata(file_pth, &file_type);
+        // CAD and OpenSCAD files get their preview and size from a converted mesh
+        let converted = if cad_metadata.is_some() {
+            let cad_config = config.clone();
+            let source = file_pth.to_path_buf();
+            let converted = workers::run(move || cad::convert_for_preview(&cad_config, &source));
+            timed(Phase::Previews, converted).await.unwrap_or_else(|e| {
+                error!("Unable to convert {}: {}", file_pth.display(), e);
                 None
-            } else {
-                Some(file_name)
-            };
+            })
+        } else if openscad::is_openscad_file_type(&file_type) {
+            timed(
+                Phase::Previews,
+                openscad::render_for_preview(config, file_pth),
+            )
+            .await
         } else {
-            preview_image = None;
+            None
+        };
+        let mesh_pth = if is_mesh {
+            Some(file_pth)
+        } else {
+            converted.as_deref()
+        };
+
+        let ([size_x, size_y, size_z], preview_image, geometry_fingerprint, measurements) =
+            match mesh_pth {
+                Some(mesh_pth) => (
+                    analyse(mesh_pth, mesh_size).await,
+                    render_preview(config, mesh_pth, file_pth, &hash).await,
+                    analyse(mesh_pth, fingerprint).await,
+                    analyse(mesh_pth, measurements::measure_file).await,
+                ),
+                None if media::is_video_file_type(&file_type) => (
+                    [None; 3],
+                    timed(
+                        Phase::Previews,
+                        media::poster_frame(config, file_pth, &hash),
+                    )
+                    .await,
+                    None,
+                    None,
+                ),
+                None if file_type == "pdf" => (
+                    [None; 3],
+                    timed(
+                        Phase::Previews,
+                        documents::first_page_thumbnail(config, file_pth, &hash),
+                    )
+                    .await,
+                    None,
+                    None,
+                ),
+                None => ([None; 3], None, None, None),
+            };
+        if let Some(converted) = &converted {
+            let _ = fs::remove_file(converted).await;
         }
 
+        let print_settings = if file_type == "3mf" {
+            slicer_settings::read_print_settings(file_pth)
+        } else {
+            None
+        };
+        let gcode_metadata = if file_type == "gcode" {
+            gcode::read_metadata(file_pth)
+        } else {
+            None
+        };
+
         let new_file = NewFile3D {
             model_id: model.id,
-            file_path: pathdiff::diff_paths(file_pth, &model_base_path)
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string(),
+            file_path: relative_path,
             preview_image,
             file_hash: Some(hash),
             file_size_bytes: fs::metadata(file_pth).await?.len() as i32,
+            file_type,
+            size_x,
+            size_y,
+            size_z,
+            cad_metadata: cad_metadata
+                .map(|metadata| serde_json::to_string(&metadata))
+                .transpose()?,
+            print_settings: print_settings
+                .map(|settings| serde_json::to_string(&settings))
+                .transpose()?,
+            geometry_fingerprint,
+            gcode_metadata: gcode_metadata
+                .map(|metadata| serde_json::to_string(&metadata))
+                .transpose()?,
+            filament_id: None,
+            measurements,
         };
         diesel::insert_into(files3d::table)
             .values(&new_file)
             .execute(connection)
-            .await
-            .unwrap();
-        debug!("Created Preview {:?}", new_file.file_path)
+            .await?;
+        debug!("Created Preview {:?}", new_file.file_path);
+        added.push(new_file.file_path);
     }
-    anyhow::Ok(())
+    anyhow::Ok(added)
 }
 
 pub async fn clean_cache<Conn>(config: Config, connection: &mut Conn) -> anyhow::Result<()>
 where
     Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
 {
-    let mut preview_cache_dir = fs::read_dir(&config.preview_cache_dir).await.unwrap();
+    let mut preview_cache_dir = fs::read_dir(&config.preview_cache_dir).await?;
 
     while let Some(entry) = preview_cache_dir.next_entry().await? {
         let pth = entry.path();
@@ -411,7 +1128,10 @@ where
             continue;
         }
 
-        let file_name = pth.file_name().unwrap().to_str().unwrap().to_string();
+        let Some(file_name) = pth.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let file_name = file_name.to_string();
 
         let exists = files3d::dsl::files3d
             .filter(files3d::dsl::preview_image.eq(&file_name))
@@ -0,0 +1,142 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::LazyLock;
+use typeshare::typeshare;
+
+use crate::types::File3D;
+
+const PRINTABLE_EXTENSIONS: &[&str] = &["stl", "3mf", "obj"];
+const VARIANT_DIRS: &[&str] = &["presupported", "pre-supported", "supported", "unsupported"];
+
+/// `part_x4`, `part-4x`, `4x_part` or `part (x4)`
+static QUANTITY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(?:(?P<pre>\d+)x[_\- ]+)?(?P<name>.*?)(?:[_\- ]+\(?x(?P<post>\d+)\)?|[_\- ]+(?P<post2>\d+)x)?$")
+        .unwrap()
+});
+
+/// `hinge_a`, `hinge-b`, `hinge 1`, `hinge_part2`
+static PART_SUFFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(?P<base>.+?)[_\- ]+(?:part)?(?:[a-z]|\d{1,2})$").unwrap());
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartFile {
+    pub file_id: i32,
+    pub name: String,
+    pub quantity: i32,
+    /// e.g. `presupported` for files from a variant folder
+    pub variant: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartGroup {
+    pub name: String,
+    /// number of prints needed for one copy of the model, variants excluded
+    pub quantity: i32,
+    pub files: Vec<PartFile>,
+}
+
+struct ParsedName {
+    stem: String,
+    quantity: i32,
+    variant: Option<String>,
+}
+
+fn parse_name(file_path: &str) -> Option<ParsedName> {
+    let pth = Path::new(file_path);
+    let extension = pth.extension()?.to_str()?.to_ascii_lowercase();
+    if !PRINTABLE_EXTENSIONS.contains(&extension.as_str()) {
+        return None;
+    }
+
+    let variant = pth
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components())
+        .map(|component| component.as_os_str().to_string_lossy().to_ascii_lowercase())
+        .find(|component| VARIANT_DIRS.contains(&component.as_str()));
+
+    let stem = pth.file_stem()?.to_string_lossy().into_owned();
+    let captures = QUANTITY.captures(&stem)?;
+    let quantity = ["pre", "post", "post2"]
+        .iter()
+        .find_map(|group| captures.name(group))
+        .and_then(|quantity| quantity.as_str().parse::<i32>().ok())
+        .unwrap_or(1)
+        .max(1);
+    let name = captures.name("name").map(|m| m.as_str()).unwrap_or(&stem);
+
+    Some(ParsedName {
+        stem: name.trim().to_string(),
+        quantity,
+        variant,
+    })
+}
+
+/// Groups the printable files of a model into parts. Files sharing a stem
+/// apart from a trailing letter or number (`hinge_a`, `hinge_b`) form one
+/// group, quantity suffixes like `_x4` are summed up and files from
+/// presupported folders are listed as variants of their part.
+pub fn group_parts(files: &[File3D]) -> Vec<PartGroup> {
+    let parsed: Vec<(&File3D, ParsedName)> = files
+        .iter()
+        .filter_map(|file| parse_name(&file.file_path).map(|parsed| (file, parsed)))
+        .collect();
+
+    let base_of = |stem: &str| -> String {
+        PART_SUFFIX
+            .captures(stem)
+            .and_then(|captures| captures.name("base"))
+            .map(|base| base.as_str().to_string())
+            .unwrap_or_else(|| stem.to_string())
+    };
+
+    // only strip a part suffix if several files share the remaining base
+    let mut base_counts: BTreeMap<String, usize> = BTreeMap::new();
+    for (_, name) in parsed.iter().filter(|(_, name)| name.variant.is_none()) {
+        *base_counts
+            .entry(base_of(&name.stem).to_lowercase())
+            .or_default() += 1;
+    }
+
+    let mut groups: BTreeMap<String, PartGroup> = BTreeMap::new();
+    for (file, name) in parsed {
+        let base = base_of(&name.stem);
+        let group_name = if base_counts.get(&base.to_lowercase()).copied().unwrap_or(0) > 1 {
+            base
+        } else {
+            name.stem.clone()
+        };
+
+        let group = groups
+            .entry(group_name.to_lowercase())
+            .or_insert_with(|| PartGroup {
+                name: group_name.clone(),
+                quantity: 0,
+                files: Vec::new(),
+            });
+        if name.variant.is_none() {
+            group.quantity += name.quantity;
+        }
+        group.files.push(PartFile {
+            file_id: file.id,
+            name: Path::new(&file.file_path)
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            quantity: name.quantity,
+            variant: name.variant,
+        });
+    }
+
+    groups
+        .into_values()
+        .map(|mut group| {
+            group.files.sort_by(|a, b| a.name.cmp(&b.name));
+            group
+        })
+        .collect()
+}
@@ -0,0 +1,98 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+
+use crate::access::Viewer;
+use crate::auth::User;
+use crate::AppState;
+
+/// What a route requires of the viewer. Handlers declare it in their
+/// signature, e.g. `Authorized(viewer, _): Authorized<Admin>`, and requests
+/// which do not meet it are rejected before the handler runs.
+pub trait Requirement: Sized {
+    fn check(viewer: &Viewer) -> Result<Self, StatusCode>;
+}
+
+/// Admins only.
+pub struct Admin;
+
+/// Anyone but anonymous requests. Without `admin_users` every request is
+/// admin and passes.
+pub struct Authenticated;
+
+/// A user account, for routes about the data of the user themselves.
+pub struct Account(pub User);
+
+impl Requirement for Admin {
+    fn check(viewer: &Viewer) -> Result<Self, StatusCode> {
+        if viewer.is_admin {
+            Ok(Admin)
+        } else {
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+impl Requirement for Authenticated {
+    fn check(viewer: &Viewer) -> Result<Self, StatusCode> {
+        if viewer.user.is_some() || viewer.is_admin {
+            Ok(Authenticated)
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+impl Requirement for Account {
+    fn check(viewer: &Viewer) -> Result<Self, StatusCode> {
+        viewer
+            .user
+            .clone()
+            .map(Account)
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// A viewer meeting the requirement `R`.
+pub struct Authorized<R>(pub Viewer, pub R);
+
+#[async_trait]
+impl<R> FromRequestParts<AppState> for Authorized<R>
+where
+    R: Requirement + Send,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let viewer = Viewer::from_request_parts(parts, state).await?;
+        let requirement = R::check(&viewer)?;
+        Ok(Authorized(viewer, requirement))
+    }
+}
+
+/// Whether the viewer created an object owned by `owner_id`, like a print
+/// log or a draft, or is an admin. Models have `Viewer::can_manage`, which
+/// also takes the tenant into account.
+pub fn is_owner_or_admin(viewer: &Viewer, owner_id: Option<i32>) -> bool {
+    if viewer.is_admin {
+        return true;
+    }
+    match (&viewer.user, owner_id) {
+        (Some(user), Some(owner_id)) => user.id == owner_id,
+        _ => false,
+    }
+}
+
+/// Like `is_owner_or_admin`, rejecting everyone else.
+pub fn owner_or_admin(viewer: &Viewer, owner_id: Option<i32>) -> Result<(), StatusCode> {
+    if is_owner_or_admin(viewer, owner_id) {
+        Ok(())
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
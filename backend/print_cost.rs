@@ -0,0 +1,337 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::gcode::GcodeMetadata;
+use crate::policy::{owner_or_admin, Admin, Authorized};
+use crate::schema::{filaments, files3d, print_logs};
+use crate::types::File3D;
+use crate::{AppState, Config};
+
+/// PLA, used when neither the filament nor the G-code tell.
+const DEFAULT_DENSITY: f64 = 1.24;
+const DEFAULT_DIAMETER: f64 = 1.75;
+
+/// A spool prints are priced with.
+#[typeshare]
+#[derive(Debug, Clone, Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = filaments)]
+pub struct Filament {
+    pub id: i32,
+    pub name: String,
+    /// `PLA`, `PETG`, ..., matched against the filament type of G-code files
+    pub material: String,
+    pub price_per_kg: f64,
+    /// g/cm³
+    pub density: f64,
+    /// mm
+    pub diameter: f64,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveFilament {
+    pub name: String,
+    pub material: String,
+    pub price_per_kg: f64,
+    pub density: Option<f64>,
+    pub diameter: Option<f64>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkFilament {
+    /// unset falls back to a filament of the sliced material
+    pub filament_id: Option<i32>,
+}
+
+/// What a print costs in filament and electricity. Parts that cannot be
+/// calculated, for lack of a price or of usage figures, are unset.
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CostEstimate {
+    pub filament: Option<Filament>,
+    pub filament_grams: Option<f64>,
+    pub filament_cost: Option<f64>,
+    pub print_hours: Option<f64>,
+    pub energy_kwh: Option<f64>,
+    pub energy_cost: Option<f64>,
+    pub total: Option<f64>,
+    pub currency: String,
+}
+
+/// Filament used and print time, as noted by the slicer or logged.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PrintUsage {
+    pub grams: Option<f64>,
+    pub seconds: Option<i32>,
+}
+
+impl PrintUsage {
+    pub fn of_gcode(gcode: &GcodeMetadata, filament: Option<&Filament>) -> Self {
+        let (density, diameter) = filament
+            .map(|filament| (filament.density, filament.diameter))
+            .unwrap_or((DEFAULT_DENSITY, DEFAULT_DIAMETER));
+        PrintUsage {
+            grams: gcode.filament_grams(density, diameter),
+            seconds: gcode.print_time_seconds,
+        }
+    }
+
+    /// Logged figures win over what the slicer estimated.
+    pub fn or(self, estimated: PrintUsage) -> Self {
+        PrintUsage {
+            grams: self.grams.or(estimated.grams),
+            seconds: self.seconds.or(estimated.seconds),
+        }
+    }
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+pub fn estimate(config: &Config, filament: Option<Filament>, usage: PrintUsage) -> CostEstimate {
+    let price_per_kg = filament
+        .as_ref()
+        .map(|filament| filament.price_per_kg)
+        .or(config.filament_price_per_kg);
+    let filament_cost = usage
+        .grams
+        .zip(price_per_kg)
+        .map(|(grams, price_per_kg)| round_cents(grams / 1000.0 * price_per_kg));
+
+    let print_hours = usage.seconds.map(|seconds| seconds as f64 / 3600.0);
+    let energy_kwh = print_hours.map(|hours| hours * config.printer_watts / 1000.0);
+    let energy_cost = energy_kwh
+        .zip(config.energy_price_per_kwh)
+        .map(|(kwh, price_per_kwh)| round_cents(kwh * price_per_kwh));
+
+    let total = match (filament_cost, energy_cost) {
+        (None, None) => None,
+        (filament_cost, energy_cost) => Some(round_cents(
+            filament_cost.unwrap_or_default() + energy_cost.unwrap_or_default(),
+        )),
+    };
+    CostEstimate {
+        filament,
+        filament_grams: usage.grams.map(|grams| (grams * 10.0).round() / 10.0),
+        filament_cost,
+        print_hours: print_hours.map(|hours| (hours * 100.0).round() / 100.0),
+        energy_kwh: energy_kwh.map(|kwh| (kwh * 1000.0).round() / 1000.0),
+        energy_cost,
+        total,
+        currency: config.currency.clone(),
+    }
+}
+
+/// The linked filament, or else the first filament of the material the
+/// file was sliced for.
+pub async fn resolve_filament<Conn>(
+    connection: &mut Conn,
+    filament_id: Option<i32>,
+    gcode: Option<&GcodeMetadata>,
+) -> QueryResult<Option<Filament>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    if let Some(filament_id) = filament_id {
+        return filaments::dsl::filaments
+            .find(filament_id)
+            .first::<Filament>(connection)
+            .await
+            .optional();
+    }
+    let Some(material) = gcode.and_then(|gcode| gcode.filament_type.as_deref()) else {
+        return Ok(None);
+    };
+    let filaments = filaments::dsl::filaments
+        .order(filaments::dsl::name)
+        .load::<Filament>(connection)
+        .await?;
+    Ok(filaments
+        .into_iter()
+        .find(|filament| filament.material.eq_ignore_ascii_case(material.trim())))
+}
+
+pub fn gcode_of(file: &File3D) -> Option<GcodeMetadata> {
+    file.gcode_metadata
+        .as_deref()
+        .and_then(|metadata| serde_json::from_str(metadata).ok())
+}
+
+/// The estimated cost of printing a sliced file.
+pub async fn file_cost<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    file: &File3D,
+) -> QueryResult<CostEstimate>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let gcode = gcode_of(file);
+    let filament = resolve_filament(connection, file.filament_id, gcode.as_ref()).await?;
+    let usage = gcode
+        .map(|gcode| PrintUsage::of_gcode(&gcode, filament.as_ref()))
+        .unwrap_or_default();
+    Ok(estimate(config, filament, usage))
+}
+
+pub async fn handle_list_filaments(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Filament>>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let filaments = filaments::dsl::filaments
+        .order(filaments::dsl::name)
+        .load::<Filament>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load filaments: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(filaments))
+}
+
+/// Adds a filament, or updates the one of the same name.
+pub async fn handle_save_filament(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Json(payload): Json<SaveFilament>,
+) -> Result<Json<Filament>, StatusCode> {
+    let name = payload.name.trim();
+    let material = payload.material.trim();
+    let density = payload.density.unwrap_or(DEFAULT_DENSITY);
+    let diameter = payload.diameter.unwrap_or(DEFAULT_DIAMETER);
+    if name.is_empty()
+        || material.is_empty()
+        || payload.price_per_kg < 0.0
+        || density <= 0.0
+        || diameter <= 0.0
+    {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let mut connection = state.connection().await?;
+
+    let filament = diesel::insert_into(filaments::table)
+        .values((
+            filaments::dsl::name.eq(name),
+            filaments::dsl::material.eq(material),
+            filaments::dsl::price_per_kg.eq(payload.price_per_kg),
+            filaments::dsl::density.eq(density),
+            filaments::dsl::diameter.eq(diameter),
+        ))
+        .on_conflict(filaments::dsl::name)
+        .do_update()
+        .set((
+            filaments::dsl::material.eq(material),
+            filaments::dsl::price_per_kg.eq(payload.price_per_kg),
+            filaments::dsl::density.eq(density),
+            filaments::dsl::diameter.eq(diameter),
+        ))
+        .get_result::<Filament>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to save filament {}: {}", name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(filament))
+}
+
+/// Deletes a filament, files and prints linked to it fall back to their
+/// sliced material.
+pub async fn handle_delete_filament(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    diesel::update(files3d::dsl::files3d.filter(files3d::dsl::filament_id.eq(id)))
+        .set(files3d::dsl::filament_id.eq(None::<i32>))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to unlink filament {} from files: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    diesel::update(print_logs::dsl::print_logs.filter(print_logs::dsl::filament_id.eq(id)))
+        .set(print_logs::dsl::filament_id.eq(None::<i32>))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to unlink filament {} from prints: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let deleted = diesel::delete(filaments::dsl::filaments.find(id))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete filament {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    match deleted {
+        0 => Err(StatusCode::NOT_FOUND),
+        _ => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
+pub async fn handle_file_cost(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+) -> Result<Json<CostEstimate>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let (file, _) = viewer.find_file(&mut connection, id).await?;
+    let cost = file_cost(&state.config, &mut connection, &file)
+        .await
+        .map_err(|e| {
+            error!("Failed to estimate the cost of file {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(cost))
+}
+
+/// Links the filament a file is printed with and returns the new estimate.
+pub async fn handle_link_filament(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+    Json(payload): Json<LinkFilament>,
+) -> Result<Json<CostEstimate>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let (file, model) = viewer.find_file(&mut connection, id).await?;
+    owner_or_admin(&viewer, model.owner_id)?;
+    if let Some(filament_id) = payload.filament_id {
+        resolve_filament(&mut connection, Some(filament_id), None)
+            .await
+            .map_err(|e| {
+                error!("Failed to load filament {}: {}", filament_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    }
+
+    let file = diesel::update(files3d::dsl::files3d.find(file.id))
+        .set(files3d::dsl::filament_id.eq(payload.filament_id))
+        .get_result::<File3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to link a filament to file {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let cost = file_cost(&state.config, &mut connection, &file)
+        .await
+        .map_err(|e| {
+            error!("Failed to estimate the cost of file {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(cost))
+}
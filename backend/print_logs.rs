@@ -0,0 +1,513 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::api_version::CURRENT_PREFIX;
+use crate::media::is_video_file_type;
+use crate::policy::{owner_or_admin, Authenticated, Authorized};
+use crate::print_cost::{estimate, gcode_of, resolve_filament, CostEstimate, PrintUsage};
+use crate::schema::{files3d, models3d, print_logs, users};
+use crate::types::{File3D, Model3D};
+use crate::webcams;
+use crate::{AppState, Config};
+
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = print_logs)]
+pub struct PrintLog {
+    pub id: i32,
+    pub model_id: i32,
+    pub file_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub filament_id: Option<i32>,
+    pub printed_at: NaiveDateTime,
+    pub duration_seconds: Option<i32>,
+    pub filament_grams: Option<f64>,
+    pub success: bool,
+    pub notes: String,
+    pub timelapse_id: Option<i32>,
+    /// file name of the webcam picture in `print_snapshot_dir`
+    pub snapshot: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrintLogResponse {
+    pub id: i32,
+    pub file_id: Option<i32>,
+    pub file_name: Option<String>,
+    pub user: Option<String>,
+    pub printed_at: NaiveDateTime,
+    pub duration_seconds: Option<i32>,
+    pub filament_grams: Option<f64>,
+    pub success: bool,
+    pub notes: String,
+    pub cost: CostEstimate,
+    pub timelapse_id: Option<i32>,
+    /// URL of the video, served with support for ranged requests
+    pub timelapse_url: Option<String>,
+    pub timelapse_poster: Option<String>,
+    /// picture of the printer taken by its webcam when the print was logged
+    pub snapshot_url: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogPrint {
+    /// the sliced file that was printed
+    pub file_id: Option<i32>,
+    pub filament_id: Option<i32>,
+    /// defaults to now
+    pub printed_at: Option<NaiveDateTime>,
+    /// unset uses the estimate of the sliced file
+    pub duration_seconds: Option<i32>,
+    pub filament_grams: Option<f64>,
+    #[serde(default = "default_success")]
+    pub success: bool,
+    #[serde(default)]
+    pub notes: String,
+    /// a video of the print among the files of the model
+    pub timelapse_id: Option<i32>,
+    /// name of the printer in `printer_webcams` to take a snapshot with,
+    /// may be left out if only one webcam is configured
+    pub printer: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachTimelapse {
+    /// unset removes the timelapse
+    pub file_id: Option<i32>,
+}
+
+fn default_success() -> bool {
+    true
+}
+
+/// The cost of a print from what was logged, filling in the estimates of
+/// the printed file where nothing was logged.
+pub async fn print_cost<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    log: &PrintLog,
+    file: Option<&File3D>,
+) -> QueryResult<CostEstimate>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let gcode = file.and_then(gcode_of);
+    let filament_id = log
+        .filament_id
+        .or_else(|| file.and_then(|file| file.filament_id));
+    let filament = resolve_filament(connection, filament_id, gcode.as_ref()).await?;
+    let logged = PrintUsage {
+        grams: log.filament_grams,
+        seconds: log.duration_seconds,
+    };
+    let usage = match &gcode {
+        Some(gcode) => logged.or(PrintUsage::of_gcode(gcode, filament.as_ref())),
+        None => logged,
+    };
+    Ok(estimate(config, filament, usage))
+}
+
+async fn to_response<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    log: PrintLog,
+) -> QueryResult<PrintLogResponse>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let file = match log.file_id {
+        Some(file_id) => files3d::dsl::files3d
+            .find(file_id)
+            .first::<File3D>(connection)
+            .await
+            .optional()?,
+        None => None,
+    };
+    let user = match log.user_id {
+        Some(user_id) => users::dsl::users
+            .find(user_id)
+            .select(users::dsl::name)
+            .first::<String>(connection)
+            .await
+            .optional()?,
+        None => None,
+    };
+    let cost = print_cost(config, connection, &log, file.as_ref()).await?;
+    let file_name = match &file {
+        Some(file) => file.get_file_name().await,
+        None => None,
+    };
+    let timelapse = match log.timelapse_id {
+        Some(timelapse_id) => files3d::dsl::files3d
+            .find(timelapse_id)
+            .first::<File3D>(connection)
+            .await
+            .optional()?,
+        None => None,
+    };
+    let timelapse_url = match &timelapse {
+        Some(timelapse) => Some(timelapse.get_url_file_path(connection, config).await),
+        None => None,
+    };
+
+    Ok(PrintLogResponse {
+        id: log.id,
+        file_id: log.file_id,
+        file_name,
+        user,
+        printed_at: log.printed_at,
+        duration_seconds: log.duration_seconds,
+        filament_grams: log.filament_grams,
+        success: log.success,
+        notes: log.notes,
+        cost,
+        timelapse_id: log.timelapse_id,
+        timelapse_url,
+        timelapse_poster: timelapse.and_then(|timelapse| timelapse.get_url_preview_path(config)),
+        snapshot_url: log
+            .snapshot
+            .as_ref()
+            .map(|_| format!("{}/prints/{}/snapshot", CURRENT_PREFIX, log.id)),
+    })
+}
+
+/// Drops the link of prints to a file removed from the library, the
+/// figures logged with them stay.
+pub async fn detach_file<Conn>(connection: &mut Conn, file_id: i32) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::update(print_logs::dsl::print_logs.filter(print_logs::dsl::file_id.eq(file_id)))
+        .set(print_logs::dsl::file_id.eq(None::<i32>))
+        .execute(connection)
+        .await?;
+    diesel::update(print_logs::dsl::print_logs.filter(print_logs::dsl::timelapse_id.eq(file_id)))
+        .set(print_logs::dsl::timelapse_id.eq(None::<i32>))
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// Checks that a timelapse is a video among the files of the model.
+async fn check_timelapse<Conn>(
+    viewer: &Viewer,
+    connection: &mut Conn,
+    model_id: i32,
+    file_id: i32,
+) -> Result<(), StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let (file, _) = viewer.find_file(connection, file_id).await?;
+    if file.model_id != model_id || !is_video_file_type(&file.file_type) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    Ok(())
+}
+
+/// Removes the print log of a purged model together with its snapshots.
+pub async fn delete_print_logs<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model_id: i32,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let snapshots = print_logs::dsl::print_logs
+        .filter(print_logs::dsl::model_id.eq(model_id))
+        .filter(print_logs::dsl::snapshot.is_not_null())
+        .select(print_logs::dsl::snapshot.assume_not_null())
+        .load::<String>(connection)
+        .await?;
+    for snapshot in snapshots {
+        webcams::delete_snapshot(config, &snapshot).await;
+    }
+    diesel::delete(print_logs::dsl::print_logs.filter(print_logs::dsl::model_id.eq(model_id)))
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// The prints of a model with their cost, latest first.
+pub async fn handle_list_prints(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+) -> Result<Json<Vec<PrintLogResponse>>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let model = viewer.find_model(&mut connection, &slug).await?;
+
+    let logs = print_logs::dsl::print_logs
+        .filter(print_logs::dsl::model_id.eq(model.id))
+        .order(print_logs::dsl::printed_at.desc())
+        .load::<PrintLog>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load prints of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut response = Vec::new();
+    for log in logs {
+        let log_id = log.id;
+        response.push(
+            to_response(&state.config, &mut connection, log)
+                .await
+                .map_err(|e| {
+                    error!("Failed to build print log {}: {}", log_id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?,
+        );
+    }
+    Ok(Json(response))
+}
+
+/// Takes a picture with the webcam of the printer for a new print log. A
+/// webcam that is offline must not keep the print from being logged.
+async fn attach_snapshot<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    log: PrintLog,
+    printer: Option<&str>,
+) -> Result<PrintLog, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let Some(url) = webcams::snapshot_url(config, printer) else {
+        return Ok(log);
+    };
+    let snapshot = match webcams::capture_snapshot(config, url, log.id).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            warn!("No snapshot for print log {}: {}", log.id, e);
+            return Ok(log);
+        }
+    };
+    diesel::update(print_logs::dsl::print_logs.find(log.id))
+        .set(print_logs::dsl::snapshot.eq(&snapshot))
+        .get_result::<PrintLog>(connection)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to store the snapshot of print log {}: {}",
+                log.id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Logs a print of a model anyone who can see it may have made.
+pub async fn handle_log_print(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Authenticated>,
+    Path(slug): Path<String>,
+    Json(payload): Json<LogPrint>,
+) -> Result<Json<PrintLogResponse>, StatusCode> {
+    if payload.duration_seconds.is_some_and(|seconds| seconds < 0)
+        || payload.filament_grams.is_some_and(|grams| grams < 0.0)
+    {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let mut connection = state.connection().await?;
+    let model = viewer.find_model(&mut connection, &slug).await?;
+
+    if let Some(file_id) = payload.file_id {
+        let (file, _) = viewer.find_file(&mut connection, file_id).await?;
+        if file.model_id != model.id {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+    if let Some(timelapse_id) = payload.timelapse_id {
+        check_timelapse(&viewer, &mut connection, model.id, timelapse_id).await?;
+    }
+    if let Some(filament_id) = payload.filament_id {
+        resolve_filament(&mut connection, Some(filament_id), None)
+            .await
+            .map_err(|e| {
+                error!("Failed to load filament {}: {}", filament_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    }
+
+    let log = diesel::insert_into(print_logs::table)
+        .values((
+            print_logs::dsl::model_id.eq(model.id),
+            print_logs::dsl::file_id.eq(payload.file_id),
+            print_logs::dsl::user_id.eq(viewer.user.as_ref().map(|user| user.id)),
+            print_logs::dsl::filament_id.eq(payload.filament_id),
+            print_logs::dsl::printed_at
+                .eq(payload.printed_at.unwrap_or_else(|| Utc::now().naive_utc())),
+            print_logs::dsl::duration_seconds.eq(payload.duration_seconds),
+            print_logs::dsl::filament_grams.eq(payload.filament_grams),
+            print_logs::dsl::success.eq(payload.success),
+            print_logs::dsl::notes.eq(payload.notes.trim()),
+            print_logs::dsl::timelapse_id.eq(payload.timelapse_id),
+        ))
+        .get_result::<PrintLog>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to log a print of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let log = attach_snapshot(
+        &state.config,
+        &mut connection,
+        log,
+        payload.printer.as_deref(),
+    )
+    .await?;
+
+    let log_id = log.id;
+    Ok(Json(
+        to_response(&state.config, &mut connection, log)
+            .await
+            .map_err(|e| {
+                error!("Failed to build print log {}: {}", log_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+    ))
+}
+
+/// Admins and whoever logged a print may delete it.
+pub async fn handle_delete_print(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let log = print_logs::dsl::print_logs
+        .find(id)
+        .first::<PrintLog>(&mut connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load print log {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    owner_or_admin(&viewer, log.user_id)?;
+
+    diesel::delete(print_logs::dsl::print_logs.find(log.id))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete print log {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if let Some(snapshot) = &log.snapshot {
+        webcams::delete_snapshot(&state.config, snapshot).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The webcam picture of a print, for anyone who may see the model.
+pub async fn handle_print_snapshot(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+) -> Result<Response, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let log = print_logs::dsl::print_logs
+        .find(id)
+        .first::<PrintLog>(&mut connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load print log {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let snapshot = log.snapshot.ok_or(StatusCode::NOT_FOUND)?;
+    let model = models3d::dsl::models3d
+        .find(log.model_id)
+        .first::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load model {}: {}", log.model_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let visible = viewer
+        .can_view(&mut connection, &model)
+        .await
+        .map_err(|e| {
+            error!("Failed to check access to {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !visible {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let image = tokio::fs::read(webcams::snapshot_path(&state.config, &snapshot))
+        .await
+        .map_err(|e| {
+            error!("Failed to read the snapshot of print log {}: {}", id, e);
+            StatusCode::NOT_FOUND
+        })?;
+    let content_type = match snapshot.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], image).into_response())
+}
+
+/// Attaches a video to a print after the fact, timelapses are usually
+/// copied off the printer once it finished. Admins and whoever logged the
+/// print may change it.
+pub async fn handle_attach_timelapse(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i32>,
+    Json(payload): Json<AttachTimelapse>,
+) -> Result<Json<PrintLogResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let log = print_logs::dsl::print_logs
+        .find(id)
+        .first::<PrintLog>(&mut connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load print log {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    owner_or_admin(&viewer, log.user_id)?;
+    if let Some(file_id) = payload.file_id {
+        check_timelapse(&viewer, &mut connection, log.model_id, file_id).await?;
+    }
+
+    let log = diesel::update(print_logs::dsl::print_logs.find(log.id))
+        .set(print_logs::dsl::timelapse_id.eq(payload.file_id))
+        .get_result::<PrintLog>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to attach a timelapse to print log {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(
+        to_response(&state.config, &mut connection, log)
+            .await
+            .map_err(|e| {
+                error!("Failed to build print log {}: {}", id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+    ))
+}
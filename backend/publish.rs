@@ -0,0 +1,389 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use diesel_async::AsyncConnection;
+use reqwest::multipart::{Form, Part};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info};
+use typeshare::typeshare;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::access::Viewer;
+use crate::audit_log::record;
+use crate::chat_notifiers::thumbnail_jpeg;
+use crate::image_limits;
+use crate::manifest::attribution;
+use crate::tags::get_model_tags;
+use crate::types::Model3D;
+use crate::workers;
+use crate::{AppState, Config};
+
+const THINGIVERSE_API: &str = "https://api.thingiverse.com";
+
+/// What a site accepts, bundles are cut down to fit.
+struct Site {
+    name: &'static str,
+    /// longest side images are scaled down to
+    image_size: u32,
+    max_images: usize,
+    max_tags: usize,
+}
+
+const PRINTABLES: Site = Site {
+    name: "printables",
+    image_size: 2048,
+    max_images: 20,
+    max_tags: 10,
+};
+
+const THINGIVERSE: Site = Site {
+    name: "thingiverse",
+    image_size: 1920,
+    max_images: 20,
+    max_tags: 20,
+};
+
+fn site(name: &str) -> Option<&'static Site> {
+    match name {
+        "printables" => Some(&PRINTABLES),
+        "thingiverse" => Some(&THINGIVERSE),
+        _ => None,
+    }
+}
+
+/// A model prepared for a site, files next to their name in the bundle.
+pub struct Publication {
+    pub title: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub license: Option<String>,
+    pub files: Vec<(String, PathBuf)>,
+    /// JPEGs within the image limits of the site
+    pub images: Vec<(String, Vec<u8>)>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Published {
+    pub site: String,
+    /// the draft on the site, to be reviewed and published there
+    pub url: String,
+}
+
+/// The description of the model ending with a credit line, so the source
+/// and license travel with the upload.
+fn description(model: &Model3D) -> String {
+    let mut description = model.description.trim().to_string();
+    if !description.is_empty() {
+        description.push_str("\n\n---\n\n");
+    }
+    description.push_str(&attribution(model));
+    description
+}
+
+async fn prepare<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+    site: &Site,
+) -> anyhow::Result<Publication>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let base_path = model.absolute_path(config);
+    let files: Vec<(String, PathBuf)> = model
+        .get_files3d(connection)
+        .await?
+        .into_iter()
+        .map(|file| {
+            let name = PathBuf::from(&file.file_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| file.file_path.clone());
+            (name, base_path.join(&file.file_path))
+        })
+        .collect();
+
+    let image_paths: Vec<PathBuf> = model
+        .relative_image_paths()
+        .into_iter()
+        .take(site.max_images)
+        .map(|image| base_path.join(image))
+        .collect();
+    let image_size = site.image_size;
+    let limits = image_limits::limits(config);
+    let images: Vec<(String, Vec<u8>)> = workers::run(move || {
+        image_paths
+            .iter()
+            .enumerate()
+            .filter_map(|(index, path)| {
+                let jpeg = thumbnail_jpeg(path, image_size, limits.clone())?;
+                Some((format!("{:02}.jpg", index + 1), jpeg))
+            })
+            .collect()
+    })
+    .await?;
+
+    let mut tags = get_model_tags(connection, model.id).await?;
+    tags.truncate(site.max_tags);
+
+    Ok(Publication {
+        title: model.title.clone(),
+        description: description(model),
+        tags,
+        license: model.license.clone(),
+        files,
+        images,
+    })
+}
+
+fn bundle(publication: Publication, site: &Site) -> anyhow::Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+
+    zip.start_file("description.md", options)?;
+    zip.write_all(publication.description.as_bytes())?;
+    zip.start_file("metadata.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&json!({
+        "site": site.name,
+        "title": publication.title,
+        "tags": publication.tags,
+        "license": publication.license,
+    }))?)?;
+    for (name, jpeg) in &publication.images {
+        zip.start_file(format!("images/{}", name), options)?;
+        zip.write_all(jpeg)?;
+    }
+    let stored = options.compression_method(zip::CompressionMethod::Stored);
+    for (name, path) in &publication.files {
+        zip.start_file(format!("files/{}", name), stored)?;
+        std::io::copy(&mut std::fs::File::open(path)?, &mut zip)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// A zip of everything a site asks for when uploading a model: the files,
+/// images scaled to the site limits and a description with attribution.
+pub async fn handle_publish_bundle(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path((slug, site_name)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    let site = site(&site_name).ok_or(StatusCode::NOT_FOUND)?;
+    let mut connection = state.connection().await?;
+    let model = viewer.manage_model(&mut connection, &slug).await?;
+    let publication = prepare(&state.config, &mut connection, &model, site)
+        .await
+        .map_err(|e| {
+            error!("Failed to prepare {} for {}: {}", slug, site.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    drop(connection);
+
+    let archive = workers::run(move || bundle(publication, site))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("Failed to bundle {} for {}: {}", slug, site.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}-{}.zip\"", model.name, site.name),
+            ),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+/// Thingiverse license codes for the usual Creative Commons and open
+/// source licenses, anything unknown falls back to CC BY.
+fn thingiverse_license(license: Option<&str>) -> &'static str {
+    let license = license
+        .unwrap_or_default()
+        .to_lowercase()
+        .replace(['-', '_'], " ");
+    let has = |part: &str| license.split_whitespace().any(|word| word == part);
+    if license.contains("public domain") || has("cc0") {
+        "pd0"
+    } else if has("lgpl") {
+        "lgpl"
+    } else if has("gpl") {
+        "gpl"
+    } else if has("bsd") {
+        "bsd"
+    } else {
+        match (has("nc"), has("sa"), has("nd")) {
+            (true, true, _) => "cc-nc-sa",
+            (true, _, true) => "cc-nc-nd",
+            (true, _, _) => "cc-nc",
+            (_, true, _) => "cc-sa",
+            (_, _, true) => "cc-nd",
+            _ => "cc",
+        }
+    }
+}
+
+/// Uploads a file to a thing: the API hands out a signed upload form,
+/// which is posted with the file and then finalized.
+async fn thingiverse_upload(
+    client: &Client,
+    token: &str,
+    thing_id: i64,
+    name: &str,
+    content: Vec<u8>,
+) -> anyhow::Result<()> {
+    let ticket: Value = client
+        .post(format!("{}/things/{}/files", THINGIVERSE_API, thing_id))
+        .bearer_auth(token)
+        .json(&json!({ "filename": name }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let action = ticket["action"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("No upload form for {}", name))?;
+
+    let mut form = Form::new();
+    let mut finalize = None;
+    if let Some(fields) = ticket["fields"].as_object() {
+        for (key, value) in fields {
+            let value = value.as_str().unwrap_or_default().to_string();
+            if key == "success_action_redirect" {
+                finalize = Some(value.clone());
+            }
+            form = form.text(key.clone(), value);
+        }
+    }
+    form = form.part("file", Part::bytes(content).file_name(name.to_string()));
+    client
+        .post(action)
+        .multipart(form)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    if let Some(finalize) = finalize {
+        client
+            .post(finalize)
+            .bearer_auth(token)
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+    Ok(())
+}
+
+/// Creates a work in progress thing with all files and images, which the
+/// owner of the token reviews and publishes on Thingiverse.
+async fn push_to_thingiverse(
+    config: &Config,
+    token: &str,
+    publication: Publication,
+) -> anyhow::Result<String> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.slow_request_timeout_seconds))
+        .user_agent(concat!("ModelVault/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+
+    let thing: Value = client
+        .post(format!("{}/things/", THINGIVERSE_API))
+        .bearer_auth(token)
+        .json(&json!({
+            "name": publication.title,
+            "license": thingiverse_license(publication.license.as_deref()),
+            "description": publication.description,
+            "tags": publication.tags,
+            "is_wip": true,
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let thing_id = thing["id"]
+        .as_i64()
+        .ok_or_else(|| anyhow::anyhow!("Thingiverse returned no thing id"))?;
+
+    for (name, path) in publication.files {
+        let content = tokio::fs::read(&path).await?;
+        thingiverse_upload(&client, token, thing_id, &name, content).await?;
+    }
+    for (name, jpeg) in publication.images {
+        thingiverse_upload(&client, token, thing_id, &name, jpeg).await?;
+    }
+
+    Ok(thing["public_url"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("https://www.thingiverse.com/thing:{}", thing_id)))
+}
+
+/// Pushes a model to a site as a draft, using the credentials from the
+/// configuration. Printables has no public upload API, its bundle is
+/// uploaded by hand.
+pub async fn handle_publish(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path((slug, site_name)): Path<(String, String)>,
+) -> Result<Json<Published>, StatusCode> {
+    let site = site(&site_name).ok_or(StatusCode::NOT_FOUND)?;
+    let token = match site.name {
+        "thingiverse" => state.config.thingiverse_token.clone(),
+        _ => None,
+    }
+    .ok_or(StatusCode::NOT_IMPLEMENTED)?;
+
+    let mut connection = state.connection().await?;
+    let model = viewer.manage_model(&mut connection, &slug).await?;
+    let publication = prepare(&state.config, &mut connection, &model, site)
+        .await
+        .map_err(|e| {
+            error!("Failed to prepare {} for {}: {}", slug, site.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    drop(connection);
+
+    let url = push_to_thingiverse(&state.config, &token, publication)
+        .await
+        .map_err(|e| {
+            error!("Failed to publish {} to {}: {}", slug, site.name, e);
+            StatusCode::BAD_GATEWAY
+        })?;
+    info!("Published {} to {} as {}", slug, site.name, url);
+
+    let mut connection = state.connection().await?;
+    if let Err(e) = record(
+        &mut connection,
+        &viewer,
+        "model.publish",
+        &model.slug,
+        json!({ "site": site.name, "url": url }),
+    )
+    .await
+    {
+        error!("Failed to record the publication of {}: {}", slug, e);
+    }
+
+    Ok(Json(Published {
+        site: site.name.to_string(),
+        url,
+    }))
+}
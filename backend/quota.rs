@@ -0,0 +1,112 @@
+use axum::{extract::State, http::StatusCode, Json};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::auth::CurrentUser;
+use crate::schema::{models3d, uploads};
+use crate::{AppState, Config};
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub user: String,
+    pub used_bytes: f64,
+    /// unset when uploads are unlimited
+    pub quota_bytes: Option<f64>,
+    pub remaining_bytes: Option<f64>,
+}
+
+pub fn quota_bytes(config: &Config) -> Option<u64> {
+    config.upload_quota_mb.map(|mb| mb * 1024 * 1024)
+}
+
+pub fn max_upload_bytes(config: &Config) -> Option<u64> {
+    config.max_upload_size_mb.map(|mb| mb * 1024 * 1024)
+}
+
+/// Size of all files below `dir`.
+pub fn dir_size(dir: &Path) -> u64 {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// Bytes uploaded by a user into models which still exist, purging a model
+/// frees its share of the quota.
+pub async fn used_bytes<Conn>(connection: &mut Conn, user_id: i32) -> QueryResult<u64>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let bytes = uploads::dsl::uploads
+        .inner_join(models3d::table)
+        .filter(uploads::dsl::user_id.eq(user_id))
+        .select(uploads::dsl::bytes)
+        .load::<i64>(connection)
+        .await?;
+    Ok(bytes.into_iter().map(|bytes| bytes.max(0) as u64).sum())
+}
+
+pub async fn record_upload<Conn>(
+    connection: &mut Conn,
+    user_id: i32,
+    model_id: i32,
+    bytes: u64,
+) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::insert_into(uploads::table)
+        .values((
+            uploads::dsl::user_id.eq(user_id),
+            uploads::dsl::model_id.eq(model_id),
+            uploads::dsl::bytes.eq(bytes as i64),
+            uploads::dsl::created_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(connection)
+        .await
+}
+
+pub async fn delete_uploads<Conn>(connection: &mut Conn, model_id: i32) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::delete(uploads::dsl::uploads.filter(uploads::dsl::model_id.eq(model_id)))
+        .execute(connection)
+        .await
+}
+
+pub async fn handle_my_quota(
+    State(state): State<AppState>,
+    current_user: CurrentUser,
+) -> Result<Json<QuotaUsage>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let user = CurrentUser::load(&current_user, &mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load user: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let used = used_bytes(&mut connection, user.id).await.map_err(|e| {
+        error!("Failed to compute quota usage of {}: {}", user.name, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let quota = quota_bytes(&state.config);
+
+    Ok(Json(QuotaUsage {
+        user: user.name,
+        used_bytes: used as f64,
+        quota_bytes: quota.map(|quota| quota as f64),
+        remaining_bytes: quota.map(|quota| quota.saturating_sub(used) as f64),
+    }))
+}
@@ -0,0 +1,143 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::model_filter::filter_models;
+use crate::schema::models3d;
+use crate::types::{ListModelParams, Model3D, ModelResponse};
+use crate::AppState;
+
+const CURSOR_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecentSort {
+    #[default]
+    Added,
+    Updated,
+}
+
+#[derive(Deserialize)]
+pub struct RecentModelParams {
+    #[serde(default)]
+    pub sort: RecentSort,
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    #[serde(flatten)]
+    pub filters: ListModelParams,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecentModelsResponse {
+    pub models: Vec<ModelResponse>,
+    /// pass as `cursor` to fetch the next page, missing on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// A position in the listing, the timestamp of the last returned model and its
+/// id to break ties between models added in the same instant.
+fn encode_cursor(timestamp: NaiveDateTime, id: i32) -> String {
+    format!("{}_{}", timestamp.format(CURSOR_TIME_FORMAT), id)
+}
+
+fn decode_cursor(cursor: &str) -> Option<(NaiveDateTime, i32)> {
+    let (timestamp, id) = cursor.rsplit_once('_')?;
+    let timestamp = NaiveDateTime::parse_from_str(timestamp, CURSOR_TIME_FORMAT).ok()?;
+    Some((timestamp, id.parse().ok()?))
+}
+
+pub async fn handle_recent_models(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(params): Query<RecentModelParams>,
+) -> Result<Json<RecentModelsResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let cursor = match &params.cursor {
+        Some(cursor) => Some(decode_cursor(cursor).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+
+    let mut query = filter_models(&params.filters, &viewer)?;
+    query = match params.sort {
+        RecentSort::Added => {
+            query = query
+                .filter(models3d::dsl::date_added.is_not_null())
+                .order((models3d::dsl::date_added.desc(), models3d::dsl::id.desc()));
+            if let Some((timestamp, id)) = cursor {
+                query = query.filter(
+                    models3d::dsl::date_added
+                        .lt(timestamp)
+                        .or(models3d::dsl::date_added
+                            .eq(timestamp)
+                            .and(models3d::dsl::id.lt(id))),
+                );
+            }
+            query
+        }
+        RecentSort::Updated => {
+            query = query
+                .filter(models3d::dsl::updated_at.is_not_null())
+                .order((models3d::dsl::updated_at.desc(), models3d::dsl::id.desc()));
+            if let Some((timestamp, id)) = cursor {
+                query = query.filter(
+                    models3d::dsl::updated_at
+                        .lt(timestamp)
+                        .or(models3d::dsl::updated_at
+                            .eq(timestamp)
+                            .and(models3d::dsl::id.lt(id))),
+                );
+            }
+            query
+        }
+    };
+
+    // one more than requested tells whether there is a next page
+    let mut models = query
+        .limit(limit + 1)
+        .load::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load recent models: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let has_more = models.len() as i64 > limit;
+    models.truncate(limit as usize);
+
+    let next_cursor = models.last().filter(|_| has_more).and_then(|last| {
+        let timestamp = match params.sort {
+            RecentSort::Added => last.date_added,
+            RecentSort::Updated => last.updated_at,
+        }?;
+        Some(encode_cursor(timestamp, last.id))
+    });
+
+    let mut responses = Vec::new();
+    for model in &models {
+        let response = ModelResponse::from_model_3d(model, &state.config, &mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to build model response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        responses.push(response);
+    }
+
+    Ok(Json(RecentModelsResponse {
+        models: responses,
+        next_cursor,
+    }))
+}
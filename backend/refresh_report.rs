@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug_span, Instrument, Span};
+use typeshare::typeshare;
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenamedModel {
+    pub from: String,
+    pub to: String,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderError {
+    pub folder_path: String,
+    pub message: String,
+}
+
+/// Summary of what a (dry-run) refresh did to the library. Model entries are
+/// folder paths relative to the library root, file entries are prefixed with
+/// their model folder.
+#[typeshare]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RefreshReport {
+    pub dry_run: bool,
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+    pub renamed: Vec<RenamedModel>,
+    pub files_added: Vec<String>,
+    pub files_removed: Vec<String>,
+    pub files_changed: Vec<String>,
+    pub errors: Vec<FolderError>,
+    pub duration_ms: i32,
+    pub timings: RefreshTimings,
+}
+
+/// Where the time of a refresh went, each phase without the phases running
+/// inside of it.
+#[typeshare]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RefreshTimings {
+    /// finding model folders
+    pub walk_ms: i32,
+    pub hash_ms: i32,
+    pub images_ms: i32,
+    /// reading metadata and updating the index
+    pub database_ms: i32,
+    /// rendering previews, CAD conversions and poster frames
+    pub previews_ms: i32,
+}
+
+impl std::fmt::Display for RefreshTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "walk {}ms, hash {}ms, images {}ms, database {}ms, previews {}ms",
+            self.walk_ms, self.hash_ms, self.images_ms, self.database_ms, self.previews_ms
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Walk,
+    Hash,
+    Images,
+    Database,
+    Previews,
+}
+
+impl Phase {
+    fn span(self) -> Span {
+        match self {
+            Phase::Walk => debug_span!("walk"),
+            Phase::Hash => debug_span!("hash"),
+            Phase::Images => debug_span!("images"),
+            Phase::Database => debug_span!("database"),
+            Phase::Previews => debug_span!("previews"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Clock {
+    totals: [Duration; 5],
+    /// the running phases, innermost last
+    running: Vec<Phase>,
+    since: Option<Instant>,
+}
+
+impl Clock {
+    /// Books the time since the last switch on the innermost phase.
+    fn switch(&mut self) {
+        let now = Instant::now();
+        if let (Some(phase), Some(since)) = (self.running.last(), self.since) {
+            self.totals[*phase as usize] += now - since;
+        }
+        self.since = Some(now);
+    }
+}
+
+tokio::task_local! {
+    static CLOCK: Arc<Mutex<Clock>>;
+}
+
+/// Measures the phases of a refresh. Only code running within `measure`
+/// is counted, scans of single models outside of a refresh are not.
+#[derive(Clone, Default)]
+pub struct PhaseClock {
+    clock: Arc<Mutex<Clock>>,
+}
+
+impl PhaseClock {
+    pub async fn measure<F: Future>(&self, future: F) -> F::Output {
+        CLOCK.scope(self.clock.clone(), future).await
+    }
+
+    pub fn timings(&self) -> RefreshTimings {
+        let clock = self.clock.lock().unwrap();
+        let ms = |phase: Phase| {
+            clock.totals[phase as usize]
+                .as_millis()
+                .min(i32::MAX as u128) as i32
+        };
+        RefreshTimings {
+            walk_ms: ms(Phase::Walk),
+            hash_ms: ms(Phase::Hash),
+            images_ms: ms(Phase::Images),
+            database_ms: ms(Phase::Database),
+            previews_ms: ms(Phase::Previews),
+        }
+    }
+}
+
+/// Runs `future` as `phase` in its own span.
+pub async fn timed<F: Future>(phase: Phase, future: F) -> F::Output {
+    let clock = CLOCK.try_with(Arc::clone).ok();
+    if let Some(clock) = &clock {
+        let mut clock = clock.lock().unwrap();
+        clock.switch();
+        clock.running.push(phase);
+    }
+    let output = future.instrument(phase.span()).await;
+    if let Some(clock) = &clock {
+        let mut clock = clock.lock().unwrap();
+        clock.switch();
+        clock.running.pop();
+    }
+    output
+}
+
+impl RefreshReport {
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            ..Default::default()
+        }
+    }
+
+    pub fn error(&mut self, folder_path: impl Into<String>, error: &anyhow::Error) {
+        self.errors.push(FolderError {
+            folder_path: folder_path.into(),
+            message: format!("{:#}", error),
+        });
+    }
+
+    /// Files removed and added again within the same model were modified.
+    pub fn record_file_changes(
+        &mut self,
+        folder_path: &str,
+        removed: Vec<String>,
+        added: Vec<String>,
+    ) {
+        let removed: BTreeSet<String> = removed.into_iter().collect();
+        let added: BTreeSet<String> = added.into_iter().collect();
+        let qualify = |file_path: &String| format!("{}/{}", folder_path, file_path);
+
+        self.files_changed
+            .extend(removed.intersection(&added).map(qualify));
+        self.files_removed
+            .extend(removed.difference(&added).map(qualify));
+        self.files_added
+            .extend(added.difference(&removed).map(qualify));
+
+        if !removed.is_empty() || !added.is_empty() {
+            self.mark_updated(folder_path);
+        }
+    }
+
+    pub fn mark_updated(&mut self, folder_path: &str) {
+        if !self.added.iter().any(|added| added == folder_path)
+            && !self.updated.iter().any(|updated| updated == folder_path)
+        {
+            self.updated.push(folder_path.to_string());
+        }
+    }
+
+    /// A removed and an added model with identical file contents is a moved
+    /// folder rather than a replacement.
+    pub fn detect_renames(
+        &mut self,
+        removed_hashes: &HashMap<String, BTreeSet<String>>,
+        added_hashes: &HashMap<String, BTreeSet<String>>,
+    ) {
+        for (from, hashes) in removed_hashes {
+            if hashes.is_empty() {
+                continue;
+            }
+            let to = added_hashes
+                .iter()
+                .filter(|(to, _)| !self.renamed.iter().any(|renamed| &renamed.to == *to))
+                .find(|(_, added)| *added == hashes)
+                .map(|(to, _)| to.clone());
+
+            if let Some(to) = to {
+                self.removed.retain(|removed| removed != from);
+                self.added.retain(|added| added != &to);
+                self.renamed.push(RenamedModel {
+                    from: from.clone(),
+                    to,
+                });
+            }
+        }
+    }
+
+    pub fn finish(&mut self, started: Instant) {
+        self.added.sort();
+        self.updated.sort();
+        self.removed.sort();
+        self.renamed.sort_by(|a, b| a.from.cmp(&b.from));
+        self.files_added.sort();
+        self.files_removed.sort();
+        self.files_changed.sort();
+        self.duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+    }
+}
@@ -0,0 +1,146 @@
+use axum::{
+    body::Bytes,
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+use crate::AppState;
+
+/// Responses kept at most, the oldest are dropped first.
+const MAX_ENTRIES: usize = 512;
+
+struct Entry {
+    body: Bytes,
+    stored_at: Instant,
+}
+
+/// Serialized list and detail responses, keyed by their ETag. The ETag
+/// already changes with the library, the time to live and `invalidate`
+/// cover changes it does not see, like tags or images.
+#[derive(Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    /// bumped by every invalidation, responses built from older data are
+    /// not stored
+    generation: Arc<AtomicU64>,
+}
+
+/// A cached JSON body, answered as is.
+pub struct CachedJson(Bytes);
+
+impl IntoResponse for CachedJson {
+    fn into_response(self) -> Response {
+        (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            self.0,
+        )
+            .into_response()
+    }
+}
+
+impl ResponseCache {
+    pub fn new(ttl_seconds: u64) -> Self {
+        ResponseCache {
+            ttl: Duration::from_secs(ttl_seconds),
+            entries: Arc::default(),
+            generation: Arc::default(),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    pub fn get(&self, key: &str) -> Option<CachedJson> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        (entry.stored_at.elapsed() < self.ttl).then(|| CachedJson(entry.body.clone()))
+    }
+
+    /// Serializes `value` and keeps it for `key`, unless the cache was
+    /// invalidated since `generation` was read.
+    pub fn store(
+        &self,
+        key: String,
+        generation: u64,
+        value: &impl Serialize,
+    ) -> Result<CachedJson, StatusCode> {
+        let body = Bytes::from(serde_json::to_vec(value).map_err(|e| {
+            error!("Failed to serialize the response for {}: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?);
+        if !self.is_enabled() {
+            return Ok(CachedJson(body));
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if self.generation() != generation {
+            debug!("Not caching {}, the cache was invalidated meanwhile", key);
+            return Ok(CachedJson(body));
+        }
+        if entries.len() >= MAX_ENTRIES {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.stored_at.elapsed() < ttl);
+        }
+        if entries.len() >= MAX_ENTRIES {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                body: body.clone(),
+                stored_at: Instant::now(),
+            },
+        );
+        Ok(CachedJson(body))
+    }
+
+    pub fn invalidate(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.clear();
+    }
+}
+
+/// Drops every cached response after a request that may have changed
+/// something, so nobody gets to see the state from before their own edit.
+pub async fn invalidate_on_change(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let response = next.run(request).await;
+    if !is_safe && !response.status().is_client_error() {
+        state.cache.invalidate();
+    }
+    response
+}
@@ -0,0 +1,156 @@
+use diesel_async::scoped_futures::ScopedBoxFuture;
+use diesel_async::AsyncConnection;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+/// A change to the file system and what it takes to revert it.
+enum Step {
+    /// a file or folder which did not exist before
+    Created(PathBuf),
+    /// a file or folder moved from the first path to the second
+    Moved(PathBuf, PathBuf),
+    /// previous content of a rewritten file, `None` if there was none
+    Written(PathBuf, Option<String>),
+    /// a file about to be replaced, kept as the second path until the
+    /// operation succeeded
+    Replaced(PathBuf, PathBuf),
+}
+
+/// Compensating actions for the file system steps of an operation, which a
+/// database transaction cannot roll back. Steps are recorded as they are
+/// done and reverted in reverse order if the operation fails.
+#[derive(Default)]
+pub struct Rollback {
+    steps: Mutex<Vec<Step>>,
+}
+
+impl Rollback {
+    fn push(&self, step: Step) {
+        self.steps.lock().unwrap().push(step);
+    }
+
+    fn take(&self) -> Vec<Step> {
+        std::mem::take(&mut *self.steps.lock().unwrap())
+    }
+
+    pub fn created(&self, path: impl Into<PathBuf>) {
+        self.push(Step::Created(path.into()));
+    }
+
+    pub fn moved(&self, from: impl Into<PathBuf>, to: impl Into<PathBuf>) {
+        self.push(Step::Moved(from.into(), to.into()));
+    }
+
+    pub fn written(&self, path: impl Into<PathBuf>, previous: Option<String>) {
+        self.push(Step::Written(path.into(), previous));
+    }
+
+    /// Creates `dir` and its missing parents, only the outermost folder
+    /// that was created is removed again.
+    pub async fn create_dir_all(&self, dir: &Path) -> std::io::Result<()> {
+        let mut outermost = None;
+        for ancestor in dir.ancestors() {
+            if fs::try_exists(ancestor).await? {
+                break;
+            }
+            outermost = Some(ancestor.to_path_buf());
+        }
+        fs::create_dir_all(dir).await?;
+        if let Some(outermost) = outermost {
+            self.created(outermost);
+        }
+        Ok(())
+    }
+
+    /// Moves an existing file out of the way of its replacement. The
+    /// scanner skips the `.part` name it is kept under.
+    pub async fn replace(&self, path: &Path) -> std::io::Result<()> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let backup = path.with_file_name(format!(".{}.{}.part", name, Uuid::new_v4()));
+        fs::rename(path, &backup).await?;
+        self.push(Step::Replaced(path.to_path_buf(), backup));
+        Ok(())
+    }
+
+    /// The operation succeeded, replaced files are not needed anymore.
+    pub async fn commit(&self) {
+        for step in self.take() {
+            if let Step::Replaced(_, backup) = step {
+                if let Err(e) = fs::remove_file(&backup).await {
+                    error!("Failed to remove {}: {}", backup.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Reverts every recorded step, newest first. Failures are logged and
+    /// do not stop the remaining steps.
+    pub async fn undo(&self) {
+        for step in self.take().into_iter().rev() {
+            let reverted = match &step {
+                Step::Created(path) if path.is_dir() => fs::remove_dir_all(path).await,
+                Step::Created(path) => fs::remove_file(path).await,
+                Step::Moved(from, to) => move_back(from, to).await,
+                Step::Written(path, Some(previous)) => fs::write(path, previous).await,
+                Step::Written(path, None) => fs::remove_file(path).await,
+                Step::Replaced(path, backup) => fs::rename(backup, path).await,
+            };
+            let path = match &step {
+                Step::Created(path)
+                | Step::Moved(_, path)
+                | Step::Written(path, _)
+                | Step::Replaced(path, _) => path,
+            };
+            match reverted {
+                Ok(()) => debug!("Reverted {}", path.display()),
+                Err(e) => error!("Failed to revert {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+/// Moves `to` back to `from`. A folder left at `from`, e.g. the emptied
+/// source of a merge, takes the content back.
+async fn move_back(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::rename(to, from).await.is_ok() {
+        return Ok(());
+    }
+    let options = fs_extra::dir::CopyOptions {
+        overwrite: true,
+        copy_inside: true,
+        content_only: from.is_dir(),
+        ..fs_extra::dir::CopyOptions::new()
+    };
+    fs_extra::dir::move_dir(to, from, &options)
+        .map(|_| ())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+}
+
+/// Runs `operation` in a database transaction and reverts the file system
+/// steps recorded in `rollback` if it fails, so the database and the
+/// library agree either way.
+pub async fn transaction<'a, 'conn, Conn, T, E, F>(
+    connection: &'conn mut Conn,
+    rollback: &Rollback,
+    operation: F,
+) -> Result<T, E>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    F: for<'r> FnOnce(&'r mut Conn) -> ScopedBoxFuture<'a, 'r, Result<T, E>> + Send + 'a,
+    E: From<diesel::result::Error> + Send + 'a,
+    T: Send + 'a,
+    'a: 'conn,
+{
+    let result = connection.transaction(operation).await;
+    match &result {
+        Ok(_) => rollback.commit().await,
+        Err(_) => rollback.undo().await,
+    }
+    result
+}
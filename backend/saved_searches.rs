@@ -0,0 +1,259 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::auth::User;
+use crate::model_filter::filter_models;
+use crate::policy::{Account, Authorized};
+use crate::schema::{models3d, saved_searches};
+use crate::types::{ListModelParams, Model3D, ModelResponse};
+use crate::AppState;
+
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = saved_searches)]
+pub struct SavedSearch {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub filters: String,
+    pub notify: bool,
+    pub created_at: NaiveDateTime,
+    pub last_checked_at: NaiveDateTime,
+    pub last_notified_at: Option<NaiveDateTime>,
+}
+
+impl SavedSearch {
+    pub fn params(&self) -> ListModelParams {
+        serde_json::from_str(&self.filters).unwrap_or_default()
+    }
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSearchResponse {
+    pub id: i32,
+    pub name: String,
+    pub filters: ListModelParams,
+    pub notify: bool,
+    pub created_at: NaiveDateTime,
+    /// models added since the new matches were last fetched, only counted
+    /// when `notify` is set
+    pub new_matches: i32,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveSearch {
+    pub name: String,
+    pub filters: ListModelParams,
+    #[serde(default)]
+    pub notify: bool,
+}
+
+/// Models matching a saved search that were added after it was last checked.
+pub async fn new_matches<Conn>(
+    connection: &mut Conn,
+    search: &SavedSearch,
+    viewer: &Viewer,
+) -> Result<Vec<Model3D>, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    matches_since(connection, search, viewer, search.last_checked_at).await
+}
+
+pub async fn matches_since<Conn>(
+    connection: &mut Conn,
+    search: &SavedSearch,
+    viewer: &Viewer,
+    since: NaiveDateTime,
+) -> Result<Vec<Model3D>, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    filter_models(&search.params(), viewer)?
+        .filter(models3d::dsl::date_added.gt(since))
+        .order(models3d::dsl::date_added.desc())
+        .load::<Model3D>(connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to match saved search {}: {}", search.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+async fn find_search<Conn>(
+    connection: &mut Conn,
+    user: &User,
+    id: i32,
+) -> Result<SavedSearch, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    saved_searches::dsl::saved_searches
+        .filter(saved_searches::dsl::id.eq(id))
+        .filter(saved_searches::dsl::user_id.eq(user.id))
+        .first::<SavedSearch>(connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load saved search {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn to_response<Conn>(
+    connection: &mut Conn,
+    search: SavedSearch,
+    viewer: &Viewer,
+) -> Result<SavedSearchResponse, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let new_matches = if search.notify {
+        new_matches(connection, &search, viewer).await?.len() as i32
+    } else {
+        0
+    };
+    Ok(SavedSearchResponse {
+        filters: search.params(),
+        id: search.id,
+        name: search.name,
+        notify: search.notify,
+        created_at: search.created_at,
+        new_matches,
+    })
+}
+
+pub async fn handle_list_searches(
+    State(state): State<AppState>,
+    Authorized(viewer, Account(user)): Authorized<Account>,
+) -> Result<Json<Vec<SavedSearchResponse>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let searches = saved_searches::dsl::saved_searches
+        .filter(saved_searches::dsl::user_id.eq(user.id))
+        .order(saved_searches::dsl::name)
+        .load::<SavedSearch>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load saved searches of {}: {}", user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut response = Vec::new();
+    for search in searches {
+        response.push(to_response(&mut connection, search, &viewer).await?);
+    }
+    Ok(Json(response))
+}
+
+/// Saves a search, replacing the one of the same name. Only models added
+/// from now on count as new matches.
+pub async fn handle_save_search(
+    State(state): State<AppState>,
+    Authorized(viewer, Account(user)): Authorized<Account>,
+    Json(payload): Json<SaveSearch>,
+) -> Result<Json<SavedSearchResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    // reject filters the list endpoint would reject
+    let _ = filter_models(&payload.filters, &viewer)?;
+    let filters = serde_json::to_string(&payload.filters).map_err(|e| {
+        error!("Failed to serialize search filters: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let now = Utc::now().naive_utc();
+    let search = diesel::insert_into(saved_searches::table)
+        .values((
+            saved_searches::dsl::user_id.eq(user.id),
+            saved_searches::dsl::name.eq(name),
+            saved_searches::dsl::filters.eq(&filters),
+            saved_searches::dsl::notify.eq(payload.notify),
+            saved_searches::dsl::created_at.eq(now),
+            saved_searches::dsl::last_checked_at.eq(now),
+            saved_searches::dsl::last_notified_at.eq(now),
+        ))
+        .on_conflict((saved_searches::dsl::user_id, saved_searches::dsl::name))
+        .do_update()
+        .set((
+            saved_searches::dsl::filters.eq(&filters),
+            saved_searches::dsl::notify.eq(payload.notify),
+            saved_searches::dsl::last_checked_at.eq(now),
+            saved_searches::dsl::last_notified_at.eq(now),
+        ))
+        .get_result::<SavedSearch>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to save search {} of {}: {}", name, user.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(to_response(&mut connection, search, &viewer).await?))
+}
+
+pub async fn handle_delete_search(
+    State(state): State<AppState>,
+    Authorized(_, Account(user)): Authorized<Account>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let search = find_search(&mut connection, &user, id).await?;
+    diesel::delete(saved_searches::dsl::saved_searches.find(search.id))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete saved search {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The models added since the last call, which marks them as seen.
+pub async fn handle_new_matches(
+    State(state): State<AppState>,
+    Authorized(viewer, Account(user)): Authorized<Account>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<ModelResponse>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let search = find_search(&mut connection, &user, id).await?;
+    let now = Utc::now().naive_utc();
+    let models = new_matches(&mut connection, &search, &viewer).await?;
+    diesel::update(saved_searches::dsl::saved_searches.find(search.id))
+        .set(saved_searches::dsl::last_checked_at.eq(now))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to update saved search {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut response = Vec::new();
+    for model in &models {
+        let model = ModelResponse::from_model_3d(model, &state.config, &mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to build model response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        response.push(model);
+    }
+    Ok(Json(response))
+}
@@ -0,0 +1,196 @@
+use chrono::Utc;
+use cron::Schedule;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+use crate::notifications::Notification;
+use crate::AppState;
+use crate::{integrity, jobs, link_checks, parse_library, subscriptions, trash};
+
+/// Starts the background tasks which keep the library tidy without user
+/// interaction.
+pub fn spawn_scheduler(state: AppState) {
+    tokio::spawn(trash_retention_task(state.clone()));
+
+    if let Some(expression) = state.config.refresh_schedule.clone() {
+        match parse_schedule(&expression) {
+            Ok(schedule) => {
+                info!("Library refresh scheduled with '{}'", expression);
+                tokio::spawn(scheduled_refresh_task(state.clone(), schedule));
+            }
+            Err(e) => error!("Invalid REFRESH_SCHEDULE '{}': {}", expression, e),
+        }
+    }
+
+    if let Some(expression) = state.config.link_check_schedule.clone() {
+        match parse_schedule(&expression) {
+            Ok(schedule) => {
+                info!("Link check scheduled with '{}'", expression);
+                tokio::spawn(scheduled_link_check_task(state.clone(), schedule));
+            }
+            Err(e) => error!("Invalid LINK_CHECK_SCHEDULE '{}': {}", expression, e),
+        }
+    }
+
+    if let Some(expression) = state.config.verify_schedule.clone() {
+        match parse_schedule(&expression) {
+            Ok(schedule) => {
+                info!("Checksum verification scheduled with '{}'", expression);
+                tokio::spawn(scheduled_verify_task(state.clone(), schedule));
+            }
+            Err(e) => error!("Invalid VERIFY_SCHEDULE '{}': {}", expression, e),
+        }
+    }
+
+    if let Some(expression) = state.config.sync_schedule.clone() {
+        match parse_schedule(&expression) {
+            Ok(schedule) => {
+                info!("Subscription sync scheduled with '{}'", expression);
+                tokio::spawn(scheduled_sync_task(state, schedule));
+            }
+            Err(e) => error!("Invalid SYNC_SCHEDULE '{}': {}", expression, e),
+        }
+    }
+}
+
+/// Accepts the classic five field cron syntax as well as the extended
+/// syntax with a leading seconds field.
+pub fn parse_schedule(expression: &str) -> Result<Schedule, cron::error::Error> {
+    let expression = expression.trim();
+    if expression.split_whitespace().count() == 5 {
+        Schedule::from_str(&format!("0 {}", expression))
+    } else {
+        Schedule::from_str(expression)
+    }
+}
+
+async fn scheduled_refresh_task(state: AppState, schedule: Schedule) {
+    while let Some(next) = schedule.upcoming(Utc).next() {
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        debug!("Next scheduled refresh at {}", next);
+        tokio::time::sleep(wait).await;
+
+        info!("Starting scheduled library refresh");
+        let (job_id, cancel) = state.jobs.start("scheduled_refresh");
+        let result =
+            parse_library::refresh_library(state.pool.clone(), state.config.clone(), cancel).await;
+        state.cache.invalidate();
+        if let Ok(report) = &result {
+            state.jobs.set_result(job_id, report);
+        }
+        state.jobs.complete(job_id, &result);
+        match result {
+            Ok(_) => state.notifier.notify(Notification::LibraryChanged),
+            Err(e) if e.is::<jobs::Cancelled>() => info!("Scheduled refresh cancelled"),
+            Err(e) => {
+                error!("Scheduled refresh failed: {}", e);
+                state.notifier.notify(Notification::RefreshFailed {
+                    trigger: "scheduled",
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+}
+
+async fn scheduled_link_check_task(state: AppState, schedule: Schedule) {
+    while let Some(next) = schedule.upcoming(Utc).next() {
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        debug!("Next scheduled link check at {}", next);
+        tokio::time::sleep(wait).await;
+
+        info!("Starting scheduled link check");
+        let (job_id, cancel) = state.jobs.start("scheduled_link_check");
+        let result = link_checks::check_links(&state, &cancel).await;
+        if let Ok(summary) = &result {
+            state.jobs.set_result(job_id, summary);
+        }
+        state.jobs.complete(job_id, &result);
+        match result {
+            Ok(_) => {}
+            Err(e) if e.is::<jobs::Cancelled>() => info!("Scheduled link check cancelled"),
+            Err(e) => error!("Scheduled link check failed: {}", e),
+        }
+    }
+}
+
+async fn scheduled_verify_task(state: AppState, schedule: Schedule) {
+    while let Some(next) = schedule.upcoming(Utc).next() {
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        debug!("Next scheduled verification at {}", next);
+        tokio::time::sleep(wait).await;
+
+        info!("Starting scheduled checksum verification");
+        let (job_id, cancel) = state.jobs.start("scheduled_verify");
+        let result = integrity::verify_library(&state, &cancel).await;
+        if let Ok(report) = &result {
+            state.jobs.set_result(job_id, report);
+        }
+        state.jobs.complete(job_id, &result);
+        match result {
+            Ok(_) => {}
+            Err(e) if e.is::<jobs::Cancelled>() => info!("Scheduled verification cancelled"),
+            Err(e) => error!("Scheduled verification failed: {}", e),
+        }
+    }
+}
+
+async fn scheduled_sync_task(state: AppState, schedule: Schedule) {
+    while let Some(next) = schedule.upcoming(Utc).next() {
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        debug!("Next scheduled subscription sync at {}", next);
+        tokio::time::sleep(wait).await;
+
+        let subscriptions = match state.pool.get().await {
+            Ok(mut connection) => subscriptions::all_subscriptions(&mut connection).await,
+            Err(e) => {
+                error!("Subscription sync could not get a connection: {}", e);
+                continue;
+            }
+        };
+        let subscriptions = match subscriptions {
+            Ok(subscriptions) if subscriptions.is_empty() => continue,
+            Ok(subscriptions) => subscriptions,
+            Err(e) => {
+                error!("Failed to load subscriptions: {}", e);
+                continue;
+            }
+        };
+
+        info!("Starting scheduled subscription sync");
+        let (job_id, cancel) = state.jobs.start("scheduled_sync");
+        let result = subscriptions::sync_subscriptions(&state, subscriptions, &cancel).await;
+        if let Ok(summary) = &result {
+            state.jobs.set_result(job_id, summary);
+        }
+        state.jobs.complete(job_id, &result);
+        match result {
+            Ok(_) => {}
+            Err(e) if e.is::<jobs::Cancelled>() => info!("Scheduled subscription sync cancelled"),
+            Err(e) => error!("Scheduled subscription sync failed: {}", e),
+        }
+    }
+}
+
+async fn trash_retention_task(state: AppState) {
+    let period = Duration::from_secs(state.config.trash_purge_interval_minutes.max(1) * 60);
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+        debug!("Running trash retention");
+
+        let mut connection = match state.pool.get().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("Trash retention could not get a connection: {}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = trash::purge_expired(&state.config, &mut connection, false).await {
+            error!("Trash retention failed: {}", e);
+        }
+    }
+}
@@ -1,5 +1,85 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> Integer,
+        created_at -> Timestamp,
+        actor -> Text,
+        action -> Text,
+        target -> Text,
+        details -> Text,
+    }
+}
+
+diesel::table! {
+    client_recent_files (user_id, file_id) {
+        user_id -> Integer,
+        file_id -> Integer,
+        model_id -> Integer,
+        used_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    client_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    designer_aliases (alias) {
+        alias -> Text,
+        designer_id -> Integer,
+    }
+}
+
+diesel::table! {
+    designers (id) {
+        id -> Integer,
+        name -> Text,
+        slug -> Text,
+    }
+}
+
+diesel::table! {
+    download_clients (model_id, day, client) {
+        model_id -> Integer,
+        day -> Date,
+        client -> Text,
+    }
+}
+
+diesel::table! {
+    download_counts (model_id, day) {
+        model_id -> Integer,
+        day -> Date,
+        downloads -> Integer,
+    }
+}
+
+diesel::table! {
+    email_subscriptions (user_id, event) {
+        user_id -> Integer,
+        event -> Text,
+    }
+}
+
+diesel::table! {
+    filaments (id) {
+        id -> Integer,
+        name -> Text,
+        material -> Text,
+        price_per_kg -> Double,
+        density -> Double,
+        diameter -> Double,
+    }
+}
+
 diesel::table! {
     files3d (id) {
         id -> Integer,
@@ -9,6 +89,70 @@ diesel::table! {
         date_added -> Nullable<Timestamp>,
         file_hash -> Nullable<Text>,
         file_size_bytes -> Integer,
+        file_type -> Text,
+        size_x -> Nullable<Double>,
+        size_y -> Nullable<Double>,
+        size_z -> Nullable<Double>,
+        cad_metadata -> Nullable<Text>,
+        print_settings -> Nullable<Text>,
+        geometry_fingerprint -> Nullable<Text>,
+        gcode_metadata -> Nullable<Text>,
+        filament_id -> Nullable<Integer>,
+        measurements -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    library_stats (id) {
+        id -> Integer,
+        created_at -> Timestamp,
+        total_models -> Integer,
+        total_files -> Integer,
+        total_bytes -> BigInt,
+        details -> Text,
+        tenant_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    link_checks (model_id) {
+        model_id -> Integer,
+        url -> Text,
+        status -> Text,
+        http_status -> Nullable<Integer>,
+        checked_at -> Timestamp,
+        failing_since -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    model_embeddings (model_id) {
+        model_id -> Integer,
+        vector -> Binary,
+    }
+}
+
+diesel::table! {
+    model_events (id) {
+        id -> Integer,
+        model_id -> Integer,
+        kind -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    model_grants (model_id, principal_kind, principal) {
+        model_id -> Integer,
+        principal_kind -> Text,
+        principal -> Text,
+    }
+}
+
+diesel::table! {
+    model_tags (model_id, tag_id) {
+        model_id -> Integer,
+        tag_id -> Integer,
     }
 }
 
@@ -24,9 +168,211 @@ diesel::table! {
         date_added -> Nullable<Timestamp>,
         images -> Text,
         description -> Text,
+        deleted_at -> Nullable<Timestamp>,
+        purge_after -> Nullable<Timestamp>,
+        trash_path -> Nullable<Text>,
+        slug -> Text,
+        updated_at -> Nullable<Timestamp>,
+        tenant_id -> Nullable<Integer>,
+        visibility -> Text,
+        owner_id -> Nullable<Integer>,
+        notes -> Text,
+        uploader_id -> Nullable<Integer>,
+        sort_key -> Text,
+        designer_id -> Nullable<Integer>,
+        name_key -> Text,
+    }
+}
+
+diesel::table! {
+    parse_errors (folder_path) {
+        folder_path -> Text,
+        message -> Text,
+        first_seen -> Timestamp,
+        last_seen -> Timestamp,
+        occurrences -> Integer,
+    }
+}
+
+diesel::table! {
+    print_logs (id) {
+        id -> Integer,
+        model_id -> Integer,
+        file_id -> Nullable<Integer>,
+        user_id -> Nullable<Integer>,
+        filament_id -> Nullable<Integer>,
+        printed_at -> Timestamp,
+        duration_seconds -> Nullable<Integer>,
+        filament_grams -> Nullable<Double>,
+        success -> Bool,
+        notes -> Text,
+        timelapse_id -> Nullable<Integer>,
+        snapshot -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    saved_searches (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        filters -> Text,
+        notify -> Bool,
+        created_at -> Timestamp,
+        last_checked_at -> Timestamp,
+        last_notified_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    slug_redirects (slug) {
+        slug -> Text,
+        model_id -> Integer,
+    }
+}
+
+diesel::table! {
+    subscriptions (id) {
+        id -> Integer,
+        url -> Text,
+        title -> Text,
+        folder -> Text,
+        created_at -> Timestamp,
+        last_synced_at -> Nullable<Timestamp>,
+        last_error -> Nullable<Text>,
+        user_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Integer,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    tenants (id) {
+        id -> Integer,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    thing_imports (source, item) {
+        source -> Text,
+        item -> Text,
+        status -> Text,
+        model_id -> Nullable<Integer>,
+        error -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    upload_drafts (id) {
+        id -> Text,
+        user_id -> Nullable<Integer>,
+        library_root -> Text,
+        title -> Text,
+        author -> Text,
+        origin -> Text,
+        license -> Text,
+        tags -> Text,
+        description -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    uploads (id) {
+        id -> Integer,
+        user_id -> Integer,
+        model_id -> Integer,
+        bytes -> BigInt,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Integer,
+        name -> Text,
+        created_at -> Timestamp,
+        email -> Nullable<Text>,
+        record_history -> Bool,
+    }
+}
+
+diesel::table! {
+    view_history (user_id, model_id) {
+        user_id -> Integer,
+        model_id -> Integer,
+        viewed_at -> Timestamp,
     }
 }
 
+diesel::joinable!(client_recent_files -> models3d (model_id));
+diesel::joinable!(client_recent_files -> users (user_id));
+diesel::joinable!(client_tokens -> users (user_id));
+diesel::joinable!(designer_aliases -> designers (designer_id));
+diesel::joinable!(download_clients -> models3d (model_id));
+diesel::joinable!(download_counts -> models3d (model_id));
+diesel::joinable!(email_subscriptions -> users (user_id));
+diesel::joinable!(files3d -> filaments (filament_id));
 diesel::joinable!(files3d -> models3d (model_id));
+diesel::joinable!(link_checks -> models3d (model_id));
+diesel::joinable!(model_embeddings -> models3d (model_id));
+diesel::joinable!(model_events -> models3d (model_id));
+diesel::joinable!(model_grants -> models3d (model_id));
+diesel::joinable!(model_tags -> models3d (model_id));
+diesel::joinable!(model_tags -> tags (tag_id));
+diesel::joinable!(models3d -> designers (designer_id));
+diesel::joinable!(models3d -> tenants (tenant_id));
+diesel::joinable!(models3d -> users (owner_id));
+diesel::joinable!(print_logs -> filaments (filament_id));
+diesel::joinable!(print_logs -> files3d (file_id));
+diesel::joinable!(print_logs -> models3d (model_id));
+diesel::joinable!(print_logs -> users (user_id));
+diesel::joinable!(saved_searches -> users (user_id));
+diesel::joinable!(slug_redirects -> models3d (model_id));
+diesel::joinable!(subscriptions -> users (user_id));
+diesel::joinable!(thing_imports -> models3d (model_id));
+diesel::joinable!(upload_drafts -> users (user_id));
+diesel::joinable!(uploads -> models3d (model_id));
+diesel::joinable!(uploads -> users (user_id));
+diesel::joinable!(view_history -> models3d (model_id));
+diesel::joinable!(view_history -> users (user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(files3d, models3d,);
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    client_recent_files,
+    client_tokens,
+    designer_aliases,
+    designers,
+    download_clients,
+    download_counts,
+    email_subscriptions,
+    filaments,
+    files3d,
+    library_stats,
+    link_checks,
+    model_embeddings,
+    model_events,
+    model_grants,
+    model_tags,
+    models3d,
+    parse_errors,
+    print_logs,
+    saved_searches,
+    slug_redirects,
+    subscriptions,
+    tags,
+    tenants,
+    thing_imports,
+    upload_drafts,
+    uploads,
+    users,
+    view_history,
+);
@@ -0,0 +1,293 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::model_filter::{filter_models, mentions};
+use crate::schema::{files3d, model_tags, tags};
+use crate::types::{ListModelParams, Model3D, ModelResponse};
+use crate::AppState;
+
+const MAX_TERMS: usize = 8;
+/// Characters of a description shown before and after the first match.
+const SNIPPET_BEFORE: usize = 60;
+const SNIPPET_AFTER: usize = 160;
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    Name,
+    Title,
+    Tag,
+    FileName,
+    Author,
+    Description,
+    Notes,
+}
+
+impl SearchField {
+    /// A match in the name counts five times as much as one in the README.
+    fn weight(&self) -> f32 {
+        match self {
+            SearchField::Name => 10.0,
+            SearchField::Title => 8.0,
+            SearchField::Tag => 6.0,
+            SearchField::FileName => 4.0,
+            SearchField::Author => 3.0,
+            SearchField::Description => 2.0,
+            SearchField::Notes => 2.0,
+        }
+    }
+
+    /// Long texts are cut down to the part around the first match.
+    fn is_long(&self) -> bool {
+        matches!(self, SearchField::Description | SearchField::Notes)
+    }
+}
+
+/// A matched range, counted in UTF-16 code units so it can be passed to
+/// `String.prototype.slice` as is.
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Highlight {
+    pub start: i32,
+    pub end: i32,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchMatch {
+    pub field: SearchField,
+    /// the field value, or a snippet of it for descriptions and notes
+    pub text: String,
+    pub highlights: Vec<Highlight>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub model: ModelResponse,
+    pub score: f32,
+    pub matches: Vec<SearchMatch>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub hits: Vec<SearchHit>,
+    /// number of matching models, independent of the page
+    pub total: i32,
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    #[serde(flatten)]
+    pub filters: ListModelParams,
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Case insensitive occurrences of the terms in `chars` as merged char
+/// ranges, and whether any of them starts a word.
+fn find_terms(chars: &[char], terms: &[Vec<char>]) -> (Vec<(usize, usize)>, bool) {
+    let lower: Vec<char> = chars.iter().copied().map(lowercase).collect();
+    let mut ranges = Vec::new();
+    let mut starts_word = false;
+    for term in terms {
+        if term.is_empty() || term.len() > lower.len() {
+            continue;
+        }
+        for start in 0..=lower.len() - term.len() {
+            if lower[start..start + term.len()] == term[..] {
+                ranges.push((start, start + term.len()));
+                starts_word |= start == 0 || !lower[start - 1].is_alphanumeric();
+            }
+        }
+    }
+    ranges.sort();
+
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    (merged, starts_word)
+}
+
+fn utf16_len(chars: &[char]) -> i32 {
+    chars.iter().map(|c| c.len_utf16() as i32).sum()
+}
+
+/// Matches `terms` in one field. Each term found adds the weight of the
+/// field, doubled when it starts a word.
+fn match_field(field: SearchField, text: &str, terms: &[Vec<char>]) -> Option<(f32, SearchMatch)> {
+    let chars: Vec<char> = text.chars().collect();
+    let found = terms
+        .iter()
+        .filter(|term| !find_terms(&chars, std::slice::from_ref(term)).0.is_empty())
+        .count();
+    if found == 0 {
+        return None;
+    }
+    let (ranges, starts_word) = find_terms(&chars, terms);
+    let score = field.weight() * found as f32 * if starts_word { 2.0 } else { 1.0 };
+
+    let (from, to) = if field.is_long() {
+        let first = match ranges.as_slice() {
+            [(start, _), ..] => *start,
+            [] => 0,
+        };
+        (
+            first.saturating_sub(SNIPPET_BEFORE),
+            (first + SNIPPET_AFTER).min(chars.len()),
+        )
+    } else {
+        (0, chars.len())
+    };
+    let highlights = ranges
+        .into_iter()
+        .filter(|(start, end)| *start >= from && *end <= to)
+        .map(|(start, end)| Highlight {
+            start: utf16_len(&chars[from..start]),
+            end: utf16_len(&chars[from..end]),
+        })
+        .collect();
+
+    Some((
+        score,
+        SearchMatch {
+            field,
+            text: chars[from..to].iter().collect(),
+            highlights,
+        },
+    ))
+}
+
+/// Ranks the models containing every search term in their name, title, tags,
+/// file names, author, description or notes, with the matched ranges.
+pub async fn handle_search(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let mut terms: Vec<String> = params
+        .q
+        .split_whitespace()
+        .map(|term| term.chars().map(lowercase).collect())
+        .collect();
+    terms.sort();
+    terms.dedup();
+    terms.truncate(MAX_TERMS);
+    if terms.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let limit = params.limit.unwrap_or(20).clamp(1, 100) as usize;
+    let offset = params.offset.unwrap_or(0).max(0) as usize;
+
+    let mut query = filter_models(&params.filters, &viewer)?;
+    for term in &terms {
+        query = mentions(query, term);
+    }
+    let models = query.load::<Model3D>(&mut connection).await.map_err(|e| {
+        error!("Failed to search models: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let ids: Vec<i32> = models.iter().map(|model| model.id).collect();
+
+    let mut tags_of: HashMap<i32, Vec<String>> = HashMap::new();
+    for (model_id, tag) in model_tags::dsl::model_tags
+        .inner_join(tags::table)
+        .filter(model_tags::dsl::model_id.eq_any(ids.clone()))
+        .select((model_tags::dsl::model_id, tags::dsl::name))
+        .load::<(i32, String)>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load tags: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+    {
+        tags_of.entry(model_id).or_default().push(tag);
+    }
+    let mut files_of: HashMap<i32, Vec<String>> = HashMap::new();
+    for (model_id, file_path) in files3d::dsl::files3d
+        .filter(files3d::dsl::model_id.eq_any(ids.clone()))
+        .select((files3d::dsl::model_id, files3d::dsl::file_path))
+        .load::<(i32, String)>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load file names: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+    {
+        files_of.entry(model_id).or_default().push(file_path);
+    }
+
+    let terms: Vec<Vec<char>> = terms.iter().map(|term| term.chars().collect()).collect();
+    let mut ranked: Vec<(f32, Vec<SearchMatch>, Model3D)> = models
+        .into_iter()
+        .map(|model| {
+            let mut fields = vec![
+                (SearchField::Name, model.name.clone()),
+                (SearchField::Title, model.title.clone()),
+            ];
+            for tag in tags_of.remove(&model.id).unwrap_or_default() {
+                fields.push((SearchField::Tag, tag));
+            }
+            for file_name in files_of.remove(&model.id).unwrap_or_default() {
+                fields.push((SearchField::FileName, file_name));
+            }
+            if let Some(author) = &model.author {
+                fields.push((SearchField::Author, author.clone()));
+            }
+            fields.push((SearchField::Description, model.description.clone()));
+            fields.push((SearchField::Notes, model.notes.clone()));
+
+            let mut score = 0.0;
+            let mut matches = Vec::new();
+            for (field, text) in fields {
+                if let Some((field_score, found)) = match_field(field, &text, &terms) {
+                    score += field_score;
+                    matches.push(found);
+                }
+            }
+            (score, matches, model)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.2.name.cmp(&b.2.name)));
+
+    let total = ranked.len() as i32;
+    let mut hits = Vec::new();
+    for (score, matches, model) in ranked.into_iter().skip(offset).take(limit) {
+        let model = ModelResponse::from_model_3d(&model, &state.config, &mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to build model response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        hits.push(SearchHit {
+            model,
+            score,
+            matches,
+        });
+    }
+
+    Ok(Json(SearchResponse { hits, total }))
+}
@@ -0,0 +1,253 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::{is_admin, Viewer};
+use crate::model_filter::filter_models;
+use crate::notifications::{base_url, model_url};
+use crate::schema::models3d;
+use crate::tenants::TenantScope;
+use crate::types::{ListModelParams, Model3D, ModelResponse};
+use crate::{AppState, Config};
+
+/// Most URLs a single sitemap file may list.
+const SITEMAP_LIMIT: i64 = 50_000;
+
+/// Unfurled links cut descriptions off well before this anyway.
+const DESCRIPTION_LENGTH: usize = 200;
+
+const SITE_NAME: &str = "ModelVault";
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MetaTag {
+    /// `og:*` and `twitter:*` properties
+    pub property: String,
+    pub content: String,
+}
+
+/// What the frontend puts into the head of a model page, so shared links
+/// unfurl with a title and preview.
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    pub title: String,
+    pub description: String,
+    pub url: String,
+    pub image: Option<String>,
+    pub license: Option<String>,
+    pub meta_tags: Vec<MetaTag>,
+    /// schema.org `3DModel`, for a `<script type="application/ld+json">`
+    pub json_ld: Value,
+}
+
+/// What anonymous visitors may see, `None` when they may see nothing
+/// because every request needs a tenant.
+fn anonymous(config: &Config) -> Option<Viewer> {
+    if config.multi_tenant {
+        return None;
+    }
+    Some(Viewer {
+        user: None,
+        groups: Vec::new(),
+        is_admin: is_admin(config, None),
+        scope: TenantScope::All,
+    })
+}
+
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// The first paragraph of a readme without markdown headings, shortened
+/// on a word boundary.
+fn summary(readme: &str) -> String {
+    let paragraph = readme
+        .split("\n\n")
+        .map(|paragraph| {
+            paragraph
+                .lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .find(|paragraph| !paragraph.is_empty())
+        .unwrap_or_default();
+    if paragraph.chars().count() <= DESCRIPTION_LENGTH {
+        return paragraph;
+    }
+    let cut: String = paragraph.chars().take(DESCRIPTION_LENGTH).collect();
+    let cut = match cut.rfind(' ') {
+        Some(end) => &cut[..end],
+        None => &cut,
+    };
+    format!("{}…", cut.trim_end())
+}
+
+fn absolute(config: &Config, path: &str) -> String {
+    if path.starts_with("http://") || path.starts_with("https://") {
+        path.to_string()
+    } else {
+        format!("{}/{}", base_url(config), path.trim_start_matches('/'))
+    }
+}
+
+/// `/sitemap.xml` with the pages of all models anonymous visitors can
+/// see. Only served when `public_url` is set, as sitemaps need absolute
+/// links.
+pub async fn handle_sitemap(State(state): State<AppState>) -> Response {
+    let config = &state.config;
+    let Some(viewer) = config.public_url.as_ref().and_then(|_| anonymous(config)) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut connection = match state.connection().await {
+        Ok(connection) => connection,
+        Err(status) => return status.into_response(),
+    };
+    let models = match filter_models(&ListModelParams::default(), &viewer) {
+        Ok(models) => models,
+        Err(status) => return status.into_response(),
+    };
+    let models = models
+        .select((models3d::dsl::slug, models3d::dsl::updated_at))
+        .limit(SITEMAP_LIMIT)
+        .load::<(String, Option<chrono::NaiveDateTime>)>(&mut connection)
+        .await;
+    let models = match models {
+        Ok(models) => models,
+        Err(e) => {
+            error!("Failed to load models for the sitemap: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (slug, updated_at) in models {
+        xml.push_str("  <url><loc>");
+        xml.push_str(&escape_xml(&model_url(config, &slug)));
+        xml.push_str("</loc>");
+        if let Some(updated_at) = updated_at {
+            xml.push_str(&format!(
+                "<lastmod>{}</lastmod>",
+                updated_at.format("%Y-%m-%d")
+            ));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+
+    (
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        xml,
+    )
+        .into_response()
+}
+
+async fn model_metadata<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> anyhow::Result<ModelMetadata>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let response = ModelResponse::from_model_3d(model, config, connection).await?;
+    let url = model_url(config, &model.slug);
+    let description = summary(&model.description);
+    let image = match response.images.as_slice() {
+        [image, ..] => Some(absolute(config, image)),
+        [] => None,
+    };
+
+    let mut meta_tags = vec![
+        ("og:type", "website".to_string()),
+        ("og:site_name", SITE_NAME.to_string()),
+        ("og:title", model.title.clone()),
+        ("og:url", url.clone()),
+    ];
+    if !description.is_empty() {
+        meta_tags.push(("og:description", description.clone()));
+    }
+    match &image {
+        Some(image) => {
+            meta_tags.push(("og:image", image.clone()));
+            meta_tags.push(("twitter:card", "summary_large_image".to_string()));
+        }
+        None => meta_tags.push(("twitter:card", "summary".to_string())),
+    }
+
+    let mut json_ld = json!({
+        "@context": "https://schema.org",
+        "@type": "3DModel",
+        "name": model.title,
+        "url": url,
+    });
+    if !description.is_empty() {
+        json_ld["description"] = json!(description);
+    }
+    if let Some(image) = &image {
+        json_ld["image"] = json!(image);
+    }
+    if let Some(license) = &model.license {
+        json_ld["license"] = json!(license);
+    }
+    if let Some(author) = model.author.as_ref().filter(|author| !author.is_empty()) {
+        json_ld["author"] = json!({ "@type": "Person", "name": author });
+    }
+    if let Some(updated_at) = model.updated_at {
+        json_ld["dateModified"] = json!(updated_at.format("%Y-%m-%d").to_string());
+    }
+    if !response.tags.is_empty() {
+        json_ld["keywords"] = json!(response.tags.join(", "));
+    }
+
+    Ok(ModelMetadata {
+        title: model.title.clone(),
+        description,
+        url,
+        image,
+        license: model.license.clone(),
+        meta_tags: meta_tags
+            .into_iter()
+            .map(|(property, content)| MetaTag {
+                property: property.to_string(),
+                content,
+            })
+            .collect(),
+        json_ld,
+    })
+}
+
+/// Link preview and structured data of a model page.
+pub async fn handle_model_metadata(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+) -> Result<Json<ModelMetadata>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let model = viewer.find_model(&mut connection, &slug).await?;
+    let metadata = model_metadata(&state.config, &mut connection, &model)
+        .await
+        .map_err(|e| {
+            error!("Failed to build metadata of {}: {}", slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(metadata))
+}
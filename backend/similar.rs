@@ -0,0 +1,218 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::schema::{model_embeddings, models3d};
+use crate::tags::get_model_tags;
+use crate::types::{Model3D, ModelResponse};
+use crate::AppState;
+
+/// Number of buckets words and word fragments are hashed into.
+const DIMENSIONS: usize = 512;
+
+/// The name says more about a model than a long README does.
+const NAME_WEIGHT: f32 = 3.0;
+const TAG_WEIGHT: f32 = 2.0;
+const DESCRIPTION_WEIGHT: f32 = 1.0;
+/// Trigrams match `phonestand` with `phone stand` or `stands`.
+const TRIGRAM_WEIGHT: f32 = 0.5;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "has", "in", "is", "it", "of",
+    "on", "or", "that", "the", "this", "to", "was", "with", "you", "your", "can", "will", "not",
+];
+
+/// FNV-1a, stable across builds so stored vectors stay comparable.
+fn hash(feature: &str) -> u64 {
+    feature.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|word| word.chars().count() > 1 && !STOP_WORDS.contains(&word.as_str()))
+}
+
+fn add_feature(vector: &mut [f32], feature: &str, weight: f32) {
+    let hash = hash(feature);
+    let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+    vector[(hash % DIMENSIONS as u64) as usize] += sign * weight;
+}
+
+fn add_text(vector: &mut [f32], text: &str, weight: f32) {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for word in words(text) {
+        *counts.entry(word).or_default() += 1;
+    }
+    for (word, count) in counts {
+        // repeating a word in a README should not drown everything else
+        let weight = weight * (1.0 + (count as f32).ln());
+        add_feature(vector, &word, weight);
+
+        let padded: Vec<char> = format!("#{}#", word).chars().collect();
+        for trigram in padded.windows(3) {
+            let trigram: String = trigram.iter().collect();
+            add_feature(vector, &format!("3:{}", trigram), weight * TRIGRAM_WEIGHT);
+        }
+    }
+}
+
+/// A normalized vector of the words in the name, tags and description of a
+/// model, close vectors describe similar models.
+pub fn embed(name: &str, title: &str, tags: &[String], description: &str) -> Vec<f32> {
+    let mut vector = vec![0.0; DIMENSIONS];
+    add_text(&mut vector, &format!("{} {}", name, title), NAME_WEIGHT);
+    add_text(&mut vector, &tags.join(" "), TAG_WEIGHT);
+    add_text(&mut vector, description, DESCRIPTION_WEIGHT);
+
+    let length = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if length > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= length);
+    }
+    vector
+}
+
+fn to_bytes(vector: &[f32]) -> Vec<u8> {
+    vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+/// Cosine similarity of two normalized vectors.
+fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+pub async fn store_embedding<Conn>(
+    connection: &mut Conn,
+    model_id: i32,
+    vector: &[f32],
+) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let vector = to_bytes(vector);
+    diesel::insert_into(model_embeddings::table)
+        .values((
+            model_embeddings::dsl::model_id.eq(model_id),
+            model_embeddings::dsl::vector.eq(&vector),
+        ))
+        .on_conflict(model_embeddings::dsl::model_id)
+        .do_update()
+        .set(model_embeddings::dsl::vector.eq(&vector))
+        .execute(connection)
+        .await
+}
+
+#[derive(Deserialize)]
+pub struct SimilarParams {
+    pub limit: Option<i64>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimilarModel {
+    pub model: ModelResponse,
+    /// cosine similarity, 1 for identical descriptions
+    pub score: f32,
+}
+
+/// The models closest to `slug` by name, tags and description, among those
+/// the viewer may see.
+pub async fn handle_similar(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+    Query(params): Query<SimilarParams>,
+) -> Result<Json<Vec<SimilarModel>>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let limit = params.limit.unwrap_or(5).clamp(1, 50) as usize;
+
+    let model = viewer.find_model(&mut connection, &slug).await?;
+    let tags = get_model_tags(&mut connection, model.id)
+        .await
+        .map_err(|e| {
+            error!("Failed to load tags of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let target = embed(&model.name, &model.title, &tags, &model.description);
+
+    let mut candidates = model_embeddings::dsl::model_embeddings
+        .inner_join(models3d::table)
+        .filter(models3d::dsl::deleted_at.is_null())
+        .filter(models3d::dsl::id.ne(model.id))
+        .into_boxed();
+    if let Some(tenant_id) = viewer.scope.tenant_id() {
+        candidates = candidates.filter(models3d::dsl::tenant_id.eq(tenant_id));
+    }
+    if let Some(visible_ids) = viewer.visible_ids() {
+        candidates = candidates.filter(models3d::dsl::id.eq_any(visible_ids));
+    }
+
+    let candidates = candidates
+        .select((
+            model_embeddings::dsl::model_id,
+            model_embeddings::dsl::vector,
+        ))
+        .load::<(i32, Vec<u8>)>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load model embeddings: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut scores: Vec<(i32, f32)> = candidates
+        .into_iter()
+        .map(|(model_id, vector)| (model_id, similarity(&target, &from_bytes(&vector))))
+        .filter(|(_, score)| *score > 0.0)
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    scores.truncate(limit);
+
+    let mut models: HashMap<i32, Model3D> = models3d::dsl::models3d
+        .filter(models3d::dsl::id.eq_any(scores.iter().map(|(id, _)| *id).collect::<Vec<_>>()))
+        .load::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load similar models: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .map(|model| (model.id, model))
+        .collect();
+
+    let mut response = Vec::new();
+    for (model_id, score) in scores {
+        let Some(model) = models.remove(&model_id) else {
+            continue;
+        };
+        let model = ModelResponse::from_model_3d(&model, &state.config, &mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to build model response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        response.push(SimilarModel { model, score });
+    }
+
+    Ok(Json(response))
+}
@@ -0,0 +1,178 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::LazyLock;
+use tracing::debug;
+use typeshare::typeshare;
+
+/// The settings a slicer saved into a 3MF project file.
+#[typeshare]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrintSettings {
+    pub slicer: Option<String>,
+    pub print_profile: Option<String>,
+    pub printer_profile: Option<String>,
+    pub filament_profile: Option<String>,
+    pub layer_height: Option<f64>,
+    pub supports: Option<bool>,
+    pub material: Option<String>,
+    pub colour: Option<String>,
+    pub infill: Option<String>,
+    pub objects: Vec<String>,
+}
+
+/// PrusaSlicer and SuperSlicer keep an INI like config, Bambu Studio and
+/// OrcaSlicer a JSON one.
+const PRUSA_CONFIG: &str = "Metadata/Slic3r_PE.config";
+const PRUSA_MODEL_CONFIG: &str = "Metadata/Slic3r_PE_model.config";
+const BAMBU_CONFIG: &str = "Metadata/project_settings.config";
+const MODEL: &str = "3D/3dmodel.model";
+
+static PRUSA_OBJECT_NAME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<metadata\s+type="object"\s+key="name"\s+value="([^"]*)""#).unwrap()
+});
+static MODEL_OBJECT_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<object\b[^>]*\bname="([^"]*)""#).unwrap());
+static GENERATOR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)<metadata\s+name="Application"\s*>([^<]*)</metadata>"#).unwrap()
+});
+
+fn read_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Option<String> {
+    let mut entry = archive.by_name(name).ok()?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// `; key = value` lines of a PrusaSlicer config.
+fn parse_prusa_config(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().trim_start_matches(';').split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Bambu configs store most values as single element lists.
+fn parse_bambu_config(content: &str) -> HashMap<String, String> {
+    let Ok(serde_json::Value::Object(values)) = serde_json::from_str(content) else {
+        return HashMap::new();
+    };
+    values
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Array(values) => values.first()?.as_str()?.to_string(),
+                serde_json::Value::Number(number) => number.to_string(),
+                serde_json::Value::Bool(flag) => flag.to_string(),
+                _ => return None,
+            };
+            Some((key, value))
+        })
+        .collect()
+}
+
+fn first<'a>(config: &'a HashMap<String, String>, keys: &[&str]) -> Option<&'a String> {
+    keys.iter()
+        .filter_map(|key| config.get(*key))
+        .find(|value| !value.is_empty())
+}
+
+fn settings_from_config(config: &HashMap<String, String>) -> PrintSettings {
+    PrintSettings {
+        print_profile: first(config, &["print_settings_id"]).cloned(),
+        printer_profile: first(config, &["printer_settings_id"]).cloned(),
+        filament_profile: first(config, &["filament_settings_id"])
+            .map(|profile| profile.trim_matches('"').to_string()),
+        layer_height: first(config, &["layer_height"]).and_then(|value| value.parse().ok()),
+        supports: first(config, &["support_material", "enable_support"])
+            .map(|value| value == "1" || value == "true"),
+        material: first(config, &["filament_type"])
+            .map(|material| material.split(';').next().unwrap_or_default().to_string()),
+        colour: first(config, &["filament_colour", "extruder_colour"])
+            .and_then(|colour| {
+                colour
+                    .split(';')
+                    .map(|colour| colour.trim_matches('"'))
+                    .find(|colour| !colour.is_empty())
+            })
+            .map(str::to_string),
+        infill: first(config, &["fill_density", "sparse_infill_density"]).cloned(),
+        ..Default::default()
+    }
+}
+
+/// Reads the slicer settings and object names of a 3MF project. Plain 3MF
+/// meshes without any slicer config yield `None`.
+pub fn read_print_settings(file_pth: &Path) -> Option<PrintSettings> {
+    let file = File::open(file_pth).ok()?;
+    let mut archive = match zip::ZipArchive::new(file) {
+        Ok(archive) => archive,
+        Err(e) => {
+            debug!("{} is no zip archive: {}", file_pth.display(), e);
+            return None;
+        }
+    };
+
+    let mut settings = if let Some(content) = read_entry(&mut archive, PRUSA_CONFIG) {
+        let config = parse_prusa_config(&content);
+        PrintSettings {
+            // `; generated by PrusaSlicer 2.7.1+linux-x64 on 2024-01-05 at 10:00:00 UTC`
+            slicer: content
+                .lines()
+                .next()
+                .and_then(|line| {
+                    line.trim_start_matches(';')
+                        .trim()
+                        .strip_prefix("generated by ")
+                })
+                .map(|generator| generator.split(" on ").next().unwrap_or(generator))
+                .map(|generator| generator.trim().to_string())
+                .or(Some("PrusaSlicer".to_string())),
+            ..settings_from_config(&config)
+        }
+    } else if let Some(content) = read_entry(&mut archive, BAMBU_CONFIG) {
+        settings_from_config(&parse_bambu_config(&content))
+    } else {
+        return None;
+    };
+
+    let model = read_entry(&mut archive, MODEL);
+    if settings.slicer.is_none() {
+        settings.slicer = model
+            .as_deref()
+            .and_then(|model| GENERATOR.captures(model))
+            .map(|captures| unescape_xml(captures[1].trim()));
+    }
+
+    settings.objects = match read_entry(&mut archive, PRUSA_MODEL_CONFIG) {
+        Some(content) => PRUSA_OBJECT_NAME
+            .captures_iter(&content)
+            .map(|captures| unescape_xml(&captures[1]))
+            .collect(),
+        None => model
+            .as_deref()
+            .map(|model| {
+                MODEL_OBJECT_NAME
+                    .captures_iter(model)
+                    .map(|captures| unescape_xml(&captures[1]))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+
+    Some(settings)
+}
@@ -0,0 +1,147 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use tracing::debug;
+
+use crate::names::{fold_name, normalize_name};
+use crate::schema::{models3d, slug_redirects};
+use crate::types::Model3D;
+
+const FALLBACK_SLUG: &str = "model";
+
+/// URL safe, transliterated slug for a model title.
+pub fn slugify(title: &str) -> String {
+    let slug = fold_name(&str_slug::slug(&normalize_name(title)));
+    if slug.is_empty() {
+        FALLBACK_SLUG.to_string()
+    } else {
+        slug
+    }
+}
+
+pub async fn slug_is_taken<Conn>(
+    connection: &mut Conn,
+    slug: &str,
+    model_id: Option<i32>,
+) -> QueryResult<bool>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let owner = models3d::dsl::models3d
+        .filter(models3d::dsl::slug.eq(slug))
+        .select(models3d::dsl::id)
+        .first::<i32>(connection)
+        .await
+        .optional()?;
+    let redirect_owner = slug_redirects::dsl::slug_redirects
+        .find(slug)
+        .select(slug_redirects::dsl::model_id)
+        .first::<i32>(connection)
+        .await
+        .optional()?;
+
+    Ok([owner, redirect_owner]
+        .into_iter()
+        .flatten()
+        .any(|owner| Some(owner) != model_id))
+}
+
+/// Finds a slug based on `base` which is neither used nor redirected by
+/// another model by appending a numeric suffix on collisions. Slugs are
+/// compared folded, see `names::fold_name`.
+pub async fn unique_slug<Conn>(
+    connection: &mut Conn,
+    base: &str,
+    model_id: Option<i32>,
+) -> QueryResult<String>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let base = fold_name(base);
+    let base = if base.is_empty() {
+        FALLBACK_SLUG
+    } else {
+        base.as_str()
+    };
+    let mut candidate = base.to_string();
+    let mut suffix = 2;
+
+    while slug_is_taken(connection, &candidate, model_id).await? {
+        candidate = format!("{}-{}", base, suffix);
+        suffix += 1;
+    }
+
+    Ok(candidate)
+}
+
+/// Gives `model` a new slug while keeping the old one reachable.
+pub async fn change_slug<Conn>(
+    connection: &mut Conn,
+    model: &Model3D,
+    new_slug: &str,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    if model.slug == new_slug {
+        return Ok(());
+    }
+
+    // the model takes back one of its previous slugs
+    diesel::delete(slug_redirects::dsl::slug_redirects.find(new_slug))
+        .execute(connection)
+        .await?;
+
+    diesel::update(models3d::dsl::models3d.find(model.id))
+        .set(models3d::dsl::slug.eq(new_slug))
+        .execute(connection)
+        .await?;
+
+    if !model.slug.is_empty() {
+        diesel::insert_into(slug_redirects::table)
+            .values((
+                slug_redirects::dsl::slug.eq(&model.slug),
+                slug_redirects::dsl::model_id.eq(model.id),
+            ))
+            .on_conflict(slug_redirects::dsl::slug)
+            .do_update()
+            .set(slug_redirects::dsl::model_id.eq(model.id))
+            .execute(connection)
+            .await?;
+    }
+    debug!("Renamed slug {} to {}", model.slug, new_slug);
+
+    Ok(())
+}
+
+/// Looks a model up by its slug, following redirects of renamed models. The
+/// slug is folded first, so links typed with other case or normalization
+/// still lead to the model.
+pub async fn find_model_by_slug<Conn>(connection: &mut Conn, slug: &str) -> QueryResult<Model3D>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let slug = fold_name(slug);
+    let slug = slug.as_str();
+    let model = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
+        .filter(models3d::dsl::slug.eq(slug))
+        .first::<Model3D>(connection)
+        .await
+        .optional()?;
+
+    if let Some(model) = model {
+        return Ok(model);
+    }
+
+    let model_id = slug_redirects::dsl::slug_redirects
+        .find(slug)
+        .select(slug_redirects::dsl::model_id)
+        .first::<i32>(connection)
+        .await?;
+
+    models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
+        .find(model_id)
+        .first::<Model3D>(connection)
+        .await
+}
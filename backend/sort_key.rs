@@ -0,0 +1,63 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use tracing::info;
+
+use crate::schema::models3d;
+
+/// Numbers are padded to this many digits, so they compare by value.
+/// Longer numbers compare by their digits, which is rarely relevant.
+const NUMBER_WIDTH: usize = 10;
+
+/// The key models are listed by. Titles are transliterated and case folded,
+/// so `Über` sorts with `uber` and other scripts sort by their latin
+/// spelling, and numbers are zero padded so `Part 2` sorts before `Part 10`.
+pub fn sort_key(title: &str) -> String {
+    let folded = str_slug::slug(title);
+    let folded = if folded.is_empty() {
+        title.trim().to_lowercase()
+    } else {
+        folded
+    };
+
+    let mut key = String::with_capacity(folded.len());
+    let mut number = String::new();
+    for c in folded.chars().chain(std::iter::once('\0')) {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        if !number.is_empty() {
+            let digits = number.trim_start_matches('0');
+            let digits = if digits.is_empty() { "0" } else { digits };
+            key.push_str(&format!("{:0>width$}", digits, width = NUMBER_WIDTH));
+            number.clear();
+        }
+        if c != '\0' {
+            key.push(c);
+        }
+    }
+    key
+}
+
+/// Fills in the keys of models indexed before they existed.
+pub async fn backfill<Conn>(connection: &mut Conn) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let models: Vec<(i32, String)> = models3d::dsl::models3d
+        .filter(models3d::dsl::sort_key.eq(""))
+        .select((models3d::dsl::id, models3d::dsl::title))
+        .load(connection)
+        .await?;
+    if models.is_empty() {
+        return Ok(());
+    }
+    info!("Computing sort keys of {} models", models.len());
+    for (id, title) in models {
+        diesel::update(models3d::dsl::models3d.find(id))
+            .set(models3d::dsl::sort_key.eq(sort_key(&title)))
+            .execute(connection)
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::schema::{model_events, models3d};
+use crate::types::{Model3D, ModelResponse};
+use crate::AppState;
+
+/// A download is a stronger signal of interest than opening the model page.
+const DOWNLOAD_WEIGHT: f64 = 5.0;
+const VIEW_WEIGHT: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Download,
+    View,
+    /// a file of the model fetched from the library, e.g. by the 3D viewer
+    Asset,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Download => "download",
+            EventKind::View => "view",
+            EventKind::Asset => "asset",
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = model_events)]
+struct NewModelEvent<'a> {
+    model_id: i32,
+    kind: &'a str,
+    created_at: NaiveDateTime,
+}
+
+pub async fn record_event<Conn>(
+    connection: &mut Conn,
+    model_id: i32,
+    kind: EventKind,
+) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::insert_into(model_events::table)
+        .values(&NewModelEvent {
+            model_id,
+            kind: kind.as_str(),
+            created_at: Utc::now().naive_utc(),
+        })
+        .execute(connection)
+        .await
+}
+
+/// Statistics must never break the request they are recorded for.
+pub async fn record_event_logged<Conn>(connection: &mut Conn, model_id: i32, kind: EventKind)
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    if let Err(e) = record_event(connection, model_id, kind).await {
+        error!(
+            "Failed to record {} of model {}: {}",
+            kind.as_str(),
+            model_id,
+            e
+        );
+    }
+}
+
+/// Parses windows like `30d`, `2w` or `12h`.
+pub fn parse_window(window: &str) -> Option<Duration> {
+    let window = window.trim();
+    let unit_start = window.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = window.split_at(unit_start);
+    let amount: i64 = amount.parse().ok().filter(|amount| *amount > 0)?;
+    match unit {
+        "h" => Some(Duration::hours(amount)),
+        "d" => Some(Duration::days(amount)),
+        "w" => Some(Duration::weeks(amount)),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TrendingParams {
+    pub window: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrendingModel {
+    pub model: ModelResponse,
+    pub score: f64,
+    pub downloads: i32,
+    pub views: i32,
+}
+
+#[derive(Default)]
+struct Score {
+    score: f64,
+    downloads: i32,
+    views: i32,
+}
+
+/// Sums the weighted events of every model within the window. Each event
+/// loses half of its weight every quarter of the window, so recent activity
+/// outweighs a burst at the beginning of the window.
+fn score_events(
+    events: Vec<(i32, String, NaiveDateTime)>,
+    now: NaiveDateTime,
+    window: Duration,
+) -> HashMap<i32, Score> {
+    let half_life = window.num_seconds() as f64 / 4.0;
+    let mut scores: HashMap<i32, Score> = HashMap::new();
+
+    for (model_id, kind, created_at) in events {
+        // fetched files follow views of the model page, counting them
+        // would count the same interest twice
+        if kind == EventKind::Asset.as_str() {
+            continue;
+        }
+        let age = (now - created_at).num_seconds().max(0) as f64;
+        let decay = 0.5_f64.powf(age / half_life);
+        let score = scores.entry(model_id).or_default();
+        if kind == EventKind::Download.as_str() {
+            score.downloads += 1;
+            score.score += DOWNLOAD_WEIGHT * decay;
+        } else {
+            score.views += 1;
+            score.score += VIEW_WEIGHT * decay;
+        }
+    }
+
+    scores
+}
+
+pub async fn handle_trending(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(params): Query<TrendingParams>,
+) -> Result<Json<Vec<TrendingModel>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let window = match &params.window {
+        Some(window) => parse_window(window).ok_or(StatusCode::BAD_REQUEST)?,
+        None => Duration::days(30),
+    };
+    let limit = params.limit.unwrap_or(12).clamp(1, 100) as usize;
+    let now = Utc::now().naive_utc();
+
+    let mut events = model_events::dsl::model_events
+        .inner_join(models3d::table)
+        .filter(models3d::dsl::deleted_at.is_null())
+        .filter(model_events::dsl::created_at.ge(now - window))
+        .into_boxed();
+    if let Some(tenant_id) = viewer.scope.tenant_id() {
+        events = events.filter(models3d::dsl::tenant_id.eq(tenant_id));
+    }
+    if let Some(visible_ids) = viewer.visible_ids() {
+        events = events.filter(models3d::dsl::id.eq_any(visible_ids));
+    }
+
+    let events = events
+        .select((
+            model_events::dsl::model_id,
+            model_events::dsl::kind,
+            model_events::dsl::created_at,
+        ))
+        .load::<(i32, String, NaiveDateTime)>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load model events: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut scores: Vec<(i32, Score)> = score_events(events, now, window).into_iter().collect();
+    scores.sort_by(|a, b| b.1.score.total_cmp(&a.1.score).then(a.0.cmp(&b.0)));
+    scores.truncate(limit);
+
+    let mut models: HashMap<i32, Model3D> = models3d::dsl::models3d
+        .filter(models3d::dsl::id.eq_any(scores.iter().map(|(id, _)| *id).collect::<Vec<_>>()))
+        .load::<Model3D>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load trending models: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .map(|model| (model.id, model))
+        .collect();
+
+    let mut response = Vec::new();
+    for (model_id, score) in scores {
+        let Some(model) = models.remove(&model_id) else {
+            continue;
+        };
+        let model = ModelResponse::from_model_3d(&model, &state.config, &mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to build model response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        response.push(TrendingModel {
+            model,
+            score: score.score,
+            downloads: score.downloads,
+            views: score.views,
+        });
+    }
+
+    Ok(Json(response))
+}
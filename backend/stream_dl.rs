@@ -3,31 +3,109 @@ use axum::{
     http::{header, HeaderValue, Response},
     response::IntoResponse,
 };
+use futures::StreamExt;
+use std::future::Future;
 use std::{io::Write, path::PathBuf};
 use tokio::fs::File as TokioFile;
 use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
 use tracing::{debug, error};
 use zip::write::SimpleFileOptions;
 use zip::ZipWriter;
 
+use crate::file_types::{file_type_of, DownloadKind};
+use crate::layout;
+use crate::materials;
+use crate::parse_library::walk_dir;
+use crate::zip_cache;
 use crate::Config;
 
-fn collect_files_to_compress(dir: &PathBuf) -> anyhow::Result<Vec<PathBuf>> {
+mod archive_name;
+
+use archive_name::archive_name;
+
+/// Files below `dir`. Symlinked files and folders are archived under their
+/// link path when following symlinks is enabled and left out otherwise.
+/// Materials and textures OBJ files reference from sibling folders are
+/// added as long as they are inside the library. Other kinds than `all`
+/// keep only their files and the metadata at the top of the folder.
+fn collect_files_to_compress(
+    dir: &PathBuf,
+    config: &Config,
+    kind: DownloadKind,
+) -> anyhow::Result<Vec<PathBuf>> {
     let mut files = Vec::new();
-    for entry in walkdir::WalkDir::new(dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path().to_path_buf();
-        if path.is_file() {
-            files.push(path);
+    for entry in walk_dir(config, dir).filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let is_metadata = path.parent() == Some(dir.as_path()) && layout::is_metadata_file(path);
+        if entry.file_type().is_file() && (is_metadata || kind.includes(&file_type_of(path))) {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let library = config.libraries_path.canonicalize()?;
+    let dependencies: Vec<PathBuf> = files
+        .iter()
+        .filter(|file| file_type_of(file) == "obj")
+        .flat_map(|file| materials::obj_dependencies(file))
+        .filter_map(|dependency| {
+            let dependency = dependency.canonicalize().ok()?;
+            let relative = dependency.strip_prefix(&library).ok()?;
+            Some(config.libraries_path.join(relative))
+        })
+        .collect();
+    for dependency in dependencies {
+        if !files.contains(&dependency) {
+            files.push(dependency);
         }
     }
     Ok(files)
 }
 
-pub async fn zip_folder_stream(folder_path: PathBuf, config: &Config) -> impl IntoResponse {
-    let files = match collect_files_to_compress(&folder_path) {
+fn zip_response(
+    folder_path: &std::path::Path,
+    kind: DownloadKind,
+    body: Body,
+    length: Option<u64>,
+) -> Response<Body> {
+    let mut name = folder_path
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+    if kind != DownloadKind::All {
+        name.push_str(&format!("-{}", kind.as_str()));
+    }
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_str(&format!("attachment; filename=\"{}.zip\"", name)).unwrap(),
+        );
+    if let Some(length) = length {
+        response = response.header(header::CONTENT_LENGTH, length);
+    }
+    response.body(body).unwrap()
+}
+
+/// Streams `folder_path` as a zip archive. `generated` files are added
+/// first, named relative to the library like the files on disk.
+/// `on_complete` runs once the client took the whole archive, it never
+/// runs for aborted downloads.
+///
+/// Archives without generated files are kept in the zip cache, repeated
+/// downloads of an unchanged folder are served from there.
+pub async fn zip_folder_stream<F>(
+    folder_path: PathBuf,
+    config: &Config,
+    kind: DownloadKind,
+    generated: Vec<(String, Vec<u8>)>,
+    on_complete: F,
+) -> impl IntoResponse
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let files = match collect_files_to_compress(&folder_path, config, kind) {
         Ok(files) => files,
         Err(err) => {
             return Response::builder()
@@ -39,12 +117,47 @@ pub async fn zip_folder_stream(folder_path: PathBuf, config: &Config) -> impl In
 
     let lib_dir = config.libraries_path.clone();
 
+    let cache_key = if zip_cache::is_enabled(config) && generated.is_empty() {
+        zip_cache::archive_key(&files, &lib_dir)
+    } else {
+        None
+    };
+    if let Some(key) = &cache_key {
+        if let Some((file, length)) = zip_cache::open(config, key).await {
+            debug!("Serving {} from the zip cache", folder_path.display());
+            let stream = async_stream::stream! {
+                let mut chunks = ReaderStream::new(file);
+                while let Some(chunk) = chunks.next().await {
+                    let failed = chunk.is_err();
+                    yield chunk;
+                    if failed {
+                        return;
+                    }
+                }
+                on_complete.await;
+            };
+            return zip_response(&folder_path, kind, Body::from_stream(stream), Some(length));
+        }
+    }
+    let config = config.clone();
+
     let stream = async_stream::stream! {
         let mut buffer = Vec::new(); // use https://doc.rust-lang.org/std/io/struct.BorrowedBuf.html in the future
         let mut append_zip = ZipWriter::new(std::io::Cursor::new(&mut buffer));
 
+        for (zip_path, contents) in generated {
+            let options = SimpleFileOptions::default();
+            if let Err(err) = append_zip.start_file(zip_path.as_str(), options) {
+                error!("Error adding {} to zip: {}", zip_path, err);
+                continue;
+            }
+            if let Err(err) = append_zip.write_all(&contents) {
+                error!("Error writing {} to zip: {}", zip_path, err);
+            }
+        }
+
         for file_path in files {
-            debug!("{:?}", file_path.to_str().unwrap());
+            debug!("{}", file_path.display());
 
 
             if let Ok(mut file) = TokioFile::open(&file_path).await {
@@ -54,10 +167,13 @@ pub async fn zip_folder_stream(folder_path: PathBuf, config: &Config) -> impl In
                     continue;
                 }
 
-                let zip_path = pathdiff::diff_paths(&file_path, &lib_dir).unwrap();
+                let Some(zip_path) = archive_name(&file_path, &lib_dir) else {
+                    error!("Not adding {} to zip, its name cannot be archived", file_path.display());
+                    continue;
+                };
 
                 let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
-                if let Err(err) = append_zip.start_file(zip_path.to_str().unwrap(), options) {
+                if let Err(err) = append_zip.start_file(zip_path.as_str(), options) {
                     error!("Error adding file to zip {}: {}", file_path.display(), err);
                     continue;
                 }
@@ -73,20 +189,16 @@ pub async fn zip_folder_stream(folder_path: PathBuf, config: &Config) -> impl In
             error!("Error finishing zip archive: {}", err);
         }
 
-        yield Ok::<_, std::io::Error>(Bytes::from(buffer));
+        let archive = Bytes::from(buffer);
+        if let Some(key) = cache_key {
+            tokio::spawn(zip_cache::store(config, key, archive.clone()));
+        }
+        yield Ok::<_, std::io::Error>(archive);
+        on_complete.await;
     };
 
-    let stream_body = Body::from_stream(stream);
-    Response::builder()
-        .header(header::CONTENT_TYPE, "application/zip")
-        .header(
-            header::CONTENT_DISPOSITION,
-            HeaderValue::from_str(&format!(
-                "attachment; filename=\"{}.zip\"",
-                folder_path.file_name().unwrap().to_string_lossy()
-            ))
-            .unwrap(),
-        )
-        .body(stream_body)
-        .unwrap()
+    zip_response(&folder_path, kind, Body::from_stream(stream), None)
 }
+
+#[cfg(test)]
+mod tests;
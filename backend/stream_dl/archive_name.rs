@@ -0,0 +1,28 @@
+//! Naming of files in download archives. Kept free of the rest of the crate
+//! so the fuzz target in `fuzz/` can build it on its own.
+
+use std::path::{Component, Path};
+
+/// The name of `file_path` in an archive, relative to the library. Paths
+/// leaving the library, names which are no valid UTF-8 and backslashes,
+/// which Windows extracts as separators, are refused.
+pub fn archive_name(file_path: &Path, lib_dir: &Path) -> Option<String> {
+    let relative = pathdiff::diff_paths(file_path, lib_dir)?;
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => {
+                let part = part.to_str()?;
+                if part.contains('\\') {
+                    return None;
+                }
+                parts.push(part);
+            }
+            _ => return None,
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(parts.join("/"))
+}
@@ -0,0 +1,470 @@
+//! Archives of pathological folder trees must extract to the very same files
+//! with every common unzip implementation. Extractors which are not
+//! installed are skipped.
+
+use axum::body::to_bytes;
+use axum::response::IntoResponse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Cursor, ErrorKind, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use super::{archive_name, zip_folder_stream};
+use crate::file_types::DownloadKind;
+use crate::Config;
+
+/// Longer means the stream hangs, e.g. reading from a FIFO.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Files of a tree by their name in the archive.
+type Tree = BTreeMap<String, Vec<u8>>;
+
+/// A library in a fresh temporary folder, removed again when dropped.
+struct Library {
+    root: PathBuf,
+    config: Config,
+}
+
+impl Library {
+    fn new() -> Library {
+        let root = std::env::temp_dir().join(format!("meshvault-stream-dl-{}", Uuid::new_v4()));
+        let libraries = root.join("libraries");
+        fs::create_dir_all(&libraries).unwrap();
+        let mut config: Config = envy::from_iter([
+            (
+                "LIBRARIES_PATH".to_string(),
+                libraries.display().to_string(),
+            ),
+            (
+                "DATA_DIR".to_string(),
+                root.join("data").display().to_string(),
+            ),
+            ("ZIP_CACHE_MAX_MB".to_string(), "0".to_string()),
+        ])
+        .unwrap();
+        config.initialize();
+        Library { root, config }
+    }
+
+    fn path(&self, relative: &str) -> PathBuf {
+        self.config.libraries_path.join(relative)
+    }
+
+    fn write(&self, relative: &str, contents: &[u8]) {
+        let path = self.path(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// The regular files below `folder`, as they should be archived.
+    fn expected(&self, folder: &str) -> Tree {
+        read_tree(&self.config.libraries_path, &self.path(folder))
+    }
+
+    async fn download(&self, folder: &str) -> Vec<u8> {
+        let response = tokio::time::timeout(DOWNLOAD_TIMEOUT, async {
+            let response = zip_folder_stream(
+                self.path(folder),
+                &self.config,
+                DownloadKind::All,
+                Vec::new(),
+                async {},
+            )
+            .await
+            .into_response();
+            assert!(response.status().is_success());
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        })
+        .await
+        .expect("the download did not finish");
+        response.to_vec()
+    }
+
+    /// Extracts `archive` with every available implementation and checks
+    /// each of them yields `expected`.
+    fn check_extracts_to(&self, archive: &[u8], expected: &Tree) {
+        assert_same_tree("zip crate", expected, &extract_with_zip_crate(archive));
+
+        let archive_path = self.root.join(format!("{}.zip", Uuid::new_v4()));
+        fs::write(&archive_path, archive).unwrap();
+        for (name, mut command, out) in external_extractors(&self.root, &archive_path) {
+            match command.status() {
+                Ok(status) => assert!(status.success(), "{} failed with {}", name, status),
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    eprintln!("{} is not installed, skipping it", name);
+                    continue;
+                }
+                Err(e) => panic!("Unable to run {}: {}", name, e),
+            }
+            assert_same_tree(name, expected, &read_tree(&out, &out));
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// xorshift64, seeded so failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn bytes(&mut self, length: usize) -> Vec<u8> {
+        (0..length).map(|_| self.next() as u8).collect()
+    }
+}
+
+fn read_tree(base: &Path, dir: &Path) -> Tree {
+    let mut tree = Tree::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let metadata = fs::symlink_metadata(&path).unwrap();
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if metadata.is_file() {
+                let name = path
+                    .strip_prefix(base)
+                    .unwrap()
+                    .components()
+                    .map(|component| component.as_os_str().to_str().unwrap())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                tree.insert(name, fs::read(&path).unwrap());
+            }
+        }
+    }
+    tree
+}
+
+fn extract_with_zip_crate(archive: &[u8]) -> Tree {
+    let mut zip = ZipArchive::new(Cursor::new(archive)).unwrap();
+    let mut tree = Tree::new();
+    for index in 0..zip.len() {
+        let mut file = zip.by_index(index).unwrap();
+        assert!(
+            file.enclosed_name().is_some(),
+            "{} leaves the extraction folder",
+            file.name()
+        );
+        if file.is_dir() {
+            continue;
+        }
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        let previous = tree.insert(file.name().to_string(), contents);
+        assert!(previous.is_none(), "{} is archived twice", file.name());
+    }
+    tree
+}
+
+/// Commands extracting `archive`, each into its own folder below `root`.
+fn external_extractors(root: &Path, archive: &Path) -> Vec<(&'static str, Command, PathBuf)> {
+    let out = root.join(format!("extracted-{}", Uuid::new_v4()));
+
+    let unzip_out = out.join("unzip");
+    let mut unzip = Command::new("unzip");
+    unzip.arg("-qq").arg(archive).arg("-d").arg(&unzip_out);
+
+    let bsdtar_out = out.join("bsdtar");
+    fs::create_dir_all(&bsdtar_out).unwrap();
+    let mut bsdtar = Command::new("bsdtar");
+    bsdtar.arg("-xf").arg(archive).arg("-C").arg(&bsdtar_out);
+
+    let python_out = out.join("python");
+    let mut python = Command::new("python3");
+    python
+        .args(["-m", "zipfile", "-e"])
+        .arg(archive)
+        .arg(&python_out);
+
+    let seven_zip_out = out.join("7z");
+    let mut seven_zip = Command::new("7z");
+    seven_zip
+        .args(["x", "-y", "-bso0", "-bsp0"])
+        .arg(format!("-o{}", seven_zip_out.display()))
+        .arg(archive);
+
+    vec![
+        ("unzip", unzip, unzip_out),
+        ("bsdtar", bsdtar, bsdtar_out),
+        ("python zipfile", python, python_out),
+        ("7z", seven_zip, seven_zip_out),
+    ]
+}
+
+fn assert_same_tree(extractor: &str, expected: &Tree, actual: &Tree) {
+    let missing: Vec<&String> = expected
+        .keys()
+        .filter(|name| !actual.contains_key(*name))
+        .take(5)
+        .collect();
+    let extra: Vec<&String> = actual
+        .keys()
+        .filter(|name| !expected.contains_key(*name))
+        .take(5)
+        .collect();
+    assert!(
+        missing.is_empty() && extra.is_empty(),
+        "{} extracted other files, missing {:?}, extra {:?}",
+        extractor,
+        missing,
+        extra
+    );
+    for (name, contents) in expected {
+        assert!(
+            actual[name] == *contents,
+            "{} extracted different contents of {}",
+            extractor,
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn deeply_nested_folders() {
+    let library = Library::new();
+    let mut rng = Rng(0x5eed_0001);
+    let mut folder = String::from("nested");
+    for depth in 0..64 {
+        folder.push_str(&format!("/level-{:02}", depth));
+        if depth % 8 == 0 {
+            let size = rng.below(4096);
+            library.write(&format!("{}/part.stl", folder), &rng.bytes(size));
+        }
+    }
+    library.write(&format!("{}/bottom.3mf", folder), &rng.bytes(100));
+
+    let expected = library.expected("nested");
+    assert_eq!(expected.len(), 9);
+    let archive = library.download("nested").await;
+    library.check_extracts_to(&archive, &expected);
+}
+
+#[tokio::test]
+async fn unicode_names() {
+    let library = Library::new();
+    let mut rng = Rng(0x5eed_0002);
+    let names = [
+        "Ünïcödé.stl",
+        "名前/部品.stl",
+        "emoji 🦊.3mf",
+        "ελληνικά/κείμενο.txt",
+        "עברית.obj",
+        // the same name composed and decomposed, distinct files on Linux
+        "Caf\u{e9}.stl",
+        "Cafe\u{301}.stl",
+        "spaces and 'quotes' & more;.stl",
+        "Straße/Ärger/öffnen.step",
+    ];
+    for name in names {
+        let size = rng.below(2048);
+        library.write(&format!("unicode/{}", name), &rng.bytes(size));
+    }
+
+    let expected = library.expected("unicode");
+    assert_eq!(expected.len(), names.len());
+    let archive = library.download("unicode").await;
+    library.check_extracts_to(&archive, &expected);
+}
+
+#[tokio::test]
+async fn empty_files() {
+    let library = Library::new();
+    library.write("empty/model.stl", b"");
+    library.write("empty/images/cover.png", b"");
+    library.write("empty/notes/readme.md", b"");
+    library.write("empty/part.3mf", b"not empty");
+
+    let expected = library.expected("empty");
+    let archive = library.download("empty").await;
+    let mut zip = ZipArchive::new(Cursor::new(archive.as_slice())).unwrap();
+    assert_eq!(zip.by_name("empty/model.stl").unwrap().size(), 0);
+    library.check_extracts_to(&archive, &expected);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn special_files_are_left_out() {
+    let library = Library::new();
+    library.write("special/model.stl", b"solid model");
+    let fifo = library.path("special/printer.fifo");
+    match Command::new("mkfifo").arg(&fifo).status() {
+        Ok(status) => assert!(status.success()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            eprintln!("mkfifo is not installed, skipping the test");
+            return;
+        }
+        Err(e) => panic!("Unable to run mkfifo: {}", e),
+    }
+    std::os::unix::fs::symlink(&fifo, library.path("special/link.fifo")).unwrap();
+
+    let expected = Tree::from([("special/model.stl".to_string(), b"solid model".to_vec())]);
+    let archive = library.download("special").await;
+    library.check_extracts_to(&archive, &expected);
+}
+
+#[tokio::test]
+#[ignore = "creates 70000 files"]
+async fn more_entries_than_zip32_allows() {
+    let library = Library::new();
+    for index in 0..70_000 {
+        library.write(
+            &format!("many/{:03}/{:05}.stl", index / 1000, index),
+            index.to_string().as_bytes(),
+        );
+    }
+
+    let expected = library.expected("many");
+    let archive = library.download("many").await;
+    assert_eq!(
+        ZipArchive::new(Cursor::new(archive.as_slice()))
+            .unwrap()
+            .len(),
+        70_000
+    );
+    library.check_extracts_to(&archive, &expected);
+}
+
+#[tokio::test]
+async fn generated_files_come_first() {
+    let library = Library::new();
+    library.write("generated/model.stl", b"solid model");
+
+    let response = zip_folder_stream(
+        library.path("generated"),
+        &library.config,
+        DownloadKind::All,
+        vec![("generated/modelpack.json".to_string(), b"{}".to_vec())],
+        async {},
+    )
+    .await
+    .into_response();
+    let archive = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let mut zip = ZipArchive::new(Cursor::new(&archive[..])).unwrap();
+    assert_eq!(zip.by_index(0).unwrap().name(), "generated/modelpack.json");
+    assert_eq!(zip.len(), 2);
+}
+
+#[test]
+fn archive_names() {
+    let library = Path::new("/srv/library");
+    let name = |path: &str| archive_name(Path::new(path), library);
+    assert_eq!(
+        name("/srv/library/model/a.stl").as_deref(),
+        Some("model/a.stl")
+    );
+    assert_eq!(
+        name("/srv/library/./model//a.stl").as_deref(),
+        Some("model/a.stl")
+    );
+    assert_eq!(name("/srv/library"), None);
+    assert_eq!(name("/srv/library/../etc/passwd"), None);
+    assert_eq!(name("/srv/other/a.stl"), None);
+    assert_eq!(name("/srv/library/model/../../a.stl"), None);
+    assert_eq!(name("/srv/library/..\\..\\evil.stl"), None);
+    assert_eq!(name("relative/a.stl"), None);
+}
+
+/// Lexically resolves `.` and `..`, `None` if it climbs above the root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    Some(normalized)
+}
+
+/// Joins random components onto random libraries. Whatever a name is
+/// produced for must be a plain relative path which extracts back to the
+/// very file, and plain files below the library must always get one.
+#[test]
+fn fuzz_archive_names() {
+    let libraries = ["/srv/library", "/srv/library/", "library", "./library", "/"];
+    let plain = ["a", "model", "x.stl", "名前", "Ü", " ", "C:", "..a", "a.."];
+    let hostile = [
+        "..",
+        ".",
+        "",
+        "/",
+        "a/..",
+        "..\\..\\evil",
+        "back\\slash",
+        "/etc",
+    ];
+    let mut rng = Rng(0x5eed_0003);
+
+    for _ in 0..50_000 {
+        let library = Path::new(libraries[rng.below(libraries.len())]);
+        let mut path = library.to_path_buf();
+        let mut only_plain = true;
+        for _ in 0..rng.below(6) {
+            if rng.below(3) == 0 {
+                path.push(hostile[rng.below(hostile.len())]);
+                only_plain = false;
+            } else {
+                path.push(plain[rng.below(plain.len())]);
+            }
+        }
+        #[cfg(unix)]
+        if rng.below(20) == 0 {
+            use std::ffi::OsString;
+            use std::os::unix::ffi::OsStringExt;
+            path.push(OsString::from_vec(vec![b'x', 0xff]));
+            only_plain = false;
+        }
+
+        match archive_name(&path, library) {
+            Some(name) => {
+                assert!(!name.is_empty());
+                assert!(!name.contains('\\'), "{:?} gave {}", path, name);
+                assert!(
+                    name.split('/')
+                        .all(|part| !part.is_empty() && part != "." && part != ".."),
+                    "{:?} gave {}",
+                    path,
+                    name
+                );
+                assert_eq!(
+                    normalize(&library.join(&name)),
+                    normalize(&path),
+                    "{:?} gave {}",
+                    path,
+                    name
+                );
+            }
+            None => assert!(
+                !only_plain || path == library,
+                "{:?} got no name below {:?}",
+                path,
+                library
+            ),
+        }
+    }
+}
@@ -0,0 +1,720 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use regex::Regex;
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+use typeshare::typeshare;
+use uuid::Uuid;
+
+use crate::api_version::CURRENT_PREFIX;
+use crate::audit_log::record;
+use crate::auth::User;
+use crate::jobs::check_cancelled;
+use crate::policy::{Admin, Authorized};
+use crate::quota;
+use crate::schema::{subscriptions, thing_imports, users};
+use crate::seo::escape_xml;
+use crate::thing_import::{
+    import_thing, is_relative_below, record_item, ImportItemError, ImportStarted, ImportSummary,
+    IMPORTED,
+};
+use crate::types::Model3D;
+use crate::upload::{QuotaBudget, UploadError};
+use crate::{AppState, Config};
+
+/// Models asked for per page of the other instance.
+const PAGE_SIZE: i64 = 50;
+
+static OPML_OUTLINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<outline\b([^>]*?)/?>").expect("valid regex"));
+static XML_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([\w:]+)\s*=\s*"([^"]*)""#).expect("valid regex"));
+
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = subscriptions)]
+pub struct Subscription {
+    pub id: i32,
+    pub url: String,
+    pub title: String,
+    pub folder: String,
+    pub created_at: NaiveDateTime,
+    pub last_synced_at: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    /// the admin who subscribed, mirrored models count against their quota
+    pub user_id: Option<i32>,
+}
+
+impl Subscription {
+    /// The `thing_imports` source the models of this subscription are
+    /// tracked under, their item is the slug on the other instance.
+    pub fn source(&self) -> String {
+        format!("sync:{}", self.url)
+    }
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscriptionResponse {
+    pub id: i32,
+    pub url: String,
+    pub title: String,
+    pub folder: String,
+    pub created_at: NaiveDateTime,
+    pub last_synced_at: Option<NaiveDateTime>,
+    /// why the last sync failed as a whole, failed models are listed with
+    /// the imports of the `sync:` source
+    pub last_error: Option<String>,
+    pub imported: i32,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Subscribe {
+    pub url: String,
+    /// the address if unset
+    pub title: Option<String>,
+    /// library folder the models are created in, the library root if unset
+    pub folder: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct RemotePage {
+    models: Vec<RemoteModel>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RemoteModel {
+    slug: String,
+}
+
+#[derive(Deserialize)]
+struct RemoteModelDetail {
+    package_name: String,
+}
+
+/// The address of an instance without a trailing slash or API prefix.
+fn normalize_url(url: &str) -> Option<String> {
+    let url = url.trim().trim_end_matches('/');
+    let url = url
+        .strip_suffix(CURRENT_PREFIX)
+        .or_else(|| url.strip_suffix("/api"))
+        .unwrap_or(url);
+    let parsed = Url::parse(url).ok()?;
+    matches!(parsed.scheme(), "http" | "https").then(|| url.to_string())
+}
+
+fn normalize_folder(folder: Option<&str>) -> Option<String> {
+    let folder = folder.unwrap_or_default().trim().trim_matches('/');
+    (folder.is_empty() || is_relative_below(folder)).then(|| folder.to_string())
+}
+
+fn client(config: &Config) -> reqwest::Result<Client> {
+    Client::builder()
+        .timeout(Duration::from_secs(config.slow_request_timeout_seconds))
+        .user_agent(concat!("ModelVault/", env!("CARGO_PKG_VERSION")))
+        .build()
+}
+
+async fn get_json<T: DeserializeOwned>(client: &Client, url: &str) -> anyhow::Result<T> {
+    let envelope: Envelope<T> = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(envelope.data)
+}
+
+/// Downloads a public model of the other instance as a zip with its
+/// manifest and imports it like an export of this instance. The download
+/// is aborted once it outgrows the largest accepted upload or the quota of
+/// the `owner` of the subscription, who is credited with the model.
+async fn pull_model<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    client: &Client,
+    subscription: &Subscription,
+    owner: Option<&User>,
+    slug: &str,
+) -> anyhow::Result<Model3D>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let api = format!("{}{}", subscription.url, CURRENT_PREFIX);
+    let detail: RemoteModelDetail = get_json(
+        client,
+        &format!(
+            "{}/model/{}",
+            api,
+            utf8_percent_encode(slug, NON_ALPHANUMERIC)
+        ),
+    )
+    .await?;
+
+    let mut budget = QuotaBudget::load(connection, config, owner)
+        .await
+        .map_err(UploadError::from)?;
+    let archive = config.upload_cache.join(format!("{}.zip", Uuid::new_v4()));
+    let result = async {
+        let mut response = client
+            .get(format!(
+                "{}/download/{}",
+                api,
+                utf8_percent_encode(&detail.package_name, NON_ALPHANUMERIC)
+            ))
+            .query(&[("manifest", "true")])
+            .send()
+            .await?
+            .error_for_status()?;
+        if let (Some(budget), Some(length)) = (&budget, response.content_length()) {
+            budget.check(length)?;
+        }
+        tokio::fs::create_dir_all(&config.upload_cache).await?;
+        let mut file = tokio::fs::File::create(&archive).await?;
+        while let Some(chunk) = response.chunk().await? {
+            if let Some(budget) = &mut budget {
+                budget.spend(chunk.len() as u64)?;
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        let library_folder = config.libraries_path.join(&subscription.folder);
+        let model = import_thing(config, connection, &archive, &library_folder).await?;
+        if let Some(owner) = owner {
+            let size = quota::dir_size(&model.absolute_path(config));
+            quota::record_upload(connection, owner.id, model.id, size).await?;
+        }
+        anyhow::Ok(model)
+    }
+    .await;
+    if let Err(e) = tokio::fs::remove_file(&archive).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove {}: {}", archive.display(), e);
+        }
+    }
+    result
+}
+
+/// Pulls the public models added to the other instance since the last
+/// sync, newest first. Failed models are retried by the next sync, paging
+/// stops at the first page holding only models mirrored before.
+pub async fn sync_subscription(
+    state: &AppState,
+    subscription: &Subscription,
+    cancel: &CancellationToken,
+) -> anyhow::Result<ImportSummary> {
+    let config = &state.config;
+    let client = client(config)?;
+    let source = subscription.source();
+    let owner = match subscription.user_id {
+        Some(user_id) => {
+            let mut connection = state.pool.get().await?;
+            users::dsl::users
+                .find(user_id)
+                .select(User::as_select())
+                .first::<User>(&mut connection)
+                .await
+                .optional()?
+        }
+        None => None,
+    };
+    let mut summary = ImportSummary::default();
+    let mut cursor: Option<String> = None;
+
+    loop {
+        check_cancelled(cancel)?;
+        let limit = PAGE_SIZE.to_string();
+        let mut request = client
+            .get(format!(
+                "{}{}/models/recent",
+                subscription.url, CURRENT_PREFIX
+            ))
+            .query(&[("sort", "added"), ("limit", limit.as_str())]);
+        if let Some(cursor) = &cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        let page: RemotePage = request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Envelope<RemotePage>>()
+            .await?
+            .data;
+
+        let mut known = 0;
+        for remote in &page.models {
+            check_cancelled(cancel)?;
+            let mut connection = state.pool.get().await?;
+            let status = thing_imports::dsl::thing_imports
+                .find((&source, &remote.slug))
+                .select(thing_imports::dsl::status)
+                .first::<String>(&mut connection)
+                .await
+                .optional()?;
+            if status.as_deref() == Some(IMPORTED) {
+                summary.skipped += 1;
+                known += 1;
+                continue;
+            }
+
+            let result = pull_model(
+                config,
+                &mut connection,
+                &client,
+                subscription,
+                owner.as_ref(),
+                &remote.slug,
+            )
+            .await;
+            record_item(&mut connection, &source, &remote.slug, &result).await?;
+            match result {
+                Ok(model) => {
+                    debug!("Mirrored {} as {}", remote.slug, model.slug);
+                    summary.imported += 1;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to mirror {} of {}: {:#}",
+                        remote.slug, subscription.url, e
+                    );
+                    summary.failed += 1;
+                    summary.errors.push(ImportItemError {
+                        item: remote.slug.clone(),
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+
+        match page.next_cursor {
+            Some(next) if known < page.models.len() => cursor = Some(next),
+            _ => break,
+        }
+    }
+
+    info!(
+        "Mirrored {} models of {}, {} known, {} failed",
+        summary.imported, subscription.url, summary.skipped, summary.failed
+    );
+    Ok(summary)
+}
+
+async fn record_sync<Conn>(
+    connection: &mut Conn,
+    subscription: &Subscription,
+    result: &anyhow::Result<ImportSummary>,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::update(subscriptions::dsl::subscriptions.find(subscription.id))
+        .set((
+            subscriptions::dsl::last_synced_at.eq(Utc::now().naive_utc()),
+            subscriptions::dsl::last_error.eq(result.as_ref().err().map(|e| format!("{:#}", e))),
+        ))
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// Syncs the given subscriptions one after the other, a failing instance
+/// does not keep the others from being synced.
+pub async fn sync_subscriptions(
+    state: &AppState,
+    subscriptions: Vec<Subscription>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<ImportSummary> {
+    let mut total = ImportSummary::default();
+    for subscription in subscriptions {
+        check_cancelled(cancel)?;
+        let result = sync_subscription(state, &subscription, cancel).await;
+        let mut connection = state.pool.get().await?;
+        record_sync(&mut connection, &subscription, &result).await?;
+        match result {
+            Ok(summary) => {
+                total.imported += summary.imported;
+                total.skipped += summary.skipped;
+                total.failed += summary.failed;
+                total.errors.extend(summary.errors);
+            }
+            Err(e) if e.is::<crate::jobs::Cancelled>() => return Err(e),
+            Err(e) => {
+                error!("Syncing {} failed: {:#}", subscription.url, e);
+                total.errors.push(ImportItemError {
+                    item: subscription.url.clone(),
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+    }
+    if total.imported > 0 {
+        state.cache.invalidate();
+        state
+            .notifier
+            .notify(crate::notifications::Notification::LibraryChanged);
+    }
+    Ok(total)
+}
+
+pub async fn all_subscriptions<Conn>(connection: &mut Conn) -> QueryResult<Vec<Subscription>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    subscriptions::dsl::subscriptions
+        .order(subscriptions::dsl::title)
+        .load::<Subscription>(connection)
+        .await
+}
+
+fn start_sync(state: AppState, subscriptions: Vec<Subscription>) -> ImportStarted {
+    let source = match subscriptions.as_slice() {
+        [subscription] => subscription.source(),
+        _ => "sync".to_string(),
+    };
+    let (job_id, cancel) = state.jobs.start("subscription_sync");
+    tokio::spawn(async move {
+        let result = sync_subscriptions(&state, subscriptions, &cancel).await;
+        if let Ok(summary) = &result {
+            state.jobs.set_result(job_id, summary);
+        }
+        state.jobs.complete(job_id, &result);
+    });
+    ImportStarted {
+        job_id: job_id.to_string(),
+        source,
+    }
+}
+
+/// Adds a subscription of `user_id` unless one for the address exists
+/// already.
+async fn subscribe<Conn>(
+    connection: &mut Conn,
+    url: &str,
+    title: Option<&str>,
+    folder: &str,
+    user_id: Option<i32>,
+) -> QueryResult<Subscription>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let title = title
+        .map(str::trim)
+        .filter(|title| !title.is_empty())
+        .unwrap_or(url);
+    diesel::insert_or_ignore_into(subscriptions::table)
+        .values((
+            subscriptions::dsl::url.eq(url),
+            subscriptions::dsl::title.eq(title),
+            subscriptions::dsl::folder.eq(folder),
+            subscriptions::dsl::created_at.eq(Utc::now().naive_utc()),
+            subscriptions::dsl::user_id.eq(user_id),
+        ))
+        .execute(connection)
+        .await?;
+    subscriptions::dsl::subscriptions
+        .filter(subscriptions::dsl::url.eq(url))
+        .first::<Subscription>(connection)
+        .await
+}
+
+async fn to_responses<Conn>(
+    connection: &mut Conn,
+    subscriptions: Vec<Subscription>,
+) -> QueryResult<Vec<SubscriptionResponse>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let imported: HashMap<String, i64> = thing_imports::dsl::thing_imports
+        .filter(thing_imports::dsl::source.like("sync:%"))
+        .filter(thing_imports::dsl::status.eq(IMPORTED))
+        .group_by(thing_imports::dsl::source)
+        .select((thing_imports::dsl::source, diesel::dsl::count_star()))
+        .load::<(String, i64)>(connection)
+        .await?
+        .into_iter()
+        .collect();
+    Ok(subscriptions
+        .into_iter()
+        .map(|subscription| SubscriptionResponse {
+            imported: imported
+                .get(&subscription.source())
+                .copied()
+                .unwrap_or_default() as i32,
+            id: subscription.id,
+            url: subscription.url,
+            title: subscription.title,
+            folder: subscription.folder,
+            created_at: subscription.created_at,
+            last_synced_at: subscription.last_synced_at,
+            last_error: subscription.last_error,
+        })
+        .collect())
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The feeds of an OPML document as address, title and folder. `htmlUrl`
+/// names the instance, `xmlUrl` its listing if exported by another tool.
+fn parse_opml(opml: &str) -> Vec<(String, Option<String>, Option<String>)> {
+    OPML_OUTLINE
+        .captures_iter(opml)
+        .filter_map(|outline| {
+            let attributes: HashMap<String, String> = XML_ATTRIBUTE
+                .captures_iter(&outline[1])
+                .map(|attribute| (attribute[1].to_string(), unescape_xml(&attribute[2])))
+                .collect();
+            let url = attributes
+                .get("htmlUrl")
+                .or(attributes.get("xmlUrl"))
+                .and_then(|url| {
+                    let url = url.split("/api/").next().unwrap_or(url);
+                    normalize_url(url)
+                })?;
+            let title = attributes.get("title").or(attributes.get("text")).cloned();
+            Some((url, title, attributes.get("folder").cloned()))
+        })
+        .collect()
+}
+
+fn opml(subscriptions: &[Subscription]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n  <head>\n    <title>ModelVault subscriptions</title>\n  </head>\n  <body>\n",
+    );
+    for subscription in subscriptions {
+        xml.push_str(&format!(
+            "    <outline type=\"modelvault\" text=\"{title}\" title=\"{title}\" htmlUrl=\"{url}\" xmlUrl=\"{url}{prefix}/models/recent\" folder=\"{folder}\"/>\n",
+            title = escape_xml(&subscription.title),
+            url = escape_xml(&subscription.url),
+            prefix = CURRENT_PREFIX,
+            folder = escape_xml(&subscription.folder),
+        ));
+    }
+    xml.push_str("  </body>\n</opml>\n");
+    xml
+}
+
+pub async fn handle_list_subscriptions(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Json<Vec<SubscriptionResponse>>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let subscriptions = all_subscriptions(&mut connection).await.map_err(|e| {
+        error!("Failed to load subscriptions: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(
+        to_responses(&mut connection, subscriptions)
+            .await
+            .map_err(|e| {
+                error!("Failed to count mirrored models: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+    ))
+}
+
+/// Subscribes to the public models of another instance, admins only. The
+/// first sync starts right away.
+pub async fn handle_subscribe(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Json(payload): Json<Subscribe>,
+) -> Result<Json<ImportStarted>, StatusCode> {
+    let url = normalize_url(&payload.url).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    let folder =
+        normalize_folder(payload.folder.as_deref()).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+
+    let mut connection = state.connection().await?;
+    let subscription = subscribe(
+        &mut connection,
+        &url,
+        payload.title.as_deref(),
+        &folder,
+        viewer.user.as_ref().map(|user| user.id),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to store the subscription: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    record(
+        &mut connection,
+        &viewer,
+        "subscription.create",
+        &url,
+        json!({ "folder": subscription.folder }),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to record the subscription: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    drop(connection);
+
+    Ok(Json(start_sync(state, vec![subscription])))
+}
+
+/// Ends a subscription. Mirrored models stay in the library.
+pub async fn handle_unsubscribe(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, StatusCode> {
+    let mut connection = state.connection().await?;
+    let subscription = subscriptions::dsl::subscriptions
+        .find(id)
+        .first::<Subscription>(&mut connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load the subscription: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    diesel::delete(subscriptions::dsl::subscriptions.find(id))
+        .execute(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete the subscription: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    record(
+        &mut connection,
+        &viewer,
+        "subscription.delete",
+        &subscription.url,
+        json!({}),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to record the subscription: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Pulls new models of one subscription now, instead of waiting for the
+/// `sync_schedule`.
+pub async fn handle_sync(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Path(id): Path<i32>,
+) -> Result<Json<ImportStarted>, StatusCode> {
+    let mut connection = state.connection().await?;
+    let subscription = subscriptions::dsl::subscriptions
+        .find(id)
+        .first::<Subscription>(&mut connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load the subscription: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    drop(connection);
+    Ok(Json(start_sync(state, vec![subscription])))
+}
+
+/// The subscriptions as an OPML document, to subscribe another instance
+/// to the same collections.
+pub async fn handle_export_opml(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Response, StatusCode> {
+    let mut connection = state.connection().await?;
+    let subscriptions = all_subscriptions(&mut connection).await.map_err(|e| {
+        error!("Failed to load subscriptions: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/x-opml; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"subscriptions.opml\"",
+            ),
+        ],
+        opml(&subscriptions),
+    )
+        .into_response())
+}
+
+/// Subscribes to every instance of an OPML document not subscribed yet and
+/// syncs them. Outlines without a folder use the library root.
+pub async fn handle_import_opml(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    body: String,
+) -> Result<Json<ImportStarted>, StatusCode> {
+    let feeds = parse_opml(&body);
+    if feeds.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let mut connection = state.connection().await?;
+    let mut subscriptions = Vec::new();
+    for (url, title, folder) in feeds {
+        let Some(folder) = normalize_folder(folder.as_deref()) else {
+            warn!("Skipping {} of the OPML, invalid folder", url);
+            continue;
+        };
+        subscriptions.push(
+            subscribe(
+                &mut connection,
+                &url,
+                title.as_deref(),
+                &folder,
+                viewer.user.as_ref().map(|user| user.id),
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to store the subscription: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+        );
+    }
+    record(
+        &mut connection,
+        &viewer,
+        "subscription.import",
+        "opml",
+        json!({ "urls": subscriptions.iter().map(|s| &s.url).collect::<Vec<_>>() }),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to record the subscriptions: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    drop(connection);
+
+    Ok(Json(start_sync(state, subscriptions)))
+}
@@ -0,0 +1,251 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use tracing::{debug, error};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::etag::library_version;
+use crate::model_filter::filter_models;
+use crate::schema::{files3d, models3d};
+use crate::types::{comma_separated_to_pathbuf_vec, ListModelParams};
+use crate::{AppState, Config};
+
+/// Candidates taken from the index before hidden models are filtered out.
+const CANDIDATES: usize = 50;
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub slug: String,
+    pub name: String,
+    pub title: String,
+    pub thumbnail: Option<String>,
+}
+
+struct Index {
+    version: u64,
+    /// lowercase words of names and titles, sorted for prefix lookups
+    words: Vec<(String, i32)>,
+    suggestions: HashMap<i32, Suggestion>,
+}
+
+/// Words of model names and titles mapped to the models, kept in memory and
+/// rebuilt whenever the library changes.
+#[derive(Clone, Default)]
+pub struct SuggestIndex {
+    index: Arc<RwLock<Option<Index>>>,
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+async fn build_index<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    version: u64,
+) -> QueryResult<Index>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let models = models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_null())
+        .select((
+            models3d::dsl::id,
+            models3d::dsl::slug,
+            models3d::dsl::name,
+            models3d::dsl::title,
+            models3d::dsl::folder_path,
+            models3d::dsl::images,
+        ))
+        .load::<(i32, String, String, String, String, String)>(connection)
+        .await?;
+
+    let mut previews: HashMap<i32, String> = HashMap::new();
+    for (model_id, preview_image) in files3d::dsl::files3d
+        .filter(files3d::dsl::preview_image.is_not_null())
+        .order(files3d::dsl::id)
+        .select((files3d::dsl::model_id, files3d::dsl::preview_image))
+        .load::<(i32, Option<String>)>(connection)
+        .await?
+    {
+        if let Some(preview_image) = preview_image {
+            previews.entry(model_id).or_insert(preview_image);
+        }
+    }
+
+    let mut words_of_models = Vec::new();
+    let mut suggestions = HashMap::new();
+    for (id, slug, name, title, folder_path, images) in models {
+        let mut model_words: HashSet<String> = words(&name).collect();
+        model_words.extend(words(&title));
+        words_of_models.extend(model_words.into_iter().map(|word| (word, id)));
+
+        // the same image the model card shows first
+        let thumbnail = match comma_separated_to_pathbuf_vec(&images).into_iter().next() {
+            Some(image) => Some(format!(
+                "{}/{}/{}",
+                config.asset_prefix,
+                folder_path,
+                image.to_string_lossy()
+            )),
+            None => previews
+                .remove(&id)
+                .map(|preview| format!("{}/{}", config.cache_prefix, preview)),
+        };
+        suggestions.insert(
+            id,
+            Suggestion {
+                slug,
+                name,
+                title,
+                thumbnail,
+            },
+        );
+    }
+    words_of_models.sort();
+
+    Ok(Index {
+        version,
+        words: words_of_models,
+        suggestions,
+    })
+}
+
+impl Index {
+    /// Models having a word starting with every word of `q`, those whose
+    /// title starts with the query first.
+    fn lookup(&self, q: &str) -> Vec<i32> {
+        let prefixes: Vec<String> = words(q).collect();
+        let mut matching: Option<HashSet<i32>> = None;
+        for prefix in &prefixes {
+            let start = self
+                .words
+                .partition_point(|(word, _)| word.as_str() < prefix.as_str());
+            let ids: HashSet<i32> = self.words[start..]
+                .iter()
+                .take_while(|(word, _)| word.starts_with(prefix.as_str()))
+                .map(|(_, id)| *id)
+                .collect();
+            matching = Some(match matching {
+                Some(matching) => matching.intersection(&ids).copied().collect(),
+                None => ids,
+            });
+        }
+
+        let q = q.trim().to_lowercase();
+        let mut ids: Vec<i32> = matching.unwrap_or_default().into_iter().collect();
+        ids.sort_by_cached_key(|id| {
+            let title = self.suggestions[id].title.to_lowercase();
+            (!title.starts_with(&q), title.len(), title)
+        });
+        ids
+    }
+}
+
+impl SuggestIndex {
+    async fn lookup<Conn>(
+        &self,
+        config: &Config,
+        connection: &mut Conn,
+        q: &str,
+    ) -> QueryResult<Vec<Suggestion>>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let mut hasher = DefaultHasher::new();
+        library_version(connection).await?.hash(&mut hasher);
+        let version = hasher.finish();
+
+        let is_current =
+            matches!(&*self.index.read().unwrap(), Some(index) if index.version == version);
+        if !is_current {
+            debug!("Rebuilding the suggestion index");
+            let index = build_index(config, connection, version).await?;
+            *self.index.write().unwrap() = Some(index);
+        }
+
+        let index = self.index.read().unwrap();
+        let Some(index) = index.as_ref() else {
+            return Ok(Vec::new());
+        };
+        Ok(index
+            .lookup(q)
+            .into_iter()
+            .take(CANDIDATES)
+            .map(|id| index.suggestions[&id].clone())
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SuggestParams {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+/// Models whose name or title words start with the typed words, for an
+/// instant search box. Answers from memory, only visibility is checked
+/// against the database.
+pub async fn handle_suggest(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(params): Query<SuggestParams>,
+) -> Result<Json<Vec<Suggestion>>, StatusCode> {
+    let limit = params.limit.unwrap_or(8).clamp(1, 20) as usize;
+    if params.q.trim().is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+    let mut connection = state.connection().await?;
+
+    let candidates = state
+        .suggest
+        .lookup(&state.config, &mut connection, &params.q)
+        .await
+        .map_err(|e| {
+            error!("Failed to look up suggestions: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if candidates.is_empty() {
+        return Ok(Json(candidates));
+    }
+
+    let visible: HashSet<String> = filter_models(&ListModelParams::default(), &viewer)?
+        .filter(
+            models3d::dsl::slug.eq_any(
+                candidates
+                    .iter()
+                    .map(|suggestion| suggestion.slug.clone())
+                    .collect::<Vec<_>>(),
+            ),
+        )
+        .select(models3d::dsl::slug)
+        .load::<String>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to check visible suggestions: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .collect();
+
+    Ok(Json(
+        candidates
+            .into_iter()
+            .filter(|suggestion| visible.contains(&suggestion.slug))
+            .take(limit)
+            .collect(),
+    ))
+}
@@ -0,0 +1,547 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::dsl::{InnerJoin, IntoBoxed};
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::audit_log::record;
+use crate::parse_library::update_modelpack_meta;
+use crate::policy::{Admin, Authorized};
+use crate::schema::{model_tags, models3d, tags};
+use crate::types::Model3D;
+use crate::{AppState, Config};
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagCount {
+    pub name: String,
+    pub count: i32,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagPair {
+    pub a: String,
+    pub b: String,
+    /// number of models carrying both tags
+    pub count: i32,
+}
+
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TagStats {
+    pub tags: Vec<TagCount>,
+    /// only computed with `co_occurrence=true`
+    pub co_occurrence: Option<Vec<TagPair>>,
+}
+
+#[derive(Deserialize)]
+pub struct TagStatsParams {
+    #[serde(default)]
+    pub co_occurrence: bool,
+    /// pairs seen on fewer models are left out
+    pub min_count: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct SuggestTagsParams {
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Tags are compared lower case with collapsed whitespace, so `Desk  Organizer`
+/// and `desk organizer` are the same tag.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!tag.is_empty()).then(|| tag.to_lowercase())
+}
+
+pub fn normalize_tags(tags: &[String]) -> BTreeSet<String> {
+    tags.iter().filter_map(|tag| normalize_tag(tag)).collect()
+}
+
+/// Escapes `%`, `_` and `\` for use in a `LIKE` pattern with `\` as escape.
+pub fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub async fn get_model_tags<Conn>(connection: &mut Conn, model_id: i32) -> QueryResult<Vec<String>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    model_tags::dsl::model_tags
+        .inner_join(tags::table)
+        .filter(model_tags::dsl::model_id.eq(model_id))
+        .select(tags::dsl::name)
+        .order(tags::dsl::name.asc())
+        .load::<String>(connection)
+        .await
+}
+
+/// Replaces the tags of a model, creating missing tags and dropping tags no
+/// model uses anymore.
+pub async fn set_model_tags<Conn>(
+    connection: &mut Conn,
+    model_id: i32,
+    tag_names: &[String],
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let tag_names: Vec<String> = normalize_tags(tag_names).into_iter().collect();
+
+    for name in &tag_names {
+        diesel::insert_or_ignore_into(tags::table)
+            .values(tags::dsl::name.eq(name))
+            .execute(connection)
+            .await?;
+    }
+
+    let tag_ids: Vec<i32> = tags::dsl::tags
+        .filter(tags::dsl::name.eq_any(&tag_names))
+        .select(tags::dsl::id)
+        .load::<i32>(connection)
+        .await?;
+
+    diesel::delete(
+        model_tags::dsl::model_tags
+            .filter(model_tags::dsl::model_id.eq(model_id))
+            .filter(model_tags::dsl::tag_id.ne_all(&tag_ids)),
+    )
+    .execute(connection)
+    .await?;
+
+    for tag_id in &tag_ids {
+        diesel::insert_or_ignore_into(model_tags::table)
+            .values((
+                model_tags::dsl::model_id.eq(model_id),
+                model_tags::dsl::tag_id.eq(*tag_id),
+            ))
+            .execute(connection)
+            .await?;
+    }
+
+    delete_unused_tags(connection).await?;
+    Ok(())
+}
+
+pub async fn delete_unused_tags<Conn>(connection: &mut Conn) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::delete(
+        tags::dsl::tags.filter(
+            tags::dsl::id.ne_all(
+                model_tags::dsl::model_tags
+                    .select(model_tags::dsl::tag_id)
+                    .distinct(),
+            ),
+        ),
+    )
+    .execute(connection)
+    .await
+}
+
+/// Tag assignments of all models visible to the viewer.
+fn scoped_model_tags(
+    viewer: &Viewer,
+) -> IntoBoxed<'static, InnerJoin<InnerJoin<model_tags::table, tags::table>, models3d::table>, Sqlite>
+{
+    let mut query = model_tags::dsl::model_tags
+        .inner_join(tags::table)
+        .inner_join(models3d::table)
+        .filter(models3d::dsl::deleted_at.is_null())
+        .into_boxed();
+    if let Some(tenant_id) = viewer.scope.tenant_id() {
+        query = query.filter(models3d::dsl::tenant_id.eq(tenant_id));
+    }
+    if let Some(visible_ids) = viewer.visible_ids() {
+        query = query.filter(models3d::dsl::id.eq_any(visible_ids));
+    }
+    query
+}
+
+/// Counts the models of each tag, most used first and alphabetically among
+/// equally used ones.
+fn count_tags(names: Vec<String>) -> Vec<TagCount> {
+    let mut counts: BTreeMap<String, i32> = BTreeMap::new();
+    for name in names {
+        *counts.entry(name).or_default() += 1;
+    }
+    let mut tags: Vec<TagCount> = counts
+        .into_iter()
+        .map(|(name, count)| TagCount { name, count })
+        .collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count));
+    tags
+}
+
+/// Counts model pairs sharing two tags. Pairs are ordered alphabetically so
+/// every combination shows up once.
+fn co_occurrence(model_tags: Vec<(i32, String)>, min_count: i32) -> Vec<TagPair> {
+    let mut tags_by_model: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+    for (model_id, tag) in model_tags {
+        tags_by_model.entry(model_id).or_default().push(tag);
+    }
+
+    let mut pairs: BTreeMap<(String, String), i32> = BTreeMap::new();
+    for mut tags in tags_by_model.into_values() {
+        tags.sort();
+        for (i, a) in tags.iter().enumerate() {
+            for b in &tags[i + 1..] {
+                *pairs.entry((a.clone(), b.clone())).or_default() += 1;
+            }
+        }
+    }
+
+    let mut pairs: Vec<TagPair> = pairs
+        .into_iter()
+        .filter(|(_, count)| *count >= min_count)
+        .map(|((a, b), count)| TagPair { a, b, count })
+        .collect();
+    pairs.sort_by(|x, y| y.count.cmp(&x.count));
+    pairs
+}
+
+pub async fn handle_tag_stats(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(params): Query<TagStatsParams>,
+) -> Result<Json<TagStats>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let names = scoped_model_tags(&viewer)
+        .select(tags::dsl::name)
+        .load::<String>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to count tags: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let tags = count_tags(names);
+
+    let co_occurrence = if params.co_occurrence {
+        let model_tags = scoped_model_tags(&viewer)
+            .select((model_tags::dsl::model_id, tags::dsl::name))
+            .load::<(i32, String)>(&mut connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to load model tags: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        Some(co_occurrence(model_tags, params.min_count.unwrap_or(1)))
+    } else {
+        None
+    };
+
+    Ok(Json(TagStats {
+        tags,
+        co_occurrence,
+    }))
+}
+
+pub async fn handle_suggest_tags(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(params): Query<SuggestTagsParams>,
+) -> Result<Json<Vec<TagCount>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let prefix = params
+        .q
+        .as_deref()
+        .and_then(normalize_tag)
+        .unwrap_or_default();
+    let limit = params.limit.unwrap_or(10).clamp(1, 100) as usize;
+
+    let names = scoped_model_tags(&viewer)
+        .filter(
+            tags::dsl::name
+                .like(format!("{}%", escape_like(&prefix)))
+                .escape('\\'),
+        )
+        .select(tags::dsl::name)
+        .load::<String>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to suggest tags: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut suggestions = count_tags(names);
+    suggestions.truncate(limit);
+    Ok(Json(suggestions))
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenameTag {
+    pub name: String,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeTag {
+    /// tag the models of the merged tag are moved to, created if missing
+    pub into: String,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagOperationResult {
+    pub tag: String,
+    /// number of models whose tags changed
+    pub models: i32,
+}
+
+async fn find_tag<Conn>(connection: &mut Conn, name: &str) -> Result<(i32, Vec<i32>), StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let name = normalize_tag(name).ok_or(StatusCode::NOT_FOUND)?;
+    let tag_id = tags::dsl::tags
+        .filter(tags::dsl::name.eq(&name))
+        .select(tags::dsl::id)
+        .first::<i32>(connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load tag {}: {}", name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let model_ids = model_tags::dsl::model_tags
+        .filter(model_tags::dsl::tag_id.eq(tag_id))
+        .select(model_tags::dsl::model_id)
+        .load::<i32>(connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load models tagged {}: {}", name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok((tag_id, model_ids))
+}
+
+/// Writes the tags of models back to their modelpack.json, so a refresh
+/// does not bring back what a tag operation changed.
+async fn sync_modelpack_tags<Conn>(config: &Config, connection: &mut Conn, model_ids: &[i32])
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let models = match models3d::dsl::models3d
+        .filter(models3d::dsl::id.eq_any(model_ids))
+        .load::<Model3D>(connection)
+        .await
+    {
+        Ok(models) => models,
+        Err(e) => {
+            error!("Failed to load retagged models: {}", e);
+            return;
+        }
+    };
+    for model in models {
+        let result = match get_model_tags(connection, model.id).await {
+            Ok(tags) => {
+                let dir = model.absolute_path(config);
+                update_modelpack_meta(config, &dir, |meta| meta.tags = Some(tags))
+                    .await
+                    .map(|_| ())
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!("Failed to write tags of {}: {}", model.slug, e);
+        }
+        if let Err(e) = model.touch(connection).await {
+            error!("Failed to touch {}: {}", model.slug, e);
+        }
+    }
+}
+
+/// Renames a tag on every model. Renaming onto an existing tag is a merge and
+/// rejected.
+pub async fn handle_rename_tag(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Path(name): Path<String>,
+    Json(payload): Json<RenameTag>,
+) -> Result<Json<TagOperationResult>, StatusCode> {
+    let new_name = normalize_tag(&payload.name).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    let mut connection = state.connection().await?;
+
+    let (tag_id, model_ids) = find_tag(&mut connection, &name).await?;
+    let old_name = normalize_tag(&name).unwrap_or_default();
+    if new_name == old_name {
+        return Ok(Json(TagOperationResult {
+            tag: new_name,
+            models: 0,
+        }));
+    }
+    match find_tag(&mut connection, &new_name).await {
+        Ok(_) => return Err(StatusCode::CONFLICT),
+        Err(StatusCode::NOT_FOUND) => {}
+        Err(status) => return Err(status),
+    }
+
+    let (viewer, old_name, new_name, models) = (&viewer, &old_name, &new_name, model_ids.len());
+    (*connection)
+        .transaction::<_, diesel::result::Error, _>(|connection| {
+            async move {
+                diesel::update(tags::dsl::tags.find(tag_id))
+                    .set(tags::dsl::name.eq(new_name))
+                    .execute(connection)
+                    .await?;
+                record(
+                    connection,
+                    viewer,
+                    "tag.rename",
+                    old_name,
+                    json!({ "name": new_name, "models": models }),
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to rename tag {}: {}", old_name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    sync_modelpack_tags(&state.config, &mut connection, &model_ids).await;
+    Ok(Json(TagOperationResult {
+        tag: new_name.clone(),
+        models: models as i32,
+    }))
+}
+
+/// Moves all models of a tag to another one and removes the merged tag.
+pub async fn handle_merge_tag(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Path(name): Path<String>,
+    Json(payload): Json<MergeTag>,
+) -> Result<Json<TagOperationResult>, StatusCode> {
+    let into = normalize_tag(&payload.into).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    let mut connection = state.connection().await?;
+
+    let (tag_id, model_ids) = find_tag(&mut connection, &name).await?;
+    let old_name = normalize_tag(&name).unwrap_or_default();
+    if into == old_name {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let (viewer, old_name, into_ref, model_ids_ref) = (&viewer, &old_name, &into, &model_ids);
+    (*connection)
+        .transaction::<_, diesel::result::Error, _>(|connection| {
+            async move {
+                diesel::insert_or_ignore_into(tags::table)
+                    .values(tags::dsl::name.eq(into_ref))
+                    .execute(connection)
+                    .await?;
+                let into_id = tags::dsl::tags
+                    .filter(tags::dsl::name.eq(into_ref))
+                    .select(tags::dsl::id)
+                    .first::<i32>(connection)
+                    .await?;
+                for model_id in model_ids_ref.iter() {
+                    diesel::insert_or_ignore_into(model_tags::table)
+                        .values((
+                            model_tags::dsl::model_id.eq(*model_id),
+                            model_tags::dsl::tag_id.eq(into_id),
+                        ))
+                        .execute(connection)
+                        .await?;
+                }
+                diesel::delete(
+                    model_tags::dsl::model_tags.filter(model_tags::dsl::tag_id.eq(tag_id)),
+                )
+                .execute(connection)
+                .await?;
+                diesel::delete(tags::dsl::tags.find(tag_id))
+                    .execute(connection)
+                    .await?;
+                record(
+                    connection,
+                    viewer,
+                    "tag.merge",
+                    old_name,
+                    json!({ "into": into_ref, "models": model_ids_ref.len() }),
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to merge tag {} into {}: {}", old_name, into, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    sync_modelpack_tags(&state.config, &mut connection, &model_ids).await;
+    Ok(Json(TagOperationResult {
+        tag: into,
+        models: model_ids.len() as i32,
+    }))
+}
+
+/// Removes a tag from every model.
+pub async fn handle_delete_tag(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Path(name): Path<String>,
+) -> Result<Json<TagOperationResult>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let (tag_id, model_ids) = find_tag(&mut connection, &name).await?;
+    let old_name = normalize_tag(&name).unwrap_or_default();
+
+    let (viewer, old_name_ref, models) = (&viewer, &old_name, model_ids.len());
+    (*connection)
+        .transaction::<_, diesel::result::Error, _>(|connection| {
+            async move {
+                diesel::delete(
+                    model_tags::dsl::model_tags.filter(model_tags::dsl::tag_id.eq(tag_id)),
+                )
+                .execute(connection)
+                .await?;
+                diesel::delete(tags::dsl::tags.find(tag_id))
+                    .execute(connection)
+                    .await?;
+                record(
+                    connection,
+                    viewer,
+                    "tag.delete",
+                    old_name_ref,
+                    json!({ "models": models }),
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to delete tag {}: {}", old_name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    sync_modelpack_tags(&state.config, &mut connection, &model_ids).await;
+    Ok(Json(TagOperationResult {
+        tag: old_name,
+        models: models as i32,
+    }))
+}
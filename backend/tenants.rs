@@ -0,0 +1,185 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::error;
+
+use crate::auth::CurrentUser;
+use crate::schema::{files3d, models3d, tenants};
+use crate::slug::find_model_by_slug;
+use crate::types::{File3D, Model3D};
+use crate::{AppState, Config};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = tenants)]
+pub struct Tenant {
+    pub id: i32,
+    pub name: String,
+}
+
+/// What a request may see. Without `multi_tenant` everything is visible,
+/// otherwise only the models below the library root of the tenant the
+/// authenticated user belongs to.
+#[derive(Debug, Clone)]
+pub enum TenantScope {
+    All,
+    Tenant(Tenant),
+}
+
+/// Resolves the tenant of a user from `tenant_members` entries like
+/// `club:alice|bob`. Users without an entry get a personal tenant named
+/// after themselves.
+pub fn tenant_name_for_user(config: &Config, user: &str) -> String {
+    config
+        .tenant_members
+        .iter()
+        .filter_map(|entry| entry.split_once(':'))
+        .find(|(_, members)| members.split('|').any(|member| member.trim() == user))
+        .map(|(tenant, _)| tenant.trim().to_string())
+        .unwrap_or_else(|| user.to_string())
+}
+
+/// The tenant owning a model folder is the first component of its path.
+pub fn tenant_name_for_folder(config: &Config, folder_path: &Path) -> Option<String> {
+    if !config.multi_tenant {
+        return None;
+    }
+    folder_path
+        .components()
+        .next()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+}
+
+pub async fn ensure_tenant<Conn>(connection: &mut Conn, name: &str) -> QueryResult<Tenant>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::insert_or_ignore_into(tenants::table)
+        .values(tenants::dsl::name.eq(name))
+        .execute(connection)
+        .await?;
+
+    tenants::dsl::tenants
+        .filter(tenants::dsl::name.eq(name))
+        .first::<Tenant>(connection)
+        .await
+}
+
+/// Stores the tenant derived from the folder of a model.
+pub async fn assign_tenant<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let tenant_id = match tenant_name_for_folder(config, Path::new(&model.folder_path)) {
+        Some(name) => Some(ensure_tenant(connection, &name).await?.id),
+        None => None,
+    };
+
+    if tenant_id != model.tenant_id {
+        diesel::update(models3d::dsl::models3d.find(model.id))
+            .set(models3d::dsl::tenant_id.eq(tenant_id))
+            .execute(connection)
+            .await?;
+    }
+    Ok(())
+}
+
+impl TenantScope {
+    pub fn tenant_id(&self) -> Option<i32> {
+        match self {
+            TenantScope::All => None,
+            TenantScope::Tenant(tenant) => Some(tenant.id),
+        }
+    }
+
+    pub fn allows(&self, model: &Model3D) -> bool {
+        match self {
+            TenantScope::All => true,
+            TenantScope::Tenant(tenant) => model.tenant_id == Some(tenant.id),
+        }
+    }
+
+    /// Folder new uploads of this scope are placed in.
+    pub fn library_root(&self, config: &Config) -> PathBuf {
+        match self {
+            TenantScope::All => config.libraries_path.clone(),
+            TenantScope::Tenant(tenant) => config.libraries_path.join(&tenant.name),
+        }
+    }
+
+    /// Looks a model up by slug, hiding models of other tenants.
+    pub async fn find_model<Conn>(
+        &self,
+        connection: &mut Conn,
+        slug: &str,
+    ) -> Result<Model3D, StatusCode>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        find_model_by_slug(connection, slug)
+            .await
+            .ok()
+            .filter(|model| self.allows(model))
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+
+    /// Looks a file up by id together with its model, hiding files of other
+    /// tenants.
+    pub async fn find_file<Conn>(
+        &self,
+        connection: &mut Conn,
+        id: i32,
+    ) -> Result<(File3D, Model3D), StatusCode>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let (file, model) = files3d::dsl::files3d
+            .inner_join(models3d::table)
+            .filter(files3d::dsl::id.eq(id))
+            .select((File3D::as_select(), Model3D::as_select()))
+            .first::<(File3D, Model3D)>(connection)
+            .await
+            .map_err(|_| StatusCode::NOT_FOUND)?;
+
+        if self.allows(&model) {
+            Ok((file, model))
+        } else {
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for TenantScope {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if !state.config.multi_tenant {
+            return Ok(TenantScope::All);
+        }
+
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+        let name = tenant_name_for_user(&state.config, &user);
+
+        let mut connection = state.connection().await?;
+        let tenant = ensure_tenant(&mut connection, &name).await.map_err(|e| {
+            error!("Failed to load tenant {}: {}", name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        Ok(TenantScope::Tenant(tenant))
+    }
+}
@@ -0,0 +1,808 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+use typeshare::typeshare;
+use uuid::Uuid;
+
+use crate::audit_log::record;
+use crate::file_types::file_type_of;
+use crate::jobs::check_cancelled;
+use crate::layout;
+use crate::manifest::{read_manifest, ModelManifest, MANIFEST_FILE, MANIFEST_VERSION};
+use crate::parse_library::{add_or_update_model, get_modelpack_meta};
+use crate::policy::{Admin, Authorized};
+use crate::schema::{models3d, thing_imports};
+use crate::types::{Model3D, ModelPackV0_1};
+use crate::upload::install_new_folder;
+use crate::workers;
+use crate::{AppState, Config};
+
+pub const IMPORTED: &str = "imported";
+const FAILED: &str = "failed";
+
+/// Files of the exports describing a thing rather than being part of it.
+const THING_METADATA_FILES: &[&str] = &["readme.txt", "license.txt", "attribution_card.html"];
+
+const DOC_FILE_TYPES: &[&str] = &["pdf", "txt", "md", "html"];
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportThings {
+    /// archive or directory below `import_dir`
+    pub path: String,
+    /// library folder the models are created in, the library root if unset
+    pub folder: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportStarted {
+    pub job_id: String,
+    pub source: String,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportItemError {
+    pub item: String,
+    pub error: String,
+}
+
+#[typeshare]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub imported: i32,
+    /// things imported by an earlier run of the same source
+    pub skipped: i32,
+    pub failed: i32,
+    pub errors: Vec<ImportItemError>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportEntriesParams {
+    pub source: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThingImportEntry {
+    pub source: String,
+    pub item: String,
+    /// `imported` or `failed`
+    pub status: String,
+    /// `None` for failed things and models deleted since
+    pub slug: Option<String>,
+    pub error: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+/// What the metadata of a thing maps to.
+#[derive(Debug, Default)]
+struct ThingMeta {
+    title: String,
+    author: String,
+    origin: String,
+    license: String,
+    description: String,
+    tags: Vec<String>,
+}
+
+fn import_root(config: &Config) -> PathBuf {
+    config
+        .import_dir
+        .clone()
+        .unwrap_or_else(|| config.data_dir.join("import"))
+}
+
+/// Paths given by clients must stay below their base directory.
+pub fn is_relative_below(path: &str) -> bool {
+    let path = Path::new(path);
+    !path.as_os_str().is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+fn lower_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn is_symlink_entry(entry: &zip::read::ZipFile) -> bool {
+    entry
+        .unix_mode()
+        .is_some_and(|mode| mode & 0o170000 == 0o120000)
+}
+
+/// Extracts the regular files of an archive entry by entry. Entries
+/// escaping the target and symlinks are skipped, so nothing written here
+/// points outside of `target`.
+async fn extract_zip(archive: &Path, target: &Path) -> anyhow::Result<()> {
+    let (source, destination) = (archive.to_path_buf(), target.to_path_buf());
+    tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let file = std::fs::File::open(&source)?;
+        let mut zip = zip::ZipArchive::new(file)?;
+        for index in 0..zip.len() {
+            let mut entry = zip.by_index(index)?;
+            let Some(relative) = entry.enclosed_name() else {
+                warn!("Skipped {} escaping the archive", entry.name());
+                continue;
+            };
+            if entry.is_dir() {
+                continue;
+            }
+            if is_symlink_entry(&entry) {
+                warn!("Skipped symlink {} in the archive", entry.name());
+                continue;
+            }
+            let path = destination.join(relative);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut output = std::fs::File::create(&path)?;
+            std::io::copy(&mut entry, &mut output)?;
+        }
+        Ok(())
+    })
+    .await?
+    .with_context(|| format!("Failed to extract {}", archive.display()))
+}
+
+fn sorted_entries(dir: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| !lower_name(path).starts_with('.') && lower_name(path) != "__macosx")
+                .collect()
+        })
+        .unwrap_or_default();
+    entries.sort();
+    entries
+}
+
+/// Exports are often wrapped in a single folder named like the archive.
+fn unwrap_folders(mut dir: PathBuf) -> PathBuf {
+    loop {
+        let entries = sorted_entries(&dir);
+        match entries.as_slice() {
+            [only] if only.is_dir() => dir = only.clone(),
+            _ => return dir,
+        }
+    }
+}
+
+/// A folder holding the files of a single thing rather than one thing per
+/// entry. Descriptions and images alone may as well belong to the export.
+fn is_thing_dir(dir: &Path) -> bool {
+    sorted_entries(dir).iter().any(|entry| {
+        let file_type = file_type_of(entry);
+        (entry.is_dir() && lower_name(entry) == "files")
+            || (entry.is_file()
+                && !is_zip(entry)
+                && !layout::is_image(entry)
+                && file_type != "json"
+                && !DOC_FILE_TYPES.contains(&file_type.as_str()))
+    })
+}
+
+/// The things of an export, either a single thing or one folder or zip
+/// per thing.
+fn thing_items(root: &Path) -> Vec<PathBuf> {
+    if is_thing_dir(root) {
+        vec![root.to_path_buf()]
+    } else {
+        sorted_entries(root)
+            .into_iter()
+            .filter(|entry| entry.is_dir() || is_zip(entry))
+            .collect()
+    }
+}
+
+/// Looks up the first of several dotted paths holding a non empty string.
+fn string_at(value: &Value, paths: &[&str]) -> Option<String> {
+    paths.iter().find_map(|path| {
+        path.split('.')
+            .try_fold(value, |value, key| value.get(key))
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    })
+}
+
+/// Tags come as plain strings or as objects with a name.
+fn tags_of(value: &Value) -> Vec<String> {
+    value
+        .get("tags")
+        .and_then(Value::as_array)
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|tag| match tag {
+                    Value::String(tag) => Some(tag.clone()),
+                    tag => string_at(tag, &["name", "tag"]),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The thing description of an export, the first JSON object naming the
+/// thing at the top of its folder.
+async fn read_thing_json(dir: &Path) -> Option<Value> {
+    for entry in sorted_entries(dir) {
+        if !entry.is_file() || !entry.extension().is_some_and(|ext| ext == "json") {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(&entry).await else {
+            continue;
+        };
+        match serde_json::from_str::<Value>(&content) {
+            Ok(value) if string_at(&value, &["name", "title"]).is_some() => return Some(value),
+            Ok(_) => {}
+            Err(e) => debug!("Skipping {}: {}", entry.display(), e),
+        }
+    }
+    None
+}
+
+/// Metadata of a thing from its JSON description, completed with the
+/// README.txt and LICENSE.txt both sites put into their downloads.
+async fn read_thing_meta(dir: &Path, item_name: &str) -> ThingMeta {
+    let mut meta = ThingMeta::default();
+
+    if let Some(value) = read_thing_json(dir).await {
+        meta.title = string_at(&value, &["name", "title"]).unwrap_or_default();
+        meta.author = string_at(
+            &value,
+            &[
+                "creator.name",
+                "creator.username",
+                "creator",
+                "user.publicUsername",
+                "user.name",
+                "author.name",
+                "author",
+            ],
+        )
+        .unwrap_or_default();
+        meta.origin = string_at(&value, &["public_url", "url", "link"]).unwrap_or_default();
+        meta.license = string_at(&value, &["license.name", "license.abbreviation", "license"])
+            .unwrap_or_default();
+        meta.description = string_at(&value, &["description", "summary"]).unwrap_or_default();
+        meta.tags = tags_of(&value);
+    }
+
+    if let Ok(readme) = fs::read_to_string(dir.join("README.txt")).await {
+        let header = Regex::new(r"^(.+?) by (.+?) on (?:Thingiverse|Printables)\S*:\s*(\S+)")
+            .expect("valid regex");
+        let mut lines = readme.lines();
+        if let Some(captures) = lines.next().and_then(|line| header.captures(line.trim())) {
+            if meta.title.is_empty() {
+                meta.title = captures[1].trim().to_string();
+            }
+            if meta.author.is_empty() {
+                meta.author = captures[2].trim().to_string();
+            }
+            if meta.origin.is_empty() {
+                meta.origin = captures[3].to_string();
+            }
+            if meta.description.is_empty() {
+                meta.description = lines.collect::<Vec<_>>().join("\n").trim().to_string();
+            }
+        }
+    }
+
+    if meta.license.is_empty() {
+        if let Ok(license) = fs::read_to_string(dir.join("LICENSE.txt")).await {
+            let licensed =
+                Regex::new(r"(?i)licensed under the\s+(.+?)\s+license").expect("valid regex");
+            if let Some(captures) = licensed.captures(&license) {
+                meta.license = captures[1].trim().to_string();
+            }
+        }
+    }
+
+    if meta.title.is_empty() {
+        meta.title = Path::new(item_name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| item_name.to_string());
+    }
+    meta
+}
+
+fn first_dir(dirs: &[String], default: &str) -> String {
+    dirs.first().cloned().unwrap_or_else(|| default.to_string())
+}
+
+/// Sorts the files of a thing into the configured layout of a new model
+/// folder with a modelpack.json and README.md.
+async fn prepare_model_folder(
+    config: &Config,
+    thing: &Path,
+    meta: &ThingMeta,
+    target: &Path,
+) -> anyhow::Result<()> {
+    let pack = ModelPackV0_1 {
+        version: "0.1".to_string(),
+        title: meta.title.clone(),
+        author: meta.author.clone(),
+        origin: meta.origin.clone(),
+        license: meta.license.clone(),
+        tags: (!meta.tags.is_empty()).then(|| meta.tags.clone()),
+    };
+    fs::create_dir_all(target).await?;
+    fs::write(
+        target.join("modelpack.json"),
+        serde_json::to_string_pretty(&pack)?,
+    )
+    .await?;
+    if !meta.description.is_empty() {
+        fs::write(target.join("README.md"), &meta.description).await?;
+    }
+
+    let files_dir = target.join(first_dir(&config.layout_files_dirs, "files"));
+    let images_dir = target.join(first_dir(&config.layout_images_dirs, "images"));
+    let docs_dir = target.join(first_dir(&config.layout_docs_dirs, "docs"));
+    let mut count = 0;
+    // links are reported as links rather than followed, and skipped
+    for entry in walkdir::WalkDir::new(thing)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let path = entry.path();
+        let name = lower_name(path);
+        let is_top_level = path.parent() == Some(thing);
+        if name.starts_with('.')
+            || (is_top_level
+                && (THING_METADATA_FILES.contains(&name.as_str())
+                    || path.extension().is_some_and(|ext| ext == "json")))
+        {
+            continue;
+        }
+        let relative = path.strip_prefix(thing)?;
+        // the folders of the export are replaced by those of the layout
+        let relative = match relative.components().next() {
+            Some(Component::Normal(first))
+                if ["files", "images", "docs"]
+                    .contains(&first.to_string_lossy().to_lowercase().as_str()) =>
+            {
+                relative.strip_prefix(first)?
+            }
+            _ => relative,
+        };
+        let dir = if layout::is_image(path) {
+            &images_dir
+        } else if DOC_FILE_TYPES.contains(&file_type_of(path).as_str()) {
+            &docs_dir
+        } else {
+            &files_dir
+        };
+        let destination = dir.join(relative);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::copy(path, &destination)
+            .await
+            .with_context(|| format!("Failed to copy {}", path.display()))?;
+        count += 1;
+    }
+    if count == 0 {
+        anyhow::bail!("No files in {}", thing.display());
+    }
+    Ok(())
+}
+
+fn manifest_meta(manifest: &ModelManifest) -> ThingMeta {
+    ThingMeta {
+        title: manifest.title.clone(),
+        author: manifest.author.clone().unwrap_or_default(),
+        origin: manifest.origin.clone().unwrap_or_default(),
+        license: manifest.license.clone().unwrap_or_default(),
+        description: manifest.description.clone(),
+        tags: manifest.tags.clone(),
+    }
+}
+
+/// Model folders exported by ModelVault are taken over as they are, once
+/// every file matches the hash in their manifest.
+async fn copy_exported_folder(
+    thing: &Path,
+    manifest: &ModelManifest,
+    target: &Path,
+) -> anyhow::Result<()> {
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!("Unsupported manifest version {}", manifest.version);
+    }
+    for file in &manifest.files {
+        let path = thing.join(&file.path);
+        let is_file = fs::symlink_metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_file());
+        if !is_relative_below(&file.path) || !is_file {
+            anyhow::bail!("{} is missing", file.path);
+        }
+        if let Some(expected) = &file.sha256 {
+            let actual = workers::digest(&path)
+                .await
+                .with_context(|| format!("Unable to read {}", file.path))?;
+            if &actual != expected {
+                anyhow::bail!("{} does not match its checksum", file.path);
+            }
+        }
+    }
+
+    let (source, destination) = (thing.to_path_buf(), target.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        // fs_extra follows links, so folders holding any are refused
+        for entry in walkdir::WalkDir::new(&source) {
+            let entry = entry?;
+            if entry.path_is_symlink() {
+                anyhow::bail!("{} is a symlink", entry.path().display());
+            }
+        }
+        std::fs::create_dir_all(&destination)?;
+        let options = fs_extra::dir::CopyOptions::new().content_only(true);
+        fs_extra::dir::copy(&source, &destination, &options)?;
+        anyhow::Ok(())
+    })
+    .await??;
+    fs::remove_file(target.join(MANIFEST_FILE)).await?;
+    Ok(())
+}
+
+/// A free folder name for a thing. A folder holding a model of the same
+/// source was created by an interrupted earlier run and is reused.
+async fn target_folder(parent: &Path, meta: &ThingMeta) -> (PathBuf, bool) {
+    let name = sanitize_filename::sanitize(&meta.title);
+    let mut counter = 1;
+    loop {
+        let candidate = match counter {
+            1 => parent.join(&name),
+            n => parent.join(format!("{} ({})", name, n)),
+        };
+        if !candidate.exists() {
+            return (candidate, false);
+        }
+        if !meta.origin.is_empty() {
+            if let Ok(pack) = get_modelpack_meta(&candidate).await {
+                if pack.origin == meta.origin {
+                    return (candidate, true);
+                }
+            }
+        }
+        counter += 1;
+    }
+}
+
+/// Creates a model from a folder or zip of a thing, or from a folder
+/// exported by ModelVault with its manifest.
+pub async fn import_thing<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    item: &Path,
+    library_folder: &Path,
+) -> anyhow::Result<Model3D>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let work_dir = config.upload_cache.join(Uuid::new_v4().to_string());
+    let result = async {
+        let thing = if is_zip(item) {
+            let extracted = work_dir.join("thing");
+            extract_zip(item, &extracted).await?;
+            unwrap_folders(extracted)
+        } else {
+            item.to_path_buf()
+        };
+        let item_name = item
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let manifest = read_manifest(&thing).await;
+        let meta = match &manifest {
+            Some(manifest) => manifest_meta(manifest),
+            None => read_thing_meta(&thing, &item_name).await,
+        };
+
+        fs::create_dir_all(library_folder).await?;
+        let (final_path, exists) = target_folder(library_folder, &meta).await;
+        if exists {
+            info!("{} was imported before", final_path.display());
+        } else {
+            let prepared = work_dir.join("model");
+            match &manifest {
+                Some(manifest) => copy_exported_folder(&thing, manifest, &prepared).await?,
+                None => prepare_model_folder(config, &thing, &meta, &prepared).await?,
+            }
+            install_new_folder(&prepared, &final_path).await?;
+        }
+
+        let model = add_or_update_model(config, connection, &final_path).await?;
+        model.scan(config, connection).await?;
+        anyhow::Ok(model)
+    }
+    .await;
+    if work_dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&work_dir).await {
+            warn!("Failed to remove {}: {}", work_dir.display(), e);
+        }
+    }
+    result
+}
+
+pub async fn record_item<Conn>(
+    connection: &mut Conn,
+    source: &str,
+    item: &str,
+    result: &anyhow::Result<Model3D>,
+) -> QueryResult<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let (status, model_id, error) = match result {
+        Ok(model) => (IMPORTED, Some(model.id), None),
+        Err(e) => (FAILED, None, Some(format!("{:#}", e))),
+    };
+    let updated_at = Utc::now().naive_utc();
+    let values = || {
+        (
+            thing_imports::dsl::status.eq(status),
+            thing_imports::dsl::model_id.eq(model_id),
+            thing_imports::dsl::error.eq(error.clone()),
+            thing_imports::dsl::updated_at.eq(updated_at),
+        )
+    };
+    diesel::insert_into(thing_imports::table)
+        .values((
+            thing_imports::dsl::source.eq(source),
+            thing_imports::dsl::item.eq(item),
+            values(),
+        ))
+        .on_conflict((thing_imports::dsl::source, thing_imports::dsl::item))
+        .do_update()
+        .set(values())
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// Keeps the import records of a deleted model, so the thing is not
+/// imported again, but drops the link to the model.
+pub async fn unlink_imports<Conn>(connection: &mut Conn, model_id: i32) -> QueryResult<usize>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::update(
+        thing_imports::dsl::thing_imports.filter(thing_imports::dsl::model_id.eq(model_id)),
+    )
+    .set(thing_imports::dsl::model_id.eq(None::<i32>))
+    .execute(connection)
+    .await
+}
+
+/// Imports every thing of an export not imported by an earlier run of the
+/// same source, failed things are retried.
+pub async fn run_import(
+    state: &AppState,
+    source: &str,
+    library_folder: &Path,
+    cancel: &CancellationToken,
+) -> anyhow::Result<ImportSummary> {
+    let config = &state.config;
+    let path = import_root(config).join(source);
+    let work_dir = config.upload_cache.join(Uuid::new_v4().to_string());
+
+    let root = if is_zip(&path) {
+        extract_zip(&path, &work_dir).await?;
+        unwrap_folders(work_dir.clone())
+    } else {
+        unwrap_folders(path.clone())
+    };
+
+    let mut summary = ImportSummary::default();
+    let result = async {
+        for item in thing_items(&root) {
+            check_cancelled(cancel)?;
+            let item_name = item
+                .strip_prefix(&root)
+                .ok()
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .map(|relative| relative.to_string_lossy().into_owned())
+                .unwrap_or_else(|| lower_name(&path));
+
+            let mut connection = state.pool.get().await?;
+            let status = thing_imports::dsl::thing_imports
+                .find((source, &item_name))
+                .select(thing_imports::dsl::status)
+                .first::<String>(&mut connection)
+                .await
+                .optional()?;
+            if status.as_deref() == Some(IMPORTED) {
+                summary.skipped += 1;
+                continue;
+            }
+
+            let result = import_thing(config, &mut connection, &item, library_folder).await;
+            record_item(&mut connection, source, &item_name, &result).await?;
+            match result {
+                Ok(model) => {
+                    debug!("Imported {} as {}", item_name, model.slug);
+                    summary.imported += 1;
+                }
+                Err(e) => {
+                    error!("Failed to import {}: {:#}", item_name, e);
+                    summary.failed += 1;
+                    summary.errors.push(ImportItemError {
+                        item: item_name,
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+        anyhow::Ok(())
+    }
+    .await;
+
+    if work_dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&work_dir).await {
+            warn!("Failed to remove {}: {}", work_dir.display(), e);
+        }
+    }
+    result?;
+    info!(
+        "Imported {} things of {}, {} skipped, {} failed",
+        summary.imported, source, summary.skipped, summary.failed
+    );
+    Ok(summary)
+}
+
+/// Starts importing a Thingiverse or Printables export lying below
+/// `import_dir`, admins only. Running it again for the same source
+/// resumes after the things imported before.
+pub async fn handle_import_things(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Json(payload): Json<ImportThings>,
+) -> Result<Json<ImportStarted>, StatusCode> {
+    let source = payload.path.trim().trim_matches('/').to_string();
+    if !is_relative_below(&source) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let path = import_root(&state.config).join(&source);
+    if !path.is_dir() && !is_zip(&path) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let library_folder = match payload
+        .folder
+        .as_deref()
+        .map(|folder| folder.trim_matches('/'))
+    {
+        Some(folder) if !folder.is_empty() => {
+            if !is_relative_below(folder) {
+                return Err(StatusCode::UNPROCESSABLE_ENTITY);
+            }
+            state.config.libraries_path.join(folder)
+        }
+        _ => state.config.libraries_path.clone(),
+    };
+
+    let mut connection = state.connection().await?;
+    record(
+        &mut connection,
+        &viewer,
+        "import.things",
+        &source,
+        json!({ "folder": payload.folder }),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to record import of {}: {}", source, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    drop(connection);
+
+    let (job_id, cancel) = state.jobs.start("thing_import");
+    let job_source = source.clone();
+    tokio::spawn(async move {
+        let result = run_import(&state, &job_source, &library_folder, &cancel).await;
+        match &result {
+            Ok(summary) => state.jobs.set_result(job_id, summary),
+            Err(e) => error!("Importing {} failed: {}", job_source, e),
+        }
+        state.jobs.complete(job_id, &result);
+        if result.is_ok() {
+            state
+                .notifier
+                .notify(crate::notifications::Notification::LibraryChanged);
+        }
+    });
+
+    Ok(Json(ImportStarted {
+        job_id: job_id.to_string(),
+        source,
+    }))
+}
+
+/// Things handled by imports, failed ones with their error.
+pub async fn handle_list_imports(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+    Query(params): Query<ImportEntriesParams>,
+) -> Result<Json<Vec<ThingImportEntry>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let mut query = thing_imports::dsl::thing_imports
+        .left_join(models3d::table)
+        .select((
+            thing_imports::dsl::source,
+            thing_imports::dsl::item,
+            thing_imports::dsl::status,
+            models3d::dsl::slug.nullable(),
+            thing_imports::dsl::error,
+            thing_imports::dsl::updated_at,
+        ))
+        .order((
+            thing_imports::dsl::source.asc(),
+            thing_imports::dsl::item.asc(),
+        ))
+        .into_boxed();
+    if let Some(source) = params.source {
+        query = query.filter(thing_imports::dsl::source.eq(source));
+    }
+
+    let entries = query
+        .load::<(
+            String,
+            String,
+            String,
+            Option<String>,
+            Option<String>,
+            NaiveDateTime,
+        )>(&mut connection)
+        .await
+        .map_err(|e| {
+            error!("Failed to load imports: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(
+        entries
+            .into_iter()
+            .map(
+                |(source, item, status, slug, error, updated_at)| ThingImportEntry {
+                    source,
+                    item,
+                    status,
+                    slug,
+                    error,
+                    updated_at,
+                },
+            )
+            .collect(),
+    ))
+}
@@ -0,0 +1,255 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::error;
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::auth::user_name;
+use crate::schema::{audit_log, download_counts, files3d, print_logs, slug_redirects, users};
+use crate::types::Model3D;
+use crate::AppState;
+
+const CURSOR_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+/// Files found this long after the model was added were added later on,
+/// the ones before came with the model.
+const ADDED_WITH_MODEL: Duration = Duration::minutes(1);
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineKind {
+    Added,
+    FilesChanged,
+    Printed,
+    Downloaded,
+    /// published to a sharing site
+    Shared,
+    /// any other recorded change, e.g. a restore from the trash or a merge
+    Changed,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineEvent {
+    /// unique within the timeline of the model, e.g. `print-12`
+    pub id: String,
+    pub kind: TimelineKind,
+    pub at: NaiveDateTime,
+    /// who did it, for changes only shown to those who may manage the model
+    pub actor: Option<String>,
+    /// files added, or downloads on the day
+    pub count: Option<i32>,
+    /// the printed file
+    pub file_name: Option<String>,
+    /// whether the print succeeded
+    pub success: Option<bool>,
+    /// the recorded action of shared and changed events, e.g. `model.merge`
+    pub action: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineResponse {
+    /// latest first
+    pub events: Vec<TimelineEvent>,
+    /// pass as `cursor` to fetch the next page, missing on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TimelineParams {
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+impl TimelineEvent {
+    fn new(id: String, kind: TimelineKind, at: NaiveDateTime) -> Self {
+        TimelineEvent {
+            id,
+            kind,
+            at,
+            actor: None,
+            count: None,
+            file_name: None,
+            success: None,
+            action: None,
+        }
+    }
+}
+
+/// A position in the timeline, the time of the last returned event and its
+/// id to break ties between events of the same instant.
+fn encode_cursor(at: NaiveDateTime, id: &str) -> String {
+    format!("{}_{}", at.format(CURSOR_TIME_FORMAT), id)
+}
+
+fn decode_cursor(cursor: &str) -> Option<(NaiveDateTime, String)> {
+    let (at, id) = cursor.split_once('_')?;
+    let at = NaiveDateTime::parse_from_str(at, CURSOR_TIME_FORMAT).ok()?;
+    Some((at, id.to_string()))
+}
+
+fn start_of(day: NaiveDate) -> NaiveDateTime {
+    day.and_time(NaiveTime::MIN)
+}
+
+/// Everything that happened to `model`, in no particular order. Files only
+/// tell when they were added, removed files leave no trace.
+async fn collect_events<Conn>(
+    connection: &mut Conn,
+    model: &Model3D,
+    show_actors: bool,
+) -> QueryResult<Vec<TimelineEvent>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let mut events = Vec::new();
+
+    if let Some(date_added) = model.date_added {
+        let mut added = TimelineEvent::new("added".to_string(), TimelineKind::Added, date_added);
+        added.actor = user_name(connection, model.uploader_id).await?;
+        events.push(added);
+    }
+
+    let file_dates: Vec<NaiveDateTime> = files3d::dsl::files3d
+        .filter(files3d::dsl::model_id.eq(model.id))
+        .filter(files3d::dsl::date_added.is_not_null())
+        .select(files3d::dsl::date_added.assume_not_null())
+        .load(connection)
+        .await?;
+    let later_than = model.date_added.map(|at| at + ADDED_WITH_MODEL);
+    let mut files_by_day: BTreeMap<NaiveDate, (NaiveDateTime, i32)> = BTreeMap::new();
+    for at in file_dates {
+        if later_than.is_some_and(|later_than| at <= later_than) {
+            continue;
+        }
+        let day = files_by_day.entry(at.date()).or_insert((at, 0));
+        day.0 = day.0.max(at);
+        day.1 += 1;
+    }
+    for (day, (at, count)) in files_by_day {
+        let mut changed =
+            TimelineEvent::new(format!("files-{}", day), TimelineKind::FilesChanged, at);
+        changed.count = Some(count);
+        events.push(changed);
+    }
+
+    let prints: Vec<(i32, NaiveDateTime, bool, Option<String>, Option<String>)> =
+        print_logs::dsl::print_logs
+            .left_join(files3d::table)
+            .left_join(users::table)
+            .filter(print_logs::dsl::model_id.eq(model.id))
+            .select((
+                print_logs::dsl::id,
+                print_logs::dsl::printed_at,
+                print_logs::dsl::success,
+                files3d::dsl::file_path.nullable(),
+                users::dsl::name.nullable(),
+            ))
+            .load(connection)
+            .await?;
+    for (id, at, success, file_name, user) in prints {
+        let mut printed = TimelineEvent::new(format!("print-{}", id), TimelineKind::Printed, at);
+        printed.actor = user;
+        printed.file_name = file_name;
+        printed.success = Some(success);
+        events.push(printed);
+    }
+
+    let downloads: Vec<(NaiveDate, i32)> = download_counts::dsl::download_counts
+        .filter(download_counts::dsl::model_id.eq(model.id))
+        .select((download_counts::dsl::day, download_counts::dsl::downloads))
+        .load(connection)
+        .await?;
+    for (day, count) in downloads {
+        let mut downloaded = TimelineEvent::new(
+            format!("downloads-{}", day),
+            TimelineKind::Downloaded,
+            start_of(day),
+        );
+        downloaded.count = Some(count);
+        events.push(downloaded);
+    }
+
+    // changes are recorded by slug, also under the ones the model had before
+    let mut slugs: Vec<String> = slug_redirects::dsl::slug_redirects
+        .filter(slug_redirects::dsl::model_id.eq(model.id))
+        .select(slug_redirects::dsl::slug)
+        .load(connection)
+        .await?;
+    slugs.push(model.slug.clone());
+    let changes: Vec<(i32, NaiveDateTime, String, String)> = audit_log::dsl::audit_log
+        .filter(audit_log::dsl::target.eq_any(slugs))
+        .filter(audit_log::dsl::action.like("model.%"))
+        .select((
+            audit_log::dsl::id,
+            audit_log::dsl::created_at,
+            audit_log::dsl::actor,
+            audit_log::dsl::action,
+        ))
+        .load(connection)
+        .await?;
+    for (id, at, actor, action) in changes {
+        let kind = match action.as_str() {
+            "model.publish" => TimelineKind::Shared,
+            _ => TimelineKind::Changed,
+        };
+        let mut changed = TimelineEvent::new(format!("change-{}", id), kind, at);
+        changed.actor = Some(actor).filter(|_| show_actors);
+        changed.action = Some(action);
+        events.push(changed);
+    }
+
+    Ok(events)
+}
+
+/// What happened to a model, from being added over prints and downloads to
+/// changes like merges and publications, latest first.
+pub async fn handle_timeline(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(slug): Path<String>,
+    Query(params): Query<TimelineParams>,
+) -> Result<Json<TimelineResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100) as usize;
+    let cursor = match &params.cursor {
+        Some(cursor) => Some(decode_cursor(cursor).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+
+    let model = viewer.find_model(&mut connection, &slug).await?;
+    let mut events = collect_events(&mut connection, &model, viewer.can_manage(&model))
+        .await
+        .map_err(|e| {
+            error!("Failed to load the timeline of {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if let Some((at, id)) = &cursor {
+        events.retain(|event| event.at < *at || (event.at == *at && event.id < *id));
+    }
+    events.sort_by(|a, b| b.at.cmp(&a.at).then_with(|| b.id.cmp(&a.id)));
+
+    let has_more = events.len() > limit;
+    events.truncate(limit);
+    let next_cursor = events
+        .last()
+        .filter(|_| has_more)
+        .map(|last| encode_cursor(last.at, &last.id));
+
+    Ok(Json(TimelineResponse {
+        events,
+        next_cursor,
+    }))
+}
@@ -0,0 +1,305 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, error, info};
+use typeshare::typeshare;
+
+use crate::access::Viewer;
+use crate::audit_log::record;
+use crate::move_model::move_folder;
+use crate::policy::{Admin, Authorized};
+use crate::schema::{files3d, models3d};
+use crate::slug::{change_slug, unique_slug};
+use crate::types::{DetailedModelResponse, Model3D};
+use crate::{AppState, Config};
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrashedModel {
+    pub id: i32,
+    pub title: String,
+    pub name: String,
+    pub slug: String,
+    pub folder_path: String,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub purge_after: Option<NaiveDateTime>,
+}
+
+impl From<&Model3D> for TrashedModel {
+    fn from(model: &Model3D) -> Self {
+        Self {
+            id: model.id,
+            title: model.title.clone(),
+            name: model.name.clone(),
+            slug: model.slug.clone(),
+            folder_path: model.folder_path.clone(),
+            deleted_at: model.deleted_at,
+            purge_after: model.purge_after,
+        }
+    }
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub models: Vec<TrashedModel>,
+}
+
+pub async fn trashed_models<Conn>(connection: &mut Conn) -> anyhow::Result<Vec<Model3D>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    Ok(models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_not_null())
+        .order(models3d::dsl::deleted_at.asc())
+        .load::<Model3D>(connection)
+        .await?)
+}
+
+pub async fn expired_models<Conn>(connection: &mut Conn) -> anyhow::Result<Vec<Model3D>>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    Ok(models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_not_null())
+        .filter(models3d::dsl::purge_after.le(chrono::Utc::now().naive_utc()))
+        .load::<Model3D>(connection)
+        .await?)
+}
+
+/// Permanently removes a trashed model including its files from disk.
+pub async fn purge_model<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> anyhow::Result<()>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    diesel::delete(files3d::dsl::files3d.filter(files3d::dsl::model_id.eq(model.id)))
+        .execute(connection)
+        .await?;
+    model.delete(config, connection).await
+}
+
+/// The latest trashed model with `slug`.
+async fn find_trashed<Conn>(connection: &mut Conn, slug: &str) -> Result<Model3D, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    models3d::dsl::models3d
+        .filter(models3d::dsl::deleted_at.is_not_null())
+        .filter(models3d::dsl::slug.eq(slug))
+        .order(models3d::dsl::deleted_at.desc())
+        .first::<Model3D>(connection)
+        .await
+        .optional()
+        .map_err(|e| {
+            error!("Failed to load trashed model {}: {}", slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Moves a trashed model back to its folder and reactivates its row. The
+/// model is rescanned, which regenerates previews that were evicted from the
+/// cache in the meantime.
+pub async fn restore_model<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    model: &Model3D,
+) -> Result<Model3D, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let trash_path = model.trash_path.as_ref().ok_or(StatusCode::CONFLICT)?;
+    let from = config.trash_dir.join(trash_path);
+    let to = model.absolute_path(config);
+    // another folder took its place in the library
+    if tokio::fs::try_exists(&to).await.unwrap_or(true) {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    move_folder(&from, &to).await.map_err(|e| {
+        error!("Failed to restore {}: {}", from.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    debug!("Restored {} to {}", from.display(), to.display());
+
+    let restored = diesel::update(models3d::dsl::models3d.find(model.id))
+        .set((
+            models3d::dsl::deleted_at.eq(None::<NaiveDateTime>),
+            models3d::dsl::purge_after.eq(None::<NaiveDateTime>),
+            models3d::dsl::trash_path.eq(None::<String>),
+        ))
+        .execute(connection)
+        .await;
+    if let Err(e) = restored {
+        error!("Failed to reactivate {}: {}", model.slug, e);
+        if let Err(e) = move_folder(&to, &from).await {
+            error!("Failed to move {} back: {}", to.display(), e);
+        }
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let slug_result: QueryResult<()> = async {
+        // the slug may redirect to another model by now, e.g. after a merge
+        let slug = unique_slug(connection, &model.slug, Some(model.id)).await?;
+        change_slug(connection, model, &slug).await
+    }
+    .await;
+    if let Err(e) = slug_result {
+        error!("Failed to assign a slug to {}: {}", model.slug, e);
+    }
+
+    let model = models3d::dsl::models3d
+        .find(model.id)
+        .first::<Model3D>(connection)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if let Err(e) = model.scan(config, connection).await {
+        error!("Failed to rescan restored model {}: {}", model.slug, e);
+    }
+    Ok(model)
+}
+
+pub async fn purge_expired<Conn>(
+    config: &Config,
+    connection: &mut Conn,
+    dry_run: bool,
+) -> anyhow::Result<RetentionReport>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let models = expired_models(connection).await?;
+
+    if !dry_run {
+        for model in &models {
+            purge_model(config, connection, model).await?;
+        }
+        if !models.is_empty() {
+            info!("Purged {} expired models from the trash", models.len());
+        }
+    }
+
+    Ok(RetentionReport {
+        dry_run,
+        models: models.iter().map(TrashedModel::from).collect(),
+    })
+}
+
+pub async fn handle_list_trash(
+    State(state): State<AppState>,
+    viewer: Viewer,
+) -> Result<Json<Vec<TrashedModel>>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let models = trashed_models(&mut connection).await.map_err(|e| {
+        error!("Failed to load trash: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(
+        models
+            .iter()
+            .filter(|model| viewer.can_manage(model))
+            .map(TrashedModel::from)
+            .collect(),
+    ))
+}
+
+pub async fn handle_retention_report(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Json<RetentionReport>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let report = purge_expired(&state.config, &mut connection, true)
+        .await
+        .map_err(|e| {
+            error!("Failed to build retention report: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(report))
+}
+
+pub async fn handle_run_retention(
+    State(state): State<AppState>,
+    _: Authorized<Admin>,
+) -> Result<Json<RetentionReport>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let report = purge_expired(&state.config, &mut connection, false)
+        .await
+        .map_err(|e| {
+            error!("Failed to purge trash: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(report))
+}
+
+pub async fn handle_restore_model(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Path(slug): Path<String>,
+) -> Result<Json<DetailedModelResponse>, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let model = find_trashed(&mut connection, &slug).await?;
+    let restored = restore_model(&state.config, &mut connection, &model).await?;
+    if let Err(e) = record(
+        &mut connection,
+        &viewer,
+        "model.restore",
+        &restored.slug,
+        json!({ "folder_path": restored.folder_path, "trashed_slug": slug }),
+    )
+    .await
+    {
+        error!("Failed to record restore of {}: {}", restored.slug, e);
+    }
+
+    let response = DetailedModelResponse::from_model_3d(&restored, &state.config, &mut connection)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(response))
+}
+
+/// Permanently removes a trashed model before its retention expires.
+pub async fn handle_purge_model(
+    State(state): State<AppState>,
+    Authorized(viewer, _): Authorized<Admin>,
+    Path(slug): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let mut connection = state.connection().await?;
+
+    let model = find_trashed(&mut connection, &slug).await?;
+    purge_model(&state.config, &mut connection, &model)
+        .await
+        .map_err(|e| {
+            error!("Failed to purge {}: {}", model.slug, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if let Err(e) = record(
+        &mut connection,
+        &viewer,
+        "model.purge",
+        &model.slug,
+        json!({ "title": model.title, "folder_path": model.folder_path }),
+    )
+    .await
+    {
+        error!("Failed to record purge of {}: {}", model.slug, e);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
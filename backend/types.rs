@@ -1,7 +1,24 @@
-use std::path::PathBuf;
-
+use std::path::{Path, PathBuf};
+
+use crate::auth::user_name;
+use crate::client_api::delete_recent_files;
+use crate::designers::designer_slug;
+use crate::documents::{document_kind, is_document, DocumentKind};
+use crate::downloads::delete_downloads;
+use crate::file_types::DownloadKind;
+use crate::history::delete_history;
+use crate::link_checks::delete_link_check;
+use crate::names::{fold_name, normalize_name};
 use crate::parse_library::{add_or_update_model, clean_file_system, load_files_and_preview};
-use crate::schema::{files3d, models3d};
+use crate::print_logs::delete_print_logs;
+use crate::quota::delete_uploads;
+use crate::schema::{
+    files3d, model_embeddings, model_events, model_grants, model_tags, models3d, slug_redirects,
+};
+use crate::slug::slugify;
+use crate::sort_key::sort_key;
+use crate::tags::{delete_unused_tags, get_model_tags};
+use crate::thing_import::unlink_imports;
 use crate::Config;
 use anyhow::{Error, Result};
 use chrono::NaiveDateTime;
@@ -12,9 +29,16 @@ use std::str::FromStr;
 use tracing::{debug, error};
 use typeshare::typeshare;
 
+use crate::cad::{is_cad_file_type, CadMetadata};
 use crate::convert;
-
-fn comma_separated_to_pathbuf_vec(input: &str) -> Vec<PathBuf> {
+use crate::gcode::GcodeMetadata;
+use crate::layout;
+use crate::media::is_video_file_type;
+use crate::mesh_units::{check_scale, ScaleWarning};
+use crate::parts::{group_parts, PartGroup};
+use crate::slicer_settings::PrintSettings;
+
+pub fn comma_separated_to_pathbuf_vec(input: &str) -> Vec<PathBuf> {
     if input.trim().is_empty() {
         return Vec::new();
     }
@@ -74,6 +98,8 @@ pub struct ModelPackV0_1 {
     pub author: String,
     pub origin: String,
     pub license: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
 }
 
 #[typeshare]
@@ -90,6 +116,20 @@ pub struct Model3D {
     pub date_added: Option<NaiveDateTime>,
     pub images: String,
     pub description: String,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub purge_after: Option<NaiveDateTime>,
+    pub trash_path: Option<String>,
+    pub slug: String,
+    pub updated_at: Option<NaiveDateTime>,
+    pub tenant_id: Option<i32>,
+    pub visibility: String,
+    pub owner_id: Option<i32>,
+    pub notes: String,
+    pub uploader_id: Option<i32>,
+    pub sort_key: String,
+    pub designer_id: Option<i32>,
+    /// the title normalized and case folded, see `names::fold_name`
+    pub name_key: String,
 }
 
 impl Model3D {
@@ -110,20 +150,59 @@ impl Model3D {
         Ok(files)
     }
 
+    /// Marks the model as changed for the recently updated listing.
+    pub async fn touch<Conn>(&self, connection: &mut Conn) -> QueryResult<usize>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        diesel::update(models3d::dsl::models3d.find(self.id))
+            .set(models3d::dsl::updated_at.eq(Some(chrono::Utc::now().naive_utc())))
+            .execute(connection)
+            .await
+    }
+
     pub fn absolute_path(&self, config: &Config) -> PathBuf {
         let mut path = config.libraries_path.clone();
         path.push(&self.folder_path);
         path
     }
 
+    /// The first image of the model, or the rendered preview of its first
+    /// file without images.
+    pub async fn cover_image_path<Conn>(
+        &self,
+        config: &Config,
+        connection: &mut Conn,
+    ) -> QueryResult<Option<PathBuf>>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        if let Some(image) = self.relative_image_paths().into_iter().next() {
+            return Ok(Some(self.absolute_path(config).join(image)));
+        }
+        Ok(files3d::dsl::files3d
+            .filter(files3d::dsl::model_id.eq(self.id))
+            .filter(files3d::dsl::preview_image.is_not_null())
+            .order(files3d::dsl::id)
+            .select(files3d::dsl::preview_image)
+            .first::<Option<String>>(connection)
+            .await
+            .optional()?
+            .flatten()
+            .map(|preview| config.preview_cache_dir.join(preview)))
+    }
+
     pub async fn scan<Conn>(&self, config: &Config, connection: &mut Conn) -> anyhow::Result<()>
     where
         Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
     {
         add_or_update_model(config, connection, &self.absolute_path(config)).await?;
         let files = self.get_files3d(connection).await?;
-        clean_file_system(config, connection, files).await?;
-        load_files_and_preview(config, connection, self).await?;
+        let removed = clean_file_system(config, connection, files).await?;
+        let added = load_files_and_preview(config, connection, self).await?;
+        if !removed.is_empty() || !added.is_empty() {
+            self.touch(connection).await?;
+        }
 
         anyhow::Ok(())
     }
@@ -132,8 +211,47 @@ impl Model3D {
     where
         Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
     {
-        fs_extra::dir::remove(self.absolute_path(config));
-        debug!("Deleted {}", self.absolute_path(config).display());
+        let folder = match &self.trash_path {
+            Some(trash_path) => config.trash_dir.join(trash_path),
+            None => self.absolute_path(config),
+        };
+        fs_extra::dir::remove(&folder);
+        debug!("Deleted {}", folder.display());
+        diesel::delete(
+            slug_redirects::dsl::slug_redirects.filter(slug_redirects::dsl::model_id.eq(self.id)),
+        )
+        .execute(connection)
+        .await?;
+        diesel::delete(
+            model_events::dsl::model_events.filter(model_events::dsl::model_id.eq(self.id)),
+        )
+        .execute(connection)
+        .await?;
+        delete_downloads(connection, self.id).await?;
+        delete_print_logs(config, connection, self.id).await?;
+        diesel::delete(files3d::dsl::files3d.filter(files3d::dsl::model_id.eq(self.id)))
+            .execute(connection)
+            .await?;
+        delete_uploads(connection, self.id).await?;
+        unlink_imports(connection, self.id).await?;
+        delete_link_check(connection, self.id).await?;
+        delete_history(connection, self.id).await?;
+        delete_recent_files(connection, self.id).await?;
+        diesel::delete(
+            model_embeddings::dsl::model_embeddings
+                .filter(model_embeddings::dsl::model_id.eq(self.id)),
+        )
+        .execute(connection)
+        .await?;
+        diesel::delete(model_tags::dsl::model_tags.filter(model_tags::dsl::model_id.eq(self.id)))
+            .execute(connection)
+            .await?;
+        diesel::delete(
+            model_grants::dsl::model_grants.filter(model_grants::dsl::model_id.eq(self.id)),
+        )
+        .execute(connection)
+        .await?;
+        delete_unused_tags(connection).await?;
         diesel::delete(models3d::dsl::models3d.filter(models3d::dsl::id.eq(self.id)))
             .execute(connection)
             .await
@@ -141,6 +259,54 @@ impl Model3D {
 
         anyhow::Ok(())
     }
+
+    pub fn is_trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Moves the model folder into the trash and marks the row as deleted.
+    /// A `purge_after` of `None` keeps the model in the trash until it is
+    /// purged manually.
+    pub async fn trash<Conn>(
+        &self,
+        config: &Config,
+        connection: &mut Conn,
+        purge_after: Option<NaiveDateTime>,
+    ) -> anyhow::Result<()>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let trash_folder = format!("{}_{}", self.id, uuid::Uuid::new_v4());
+        let target = config.trash_dir.join(&trash_folder);
+        tokio::fs::create_dir_all(&config.trash_dir).await?;
+
+        if tokio::fs::rename(self.absolute_path(config), &target)
+            .await
+            .is_err()
+        {
+            // trash lives on another file system than the library
+            fs_extra::dir::move_dir(
+                self.absolute_path(config),
+                &target,
+                &fs_extra::dir::CopyOptions {
+                    copy_inside: true,
+                    ..fs_extra::dir::CopyOptions::new()
+                },
+            )?;
+        }
+        debug!("Trashed {} to {}", self.folder_path, target.display());
+
+        diesel::update(models3d::dsl::models3d.find(self.id))
+            .set((
+                models3d::dsl::deleted_at.eq(Some(chrono::Utc::now().naive_utc())),
+                models3d::dsl::purge_after.eq(purge_after),
+                models3d::dsl::trash_path.eq(Some(trash_folder)),
+            ))
+            .execute(connection)
+            .await?;
+
+        anyhow::Ok(())
+    }
 }
 
 #[typeshare]
@@ -149,11 +315,18 @@ pub struct ModelResponse {
     pub id: i32,
     pub title: String,
     pub name: String,
+    pub slug: String,
     pub license: Option<String>,
     pub author: Option<String>,
+    /// slug of the designer the author resolves to
+    pub designer: Option<String>,
     pub origin: Option<String>,
     pub images: Vec<String>,
     pub description: String,
+    pub tags: Vec<String>,
+    pub has_cad_source: bool,
+    pub owner: Option<String>,
+    pub uploader: Option<String>,
 }
 
 impl ModelResponse {
@@ -177,7 +350,13 @@ impl ModelResponse {
             })
             .collect();
 
-        let files = model.get_files3d(connection).await?;
+        // the first page of a document is no picture of the model
+        let files: Vec<File3D> = model
+            .get_files3d(connection)
+            .await?
+            .into_iter()
+            .filter(|file| !is_document(Path::new(&file.file_path)))
+            .collect();
 
         for file in &files {
             if let Some(preview_path) = file.get_url_preview_path(config) {
@@ -189,15 +368,26 @@ impl ModelResponse {
             id: model.id,
             title: model.title.clone(),
             name: model.name.clone(),
+            slug: model.slug.clone(),
             license: model.license.clone(),
             author: model.author.clone(),
+            designer: designer_slug(connection, model.designer_id).await?,
             origin: model.origin.clone(),
             images,
             description: model.description.clone(),
+            tags: get_model_tags(connection, model.id).await?,
+            has_cad_source: has_cad_source(&files),
+            owner: user_name(connection, model.owner_id).await?,
+            uploader: user_name(connection, model.uploader_id).await?,
         })
     }
 }
 
+/// Whether a model ships a CAD source next to its meshes.
+fn has_cad_source(files: &[File3D]) -> bool {
+    files.iter().any(|file| is_cad_file_type(&file.file_type))
+}
+
 #[typeshare]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ModelResponseList {
@@ -241,6 +431,10 @@ pub struct NewModel3D {
     pub origin: Option<String>,
     pub images: String,
     pub description: String,
+    pub slug: String,
+    pub notes: String,
+    pub sort_key: String,
+    pub name_key: String,
 }
 
 impl NewModel3D {
@@ -250,15 +444,21 @@ impl NewModel3D {
         image_paths: Vec<PathBuf>,
         readme: String,
     ) -> Result<Self, Error> {
+        let title = normalize_name(&pack.title);
+        let name = slugify(&title);
         Ok(Self {
-            title: pack.title.clone(),
-            name: str_slug::slug(pack.title.clone()),
+            slug: name.clone(),
+            name,
             license: Some(pack.license.clone()),
             author: Some(pack.author.clone()),
             folder_path: folder_path.clone().into_os_string().into_string().unwrap(),
             origin: Some(pack.origin.clone()),
             images: pathbuf_vec_to_comma_separated(image_paths),
             description: readme,
+            notes: String::new(),
+            sort_key: sort_key(&title),
+            name_key: fold_name(&title),
+            title,
         })
     }
 
@@ -267,6 +467,25 @@ impl NewModel3D {
     }
 }
 
+impl From<&Model3D> for NewModel3D {
+    fn from(model: &Model3D) -> Self {
+        Self {
+            title: model.title.clone(),
+            name: model.name.clone(),
+            license: model.license.clone(),
+            author: model.author.clone(),
+            folder_path: model.folder_path.clone(),
+            origin: model.origin.clone(),
+            images: model.images.clone(),
+            description: model.description.clone(),
+            slug: model.slug.clone(),
+            notes: model.notes.clone(),
+            sort_key: model.sort_key.clone(),
+            name_key: model.name_key.clone(),
+        }
+    }
+}
+
 #[typeshare]
 #[derive(
     Debug, Serialize, Deserialize, Queryable, Identifiable, Associations, Selectable, Clone,
@@ -281,6 +500,16 @@ pub struct File3D {
     pub date_added: Option<NaiveDateTime>,
     pub file_hash: Option<String>,
     pub file_size_bytes: i32,
+    pub file_type: String,
+    pub size_x: Option<f64>,
+    pub size_y: Option<f64>,
+    pub size_z: Option<f64>,
+    pub cad_metadata: Option<String>,
+    pub print_settings: Option<String>,
+    pub geometry_fingerprint: Option<String>,
+    pub gcode_metadata: Option<String>,
+    pub filament_id: Option<i32>,
+    pub measurements: Option<String>,
 }
 
 impl File3D {
@@ -471,6 +700,44 @@ pub struct NewFile3D {
     pub preview_image: Option<String>,
     pub file_hash: Option<String>,
     pub file_size_bytes: i32,
+    pub file_type: String,
+    pub size_x: Option<f64>,
+    pub size_y: Option<f64>,
+    pub size_z: Option<f64>,
+    pub cad_metadata: Option<String>,
+    pub print_settings: Option<String>,
+    pub geometry_fingerprint: Option<String>,
+    pub gcode_metadata: Option<String>,
+    pub filament_id: Option<i32>,
+    pub measurements: Option<String>,
+}
+
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentResponse {
+    pub id: i32,
+    pub name: String,
+    pub file_path: String,
+    pub kind: DocumentKind,
+    /// the first page of a PDF
+    pub preview_image: Option<String>,
+    pub file_size: String,
+}
+
+impl DocumentResponse {
+    pub async fn from_file_3d<Conn>(file: &File3D, connection: &mut Conn, config: &Config) -> Self
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        Self {
+            id: file.id,
+            name: file.get_file_name().await.unwrap(),
+            file_path: file.get_url_file_path(connection, config).await,
+            kind: document_kind(Path::new(&file.file_path)).unwrap_or(DocumentKind::Text),
+            preview_image: file.get_url_preview_path(config),
+            file_size: human_bytes::human_bytes(file.file_size_bytes as f64),
+        }
+    }
 }
 
 #[typeshare]
@@ -484,10 +751,21 @@ pub struct DetailedFileResponse {
     pub date_added: Option<NaiveDateTime>,
     pub file_hash: Option<String>,
     pub file_size: String,
+    pub size_x: Option<f64>,
+    pub size_y: Option<f64>,
+    pub size_z: Option<f64>,
     pub stl_conversion_is_supported: bool,
     pub threemf_conversion_is_supported: bool,
     pub iges_conversion_is_supported: bool,
     pub step_conversion_is_supported: bool,
+    pub cad_metadata: Option<CadMetadata>,
+    pub print_settings: Option<PrintSettings>,
+    pub gcode: Option<GcodeMetadata>,
+    pub filament_id: Option<i32>,
+    /// set if the mesh was probably exported in other units than mm
+    pub scale_warning: Option<ScaleWarning>,
+    /// played instead of shown in the 3D viewer
+    pub is_video: bool,
 }
 
 impl DetailedFileResponse {
@@ -506,10 +784,28 @@ impl DetailedFileResponse {
             date_added: file.date_added,
             file_hash: file.file_hash.clone(),
             file_size: human_bytes::human_bytes(file.file_size_bytes as f64),
+            size_x: file.size_x,
+            size_y: file.size_y,
+            size_z: file.size_z,
             stl_conversion_is_supported: file.stl_conversion_is_supported(),
             threemf_conversion_is_supported: file.threemf_conversion_is_supported(),
             iges_conversion_is_supported: file.iges_conversion_is_supported(),
             step_conversion_is_supported: file.step_conversion_is_supported(),
+            cad_metadata: file
+                .cad_metadata
+                .as_deref()
+                .and_then(|metadata| serde_json::from_str(metadata).ok()),
+            print_settings: file
+                .print_settings
+                .as_deref()
+                .and_then(|settings| serde_json::from_str(settings).ok()),
+            gcode: file
+                .gcode_metadata
+                .as_deref()
+                .and_then(|metadata| serde_json::from_str(metadata).ok()),
+            filament_id: file.filament_id,
+            scale_warning: check_scale([file.size_x, file.size_y, file.size_z]),
+            is_video: is_video_file_type(&file.file_type),
         }
     }
 }
@@ -520,13 +816,26 @@ pub struct DetailedModelResponse {
     pub id: i32,
     pub title: String,
     pub name: String,
+    pub slug: String,
     pub license: Option<String>,
     pub package_name: String,
     pub author: Option<String>,
+    /// slug of the designer the author resolves to
+    pub designer: Option<String>,
     pub origin: Option<String>,
     pub images: Vec<String>,
     pub files: Vec<DetailedFileResponse>,
+    pub parts: Vec<PartGroup>,
+    /// PDFs, instructions and spreadsheets of the model, not part of `files`
+    pub documentation: Vec<DocumentResponse>,
+    /// every file of the doc directories
+    pub documents: Vec<String>,
     pub description: String,
+    pub notes: String,
+    pub tags: Vec<String>,
+    pub has_cad_source: bool,
+    pub owner: Option<String>,
+    pub uploader: Option<String>,
 }
 
 impl DetailedModelResponse {
@@ -550,7 +859,11 @@ impl DetailedModelResponse {
             })
             .collect();
 
-        let files = model.get_files3d(connection).await?;
+        let (documentation_files, files): (Vec<File3D>, Vec<File3D>) = model
+            .get_files3d(connection)
+            .await?
+            .into_iter()
+            .partition(|file| is_document(Path::new(&file.file_path)));
 
         for file in &files {
             if let Some(preview_path) = file.get_url_preview_path(config) {
@@ -558,6 +871,25 @@ impl DetailedModelResponse {
             }
         }
 
+        let model_base_path = model.absolute_path(config);
+        let documents = layout::doc_files(config, &model_base_path)
+            .iter()
+            .filter_map(|pth| pathdiff::diff_paths(pth, &model_base_path))
+            .map(|pth| {
+                format!(
+                    "{}/{}/{}",
+                    config.asset_prefix,
+                    model.folder_path,
+                    pth.to_string_lossy()
+                )
+            })
+            .collect();
+
+        let mut documentation = Vec::new();
+        for file in &documentation_files {
+            documentation.push(DocumentResponse::from_file_3d(file, connection, config).await);
+        }
+
         let mut detailed_files: Vec<DetailedFileResponse> = Vec::new();
 
         for file in &files {
@@ -569,21 +901,42 @@ impl DetailedModelResponse {
             id: model.id,
             title: model.title.clone(),
             name: model.name.clone(),
+            slug: model.slug.clone(),
             package_name: model.folder_path.clone(),
             license: model.license.clone(),
             author: model.author.clone(),
+            designer: designer_slug(connection, model.designer_id).await?,
             origin: model.origin.clone(),
             images,
             files: detailed_files,
+            parts: group_parts(&files),
+            documentation,
+            documents,
             description: model.description.clone(),
+            notes: model.notes.clone(),
+            tags: get_model_tags(connection, model.id).await?,
+            has_cad_source: has_cad_source(&files),
+            owner: user_name(connection, model.owner_id).await?,
+            uploader: user_name(connection, model.uploader_id).await?,
         })
     }
 }
 
-#[derive(Deserialize)]
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListModelParams {
     pub q: Option<String>,
     pub licenses: Option<String>,
+    /// comma separated file types every returned model must contain, e.g. `3mf,step`
+    pub has: Option<String>,
+    /// build volume as `XxYxZ` in millimeters, e.g. `220x220x250`
+    pub fits: Option<String>,
+    /// comma separated tags every returned model must have
+    pub tags: Option<String>,
+    /// name of the user who uploaded the models, `me` for the viewer
+    pub uploader: Option<String>,
+    /// slug of the designer of the models
+    pub designer: Option<String>,
 }
 
 impl Default for ListModelParams {
@@ -591,6 +944,44 @@ impl Default for ListModelParams {
         Self {
             q: None,
             licenses: None,
+            has: None,
+            fits: None,
+            tags: None,
+            uploader: None,
+            designer: None,
         }
     }
 }
+
+#[derive(Deserialize, Default)]
+pub struct RefreshParams {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ZipDownloadParams {
+    /// add a `modelvault.json` with the metadata, to import the archive
+    /// into another instance
+    #[serde(default)]
+    pub manifest: bool,
+    /// `printable`, `images`, `source` or `all`
+    #[serde(default)]
+    pub kind: DownloadKind,
+}
+
+#[derive(Deserialize)]
+pub struct RandomModelParams {
+    pub count: Option<i64>,
+    #[serde(flatten)]
+    pub filters: ListModelParams,
+}
+
+#[derive(Deserialize, Default)]
+pub struct DeleteModelParams {
+    /// overrides `trash_retention_days` for this deletion
+    pub retention_days: Option<i64>,
+    /// keep the model in the trash until it is purged manually
+    #[serde(default)]
+    pub keep: bool,
+}
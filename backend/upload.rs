@@ -1,38 +1,49 @@
 use anyhow::{Context, Ok as anyOk, Result};
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
 use axum::{extract::Multipart, extract::State, http::StatusCode, Json};
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
 use diesel_async::{AsyncConnection, RunQueryDsl};
 use serde_json::{json, Value};
 use std::collections::VecDeque;
 use std::path::{Path, PathBuf};
 use tokio::fs::{self, File};
 use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info};
 use uuid::Uuid;
+use walkdir::WalkDir;
 
-use diesel::prelude::*;
-
+use crate::access::{self, Viewer};
+use crate::auth::{CurrentUser, User};
+use crate::cad;
+use crate::jobs::{JobRegistry, JobStatus};
+use crate::notifications::{Notification, Notifier};
 use crate::parse_library::{self, add_or_update_model};
+use crate::quota;
+use crate::rollback::{self, Rollback};
 use crate::schema::models3d;
+use crate::tenants::{tenant_name_for_folder, TenantScope};
 use crate::types::Model3D;
 
 use crate::Config;
 
-fn cleanup_temp_dir(temp_dir: &PathBuf) {
+pub fn cleanup_temp_dir(temp_dir: &PathBuf) {
     debug!("cleaned {}", temp_dir.display());
     fs_extra::remove_items(&[temp_dir.clone()])
         .unwrap_or_else(|e| debug!("Failed to remove temp dir: {}", e));
 }
 
 const MESH_FILE_FORMATS: &[&str] = &["obj", "stl", "3mf"];
-const CAD_FILE_FORMATS: &[&str] = &["step", "stp", "f3d", "scad", "igs", "iges"];
-const IMAGE_FILE_FORMATS: &[&str] = &["jpg", "jpeg", "png", "gif", "bmp", "tiff", "webp"];
+pub const IMAGE_FILE_FORMATS: &[&str] = &["jpg", "jpeg", "png", "gif", "bmp", "tiff", "webp"];
 
-fn categorize_file(file_name: &PathBuf) -> &str {
+pub fn categorize_file(file_name: &PathBuf) -> &str {
     if let Some(ext) = file_name.extension().and_then(|s| s.to_str()) {
         let ext = ext.to_ascii_lowercase();
         if MESH_FILE_FORMATS.contains(&ext.as_str()) {
             "mesh"
-        } else if CAD_FILE_FORMATS.contains(&ext.as_str()) {
+        } else if cad::is_cad_file_type(&ext) {
             "cad"
         } else if IMAGE_FILE_FORMATS.contains(&ext.as_str()) {
             "image"
@@ -44,13 +55,62 @@ fn categorize_file(file_name: &PathBuf) -> &str {
     }
 }
 
-async fn merge_directories(src: &Path, dest: &Path, overwrite: bool) -> Result<()> {
-    if !src.is_dir() || !dest.is_dir() {
-        anyhow::bail!("Both paths must be directories");
+/// Where an uploaded file goes. Directory uploads name their files by
+/// `webkitRelativePath`, like `Benchy/files/benchy.stl`. The first component
+/// is the directory the user picked and dropped, the rest of the structure
+/// is kept. Every component is sanitized, so nothing ends up outside of the
+/// model folder.
+fn upload_path(file_name: &str) -> PathBuf {
+    let components: Vec<String> = file_name
+        .split(['/', '\\'])
+        .filter(|component| !matches!(*component, "" | "." | ".."))
+        .map(sanitize_filename::sanitize)
+        .filter(|component| !component.is_empty())
+        .collect();
+    match components.as_slice() {
+        [] => PathBuf::from("unknown"),
+        [name] => PathBuf::from(name),
+        [_, rest @ ..] => rest.iter().collect(),
     }
+}
+
+/// Name of a file while it is being written, `.part` files keep the
+/// scanner away from the folder until they are renamed.
+fn partial_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.part", name, Uuid::new_v4()))
+}
+
+/// Flushes a written file to disk before it gets its final name.
+async fn sync_file(path: &Path) -> Result<()> {
+    File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {:?}", path))?
+        .sync_all()
+        .await
+        .with_context(|| format!("Failed to sync {:?}", path))
+}
 
+/// Syncs a directory after renames in it, where the platform supports it.
+async fn sync_dir(path: &Path) {
+    if let Ok(dir) = File::open(path).await {
+        let _ = dir.sync_all().await;
+    }
+}
+
+/// Moves the files of `src` into `dest`. Each file is first placed next to
+/// its destination under a partial name, synced and then renamed, so a
+/// crash never leaves a half written file in the library. Without
+/// `overwrite` existing files are kept.
+async fn merge_directories(src: &Path, dest: &Path, overwrite: bool) -> Result<()> {
     if !dest.exists() {
-        std::fs::create_dir_all(dest)?;
+        fs::create_dir_all(dest).await?;
+    }
+    if !src.is_dir() || !dest.is_dir() {
+        anyhow::bail!("Both paths must be directories");
     }
 
     let mut queue = VecDeque::new();
@@ -71,60 +131,468 @@ async fn merge_directories(src: &Path, dest: &Path, overwrite: bool) -> Result<(
                     .with_context(|| format!("Failed to create directory: {:?}", dest_path))?;
                 queue.push_back((path, dest_path));
             } else if path.is_file() && (overwrite || !fs::try_exists(&dest_path).await?) {
-                fs::copy(&path, &dest_path).await.with_context(|| {
-                    format!("Failed to copy file from {:?} to {:?}", path, dest_path)
-                })?;
+                let partial = partial_path(&dest_path);
+                // a rename suffices on the same file system, copy otherwise
+                if fs::rename(&path, &partial).await.is_err() {
+                    if let Err(e) = fs::copy(&path, &partial).await {
+                        let _ = fs::remove_file(&partial).await;
+                        return Err(e).with_context(|| {
+                            format!("Failed to copy file from {:?} to {:?}", path, dest_path)
+                        });
+                    }
+                }
+                sync_file(&partial).await?;
+                fs::rename(&partial, &dest_path)
+                    .await
+                    .with_context(|| format!("Failed to rename {:?}", partial))?;
             }
         }
+        sync_dir(&current_dest).await;
     }
 
     anyOk(())
 }
 
+/// Records what merging `src` into `dest` will change: files about to be
+/// overwritten are moved aside and new files and folders noted, so a failed
+/// merge can be reverted.
+async fn record_merge(src: &Path, dest: &Path, rollback: &Rollback) -> Result<()> {
+    let mut entries = WalkDir::new(src).min_depth(1).into_iter();
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let target = dest.join(entry.path().strip_prefix(src)?);
+        if !fs::try_exists(&target).await? {
+            rollback.created(target);
+            // everything below a new folder is new as well
+            if entry.file_type().is_dir() {
+                entries.skip_current_dir();
+            }
+        } else if entry.file_type().is_file() {
+            rollback.replace(&target).await?;
+        }
+    }
+    anyOk(())
+}
+
+/// Moves a prepared model folder into the library as `final_path`. It is
+/// assembled in a partial folder the scanner skips and appears in the
+/// library with a single rename.
+pub async fn install_new_folder(prepared: &Path, final_path: &Path) -> Result<()> {
+    let staging = partial_path(final_path);
+    let installed = match merge_directories(prepared, &staging, false).await {
+        Ok(()) => fs::rename(&staging, final_path).await.map_err(Into::into),
+        Err(e) => Err(e),
+    };
+    if installed.is_err() {
+        cleanup_temp_dir(&staging);
+    }
+    installed?;
+    if let Some(parent) = final_path.parent() {
+        sync_dir(parent).await;
+    }
+    anyOk(())
+}
+
+/// Streams a multipart field into `path` chunk by chunk, so memory stays
+/// bounded whatever the size of the file and a slow disk slows down the
+/// client. The file only gets its name once it is complete and synced, a
+/// field outgrowing the `budget` is aborted and its partial file removed.
+pub async fn write_field(
+    field: &mut axum::extract::multipart::Field<'_>,
+    path: &Path,
+    cancel: &CancellationToken,
+    mut budget: Option<&mut QuotaBudget>,
+) -> Result<(), UploadError> {
+    let partial = partial_path(path);
+    let result: Result<(), UploadError> = async {
+        let mut file = File::create(&partial).await.map_err(|e| {
+            error!("Failed to create {}: {}", partial.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        while let Some(chunk) = field.chunk().await.map_err(|e| {
+            debug!("Upload of {} aborted: {}", path.display(), e);
+            StatusCode::BAD_REQUEST
+        })? {
+            if cancel.is_cancelled() {
+                return Err(StatusCode::CONFLICT.into());
+            }
+            if let Some(budget) = budget.as_deref_mut() {
+                budget.spend(chunk.len() as u64)?;
+            }
+            file.write_all(&chunk).await.map_err(|e| {
+                error!("Failed to write {}: {}", partial.display(), e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        }
+        file.flush().await.map_err(|e| {
+            error!("Failed to write {}: {}", partial.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        file.sync_all().await.map_err(|e| {
+            error!("Failed to sync {}: {}", partial.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        fs::rename(&partial, path).await.map_err(|e| {
+            error!("Failed to rename {}: {}", partial.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        Ok(())
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&partial).await;
+    }
+    result
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    Status(StatusCode),
+    QuotaExceeded { used: u64, quota: u64, size: u64 },
+    TooLarge { max: u64 },
+}
+
+impl From<StatusCode> for UploadError {
+    fn from(status: StatusCode) -> Self {
+        UploadError::Status(status)
+    }
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadError::Status(status) => write!(f, "{}", status),
+            UploadError::QuotaExceeded { used, quota, size } => write!(
+                f,
+                "Upload quota exceeded: the upload needs {} but only {} of {} are left",
+                human_bytes::human_bytes(*size as f64),
+                human_bytes::human_bytes(quota.saturating_sub(*used) as f64),
+                human_bytes::human_bytes(*quota as f64)
+            ),
+            UploadError::TooLarge { max } => write!(
+                f,
+                "Upload too large: at most {} are accepted",
+                human_bytes::human_bytes(*max as f64)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        match self {
+            UploadError::Status(status) => status.into_response(),
+            UploadError::QuotaExceeded { .. } | UploadError::TooLarge { .. } => {
+                (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()).into_response()
+            }
+        }
+    }
+}
+
+pub fn complete_upload_job<T>(
+    jobs: &JobRegistry,
+    job_id: Uuid,
+    cancel: &CancellationToken,
+    result: &Result<T, UploadError>,
+) {
+    match result {
+        Ok(_) => jobs.finish(job_id, JobStatus::Finished, None),
+        Err(_) if cancel.is_cancelled() => jobs.finish(job_id, JobStatus::Cancelled, None),
+        Err(e) => jobs.finish(job_id, JobStatus::Failed, Some(e.to_string())),
+    }
+}
+
+/// Emails the uploader once the model is indexed, anonymous uploads may
+/// still match saved searches.
+pub fn notify_upload(
+    notifier: &Notifier,
+    uploader: Option<&User>,
+    result: &Result<Json<Value>, UploadError>,
+) {
+    let Ok(Json(response)) = result else {
+        return;
+    };
+    match (uploader, response["slug"].as_str()) {
+        (Some(user), Some(slug)) => notifier.notify(Notification::UploadFinished {
+            user_id: user.id,
+            slug: slug.to_string(),
+        }),
+        _ => notifier.notify(Notification::LibraryChanged),
+    }
+}
+
+pub async fn load_uploader<Conn>(
+    connection: &mut Conn,
+    current_user: &CurrentUser,
+) -> Result<Option<User>, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    current_user.load(connection).await.map_err(|e| {
+        error!("Failed to load uploading user: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// What is left of the quota of an uploader and of the largest accepted
+/// upload while an upload is streamed in, so an upload over either limit is
+/// stopped before it fills the disk.
+pub struct QuotaBudget {
+    user: String,
+    used: u64,
+    /// unset for anonymous uploads and without a configured quota
+    quota: Option<u64>,
+    max_size: Option<u64>,
+    written: u64,
+}
+
+impl QuotaBudget {
+    /// `None` when neither a quota applies nor the upload size is limited.
+    pub async fn load<Conn>(
+        connection: &mut Conn,
+        config: &Config,
+        uploader: Option<&User>,
+    ) -> Result<Option<Self>, StatusCode>
+    where
+        Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+    {
+        let quota = uploader.and(quota::quota_bytes(config));
+        let max_size = quota::max_upload_bytes(config);
+        if quota.is_none() && max_size.is_none() {
+            return Ok(None);
+        }
+        let used = match (uploader, quota) {
+            (Some(user), Some(_)) => quota::used_bytes(connection, user.id).await.map_err(|e| {
+                error!("Failed to compute quota usage of {}: {}", user.name, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+            _ => 0,
+        };
+        Ok(Some(QuotaBudget {
+            user: uploader
+                .map_or("anonymous", |user| user.name.as_str())
+                .to_string(),
+            used,
+            quota,
+            max_size,
+            written: 0,
+        }))
+    }
+
+    /// Rejects `size` more bytes which do not fit next to what was written.
+    pub fn check(&self, size: u64) -> Result<(), UploadError> {
+        let size = self.written + size;
+        if let Some(max) = self.max_size.filter(|max| size > *max) {
+            info!("Rejected upload of {}, too large", self.user);
+            return Err(UploadError::TooLarge { max });
+        }
+        if let Some(quota) = self.quota.filter(|quota| self.used + size > *quota) {
+            info!("Rejected upload of {}, quota exceeded", self.user);
+            return Err(UploadError::QuotaExceeded {
+                used: self.used,
+                quota,
+                size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Counts `size` written bytes against the limits.
+    pub fn spend(&mut self, size: u64) -> Result<(), UploadError> {
+        self.check(size)?;
+        self.written += size;
+        Ok(())
+    }
+}
+
+/// Rejects anonymous uploads while a quota is configured, there would be
+/// nobody to count them against.
+pub fn require_uploader(config: &Config, uploader: Option<&User>) -> Result<(), StatusCode> {
+    if uploader.is_none() && quota::quota_bytes(config).is_some() {
+        info!("Rejected anonymous upload, uploads count against a quota");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+}
+
+/// The announced size of a request body. Multipart bodies are a little
+/// larger than their files, which is close enough to turn away uploads that
+/// cannot fit before anything is written.
+pub fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Rejects an upload of `size` bytes which does not fit into the quota of
+/// its uploader or is larger than any accepted upload.
+pub async fn check_quota<Conn>(
+    connection: &mut Conn,
+    config: &Config,
+    uploader: Option<&User>,
+    size: u64,
+) -> Result<(), UploadError>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    match QuotaBudget::load(connection, config, uploader).await? {
+        Some(budget) => budget.check(size),
+        None => Ok(()),
+    }
+}
+
+/// Indexes a model folder which just arrived in the library and credits it
+/// to its uploader in one transaction. If an upload renamed the folder of
+/// `renamed`, its row follows the folder like a move, so the model keeps
+/// its id and its old slug redirects to the new one. If indexing fails, the
+/// steps recorded in `rollback` take the folder out of the library again.
+pub async fn index_uploaded_model<Conn>(
+    connection: &mut Conn,
+    config: &Config,
+    final_path: &Path,
+    uploader: Option<&User>,
+    upload_size: u64,
+    renamed: Option<Model3D>,
+    rollback: &Rollback,
+) -> Result<Model3D, StatusCode>
+where
+    Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
+{
+    let model = rollback::transaction(connection, rollback, |connection| {
+        async move {
+            if let Some(renamed) = renamed {
+                let relative = pathdiff::diff_paths(final_path, &config.libraries_path)
+                    .with_context(|| {
+                        format!("{} is outside of the library", final_path.display())
+                    })?;
+                diesel::update(models3d::dsl::models3d.find(renamed.id))
+                    .set((
+                        models3d::dsl::folder_path.eq(relative.to_string_lossy().into_owned()),
+                        models3d::dsl::updated_at.eq(Some(chrono::Utc::now().naive_utc())),
+                    ))
+                    .execute(connection)
+                    .await?;
+            }
+            let model = add_or_update_model(config, connection, &final_path.to_path_buf()).await?;
+            // picks up a slug changed with the folder name
+            let model = models3d::dsl::models3d
+                .find(model.id)
+                .first::<Model3D>(connection)
+                .await?;
+            if let Some(user) = uploader {
+                quota::record_upload(connection, user.id, model.id, upload_size).await?;
+                if model.owner_id.is_none() {
+                    access::set_owner(connection, &model, user).await?;
+                }
+                if model.uploader_id.is_none() {
+                    access::set_uploader(connection, &model, user).await?;
+                }
+            }
+            anyOk(model)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(|e| {
+        error!("Failed to index {}: {:#}", final_path.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // the files are picked up outside of the transaction, a failure leaves
+    // them to the next refresh
+    if let Err(e) = model.scan(config, connection).await {
+        error!("Failed to scan {}: {:#}", final_path.display(), e);
+    }
+    debug!("Indexed {}", final_path.display());
+    Ok(model)
+}
+
 pub async fn handle_upload(
     State(state): State<crate::AppState>,
+    current_user: CurrentUser,
+    scope: TenantScope,
+    headers: HeaderMap,
     multipart: Multipart,
-) -> Result<Json<Value>, StatusCode> {
-    let mut connection = state.pool.get().await.unwrap();
-    Ok(
-        handle_upload_internally(&mut connection, &state.config.clone(), multipart, None)
-            .await
-            .unwrap(),
+) -> Result<Json<Value>, UploadError> {
+    let mut connection = state.connection().await?;
+    let uploader = load_uploader(&mut connection, &current_user).await?;
+    let (job_id, cancel) = state.jobs.start("upload");
+
+    let result = handle_upload_internally(
+        &mut connection,
+        &state.config.clone(),
+        multipart,
+        None,
+        scope.library_root(&state.config),
+        uploader.as_ref(),
+        content_length(&headers),
+        &cancel,
     )
+    .await;
+    complete_upload_job(&state.jobs, job_id, &cancel, &result);
+    notify_upload(&state.notifier, uploader.as_ref(), &result);
+    result
 }
 
 pub async fn handle_upload_update(
     State(state): State<crate::AppState>,
     axum::extract::Path(slug): axum::extract::Path<String>,
+    viewer: Viewer,
+    headers: HeaderMap,
     multipart: Multipart,
-) -> Result<Json<Value>, StatusCode> {
-    let mut connection = state.pool.get().await.unwrap();
+) -> Result<Json<Value>, UploadError> {
+    let mut connection = state.connection().await?;
+
+    let result = viewer.manage_model(&mut connection, &slug).await?;
+    // the model stays below the library root of its tenant, even when an
+    // admin updates it
+    let library_root = match tenant_name_for_folder(&state.config, Path::new(&result.folder_path)) {
+        Some(tenant) => state.config.libraries_path.join(tenant),
+        None => state.config.libraries_path.clone(),
+    };
 
-    let result = models3d::dsl::models3d
-        .filter(models3d::dsl::name.eq(slug))
-        .first::<Model3D>(&mut connection)
-        .await
-        .unwrap();
+    let (job_id, cancel) = state.jobs.start("upload");
 
-    Ok(handle_upload_internally(
+    let result = handle_upload_internally(
         &mut connection,
         &state.config.clone(),
         multipart,
         Some(result),
+        library_root,
+        viewer.user.as_ref(),
+        content_length(&headers),
+        &cancel,
     )
-    .await
-    .unwrap())
+    .await;
+    complete_upload_job(&state.jobs, job_id, &cancel, &result);
+    notify_upload(&state.notifier, viewer.user.as_ref(), &result);
+    result
 }
 
 pub async fn handle_upload_internally<Conn>(
-    mut connection: &mut Conn,
+    connection: &mut Conn,
     config: &Config,
     mut multipart: Multipart,
     existing_model: Option<Model3D>,
-) -> Result<Json<Value>, StatusCode>
+    libraries_path: PathBuf,
+    uploader: Option<&User>,
+    content_length: Option<u64>,
+    cancel: &CancellationToken,
+) -> Result<Json<Value>, UploadError>
 where
     Conn: AsyncConnection<Backend = diesel::sqlite::Sqlite>,
 {
+    require_uploader(config, uploader)?;
+    let mut budget = QuotaBudget::load(connection, config, uploader).await?;
+    if let (Some(budget), Some(length)) = (&budget, content_length) {
+        budget.check(length)?;
+    }
+
     let mut temp_dir = config.upload_cache.clone();
     temp_dir.push(Uuid::new_v4().to_string());
 
@@ -139,34 +607,52 @@ where
     let mut cad_files = Vec::new();
     let mut image_files = Vec::new();
     let mut uploaded_files = Vec::new();
+    // files of directory uploads below the uploaded directory, relative to it
+    let tree_dir = temp_dir.join(".directory");
+    let mut tree_files = Vec::new();
 
-    while let Some(field) = multipart.next_field().await.map_err(|_| {
+    while let Some(mut field) = multipart.next_field().await.map_err(|_| {
         cleanup_temp_dir(&temp_dir);
         StatusCode::BAD_REQUEST
     })? {
+        if cancel.is_cancelled() {
+            cleanup_temp_dir(&temp_dir);
+            return Err(StatusCode::CONFLICT.into());
+        }
+
         let file_name = field
             .file_name()
             .map(|f| f.to_string())
             .unwrap_or_else(|| "unknown".to_string());
 
-        let data = field.bytes().await.map_err(|_| {
-            cleanup_temp_dir(&temp_dir);
-            StatusCode::BAD_REQUEST
-        })?;
-
-        let file_path = temp_dir
-            .clone()
-            .join(sanitize_filename::sanitize(&file_name));
+        let relative_path = upload_path(&file_name);
+        if relative_path.components().count() > 1 {
+            let file_path = tree_dir.join(&relative_path);
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent).await.map_err(|e| {
+                    error!("Failed to create {}: {}", parent.display(), e);
+                    cleanup_temp_dir(&temp_dir);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            }
+            write_field(&mut field, &file_path, cancel, budget.as_mut())
+                .await
+                .map_err(|e| {
+                    cleanup_temp_dir(&temp_dir);
+                    e
+                })?;
+            tree_files.push(relative_path);
+            continue;
+        }
 
-        let mut file = File::create(&file_path).await.map_err(|_| {
-            cleanup_temp_dir(&temp_dir);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        let file_path = temp_dir.clone().join(relative_path);
 
-        file.write_all(&data).await.map_err(|_| {
-            cleanup_temp_dir(&temp_dir);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        write_field(&mut field, &file_path, cancel, budget.as_mut())
+            .await
+            .map_err(|e| {
+                cleanup_temp_dir(&temp_dir);
+                e
+            })?;
 
         match categorize_file(&file_path) {
             "mesh" => mesh_files.push(file_path.clone()),
@@ -248,15 +734,46 @@ where
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
     }
+    for relative_path in &tree_files {
+        let dest = tmp_final_structure.join(relative_path);
+        let moved = async {
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::rename(tree_dir.join(relative_path), &dest).await
+        }
+        .await;
+        if let Err(e) = moved {
+            error!("Failed to move {}: {}", dest.display(), e);
+            cleanup_temp_dir(&temp_dir);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    }
 
-    let libraries_path = config.libraries_path.clone();
-    let final_path = libraries_path.join(&final_folder_name);
+    // last point where the upload can be aborted without touching the library
+    if cancel.is_cancelled() {
+        cleanup_temp_dir(&temp_dir);
+        return Err(StatusCode::CONFLICT.into());
+    }
 
-    let mut merge = false;
+    let upload_size = quota::dir_size(&tmp_final_structure);
+    if let Err(e) = check_quota(connection, config, uploader, upload_size).await {
+        cleanup_temp_dir(&temp_dir);
+        return Err(e);
+    }
+
+    fs::create_dir_all(&libraries_path).await.map_err(|e| {
+        error!("Failed to create {}: {}", libraries_path.display(), e);
+        cleanup_temp_dir(&temp_dir);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let final_path = libraries_path.join(&final_folder_name);
+    let rollback = Rollback::default();
 
     // rename existing model folder
+    let mut renamed = None;
+    let merge = existing_model.is_some();
     if let Some(model_to_move) = existing_model {
-        merge = true;
         let old_absolute_path = model_to_move.absolute_path(config);
         if old_absolute_path != final_path {
             debug!(
@@ -264,51 +781,67 @@ where
                 old_absolute_path.display(),
                 final_path.display()
             );
-            tokio::fs::rename(old_absolute_path, &final_path)
-                .await
-                .map_err(|e| {
-                    error!("Rename operation failed: {:?}", e);
-                    cleanup_temp_dir(&temp_dir);
-                    StatusCode::INTERNAL_SERVER_ERROR
-                });
-
-            model_to_move.delete(config, &mut connection).await;
+            if let Err(e) = fs::rename(&old_absolute_path, &final_path).await {
+                error!("Rename operation failed: {:?}", e);
+                cleanup_temp_dir(&temp_dir);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+            rollback.moved(old_absolute_path, &final_path);
+            renamed = Some(model_to_move);
         } else {
             debug!("No Model rename detected");
         }
     }
 
-    fs_extra::move_items(
-        &[tmp_final_structure],
-        libraries_path.clone(),
-        &fs_extra::dir::CopyOptions {
-            overwrite: merge,
-            copy_inside: merge,
-            ..fs_extra::dir::CopyOptions::new()
-        },
-    )
-    .map_err(|e| {
-        error!("Final move operation failed: {:?}, merge: {}", e, merge);
-        cleanup_temp_dir(&temp_dir);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    if merge {
+        let merged = match record_merge(&tmp_final_structure, &final_path, &rollback).await {
+            Ok(()) => merge_directories(&tmp_final_structure, &final_path, true).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = merged {
+            error!("Final move operation failed: {:?}, merge: {}", e, merge);
+            rollback.undo().await;
+            cleanup_temp_dir(&temp_dir);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    } else {
+        if final_path.exists() {
+            error!("{} exists already", final_path.display());
+            cleanup_temp_dir(&temp_dir);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+        if let Err(e) = install_new_folder(&tmp_final_structure, &final_path).await {
+            error!("Final move operation failed: {:?}, merge: {}", e, merge);
+            cleanup_temp_dir(&temp_dir);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+        rollback.created(&final_path);
+    }
 
     cleanup_temp_dir(&temp_dir);
 
-    let model = add_or_update_model(config, &mut connection, &final_path)
-        .await
-        .unwrap();
-    model.scan(config, &mut connection).await;
-    debug!("Indexed {}", final_folder_name);
+    let model = index_uploaded_model(
+        connection,
+        config,
+        &final_path,
+        uploader,
+        upload_size,
+        renamed,
+        &rollback,
+    )
+    .await?;
 
     let response = crate::types::UploadResponse {
         success: true,
-        slug: model.name,
+        slug: model.slug,
         message: format!(
             "Successfully uploaded {} files",
-            image_files.len() + mesh_files.len() + cad_files.len()
+            image_files.len() + mesh_files.len() + cad_files.len() + tree_files.len()
         ),
     };
 
     Ok(Json(json!(response)))
 }
+
+#[cfg(test)]
+mod tests;
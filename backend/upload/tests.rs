@@ -0,0 +1,116 @@
+//! Uploads renaming a model move its row along with the folder, so the
+//! model keeps its id and links to the old slug keep working.
+
+use diesel::sqlite::SqliteConnection;
+use diesel_async::sync_connection_wrapper::SyncConnectionWrapper;
+use diesel_async::AsyncConnection;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use super::index_uploaded_model;
+use crate::rollback::Rollback;
+use crate::slug::find_model_by_slug;
+use crate::Config;
+
+/// A library and database in a fresh temporary folder, removed again when
+/// dropped.
+struct Library {
+    root: PathBuf,
+    config: Config,
+}
+
+impl Library {
+    fn new() -> Library {
+        let root = std::env::temp_dir().join(format!("meshvault-upload-{}", Uuid::new_v4()));
+        let libraries = root.join("libraries");
+        fs::create_dir_all(&libraries).unwrap();
+        let mut config: Config = envy::from_iter([
+            (
+                "LIBRARIES_PATH".to_string(),
+                libraries.display().to_string(),
+            ),
+            (
+                "DATA_DIR".to_string(),
+                root.join("data").display().to_string(),
+            ),
+        ])
+        .unwrap();
+        config.initialize();
+        fs::create_dir_all(&config.data_dir).unwrap();
+        crate::migrate(&config);
+        Library { root, config }
+    }
+
+    async fn connect(&self) -> SyncConnectionWrapper<SqliteConnection> {
+        SyncConnectionWrapper::<SqliteConnection>::establish(
+            self.config.database_url.to_str().unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Writes a model folder titled `title`.
+    fn model(&self, folder: &str, title: &str) -> PathBuf {
+        let path = self.config.libraries_path.join(folder);
+        fs::create_dir_all(&path).unwrap();
+        let pack = serde_json::json!({
+            "version": "0.1",
+            "title": title,
+            "author": "",
+            "origin": "",
+            "license": "",
+        });
+        fs::write(path.join("modelpack.json"), pack.to_string()).unwrap();
+        path
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[tokio::test]
+async fn renaming_upload_redirects_the_old_slug() {
+    let library = Library::new();
+    let config = &library.config;
+    let mut connection = library.connect().await;
+
+    let path = library.model("Benchy", "Benchy");
+    let model = index_uploaded_model(
+        &mut connection,
+        config,
+        &path,
+        None,
+        0,
+        None,
+        &Rollback::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(model.slug, "benchy");
+
+    // the upload moved the folder and wrote the new title
+    let renamed = config.libraries_path.join("Benchy Remix");
+    fs::rename(&path, &renamed).unwrap();
+    library.model("Benchy Remix", "Benchy Remix");
+    let updated = index_uploaded_model(
+        &mut connection,
+        config,
+        &renamed,
+        None,
+        0,
+        Some(model),
+        &Rollback::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(updated.slug, "benchy-remix");
+    assert_eq!(updated.folder_path, "Benchy Remix");
+
+    let redirected = find_model_by_slug(&mut connection, "benchy").await.unwrap();
+    assert_eq!(redirected.id, updated.id);
+    assert_eq!(redirected.slug, "benchy-remix");
+}
@@ -0,0 +1,84 @@
+use anyhow::Context;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs;
+use tracing::debug;
+
+use crate::Config;
+
+/// Larger answers are no snapshot but a stream or an error page.
+const MAX_SNAPSHOT_BYTES: usize = 20 * 1024 * 1024;
+
+/// The snapshot URL of a printer from `printer_webcams`. Without a printer
+/// the only configured webcam is used.
+pub fn snapshot_url<'a>(config: &'a Config, printer: Option<&str>) -> Option<&'a str> {
+    let webcams: Vec<(&str, &str)> = config
+        .printer_webcams
+        .iter()
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(name, url)| (name.trim(), url.trim()))
+        .collect();
+    match printer.map(str::trim) {
+        Some(printer) => webcams
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(printer))
+            .map(|(_, url)| url),
+        None => match webcams.as_slice() {
+            [(_, url)] => Some(url),
+            _ => None,
+        },
+    }
+}
+
+pub fn snapshot_path(config: &Config, snapshot: &str) -> PathBuf {
+    config.print_snapshot_dir.join(snapshot)
+}
+
+/// Takes a picture with the webcam at `url`, e.g. the snapshot endpoint of
+/// Moonraker or OctoPrint, and stores it for the print log `log_id`.
+/// Returns the file name of the stored picture.
+pub async fn capture_snapshot(config: &Config, url: &str, log_id: i32) -> anyhow::Result<String> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.webcam_timeout_seconds))
+        .user_agent(concat!("ModelVault/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    let extension = match response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(';').next().unwrap_or_default().trim())
+    {
+        Some("image/jpeg") => "jpg",
+        Some("image/png") => "png",
+        Some("image/webp") => "webp",
+        other => anyhow::bail!("{} returned {:?} instead of an image", url, other),
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length as usize > MAX_SNAPSHOT_BYTES)
+    {
+        anyhow::bail!("The snapshot of {} is too large", url);
+    }
+    let image = response.bytes().await?;
+    if image.len() > MAX_SNAPSHOT_BYTES {
+        anyhow::bail!("The snapshot of {} is too large", url);
+    }
+
+    let file_name = format!("{}.{}", log_id, extension);
+    fs::create_dir_all(&config.print_snapshot_dir).await?;
+    fs::write(snapshot_path(config, &file_name), &image)
+        .await
+        .with_context(|| format!("Failed to store the snapshot of print log {}", log_id))?;
+    debug!("Stored a snapshot of {} for print log {}", url, log_id);
+    Ok(file_name)
+}
+
+pub async fn delete_snapshot(config: &Config, snapshot: &str) {
+    let path = snapshot_path(config, snapshot);
+    if let Err(e) = fs::remove_file(&path).await {
+        debug!("Failed to remove {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,136 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tokio::sync::Semaphore;
+use tokio::task::JoinError;
+use tracing::warn;
+
+use crate::Config;
+
+/// Rendering previews, hashing and analysing meshes run on blocking threads,
+/// at most `cpu_workers` at a time. The runtime threads stay free for
+/// requests while a large import is scanned.
+struct CpuPool {
+    size: usize,
+    permits: Semaphore,
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+    completed: AtomicU64,
+}
+
+static POOL: OnceLock<CpuPool> = OnceLock::new();
+
+impl CpuPool {
+    fn new(size: usize) -> Self {
+        let size = size.max(1);
+        CpuPool {
+            size,
+            permits: Semaphore::new(size),
+            queued: AtomicUsize::new(0),
+            busy: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+        }
+    }
+}
+
+/// All but one core, so requests keep a core while the library is scanned.
+pub fn default_size() -> usize {
+    std::thread::available_parallelism()
+        .map(|cores| cores.get().saturating_sub(1))
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// Sizes the pool, before anything ran on it.
+pub fn init(config: &Config) {
+    if POOL.set(CpuPool::new(config.cpu_workers)).is_err() {
+        warn!("The CPU worker pool was used before it was configured");
+    }
+}
+
+fn pool() -> &'static CpuPool {
+    POOL.get_or_init(|| CpuPool::new(default_size()))
+}
+
+/// Decrements a gauge when dropped, also if the work panics.
+struct Counted<'a>(&'a AtomicUsize);
+
+impl<'a> Counted<'a> {
+    fn new(gauge: &'a AtomicUsize) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Counted(gauge)
+    }
+}
+
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Runs CPU-heavy `work` on the pool, waiting for a free worker first. A
+/// drop-in for `tokio::task::spawn_blocking(work).await`.
+pub async fn run<F, T>(work: F) -> Result<T, JoinError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool();
+    let permit = {
+        let _queued = Counted::new(&pool.queued);
+        pool.permits
+            .acquire()
+            .await
+            .expect("the pool semaphore is never closed")
+    };
+    let span = tracing::Span::current();
+    let result = tokio::task::spawn_blocking(move || {
+        let _busy = Counted::new(&pool.busy);
+        let _span = span.entered();
+        work()
+    })
+    .await;
+    drop(permit);
+    pool.completed.fetch_add(1, Ordering::Relaxed);
+    result
+}
+
+/// The SHA-256 of a file, computed on the pool.
+pub async fn digest(path: impl AsRef<Path>) -> io::Result<String> {
+    let path = path.as_ref().to_path_buf();
+    run(move || sha256::try_digest(path.as_path()))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Utilization of the pool for the metrics endpoint.
+pub fn gauges() -> [(&'static str, &'static str, &'static str, u64); 4] {
+    let pool = pool();
+    [
+        (
+            "modelvault_cpu_pool_size",
+            "gauge",
+            "Most CPU-heavy tasks running at once.",
+            pool.size as u64,
+        ),
+        (
+            "modelvault_cpu_pool_busy_workers",
+            "gauge",
+            "CPU-heavy tasks running.",
+            pool.busy.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "modelvault_cpu_pool_queued_tasks",
+            "gauge",
+            "CPU-heavy tasks waiting for a worker.",
+            pool.queued.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "modelvault_cpu_pool_completed_total",
+            "counter",
+            "CPU-heavy tasks finished since the start.",
+            pool.completed.load(Ordering::Relaxed),
+        ),
+    ]
+}
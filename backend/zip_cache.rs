@@ -0,0 +1,139 @@
+use axum::body::Bytes;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File as TokioFile;
+use tracing::{debug, error, info};
+
+use crate::Config;
+
+pub fn is_enabled(config: &Config) -> bool {
+    config.zip_cache_max_mb > 0
+}
+
+fn max_bytes(config: &Config) -> u64 {
+    config.zip_cache_max_mb * 1024 * 1024
+}
+
+fn archive_path(config: &Config, key: &str) -> PathBuf {
+    config.zip_cache_dir.join(format!("{}.zip", key))
+}
+
+/// Identifies the content of an archive by the path, size and modification
+/// time of every file in it, so any change to the folder yields a new key
+/// without reading the files.
+pub fn archive_key(files: &[PathBuf], lib_dir: &Path) -> Option<String> {
+    let mut fingerprint = String::new();
+    for file in files {
+        let metadata = std::fs::metadata(file).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos();
+        let relative = file.strip_prefix(lib_dir).unwrap_or(file);
+        fingerprint.push_str(&format!(
+            "{}\0{}\0{}\n",
+            relative.display(),
+            metadata.len(),
+            modified
+        ));
+    }
+    Some(sha256::digest(fingerprint))
+}
+
+/// The cached archive for `key` and its size. A hit counts as a use, which
+/// keeps popular archives from being evicted.
+pub async fn open(config: &Config, key: &str) -> Option<(TokioFile, u64)> {
+    let path = archive_path(config, key);
+    let (file, size) = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::options().read(true).write(true).open(path)?;
+        file.set_modified(SystemTime::now())?;
+        let size = file.metadata()?.len();
+        std::io::Result::Ok((file, size))
+    })
+    .await
+    .ok()?
+    .ok()?;
+    Some((TokioFile::from_std(file), size))
+}
+
+/// Keeps an archive for the next download of the same content, unless it
+/// would not fit into the cache on its own.
+pub async fn store(config: Config, key: String, archive: Bytes) {
+    if archive.len() as u64 > max_bytes(&config) {
+        debug!("Archive {} is too large for the zip cache", key);
+        return;
+    }
+    let path = archive_path(&config, &key);
+    let partial = path.with_extension("zip.partial");
+    let written = async {
+        tokio::fs::create_dir_all(&config.zip_cache_dir).await?;
+        tokio::fs::write(&partial, &archive).await?;
+        tokio::fs::rename(&partial, &path).await
+    }
+    .await;
+    if let Err(e) = written {
+        error!("Failed to cache archive {}: {}", path.display(), e);
+        let _ = tokio::fs::remove_file(&partial).await;
+        return;
+    }
+
+    let result = tokio::task::spawn_blocking(move || evict(&config)).await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("Failed to evict cached archives: {}", e),
+        Err(e) => error!("Failed to evict cached archives: {}", e),
+    }
+}
+
+/// Space a file takes on disk. Sparse and reflinked copies take less than
+/// their length.
+#[cfg(unix)]
+fn disk_usage(metadata: &std::fs::Metadata) -> (u64, Option<(u64, u64)>) {
+    use std::os::unix::fs::MetadataExt;
+    (
+        metadata.blocks() * 512,
+        Some((metadata.dev(), metadata.ino())),
+    )
+}
+
+#[cfg(not(unix))]
+fn disk_usage(metadata: &std::fs::Metadata) -> (u64, Option<(u64, u64)>) {
+    (metadata.len(), None)
+}
+
+/// Removes the least recently used archives until the cache fits into
+/// `zip_cache_max_mb`. Hard linked archives are counted once.
+fn evict(config: &Config) -> std::io::Result<()> {
+    let mut archives = Vec::new();
+    let mut seen = HashSet::new();
+    let mut total = 0;
+    for entry in std::fs::read_dir(&config.zip_cache_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("zip") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let (mut size, inode) = disk_usage(&metadata);
+        if inode.is_some_and(|inode| !seen.insert(inode)) {
+            size = 0;
+        }
+        total += size;
+        archives.push((metadata.modified()?, size, path));
+    }
+
+    archives.sort();
+    let limit = max_bytes(config);
+    for (_, size, path) in archives {
+        if total <= limit {
+            break;
+        }
+        std::fs::remove_file(&path)?;
+        total = total.saturating_sub(size);
+        info!("Evicted cached archive {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Collects the files of the built frontend for the `embed-frontend`
+/// feature into a table of `include_bytes!`, so the binary needs no `dist`
+/// folder next to it.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EMBED_FRONTEND").is_none() {
+        return;
+    }
+
+    let dist = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("dist");
+    println!("cargo:rerun-if-changed={}", dist.display());
+    if !dist.join("index.html").is_file() {
+        panic!(
+            "embed-frontend needs the built frontend in {}, run `npm run build` first",
+            dist.display()
+        );
+    }
+
+    let mut files = Vec::new();
+    collect(&dist, &mut files);
+    files.sort();
+
+    let mut table = String::from("pub static ASSETS: &[(&str, &[u8])] = &[\n");
+    for file in files {
+        let relative = file
+            .strip_prefix(&dist)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        table.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            relative,
+            file.display().to_string()
+        ));
+    }
+    table.push_str("];\n");
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("frontend_assets.rs");
+    fs::write(out, table).unwrap();
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
@@ -9,7 +9,7 @@ import { useState } from "react";
 
 import { NavLink } from "react-router-dom";
 import { Button } from "./components/ui/button";
-import { BACKEND_BASE_URL } from "./lib/api";
+import { API_URL, csrfHeaders } from "./lib/api";
 import AboutModelPack from "./ModelPack";
 import UploadModel from "./UploadModel";
 import { Toaster } from "./components/ui/toaster";
@@ -42,8 +42,9 @@ function Refresh() {
     async function handleRefresh() {
         setLoading(true);
         try {
-            const response = await fetch(BACKEND_BASE_URL + "/api/refresh", {
+            const response = await fetch(API_URL + "/refresh", {
                 method: "POST",
+                headers: csrfHeaders(),
             });
             if (!response.ok) {
                 throw new Error("Network response was not ok");
@@ -6,7 +6,7 @@ import { Input } from "@/components/ui/input";
 import { Label } from "@/components/ui/label";
 import { Textarea } from "@/components/ui/textarea";
 import { Alert, AlertDescription, AlertTitle } from "@/components/ui/alert";
-import { BACKEND_BASE_URL } from "./lib/api";
+import { API_URL, csrfHeaders, readData, readError } from "./lib/api";
 import { useToast } from "./hooks/use-toast";
 import { DetailedModelResponse, ModelPackV0_1, UploadResponse } from "./bindings";
 import { useNavigate, useParams } from "react-router-dom";
@@ -27,7 +27,7 @@ function EditModel() {
     const navigate = useNavigate();
 
     async function getModel() {
-        fetch(BACKEND_BASE_URL + `/api/model/${slug}`, {
+        fetch(API_URL + `/model/${slug}`, {
             method: "GET",
         })
             .then((response) => {
@@ -38,7 +38,7 @@ function EditModel() {
                     });
                     navigate("/");
                 }
-                return response.json();
+                return readData(response);
             })
             .then((model: DetailedModelResponse) => {
                 setModelData({
@@ -106,17 +106,18 @@ function EditModel() {
                 formData.append(`image_files`, file);
             });
 
-            const response = await fetch(`${BACKEND_BASE_URL}/api/model/${slug}/update`, {
+            const response = await fetch(`${API_URL}/model/${slug}/update`, {
                 method: "POST",
+                headers: csrfHeaders(),
                 body: formData,
             });
 
             if (!response.ok) {
-                const errorText = await response.text();
+                const errorText = await readError(response);
                 throw new Error(errorText || "Upload failed");
             }
 
-            const result: UploadResponse = await response.json();
+            const result: UploadResponse = await readData<UploadResponse>(response);
 
             toast({
                 title: "Upload Successful",
@@ -4,8 +4,8 @@ import { Download, Heart, MoreVertical, RefreshCcw, Bookmark } from "lucide-reac
 
 import { useState, useEffect, useRef } from "react";
 import { useNavigate, useParams } from "react-router-dom";
-import { DetailedFileResponse, DetailedModelResponse } from "./bindings";
-import { BACKEND_BASE_URL } from "./lib/api";
+import { DetailedFileResponse, DetailedModelResponse, DocumentKind } from "./bindings";
+import { API_URL, BACKEND_BASE_URL, csrfHeaders, readData } from "./lib/api";
 import { saveAs } from "file-saver";
 import { ChevronLeft, ChevronRight } from "lucide-react";
 import ReactMarkdown from "react-markdown";
@@ -43,8 +43,9 @@ function OptionsDropdownMenu({ model }: { model: DetailedModelResponse }) {
     const navigate = useNavigate();
 
     async function deleteModel() {
-        fetch(BACKEND_BASE_URL + `/api/model/${model.name}/delete`, {
+        fetch(API_URL + `/model/${model.slug}/delete`, {
             method: "POST",
+            headers: csrfHeaders(),
         })
             .then((response) => {
                 if (!response.ok) {
@@ -73,7 +74,7 @@ function OptionsDropdownMenu({ model }: { model: DetailedModelResponse }) {
                 <DropdownMenuContent>
                     <DropdownMenuLabel>Model Options</DropdownMenuLabel>
                     <DropdownMenuSeparator></DropdownMenuSeparator>
-                    <DropdownMenuItem onClick={() => navigate(`/model/${model.name}/edit`)}>Edit</DropdownMenuItem>
+                    <DropdownMenuItem onClick={() => navigate(`/model/${model.slug}/edit`)}>Edit</DropdownMenuItem>
                     <DropdownMenuItem onClick={() => setIsDeleteDialogOpen(true)}>Delete</DropdownMenuItem>
                 </DropdownMenuContent>
             </DropdownMenu>
@@ -83,8 +84,8 @@ function OptionsDropdownMenu({ model }: { model: DetailedModelResponse }) {
                     <AlertDialogHeader>
                         <AlertDialogTitle>Are you absolutely sure to delete "{model.title}"?</AlertDialogTitle>
                         <AlertDialogDescription>
-                            This action cannot be undone. This will permanently delete "{model.title}" from database and
-                            filesystem.
+                            This will move "{model.title}" to the trash. It is permanently deleted once the trash
+                            retention period has passed.
                         </AlertDialogDescription>
                     </AlertDialogHeader>
                     <AlertDialogFooter>
@@ -226,7 +227,7 @@ function InfoCard({ model, refresh }: { model: DetailedModelResponse; refresh: (
                 <Button
                     size="lg"
                     className="w-full"
-                    onClick={() => (window.location.href = BACKEND_BASE_URL + "/api/download/" + model.package_name)}
+                    onClick={() => (window.location.href = API_URL + "/download/" + model.package_name)}
                 >
                     <Download className="mr-2 h-5 w-5" />
                     Download
@@ -283,8 +284,9 @@ function File({ file, reload: reload }: { file: DetailedFileResponse; reload: ()
     const [isDeleteDialogOpen, setIsDeleteDialogOpen] = useState(false);
 
     async function deleteFile() {
-        fetch(BACKEND_BASE_URL + `/api/file/${file.id}/delete`, {
+        fetch(API_URL + `/file/${file.id}/delete`, {
             method: "POST",
+            headers: csrfHeaders(),
         })
             .then((response) => {
                 if (!response.ok) {
@@ -320,22 +322,43 @@ function File({ file, reload: reload }: { file: DetailedFileResponse; reload: ()
                     </div>
 
                     <div className="flex gap-2">
-                        <Dialog>
-                            <DialogTrigger asChild>
-                                <Button variant="outline">3D Viewer</Button>
-                            </DialogTrigger>
-
-                            <DialogContent className="w-full h-full max-w-[90vw] max-h-[90vh] flex flex-col">
-                                <DialogHeader>
-                                    <DialogTitle className="large-text">3D Viewer: {file.name}</DialogTitle>
-                                    <DialogDescription className="large-text">
-                                        Pan with Right Mouse Button, Rotate with Left Mouse Button and Zoom with Scroll
-                                        Wheel
-                                    </DialogDescription>
-                                </DialogHeader>
-                                <ModelViewer file_path={file.file_path} />
-                            </DialogContent>
-                        </Dialog>
+                        {file.is_video ? (
+                            <Dialog>
+                                <DialogTrigger asChild>
+                                    <Button variant="outline">Play</Button>
+                                </DialogTrigger>
+
+                                <DialogContent className="w-full max-w-[90vw] max-h-[90vh] flex flex-col">
+                                    <DialogHeader>
+                                        <DialogTitle className="large-text">{file.name}</DialogTitle>
+                                    </DialogHeader>
+                                    <video
+                                        src={BACKEND_BASE_URL + file.file_path}
+                                        poster={file.preview_image ? BACKEND_BASE_URL + file.preview_image : undefined}
+                                        className="w-full max-h-[75vh]"
+                                        preload="metadata"
+                                        controls
+                                    />
+                                </DialogContent>
+                            </Dialog>
+                        ) : (
+                            <Dialog>
+                                <DialogTrigger asChild>
+                                    <Button variant="outline">3D Viewer</Button>
+                                </DialogTrigger>
+
+                                <DialogContent className="w-full h-full max-w-[90vw] max-h-[90vh] flex flex-col">
+                                    <DialogHeader>
+                                        <DialogTitle className="large-text">3D Viewer: {file.name}</DialogTitle>
+                                        <DialogDescription className="large-text">
+                                            Pan with Right Mouse Button, Rotate with Left Mouse Button and Zoom with
+                                            Scroll Wheel
+                                        </DialogDescription>
+                                    </DialogHeader>
+                                    <ModelViewer file_path={file.file_path} />
+                                </DialogContent>
+                            </Dialog>
+                        )}
 
                         <Button
                             variant="outline"
@@ -360,7 +383,7 @@ function File({ file, reload: reload }: { file: DetailedFileResponse; reload: ()
                                     <DropdownMenuItem
                                         onClick={() =>
                                             saveAs(
-                                                BACKEND_BASE_URL + `/api/file/${file.id}/convert/stl`,
+                                                API_URL + `/file/${file.id}/convert/stl`,
                                                 file.name + ".stl",
                                             )
                                         }
@@ -372,7 +395,7 @@ function File({ file, reload: reload }: { file: DetailedFileResponse; reload: ()
                                     <DropdownMenuItem
                                         onClick={() =>
                                             saveAs(
-                                                BACKEND_BASE_URL + `/api/file/${file.id}/convert/threemf`,
+                                                API_URL + `/file/${file.id}/convert/threemf`,
                                                 file.name + ".3mf",
                                             )
                                         }
@@ -384,7 +407,7 @@ function File({ file, reload: reload }: { file: DetailedFileResponse; reload: ()
                                     <DropdownMenuItem
                                         onClick={() =>
                                             saveAs(
-                                                BACKEND_BASE_URL + `/api/file/${file.id}/convert/step`,
+                                                API_URL + `/file/${file.id}/convert/step`,
                                                 file.name + ".step",
                                             )
                                         }
@@ -396,7 +419,7 @@ function File({ file, reload: reload }: { file: DetailedFileResponse; reload: ()
                                     <DropdownMenuItem
                                         onClick={() =>
                                             saveAs(
-                                                BACKEND_BASE_URL + `/api/file/${file.id}/convert/iges`,
+                                                API_URL + `/file/${file.id}/convert/iges`,
                                                 file.name + ".iges",
                                             )
                                         }
@@ -444,7 +467,7 @@ function FileList({ model, reload: reload }: { model: DetailedModelResponse; rel
                 <Button
                     variant="outline"
                     className="flex items-center gap-2"
-                    onClick={() => (window.location.href = BACKEND_BASE_URL + "/api/download/" + model.package_name)}
+                    onClick={() => (window.location.href = API_URL + "/download/" + model.package_name)}
                 >
                     <Download size={16} />
                     All Files (483 KB)
@@ -460,6 +483,59 @@ function FileList({ model, reload: reload }: { model: DetailedModelResponse; rel
     );
 }
 
+const DOCUMENT_KIND_LABELS: Record<DocumentKind, string> = {
+    [DocumentKind.Instructions]: "Instructions",
+    [DocumentKind.Pdf]: "PDF",
+    [DocumentKind.Spreadsheet]: "Spreadsheet",
+    [DocumentKind.Text]: "Text",
+};
+
+function Documentation({ model }: { model: DetailedModelResponse }) {
+    if (model.documentation.length === 0) {
+        return null;
+    }
+
+    return (
+        <div className="max-w-6xl mx-auto p-6">
+            <h2 className="mb-6 text-xl font-bold">Documentation</h2>
+            <div className="space-y-4">
+                {model.documentation.map((document) => (
+                    <Card className="p-4" key={document.id}>
+                        <div className="flex items-center justify-between">
+                            <div className="flex items-center gap-4">
+                                {document.preview_image && (
+                                    <img src={BACKEND_BASE_URL + document.preview_image} className="h-24" />
+                                )}
+                                <div>
+                                    <h3 className="font-medium">{document.name}</h3>
+                                    <p className="text-sm text-gray-500">
+                                        {DOCUMENT_KIND_LABELS[document.kind]} | {document.file_size}
+                                    </p>
+                                </div>
+                            </div>
+                            <div className="flex gap-2">
+                                <Button variant="outline" asChild>
+                                    <a href={BACKEND_BASE_URL + document.file_path} target="_blank" rel="noreferrer">
+                                        Open
+                                    </a>
+                                </Button>
+                                <Button
+                                    variant="outline"
+                                    className="flex items-center gap-2"
+                                    onClick={() => saveAs(BACKEND_BASE_URL + document.file_path, document.name)}
+                                >
+                                    <Download size={16} />
+                                    Download
+                                </Button>
+                            </div>
+                        </div>
+                    </Card>
+                ))}
+            </div>
+        </div>
+    );
+}
+
 function Model() {
     const { slug } = useParams();
 
@@ -467,7 +543,7 @@ function Model() {
     const { toast } = useToast();
 
     async function getModel() {
-        fetch(BACKEND_BASE_URL + `/api/model/${slug}`, {
+        fetch(API_URL + `/model/${slug}`, {
             method: "GET",
         })
             .then((response) => {
@@ -477,7 +553,7 @@ function Model() {
                         description: `Loading '${model?.name}' failed`,
                     });
                 }
-                return response.json();
+                return readData(response);
             })
             .then((response_models: DetailedModelResponse) => {
                 setModel(response_models);
@@ -489,8 +565,9 @@ function Model() {
     }
 
     async function refresh() {
-        fetch(BACKEND_BASE_URL + `/api/model/${slug}/refresh`, {
-            method: "GET",
+        fetch(API_URL + `/model/${slug}/refresh`, {
+            method: "POST",
+            headers: csrfHeaders(),
         })
             .then((response) => {
                 if (!response.ok) {
@@ -503,7 +580,7 @@ function Model() {
                     title: "Refresh Successful",
                     description: `Refreshing '${model?.name}' successful`,
                 });
-                return response.json();
+                return readData(response);
             })
             .then((response_models: DetailedModelResponse) => {
                 setModel(response_models);
@@ -530,6 +607,7 @@ function Model() {
                         </div>
                     </div>
                     <Description model={model} />
+                    <Documentation model={model} />
                     <FileList model={model} reload={getModel} />
                 </div>
             )}
@@ -88,6 +88,11 @@ const AboutModelPack = () => {
     "license": {
       "type": "string",
       "description": "The license under which the ModelPack is distributed."
+    },
+    "tags": {
+      "type": "array",
+      "items": { "type": "string" },
+      "description": "Optional tags used to group and find the ModelPack."
     }
   },
   "required": ["version", "title", "author", "origin", "license"]
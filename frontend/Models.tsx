@@ -14,7 +14,7 @@ import {
     PaginationPrevious,
 } from "./components/ui/pagination";
 import { ModelResponse, ModelResponseList } from "./bindings";
-import { BACKEND_BASE_URL } from "./lib/api";
+import { API_URL, BACKEND_BASE_URL, readData } from "./lib/api";
 import { useTheme } from "./components/theme-provider";
 import { Link } from "react-router-dom";
 import { Checkbox } from "./components/ui/checkbox";
@@ -38,7 +38,7 @@ export function ModelCard({ model }: { model: ModelResponse }) {
     const { theme } = useTheme();
     const fillColor = theme === "dark" ? "white" : "black";
     const heart = true;
-    const detail_link = `/model/${model.name}`;
+    const detail_link = `/model/${model.slug}`;
 
     return (
         <Card className="w-full bg-background border-border hover:shadow-lg transition-shadow duration-200">
@@ -75,14 +75,14 @@ function Models() {
     const [selectedLicenses, setSelectedLicenses] = useState<string[]>([]);
 
     async function getModels() {
-        fetch(BACKEND_BASE_URL + "/api/models/list", {
+        fetch(API_URL + "/models/list", {
             method: "GET",
         })
             .then((response) => {
                 if (!response.ok) {
                     throw new Error("Network response was not ok");
                 }
-                return response.json();
+                return readData(response);
             })
             .then((response_models: ModelResponseList) => {
                 setModels(response_models);
@@ -96,14 +96,14 @@ function Models() {
         const selectedLicensesString = selectedLicenses.join(",");
         const queryString = selectedLicensesString ? `?licenses=${selectedLicensesString}` : "";
 
-        fetch(BACKEND_BASE_URL + "/api/models/list" + queryString, {
+        fetch(API_URL + "/models/list" + queryString, {
             method: "GET",
         })
             .then((response) => {
                 if (!response.ok) {
                     throw new Error("Network response was not ok");
                 }
-                return response.json();
+                return readData(response);
             })
             .then((response_models: ModelResponseList) => {
                 setModels(response_models);
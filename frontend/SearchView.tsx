@@ -1,21 +1,21 @@
 import { useEffect } from "react";
 import { useState } from "react";
 import { ModelResponseList } from "./bindings";
-import { BACKEND_BASE_URL } from "./lib/api";
+import { API_URL, readData } from "./lib/api";
 import { ModelCard } from "./Models";
 
 function SearchView({ searchValue, setSearchValue }: { searchValue: string; setSearchValue: (value: string) => void }) {
     const [models, setModels] = useState<ModelResponseList>();
 
     async function getModels() {
-        fetch(BACKEND_BASE_URL + "/api/models/list?q=" + searchValue, {
+        fetch(API_URL + "/models/list?q=" + searchValue, {
             method: "GET",
         })
             .then((response) => {
                 if (!response.ok) {
                     throw new Error("Network response was not ok");
                 }
-                return response.json();
+                return readData(response);
             })
             .then((response_models: ModelResponseList) => {
                 setModels(response_models);
@@ -6,7 +6,7 @@ import { Input } from "@/components/ui/input";
 import { Label } from "@/components/ui/label";
 import { Textarea } from "@/components/ui/textarea";
 import { Alert, AlertDescription, AlertTitle } from "@/components/ui/alert";
-import { BACKEND_BASE_URL } from "./lib/api";
+import { API_URL, csrfHeaders, readData, readError } from "./lib/api";
 import { useToast } from "./hooks/use-toast";
 import { ModelPackV0_1, UploadResponse } from "./bindings";
 import { useNavigate } from "react-router-dom";
@@ -88,17 +88,18 @@ function UploadModel() {
                 formData.append(`image_files`, file);
             });
 
-            const response = await fetch(`${BACKEND_BASE_URL}/api/upload`, {
+            const response = await fetch(`${API_URL}/upload`, {
                 method: "POST",
+                headers: csrfHeaders(),
                 body: formData,
             });
 
             if (!response.ok) {
-                const errorText = await response.text();
+                const errorText = await readError(response);
                 throw new Error(errorText || "Upload failed");
             }
 
-            const result: UploadResponse = await response.json();
+            const result: UploadResponse = await readData<UploadResponse>(response);
 
             toast({
                 title: "Upload Successful",
@@ -5,3 +5,37 @@ if (backendBaseUrl.endsWith("/")) {
 }
 
 export const BACKEND_BASE_URL = backendBaseUrl;
+
+export const API_URL = `${backendBaseUrl}/api/v1`;
+
+/**
+ * Unwraps the `{ data }` envelope of a successful API response.
+ */
+export async function readData<T>(response: Response): Promise<T> {
+    const body = await response.json();
+    return body.data as T;
+}
+
+/**
+ * The message of an `{ error }` envelope, for failed API responses.
+ */
+export async function readError(response: Response): Promise<string> {
+    try {
+        const body = await response.json();
+        return body.error?.message || response.statusText;
+    } catch {
+        return response.statusText;
+    }
+}
+
+/**
+ * Echoes the CSRF cookie handed out by the backend, required on state
+ * changing requests when CSRF_PROTECTION is enabled.
+ */
+export function csrfHeaders(): Record<string, string> {
+    const token = document.cookie
+        .split(";")
+        .map((cookie) => cookie.trim().split("="))
+        .find(([name]) => name === "csrf_token")?.[1];
+    return token ? { "X-CSRF-Token": token } : {};
+}
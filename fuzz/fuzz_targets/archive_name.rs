@@ -0,0 +1,61 @@
+//! Joins arbitrary bytes onto a library path and checks that whatever name
+//! `archive_name` hands out stays below the library. Run with
+//! `cargo +nightly fuzz run archive_name` from the repository root.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+#[path = "../../backend/stream_dl/archive_name.rs"]
+mod archive_name;
+
+use archive_name::archive_name;
+
+const LIBRARIES: &[&str] = &["/srv/library", "/srv/library/", "library", "./library", "/"];
+
+/// Lexically resolves `.` and `..`, `None` if it climbs above the root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    Some(normalized)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, rest)) = data.split_first() else {
+        return;
+    };
+    let library = Path::new(LIBRARIES[selector as usize % LIBRARIES.len()]);
+    let path = library.join(OsStr::from_bytes(rest));
+
+    let Some(name) = archive_name(&path, library) else {
+        return;
+    };
+    assert!(!name.is_empty());
+    assert!(!name.contains('\\'), "{:?} gave {}", path, name);
+    assert!(
+        name.split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".."),
+        "{:?} gave {}",
+        path,
+        name
+    );
+    assert_eq!(
+        normalize(&library.join(&name)),
+        normalize(&path),
+        "{:?} gave {}",
+        path,
+        name
+    );
+});
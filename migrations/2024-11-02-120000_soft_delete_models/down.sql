@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE models3d DROP COLUMN trash_path;
+ALTER TABLE models3d DROP COLUMN purge_after;
+ALTER TABLE models3d DROP COLUMN deleted_at;
@@ -0,0 +1,3 @@
+ALTER TABLE models3d ADD COLUMN deleted_at TIMESTAMP;
+ALTER TABLE models3d ADD COLUMN purge_after TIMESTAMP; -- NULL keeps the model in the trash forever
+ALTER TABLE models3d ADD COLUMN trash_path VARCHAR(4096);
@@ -0,0 +1,25 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE slug_redirects;
+
+CREATE TABLE models3d_old (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    title VARCHAR(4096) NOT NULL,
+    name VARCHAR(4096) NOT NULL UNIQUE, -- unique slug
+    license VARCHAR(256),
+    author VARCHAR(256),
+    folder_path VARCHAR(4096) NOT NULL UNIQUE,
+    origin VARCHAR(2048),
+    date_added TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    images VARCHAR,
+    description VARCHAR DEFAULT '',
+    deleted_at TIMESTAMP,
+    purge_after TIMESTAMP,
+    trash_path VARCHAR(4096)
+);
+
+INSERT INTO models3d_old (id, title, name, license, author, folder_path, origin, date_added, images, description, deleted_at, purge_after, trash_path)
+SELECT id, title, slug, license, author, folder_path, origin, date_added, images, description, deleted_at, purge_after, trash_path
+FROM models3d;
+
+DROP TABLE models3d;
+ALTER TABLE models3d_old RENAME TO models3d;
@@ -0,0 +1,30 @@
+-- name stays the plain slugified title, slug is the unique url identifier
+CREATE TABLE models3d_new (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    title VARCHAR(4096) NOT NULL,
+    name VARCHAR(4096) NOT NULL,
+    license VARCHAR(256),
+    author VARCHAR(256),
+    folder_path VARCHAR(4096) NOT NULL UNIQUE,
+    origin VARCHAR(2048),
+    date_added TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    images VARCHAR,
+    description VARCHAR DEFAULT '',
+    deleted_at TIMESTAMP,
+    purge_after TIMESTAMP,
+    trash_path VARCHAR(4096),
+    slug VARCHAR(4096) NOT NULL UNIQUE -- unique slug
+);
+
+INSERT INTO models3d_new (id, title, name, license, author, folder_path, origin, date_added, images, description, deleted_at, purge_after, trash_path, slug)
+SELECT id, title, name, license, author, folder_path, origin, date_added, images, description, deleted_at, purge_after, trash_path, name
+FROM models3d;
+
+DROP TABLE models3d;
+ALTER TABLE models3d_new RENAME TO models3d;
+
+CREATE TABLE slug_redirects (
+    slug VARCHAR(4096) NOT NULL PRIMARY KEY, -- slug a model was reachable under before a rename
+    model_id INTEGER NOT NULL,
+    FOREIGN KEY (model_id) REFERENCES models3d(id) ON DELETE CASCADE
+);
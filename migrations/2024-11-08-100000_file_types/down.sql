@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX files3d_file_type;
+ALTER TABLE files3d DROP COLUMN file_type;
@@ -0,0 +1,6 @@
+ALTER TABLE files3d ADD COLUMN file_type VARCHAR(16) NOT NULL DEFAULT '';
+-- everything after the last dot of the path
+UPDATE files3d
+SET file_type = LOWER(REPLACE(file_path, RTRIM(file_path, REPLACE(file_path, '.', '')), ''))
+WHERE INSTR(file_path, '.') > 0;
+CREATE INDEX files3d_file_type ON files3d (file_type, model_id);
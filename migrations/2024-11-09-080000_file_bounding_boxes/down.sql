@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE files3d DROP COLUMN size_z;
+ALTER TABLE files3d DROP COLUMN size_y;
+ALTER TABLE files3d DROP COLUMN size_x;
@@ -0,0 +1,3 @@
+ALTER TABLE files3d ADD COLUMN size_x DOUBLE;
+ALTER TABLE files3d ADD COLUMN size_y DOUBLE;
+ALTER TABLE files3d ADD COLUMN size_z DOUBLE;
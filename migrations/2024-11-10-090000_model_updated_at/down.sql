@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX models3d_updated_at;
+DROP INDEX models3d_date_added;
+ALTER TABLE models3d DROP COLUMN updated_at;
@@ -0,0 +1,4 @@
+ALTER TABLE models3d ADD COLUMN updated_at TIMESTAMP;
+UPDATE models3d SET updated_at = date_added;
+CREATE INDEX models3d_date_added ON models3d (date_added, id);
+CREATE INDEX models3d_updated_at ON models3d (updated_at, id);
@@ -0,0 +1,8 @@
+CREATE TABLE model_events (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    model_id INTEGER NOT NULL,
+    kind VARCHAR(16) NOT NULL, -- download or view
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (model_id) REFERENCES models3d(id) ON DELETE CASCADE
+);
+CREATE INDEX model_events_created_at ON model_events (created_at, model_id);
@@ -0,0 +1,13 @@
+CREATE TABLE tags (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR(256) NOT NULL UNIQUE -- normalized, lower case
+);
+
+CREATE TABLE model_tags (
+    model_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    PRIMARY KEY (model_id, tag_id),
+    FOREIGN KEY (model_id) REFERENCES models3d(id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+);
+CREATE INDEX model_tags_tag_id ON model_tags (tag_id, model_id);
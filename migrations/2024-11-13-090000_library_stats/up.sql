@@ -0,0 +1,9 @@
+-- one snapshot per library refresh
+CREATE TABLE library_stats (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    total_models INTEGER NOT NULL,
+    total_files INTEGER NOT NULL,
+    total_bytes BIGINT NOT NULL,
+    details TEXT NOT NULL -- json encoded LibraryStats
+);
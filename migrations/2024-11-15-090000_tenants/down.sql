@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE library_stats DROP COLUMN tenant_id;
+DROP INDEX models3d_tenant_id;
+ALTER TABLE models3d DROP COLUMN tenant_id;
+DROP TABLE tenants;
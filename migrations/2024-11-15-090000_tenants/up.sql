@@ -0,0 +1,10 @@
+-- a tenant owns the top level folder of the library with its name
+CREATE TABLE tenants (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR(256) NOT NULL UNIQUE
+);
+
+ALTER TABLE models3d ADD COLUMN tenant_id INTEGER REFERENCES tenants(id);
+CREATE INDEX models3d_tenant_id ON models3d (tenant_id);
+
+ALTER TABLE library_stats ADD COLUMN tenant_id INTEGER REFERENCES tenants(id);
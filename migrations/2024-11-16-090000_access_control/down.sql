@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX model_grants_principal;
+DROP TABLE model_grants;
+ALTER TABLE models3d DROP COLUMN owner_id;
+ALTER TABLE models3d DROP COLUMN visibility;
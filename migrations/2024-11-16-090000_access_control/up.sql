@@ -0,0 +1,11 @@
+-- public: everyone, internal: authenticated users, private: owner, grants and admins
+ALTER TABLE models3d ADD COLUMN visibility VARCHAR(16) NOT NULL DEFAULT 'public';
+ALTER TABLE models3d ADD COLUMN owner_id INTEGER REFERENCES users(id);
+
+CREATE TABLE model_grants (
+    model_id INTEGER NOT NULL REFERENCES models3d(id),
+    principal_kind VARCHAR(16) NOT NULL,
+    principal VARCHAR(256) NOT NULL,
+    PRIMARY KEY (model_id, principal_kind, principal)
+);
+CREATE INDEX model_grants_principal ON model_grants (principal_kind, principal);
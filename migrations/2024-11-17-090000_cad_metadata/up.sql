@@ -0,0 +1,2 @@
+-- units, part names and schema read from STEP and IGES headers, as JSON
+ALTER TABLE files3d ADD COLUMN cad_metadata TEXT;
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE files3d DROP COLUMN print_settings;
@@ -0,0 +1,2 @@
+-- slicer profile, layer height, supports, material and objects of 3MF projects, as JSON
+ALTER TABLE files3d ADD COLUMN print_settings TEXT;
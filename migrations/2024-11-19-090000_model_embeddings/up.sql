@@ -0,0 +1,5 @@
+-- hashed bag of words of name, tags and description, little endian f32
+CREATE TABLE model_embeddings (
+    model_id INTEGER PRIMARY KEY NOT NULL REFERENCES models3d(id),
+    vector BLOB NOT NULL
+);
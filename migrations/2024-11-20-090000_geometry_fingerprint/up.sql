@@ -0,0 +1,2 @@
+-- histogram of distances between random surface points, as JSON
+ALTER TABLE files3d ADD COLUMN geometry_fingerprint TEXT;
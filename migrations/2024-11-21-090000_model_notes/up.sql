@@ -0,0 +1,2 @@
+-- content of notes.md or notes.txt next to the README
+ALTER TABLE models3d ADD COLUMN notes TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,12 @@
+CREATE TABLE saved_searches (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    name VARCHAR(256) NOT NULL,
+    -- the list filters as JSON
+    filters TEXT NOT NULL,
+    notify BOOLEAN NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL,
+    -- models added after this are new matches
+    last_checked_at TIMESTAMP NOT NULL,
+    UNIQUE (user_id, name)
+);
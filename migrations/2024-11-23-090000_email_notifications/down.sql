@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE saved_searches DROP COLUMN last_notified_at;
+DROP TABLE email_subscriptions;
+ALTER TABLE users DROP COLUMN email;
@@ -0,0 +1,10 @@
+-- addresses and opted in events for email notifications
+ALTER TABLE users ADD COLUMN email TEXT;
+CREATE TABLE email_subscriptions (
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    -- upload_finished, refresh_failed or saved_search_matches
+    event VARCHAR(64) NOT NULL,
+    PRIMARY KEY (user_id, event)
+);
+-- models added after this were already emailed
+ALTER TABLE saved_searches ADD COLUMN last_notified_at TIMESTAMP;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE download_clients;
+DROP TABLE download_counts;
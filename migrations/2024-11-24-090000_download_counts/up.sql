@@ -0,0 +1,14 @@
+-- downloads per model and day, every client is counted once a day
+CREATE TABLE download_counts (
+    model_id INTEGER NOT NULL REFERENCES models3d(id),
+    day DATE NOT NULL,
+    downloads INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (model_id, day)
+);
+-- hashed clients which downloaded a model on a day
+CREATE TABLE download_clients (
+    model_id INTEGER NOT NULL REFERENCES models3d(id),
+    day DATE NOT NULL,
+    client VARCHAR(64) NOT NULL,
+    PRIMARY KEY (model_id, day, client)
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE print_logs;
+ALTER TABLE files3d DROP COLUMN filament_id;
+DROP TABLE filaments;
+ALTER TABLE files3d DROP COLUMN gcode_metadata;
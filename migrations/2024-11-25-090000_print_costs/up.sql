@@ -0,0 +1,25 @@
+-- filament usage and print time noted by the slicer in G-code comments, as JSON
+ALTER TABLE files3d ADD COLUMN gcode_metadata TEXT;
+-- spools the cost of prints is calculated with
+CREATE TABLE filaments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name VARCHAR(256) NOT NULL UNIQUE,
+    material VARCHAR(64) NOT NULL,
+    price_per_kg DOUBLE NOT NULL,
+    density DOUBLE NOT NULL DEFAULT 1.24,
+    diameter DOUBLE NOT NULL DEFAULT 1.75
+);
+ALTER TABLE files3d ADD COLUMN filament_id INTEGER REFERENCES filaments(id);
+-- prints made of a model, optionally of one of its sliced files
+CREATE TABLE print_logs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    model_id INTEGER NOT NULL REFERENCES models3d(id),
+    file_id INTEGER REFERENCES files3d(id),
+    user_id INTEGER REFERENCES users(id),
+    filament_id INTEGER REFERENCES filaments(id),
+    printed_at TIMESTAMP NOT NULL,
+    duration_seconds INTEGER,
+    filament_grams DOUBLE,
+    success BOOLEAN NOT NULL DEFAULT 1,
+    notes TEXT NOT NULL DEFAULT ''
+);
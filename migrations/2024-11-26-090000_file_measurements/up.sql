@@ -0,0 +1,2 @@
+-- bounding box, centre of mass, bed contact and suggested orientation of meshes, as JSON
+ALTER TABLE files3d ADD COLUMN measurements TEXT;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX audit_log_created_at;
+DROP TABLE audit_log;
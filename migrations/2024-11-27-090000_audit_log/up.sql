@@ -0,0 +1,10 @@
+-- administrative changes, who made them and what they touched
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    actor VARCHAR(256) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    target TEXT NOT NULL,
+    details TEXT NOT NULL DEFAULT '{}'
+);
+CREATE INDEX audit_log_created_at ON audit_log(created_at);
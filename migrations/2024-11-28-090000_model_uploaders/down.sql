@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX models3d_owner_id;
+DROP INDEX models3d_uploader_id;
+ALTER TABLE models3d DROP COLUMN uploader_id;
@@ -0,0 +1,10 @@
+-- who uploaded a model, unlike the owner this never changes
+ALTER TABLE models3d ADD COLUMN uploader_id INTEGER REFERENCES users(id);
+UPDATE models3d SET uploader_id = (
+    SELECT user_id FROM uploads
+    WHERE uploads.model_id = models3d.id
+    ORDER BY created_at ASC
+    LIMIT 1
+);
+CREATE INDEX models3d_uploader_id ON models3d (uploader_id);
+CREATE INDEX models3d_owner_id ON models3d (owner_id);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE print_logs DROP COLUMN timelapse_id;
@@ -0,0 +1,2 @@
+-- a video of the print, one of the files of the model
+ALTER TABLE print_logs ADD COLUMN timelapse_id INTEGER REFERENCES files3d(id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX models3d_sort_key;
+ALTER TABLE models3d DROP COLUMN sort_key;
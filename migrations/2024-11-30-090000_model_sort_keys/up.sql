@@ -0,0 +1,3 @@
+-- natural sort key of the title, filled in by the backend
+ALTER TABLE models3d ADD COLUMN sort_key TEXT NOT NULL DEFAULT '';
+CREATE INDEX models3d_sort_key ON models3d (sort_key);
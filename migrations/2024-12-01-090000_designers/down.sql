@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX models3d_designer_id;
+ALTER TABLE models3d DROP COLUMN designer_id;
+DROP TABLE designer_aliases;
+DROP TABLE designers;
@@ -0,0 +1,15 @@
+-- designers of models, filled in from the author of their metadata
+CREATE TABLE designers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL UNIQUE
+);
+-- normalized author names resolving to a designer, merged designers keep
+-- the names of the designers merged into them
+CREATE TABLE designer_aliases (
+    alias TEXT PRIMARY KEY NOT NULL,
+    designer_id INTEGER NOT NULL REFERENCES designers(id) ON DELETE CASCADE
+);
+CREATE INDEX designer_aliases_designer_id ON designer_aliases (designer_id);
+ALTER TABLE models3d ADD COLUMN designer_id INTEGER REFERENCES designers(id);
+CREATE INDEX models3d_designer_id ON models3d (designer_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX link_checks_status;
+DROP TABLE link_checks;
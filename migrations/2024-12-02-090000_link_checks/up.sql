@@ -0,0 +1,10 @@
+-- last result of checking the source link of a model
+CREATE TABLE link_checks (
+    model_id INTEGER PRIMARY KEY NOT NULL REFERENCES models3d(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    status TEXT NOT NULL,
+    http_status INTEGER,
+    checked_at TIMESTAMP NOT NULL,
+    failing_since TIMESTAMP
+);
+CREATE INDEX link_checks_status ON link_checks (status);
@@ -0,0 +1,11 @@
+-- things of Thingiverse and Printables exports already handled, so an
+-- interrupted import resumes where it stopped
+CREATE TABLE thing_imports (
+    source TEXT NOT NULL,
+    item TEXT NOT NULL,
+    status TEXT NOT NULL,
+    model_id INTEGER REFERENCES models3d(id) ON DELETE SET NULL,
+    error TEXT,
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (source, item)
+);
@@ -0,0 +1,8 @@
+-- folders the last refresh could not read, cleared once they are fixed
+CREATE TABLE parse_errors (
+    folder_path TEXT PRIMARY KEY NOT NULL,
+    message TEXT NOT NULL,
+    first_seen TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL,
+    occurrences INTEGER NOT NULL DEFAULT 1
+);
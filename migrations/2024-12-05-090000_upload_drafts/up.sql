@@ -0,0 +1,15 @@
+-- models being put together before they are published into the library,
+-- their files wait in DATA_DIR/drafts/<id>
+CREATE TABLE upload_drafts (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    library_root TEXT NOT NULL,
+    title TEXT NOT NULL,
+    author TEXT NOT NULL DEFAULT '',
+    origin TEXT NOT NULL DEFAULT '',
+    license TEXT NOT NULL DEFAULT '',
+    tags TEXT NOT NULL DEFAULT '[]',
+    description TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
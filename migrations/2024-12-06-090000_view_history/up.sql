@@ -0,0 +1,10 @@
+-- the models each user viewed last, for continuing where they left off
+CREATE TABLE view_history (
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    model_id INTEGER NOT NULL REFERENCES models3d(id),
+    viewed_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, model_id)
+);
+CREATE INDEX view_history_user_viewed_at ON view_history (user_id, viewed_at);
+-- users may opt out of having their views recorded
+ALTER TABLE users ADD COLUMN record_history BOOLEAN NOT NULL DEFAULT 1;
@@ -0,0 +1,13 @@
+-- other instances whose public models are mirrored into a library folder,
+-- the mirrored models are tracked in thing_imports with a `sync:` source
+CREATE TABLE subscriptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- address of the other instance, e.g. https://models.example.org
+    url TEXT NOT NULL UNIQUE,
+    title TEXT NOT NULL,
+    -- library folder the models are created in, the library root if empty
+    folder TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL,
+    last_synced_at TIMESTAMP,
+    last_error TEXT
+);
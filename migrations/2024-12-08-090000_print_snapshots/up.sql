@@ -0,0 +1,3 @@
+-- a photo taken by the webcam of the printer when the print was logged,
+-- the file name in the print snapshot directory
+ALTER TABLE print_logs ADD COLUMN snapshot TEXT;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE client_recent_files;
+DROP TABLE client_tokens;
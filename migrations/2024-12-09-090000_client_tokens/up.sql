@@ -0,0 +1,20 @@
+-- tokens of slicer plugins and desktop helpers using the client API, only
+-- the hash of a token is kept
+CREATE TABLE client_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL,
+    last_used_at TIMESTAMP
+);
+CREATE INDEX client_tokens_user_id ON client_tokens (user_id);
+-- the files each user fetched through the client API last
+CREATE TABLE client_recent_files (
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    file_id INTEGER NOT NULL,
+    model_id INTEGER NOT NULL REFERENCES models3d(id),
+    used_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, file_id)
+);
+CREATE INDEX client_recent_files_user_used_at ON client_recent_files (user_id, used_at);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX models3d_name_key;
+ALTER TABLE models3d DROP COLUMN name_key;
@@ -0,0 +1,4 @@
+-- NFC normalized, case folded title to compare names beyond ASCII, filled in
+-- by the backend which also resolves slugs colliding under the same rules
+ALTER TABLE models3d ADD COLUMN name_key TEXT NOT NULL DEFAULT '';
+CREATE INDEX models3d_name_key ON models3d (name_key);
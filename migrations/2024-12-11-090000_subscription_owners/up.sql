@@ -0,0 +1,2 @@
+-- the admin who subscribed, mirrored models count against their quota
+ALTER TABLE subscriptions ADD COLUMN user_id INTEGER REFERENCES users(id);